@@ -727,6 +727,7 @@ function convertFromTunnelState(tunnelState: grpcTypes.TunnelState): TunnelState
         details:
           tunnelStateObject.connecting?.relayInfo &&
           convertFromTunnelStateRelayInfo(tunnelStateObject.connecting.relayInfo),
+        reconnectAttempt: tunnelStateObject.connecting?.reconnectAttempt,
       };
     case grpcTypes.TunnelState.StateCase.CONNECTED: {
       const relayInfo =
@@ -585,7 +585,7 @@ export default class AppRenderer {
 
     switch (tunnelState.state) {
       case 'connecting':
-        actions.connection.connecting(tunnelState.details);
+        actions.connection.connecting(tunnelState.details, tunnelState.reconnectAttempt);
         break;
 
       case 'connected':
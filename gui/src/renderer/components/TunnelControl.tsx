@@ -1,10 +1,11 @@
 import * as React from 'react';
+import { sprintf } from 'sprintf-js';
 import styled from 'styled-components';
 import { TunnelState } from '../../shared/daemon-rpc-types';
 import { messages } from '../../shared/gettext';
 import ConnectionPanelContainer from '../containers/ConnectionPanelContainer';
 import * as AppButton from './AppButton';
-import { bigText } from './common-styles';
+import { bigText, smallText } from './common-styles';
 import ImageView from './ImageView';
 import Marquee from './Marquee';
 import { MultiButton } from './MultiButton';
@@ -34,6 +35,11 @@ const Secured = styled(SecuredLabel)({
   marginBottom: '2px',
 });
 
+const ReconnectAttempt = styled.span({
+  ...smallText,
+  marginBottom: '2px',
+});
+
 const Footer = styled.div({
   display: 'flex',
   flexDirection: 'column',
@@ -143,6 +149,7 @@ export default class TunnelControl extends React.Component<ITunnelControlProps>
           <Wrapper>
             <Body>
               <Secured displayStyle={SecuredDisplayStyle.securing} />
+              {this.renderReconnectAttempt()}
               <Location>
                 {this.renderCity()}
                 {this.renderCountry()}
@@ -240,6 +247,26 @@ export default class TunnelControl extends React.Component<ITunnelControlProps>
     }
   }
 
+  private renderReconnectAttempt() {
+    if (this.props.tunnelState.state !== 'connecting' || !this.props.tunnelState.reconnectAttempt) {
+      return null;
+    }
+
+    const { attempt, nextDelaySecs } = this.props.tunnelState.reconnectAttempt;
+    return (
+      <ReconnectAttempt>
+        {sprintf(
+          // TRANSLATORS: Shown while a tunnel that went down is being brought back up.
+          // TRANSLATORS: Available placeholders:
+          // TRANSLATORS: %(attempt)d - how many times in a row reconnecting has been tried
+          // TRANSLATORS: %(seconds)d - how many seconds until the attempt is made
+          messages.pgettext('tunnel-control', 'Reconnecting, attempt %(attempt)d in %(seconds)d s'),
+          { attempt, seconds: nextDelaySecs },
+        )}
+      </ReconnectAttempt>
+    );
+  }
+
   private renderCity() {
     return <StyledMarquee>{this.props.city}</StyledMarquee>;
   }
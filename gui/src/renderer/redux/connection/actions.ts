@@ -2,12 +2,14 @@ import {
   AfterDisconnect,
   IErrorState,
   ILocation,
+  IReconnectAttempt,
   ITunnelStateRelayInfo,
 } from '../../../shared/daemon-rpc-types';
 
 interface IConnectingAction {
   type: 'CONNECTING';
   details?: ITunnelStateRelayInfo;
+  reconnectAttempt?: IReconnectAttempt;
 }
 
 interface IConnectedAction {
@@ -48,10 +50,14 @@ export type ConnectionAction =
   | IBlockedAction
   | IUpdateBlockStateAction;
 
-function connecting(details?: ITunnelStateRelayInfo): IConnectingAction {
+function connecting(
+  details?: ITunnelStateRelayInfo,
+  reconnectAttempt?: IReconnectAttempt,
+): IConnectingAction {
   return {
     type: 'CONNECTING',
     details,
+    reconnectAttempt,
   };
 }
 
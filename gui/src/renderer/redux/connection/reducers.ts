@@ -51,7 +51,11 @@ export default function (
     case 'CONNECTING':
       return {
         ...state,
-        status: { state: 'connecting', details: action.details },
+        status: {
+          state: 'connecting',
+          details: action.details,
+          reconnectAttempt: action.reconnectAttempt,
+        },
       };
 
     case 'CONNECTED':
@@ -104,9 +104,19 @@ export interface ITunnelStateRelayInfo {
   location?: ILocation;
 }
 
+export interface IReconnectAttempt {
+  attempt: number;
+  nextDelaySecs: number;
+}
+
 export type TunnelState =
   | { state: 'disconnected' }
-  | { state: 'connecting'; details?: ITunnelStateRelayInfo }
+  | {
+      state: 'connecting';
+      details?: ITunnelStateRelayInfo;
+      // Set while the VPN client is bringing a tunnel that went down back up on its own
+      reconnectAttempt?: IReconnectAttempt;
+    }
   | { state: 'connected'; details: ITunnelStateRelayInfo }
   | { state: 'disconnecting'; details: AfterDisconnect }
   | { state: 'error'; details: IErrorState };
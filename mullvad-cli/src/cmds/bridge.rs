@@ -1,11 +1,15 @@
-use crate::{location, new_rpc_client, Command, Result};
+use crate::{location, new_rpc_client, output::Output, Command, Error, Result};
 use clap::value_t;
 
-use mullvad_management_interface::types::{
-    bridge_settings::{Type as BridgeSettingsType, *},
-    bridge_state::State as BridgeStateType,
-    BridgeSettings, BridgeState,
+use mullvad_management_interface::{
+    types::{
+        bridge_settings::{Type as BridgeSettingsType, *},
+        bridge_state::State as BridgeStateType,
+        BridgeSettings, BridgeState, RelayListCountry, RelayLocation,
+    },
+    ManagementServiceClient,
 };
+use serde::Serialize;
 use talpid_types::net::openvpn::SHADOWSOCKS_CIPHERS;
 
 use std::net::{IpAddr, SocketAddr};
@@ -33,7 +37,7 @@ impl Command for Bridge {
         match matches.subcommand() {
             ("set", Some(set_matches)) => Self::handle_set(set_matches).await,
             ("get", _) => Self::handle_get().await,
-            ("list", _) => Self::list_bridge_relays().await,
+            ("list", Some(list_matches)) => Self::list_bridge_relays(list_matches).await,
             _ => unreachable!("unhandled command"),
         }
     }
@@ -54,7 +58,7 @@ fn create_bridge_set_subcommand() -> clap::App<'static, 'static> {
 
 fn create_set_custom_settings_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("custom")
-        .about("Configure a SOCKS5 proxy")
+        .about("Configure a custom proxy")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(
             clap::SubCommand::with_name("local")
@@ -106,6 +110,33 @@ fn create_set_custom_settings_subcommand() -> clap::App<'static, 'static> {
                         .index(4),
                 ),
         )
+        .subcommand(
+            clap::SubCommand::with_name("http")
+                .about("Registers a remote HTTP proxy")
+                .arg(
+                    clap::Arg::with_name("remote-ip")
+                        .help("Specifies the IP of the HTTP proxy server")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("remote-port")
+                        .help("Specifies the port the HTTP proxy server is listening on")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    clap::Arg::with_name("username")
+                        .help("Specifies the username for basic authentication")
+                        .requires("password")
+                        .index(3),
+                )
+                .arg(
+                    clap::Arg::with_name("password")
+                        .help("Specifies the password for basic authentication")
+                        .index(4),
+                ),
+        )
         .subcommand(
             clap::SubCommand::with_name("shadowsocks")
                 .about("Configure bundled Shadowsocks proxy")
@@ -170,9 +201,13 @@ impl Bridge {
         match settings.bridge_settings.unwrap().r#type.unwrap() {
             BridgeSettingsType::Local(local_proxy) => Self::print_local_proxy(&local_proxy),
             BridgeSettingsType::Remote(remote_proxy) => Self::print_remote_proxy(&remote_proxy),
+            BridgeSettingsType::Http(http_proxy) => Self::print_http_proxy(&http_proxy),
             BridgeSettingsType::Shadowsocks(shadowsocks_proxy) => {
                 Self::print_shadowsocks_proxy(&shadowsocks_proxy)
             }
+            BridgeSettingsType::Obfuscation(obfuscation_proxy) => {
+                Self::print_obfuscation_proxy(&obfuscation_proxy)
+            }
             BridgeSettingsType::Normal(constraints) => {
                 println!(
                     "Bridge constraints - {}",
@@ -184,14 +219,23 @@ impl Bridge {
     }
 
     async fn handle_set_bridge_location(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let output = Output::from_matches(matches);
         let constraints = location::get_constraint(matches);
         let mut rpc = new_rpc_client().await?;
+        if !constraints.country.is_empty() {
+            validate_bridge_location(&get_bridge_countries(&mut rpc).await?, &constraints)?;
+        }
+        let message = format!(
+            "Bridge location set to {}",
+            location::format_location(Some(&constraints))
+        );
         rpc.set_bridge_settings(BridgeSettings {
             r#type: Some(BridgeSettingsType::Normal(BridgeConstraints {
                 location: Some(constraints),
             })),
         })
         .await?;
+        output.success(None, Some(&message));
         Ok(())
     }
 
@@ -275,6 +319,45 @@ impl Bridge {
                 r#type: Some(BridgeSettingsType::Remote(prost_proxy)),
             })
             .await?;
+        } else if let Some(args) = matches.subcommand_matches("http") {
+            let remote_ip =
+                value_t!(args.value_of("remote-ip"), IpAddr).unwrap_or_else(|e| e.exit());
+            let remote_port =
+                value_t!(args.value_of("remote-port"), u16).unwrap_or_else(|e| e.exit());
+            let username = args.value_of("username");
+            let password = args.value_of("password");
+
+            let auth = match (username, password) {
+                (Some(username), Some(password)) => Some(openvpn::ProxyAuth {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                }),
+                _ => None,
+            };
+            let prost_auth = auth.clone().map(|auth| RemoteProxyAuth {
+                username: auth.username.clone(),
+                password: auth.password.clone(),
+            });
+
+            let proxy = openvpn::HttpProxySettings {
+                address: SocketAddr::new(remote_ip, remote_port),
+                auth,
+            };
+            let prost_proxy = HttpProxySettings {
+                address: proxy.address.to_string(),
+                auth: prost_auth,
+            };
+
+            let packed_proxy = openvpn::ProxySettings::Http(proxy);
+            if let Err(error) = openvpn::validate_proxy_settings(&packed_proxy) {
+                panic!(error);
+            }
+
+            let mut rpc = new_rpc_client().await?;
+            rpc.set_bridge_settings(BridgeSettings {
+                r#type: Some(BridgeSettingsType::Http(prost_proxy)),
+            })
+            .await?;
         } else if let Some(args) = matches.subcommand_matches("shadowsocks") {
             let remote_ip =
                 value_t!(args.value_of("remote-ip"), IpAddr).unwrap_or_else(|e| e.exit());
@@ -339,6 +422,18 @@ impl Bridge {
         }
     }
 
+    fn print_http_proxy(proxy: &HttpProxySettings) {
+        println!("proxy: HTTP");
+        println!("  server address: {}", proxy.address);
+
+        if let Some(ref auth) = proxy.auth {
+            println!("  auth username: {}", auth.username);
+            println!("  auth password: {}", auth.password);
+        } else {
+            println!("  auth: none");
+        }
+    }
+
     fn print_shadowsocks_proxy(proxy: &ShadowsocksProxySettings) {
         println!("proxy: Shadowsocks");
         println!("  peer address: {}", proxy.peer);
@@ -346,53 +441,247 @@ impl Bridge {
         println!("  cipher: {}", proxy.cipher);
     }
 
-    async fn list_bridge_relays() -> Result<()> {
+    fn print_obfuscation_proxy(proxy: &ObfuscationProxySettings) {
+        println!("proxy: obfuscation");
+        println!("  peer address: {}", proxy.peer);
+        println!("  key: {}", proxy.key);
+    }
+
+    async fn list_bridge_relays(matches: &clap::ArgMatches<'_>) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let mut locations = rpc.get_relay_locations(()).await?.into_inner();
+        let countries = get_bridge_countries(&mut rpc).await?;
+
+        if Output::from_matches(matches).is_json() {
+            println!(
+                "{}",
+                serde_json::to_string(&bridge_relays(&countries))
+                    .expect("Failed to serialize bridge list")
+            );
+            return Ok(());
+        }
+
+        for country in countries {
+            println!("{} ({})", country.name, country.code);
+            for city in country.cities {
+                println!(
+                    "\t{} ({}) @ {:.5}°N, {:.5}°W",
+                    city.name, city.code, city.latitude, city.longitude
+                );
+                for relay in &city.relays {
+                    println!("\t\t{} ({})", relay.hostname, relay.ipv4_addr_in);
+                }
+            }
+            println!();
+        }
+        Ok(())
+    }
+}
 
-        let mut countries = Vec::new();
+/// Returns the countries, cities and relays that have active bridges, sorted by name.
+async fn get_bridge_countries(rpc: &mut ManagementServiceClient) -> Result<Vec<RelayListCountry>> {
+    let mut locations = rpc.get_relay_locations(()).await?.into_inner();
+    let mut countries = Vec::new();
+    while let Some(country) = locations.message().await? {
+        countries.push(country);
+    }
+    Ok(bridge_countries(countries))
+}
 
-        while let Some(mut country) = locations.message().await? {
+/// Removes everything but the active bridge relays from `countries`, and sorts what is left by
+/// name.
+fn bridge_countries(countries: Vec<RelayListCountry>) -> Vec<RelayListCountry> {
+    let mut countries: Vec<_> = countries
+        .into_iter()
+        .filter_map(|mut country| {
             country.cities = country
                 .cities
                 .into_iter()
                 .filter_map(|mut city| {
                     city.relays.retain(|relay| {
                         relay.active
-                            && relay.bridges.is_some()
-                            && !relay.bridges.as_ref().unwrap().shadowsocks.is_empty()
+                            && relay
+                                .bridges
+                                .as_ref()
+                                .map(|bridges| !bridges.shadowsocks.is_empty())
+                                .unwrap_or(false)
                     });
-                    if !city.relays.is_empty() {
-                        Some(city)
-                    } else {
-                        None
-                    }
+                    city.relays
+                        .sort_by(|r1, r2| natord::compare_ignore_case(&r1.hostname, &r2.hostname));
+                    Some(city).filter(|city| !city.relays.is_empty())
                 })
                 .collect();
-            if !country.cities.is_empty() {
-                countries.push(country);
-            }
-        }
-
-        countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
-        for mut country in countries {
             country
                 .cities
                 .sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
-            println!("{} ({})", country.name, country.code);
-            for mut city in country.cities {
-                city.relays
-                    .sort_by(|r1, r2| natord::compare_ignore_case(&r1.hostname, &r2.hostname));
-                println!(
-                    "\t{} ({}) @ {:.5}°N, {:.5}°W",
-                    city.name, city.code, city.latitude, city.longitude
-                );
-                for relay in &city.relays {
-                    println!("\t\t{} ({})", relay.hostname, relay.ipv4_addr_in);
-                }
+            Some(country).filter(|country| !country.cities.is_empty())
+        })
+        .collect();
+    countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+    countries
+}
+
+/// Fails unless `location` matches a country, city or relay among the bridges in `countries`.
+fn validate_bridge_location(
+    countries: &[RelayListCountry],
+    location: &RelayLocation,
+) -> Result<()> {
+    if location::location_exists(countries, location) {
+        Ok(())
+    } else {
+        Err(Error::InvalidCommand(
+            "The given location has no bridges. Use 'mullvad bridge list' to show available \
+             alternatives.",
+        ))
+    }
+}
+
+/// A bridge relay, as printed by `bridge list` in JSON mode.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct BridgeRelay {
+    country: String,
+    country_code: String,
+    city: String,
+    city_code: String,
+    hostname: String,
+    ipv4_addr_in: String,
+}
+
+fn bridge_relays(countries: &[RelayListCountry]) -> Vec<BridgeRelay> {
+    let mut relays = Vec::new();
+    for country in countries {
+        for city in &country.cities {
+            for relay in &city.relays {
+                relays.push(BridgeRelay {
+                    country: country.name.clone(),
+                    country_code: country.code.clone(),
+                    city: city.name.clone(),
+                    city_code: city.code.clone(),
+                    hostname: relay.hostname.clone(),
+                    ipv4_addr_in: relay.ipv4_addr_in.clone(),
+                });
             }
-            println!();
         }
-        Ok(())
+    }
+    relays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mullvad_management_interface::types::{
+        Relay, RelayBridges, RelayListCity, ShadowsocksEndpointData,
+    };
+
+    fn bridge(hostname: &str, active: bool) -> Relay {
+        Relay {
+            hostname: hostname.to_owned(),
+            ipv4_addr_in: "10.0.0.1".to_owned(),
+            active,
+            bridges: Some(RelayBridges {
+                shadowsocks: vec![ShadowsocksEndpointData::default()],
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn countries() -> Vec<RelayListCountry> {
+        vec![
+            RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "se".to_owned(),
+                cities: vec![RelayListCity {
+                    name: "Gothenburg".to_owned(),
+                    code: "got".to_owned(),
+                    relays: vec![
+                        bridge("se-got-br-001", true),
+                        bridge("se-got-br-002", false),
+                    ],
+                    ..Default::default()
+                }],
+            },
+            RelayListCountry {
+                name: "Germany".to_owned(),
+                code: "de".to_owned(),
+                cities: vec![RelayListCity {
+                    name: "Berlin".to_owned(),
+                    code: "ber".to_owned(),
+                    relays: vec![Relay {
+                        hostname: "de-ber-001".to_owned(),
+                        active: true,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            },
+        ]
+    }
+
+    fn parse_location(args: &[&str]) -> RelayLocation {
+        let matches = Bridge
+            .clap_subcommand()
+            .get_matches_from_safe(args)
+            .expect("failed to parse arguments");
+        let set_matches = matches.subcommand_matches("set").unwrap();
+        location::get_constraint(set_matches.subcommand_matches("location").unwrap())
+    }
+
+    #[test]
+    fn test_parse_bridge_location() {
+        assert_eq!(
+            parse_location(&["bridge", "set", "location", "SE", "got"]),
+            RelayLocation {
+                country: "se".to_owned(),
+                city: "got".to_owned(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            parse_location(&["bridge", "set", "location", "any"]),
+            RelayLocation::default()
+        );
+        assert!(Bridge
+            .clap_subcommand()
+            .get_matches_from_safe(&["bridge", "set", "location", "swe"])
+            .is_err());
+        assert!(Bridge
+            .clap_subcommand()
+            .get_matches_from_safe(&["bridge", "set", "location"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_only_active_bridges_listed() {
+        let relays = bridge_relays(&bridge_countries(countries()));
+        assert_eq!(relays.len(), 1);
+        assert_eq!(relays[0].hostname, "se-got-br-001");
+        assert_eq!(relays[0].country_code, "se");
+    }
+
+    #[test]
+    fn test_bridge_location_not_found() {
+        let countries = bridge_countries(countries());
+        let location = |country: &str, city: &str, hostname: &str| RelayLocation {
+            country: country.to_owned(),
+            city: city.to_owned(),
+            hostname: hostname.to_owned(),
+        };
+
+        assert!(validate_bridge_location(&countries, &location("se", "got", "")).is_ok());
+        assert!(
+            validate_bridge_location(&countries, &location("se", "got", "se-got-br-001")).is_ok()
+        );
+        // Germany only has a relay without bridges.
+        assert!(matches!(
+            validate_bridge_location(&countries, &location("de", "", "")),
+            Err(Error::InvalidCommand(_))
+        ));
+        assert!(matches!(
+            validate_bridge_location(&countries, &location("se", "got", "se-got-br-002")),
+            Err(Error::InvalidCommand(_))
+        ));
+        assert!(matches!(
+            validate_bridge_location(&countries, &location("us", "", "")),
+            Err(Error::InvalidCommand(_))
+        ));
     }
 }
@@ -1,5 +1,16 @@
-use crate::{new_rpc_client, Command, Result};
-use talpid_types::ErrorExt;
+use crate::{location, new_rpc_client, output::Output, Command, Error, Result};
+use futures03::{Stream, TryStreamExt};
+use mullvad_management_interface::{
+    types::{
+        daemon_event::Event as EventType, relay_settings_update, tunnel_state::State,
+        AfterDisconnect, DaemonEvent, NormalRelaySettingsUpdate, RelaySettingsUpdate, TunnelState,
+    },
+    ManagementServiceClient, Streaming,
+};
+use std::time::Duration;
+
+/// Default number of seconds to wait for the tunnel to connect when `--wait` is given.
+const DEFAULT_WAIT_TIMEOUT_SECS: &str = "60";
 
 pub struct Connect;
 
@@ -10,15 +21,413 @@ impl Command for Connect {
     }
 
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
-        clap::SubCommand::with_name(self.name())
-            .about("Command the client to start establishing a VPN tunnel")
+        location::add_location_args(
+            clap::SubCommand::with_name(self.name()).about(
+                "Command the client to start establishing a VPN tunnel. If a location is \
+                 given, the relay constraints are updated to it before connecting",
+            ),
+            false,
+        )
+        .arg(get_wait_arg())
+        .arg(get_timeout_arg())
     }
 
-    async fn run(&self, _: &clap::ArgMatches<'_>) -> Result<()> {
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let output = Output::from_matches(matches);
         let mut rpc = new_rpc_client().await?;
-        if let Err(e) = rpc.connect_tunnel(()).await {
-            eprintln!("{}", e.display_chain());
+
+        if let Some(location) = location::get_optional_constraint(matches) {
+            location::validate_location(&mut rpc, &location).await?;
+            rpc.update_relay_settings(RelaySettingsUpdate {
+                r#type: Some(relay_settings_update::Type::Normal(
+                    NormalRelaySettingsUpdate {
+                        location: Some(location),
+                        ..Default::default()
+                    },
+                )),
+            })
+            .await?;
         }
+
+        if let Some(timeout) = get_wait_timeout(matches)? {
+            // Subscribe before connecting so that no state change is missed
+            let events = rpc.events_listen(()).await?.into_inner();
+            let state_before = rpc.get_tunnel_state(()).await?.into_inner();
+            rpc.connect_tunnel(()).await?;
+            wait_for_connected(events, ConnectionAttempt::new(&state_before), timeout).await?;
+        } else {
+            rpc.connect_tunnel(()).await?;
+        }
+
+        let relay = if output.is_json() {
+            current_relay(&mut rpc).await?
+        } else {
+            None
+        };
+        output.success(relay, None);
         Ok(())
     }
 }
+
+/// Returns the `--wait` argument, for commands that can wait for the tunnel to connect.
+pub fn get_wait_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("wait")
+        .long("wait")
+        .short("w")
+        .help("Wait until the tunnel is connected. Fails if the tunnel cannot be connected")
+}
+
+/// Returns the `--timeout` argument that bounds how long `--wait` waits.
+pub fn get_timeout_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("timeout")
+        .long("timeout")
+        .takes_value(true)
+        .value_name("SECONDS")
+        .default_value(DEFAULT_WAIT_TIMEOUT_SECS)
+        .requires("wait")
+        .help("Maximum number of seconds to wait for the tunnel to connect")
+}
+
+/// Returns the timeout to wait for, or `None` if `--wait` was not given.
+pub fn get_wait_timeout(matches: &clap::ArgMatches<'_>) -> Result<Option<Duration>> {
+    if !matches.is_present("wait") {
+        return Ok(None);
+    }
+    let secs = clap::value_t!(matches.value_of("timeout"), u64)
+        .map_err(|_| Error::InvalidCommand("The timeout must be a number of seconds"))?;
+    Ok(Some(Duration::from_secs(secs)))
+}
+
+/// Waits until the tunnel is connected, using `events` to follow `attempt`. `events` should be
+/// subscribed to before the connection attempt is started.
+pub async fn wait_for_connected(
+    events: Streaming<DaemonEvent>,
+    attempt: ConnectionAttempt,
+    timeout: Duration,
+) -> Result<()> {
+    let states = events.try_filter_map(|event| async move {
+        Ok(match event.event {
+            Some(EventType::TunnelState(state)) => Some(state),
+            _ => None,
+        })
+    });
+    tokio::time::timeout(timeout, connection_outcome(attempt, states))
+        .await
+        .map_err(|_| Error::ConnectTimeout)?
+}
+
+/// Follows `attempt` through `states` until it has succeeded or failed.
+async fn connection_outcome<E>(
+    mut attempt: ConnectionAttempt,
+    states: impl Stream<Item = std::result::Result<TunnelState, E>>,
+) -> Result<()>
+where
+    Error: From<E>,
+{
+    futures03::pin_mut!(states);
+    if attempt.connected {
+        return Ok(());
+    }
+    while let Some(state) = states.try_next().await? {
+        if let Some(result) = attempt.update(&state) {
+            return result;
+        }
+    }
+    Err(Error::ConnectFailed("The daemon stopped sending events"))
+}
+
+/// Tracks the tunnel state transitions of a connection attempt. Whether the tunnel ends up
+/// disconnected because someone asked it to be is only known from the state it passed through.
+#[derive(Debug, Default)]
+pub struct ConnectionAttempt {
+    /// Whether the tunnel has started connecting. Until then, the tunnel may still be passing
+    /// through states that predate the command, such as the end of an earlier disconnect.
+    started: bool,
+    /// Whether the tunnel was already connected when the command was issued.
+    connected: bool,
+    disconnect_requested: bool,
+}
+
+impl ConnectionAttempt {
+    /// Follows a connection attempt, given the tunnel state from before the command was issued.
+    pub fn new(state_before: &TunnelState) -> Self {
+        let (started, connected) = match state_before.state {
+            Some(State::Connecting(_)) => (true, false),
+            Some(State::Connected(_)) => (true, true),
+            _ => (false, false),
+        };
+        ConnectionAttempt {
+            started,
+            connected,
+            disconnect_requested: false,
+        }
+    }
+
+    /// Follows a reconnect. The tunnel is only considered connected again once it has passed
+    /// through the connecting state, since it is still connected to the old relay before that.
+    pub fn reconnect() -> Self {
+        ConnectionAttempt::default()
+    }
+
+    /// Maps the next tunnel state to the outcome of the connection attempt, or `None` if the
+    /// attempt is still in progress.
+    fn update(&mut self, state: &TunnelState) -> Option<Result<()>> {
+        match state.state.as_ref()? {
+            State::Connected(_) if self.started => Some(Ok(())),
+            State::Connected(_) => None,
+            State::Connecting(_) => {
+                self.started = true;
+                self.disconnect_requested = false;
+                None
+            }
+            State::Disconnecting(_) | State::Disconnected(_) if !self.started => None,
+            State::Disconnecting(disconnecting) => {
+                self.disconnect_requested =
+                    disconnecting.after_disconnect == i32::from(AfterDisconnect::Nothing);
+                None
+            }
+            State::Disconnected(_) if self.disconnect_requested => {
+                Some(Err(Error::ConnectCancelled))
+            }
+            State::Disconnected(_) => {
+                Some(Err(Error::ConnectFailed("The tunnel was disconnected")))
+            }
+            State::Error(_) => Some(Err(Error::ConnectBlocked)),
+        }
+    }
+}
+
+/// Returns the address of the relay that the daemon is connecting or connected to, if any.
+async fn current_relay(rpc: &mut ManagementServiceClient) -> Result<Option<String>> {
+    let state = rpc.get_tunnel_state(()).await?.into_inner();
+    let relay_info = match state.state {
+        Some(State::Connecting(connecting)) => connecting.relay_info,
+        Some(State::Connected(connected)) => connected.relay_info,
+        _ => None,
+    };
+    Ok(relay_info
+        .and_then(|relay_info| relay_info.tunnel_endpoint)
+        .map(|endpoint| endpoint.address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mullvad_management_interface::types::{tunnel_state, RelayLocation};
+
+    fn parse_location(args: &[&str]) -> Option<RelayLocation> {
+        let matches = Connect
+            .clap_subcommand()
+            .get_matches_from_safe(args)
+            .expect("failed to parse arguments");
+        location::get_optional_constraint(&matches)
+    }
+
+    #[test]
+    fn test_no_location() {
+        assert_eq!(parse_location(&["connect"]), None);
+    }
+
+    #[test]
+    fn test_country() {
+        assert_eq!(
+            parse_location(&["connect", "SE"]),
+            Some(RelayLocation {
+                country: "se".to_string(),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_country_city_hostname() {
+        assert_eq!(
+            parse_location(&["connect", "se", "got", "se-got-001"]),
+            Some(RelayLocation {
+                country: "se".to_string(),
+                city: "got".to_string(),
+                hostname: "se-got-001".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_any_country() {
+        assert_eq!(
+            parse_location(&["connect", "any"]),
+            Some(RelayLocation::default())
+        );
+    }
+
+    fn state(state: State) -> TunnelState {
+        TunnelState { state: Some(state) }
+    }
+
+    fn disconnecting(after_disconnect: AfterDisconnect) -> TunnelState {
+        state(State::Disconnecting(tunnel_state::Disconnecting {
+            after_disconnect: i32::from(after_disconnect),
+        }))
+    }
+
+    fn outcome(state_before: TunnelState, states: Vec<TunnelState>) -> Result<()> {
+        let states = futures03::stream::iter(
+            states
+                .into_iter()
+                .map(Ok::<_, mullvad_management_interface::Status>),
+        );
+        futures03::executor::block_on(connection_outcome(
+            ConnectionAttempt::new(&state_before),
+            states,
+        ))
+    }
+
+    #[test]
+    fn test_connect_result() {
+        let result = |next| {
+            ConnectionAttempt::new(&state(State::Connecting(Default::default()))).update(&next)
+        };
+
+        assert!(result(state(State::Connecting(Default::default()))).is_none());
+        assert!(result(state(State::Disconnecting(Default::default()))).is_none());
+        assert!(matches!(
+            result(state(State::Connected(tunnel_state::Connected::default()))),
+            Some(Ok(()))
+        ));
+        assert!(matches!(
+            result(state(State::Disconnected(Default::default()))),
+            Some(Err(Error::ConnectFailed(_)))
+        ));
+        assert!(matches!(
+            result(state(State::Error(Default::default()))),
+            Some(Err(Error::ConnectBlocked))
+        ));
+    }
+
+    #[test]
+    fn test_disconnected_while_waiting() {
+        let result = outcome(
+            state(State::Connecting(Default::default())),
+            vec![
+                disconnecting(AfterDisconnect::Nothing),
+                state(State::Disconnected(Default::default())),
+            ],
+        );
+        assert!(matches!(result, Err(Error::ConnectCancelled)));
+        assert_eq!(result.unwrap_err().exit_code(), 2);
+    }
+
+    #[test]
+    fn test_ignores_states_before_command() {
+        // The end of an earlier disconnect may still be reported after the connect command
+        let result = outcome(
+            disconnecting(AfterDisconnect::Nothing),
+            vec![
+                state(State::Disconnected(Default::default())),
+                state(State::Connecting(Default::default())),
+                state(State::Connected(tunnel_state::Connected::default())),
+            ],
+        );
+        assert!(result.is_ok());
+
+        let result = outcome(
+            state(State::Disconnected(Default::default())),
+            vec![
+                state(State::Connecting(Default::default())),
+                state(State::Connected(tunnel_state::Connected::default())),
+            ],
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let outcome = |states: Vec<TunnelState>| {
+            let states = futures03::stream::iter(
+                states
+                    .into_iter()
+                    .map(Ok::<_, mullvad_management_interface::Status>),
+            );
+            futures03::executor::block_on(connection_outcome(
+                ConnectionAttempt::reconnect(),
+                states,
+            ))
+        };
+
+        // The connected state from before the reconnect must not end the wait
+        let result = outcome(vec![
+            state(State::Connected(tunnel_state::Connected::default())),
+            disconnecting(AfterDisconnect::Reconnect),
+        ]);
+        assert!(matches!(result, Err(Error::ConnectFailed(_))));
+
+        let result = outcome(vec![
+            state(State::Connected(tunnel_state::Connected::default())),
+            disconnecting(AfterDisconnect::Reconnect),
+            state(State::Connecting(Default::default())),
+            state(State::Connected(tunnel_state::Connected::default())),
+        ]);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_already_connected() {
+        let result = outcome(
+            state(State::Connected(tunnel_state::Connected::default())),
+            vec![],
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_error_while_waiting() {
+        let result = outcome(
+            state(State::Connecting(Default::default())),
+            vec![
+                disconnecting(AfterDisconnect::Block),
+                state(State::Error(Default::default())),
+            ],
+        );
+        assert!(matches!(result, Err(Error::ConnectBlocked)));
+        assert_eq!(result.unwrap_err().exit_code(), 3);
+
+        let result = outcome(
+            state(State::Connecting(Default::default())),
+            vec![
+                disconnecting(AfterDisconnect::Reconnect),
+                state(State::Connecting(Default::default())),
+                state(State::Connected(tunnel_state::Connected::default())),
+            ],
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let parse = |args: &[&str]| {
+            let matches = Connect.clap_subcommand().get_matches_from_safe(args);
+            get_wait_timeout(&matches.expect("failed to parse arguments"))
+        };
+
+        assert_eq!(parse(&["connect"]).unwrap(), None);
+        assert_eq!(
+            parse(&["connect", "--wait"]).unwrap(),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            parse(&["connect", "--wait", "--timeout", "5"]).unwrap(),
+            Some(Duration::from_secs(5))
+        );
+        assert!(matches!(
+            parse(&["connect", "--wait", "--timeout", "soon"]),
+            Err(Error::InvalidCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_country_code() {
+        assert!(Connect
+            .clap_subcommand()
+            .get_matches_from_safe(&["connect", "swe"])
+            .is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{new_rpc_client, output::Output, Command, Error, Result};
+use mullvad_management_interface::types::Route;
+use serde::Serialize;
+use std::fmt::Write;
+
+pub struct Debug;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Inspect the internal state of the daemon")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("routes")
+                    .about("Display the routes that the daemon has currently applied"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(routes_matches) = matches.subcommand_matches("routes") {
+            self.routes(Output::from_matches(routes_matches)).await
+        } else {
+            unreachable!("No debug command given");
+        }
+    }
+}
+
+impl Debug {
+    async fn routes(&self, output: Output) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let routes = match rpc.get_routes(()).await {
+            Ok(response) => response.into_inner().routes,
+            Err(status) if status.code() == mullvad_management_interface::Code::Unimplemented => {
+                return Err(Error::RoutesUnsupported);
+            }
+            Err(status) => return Err(Error::GrpcClientError(status)),
+        };
+
+        let report = RoutesReport::new(&routes);
+        if output.is_json() {
+            println!(
+                "{}",
+                serde_json::to_string(&report).expect("Failed to serialize routes report")
+            );
+        } else {
+            print!("{}", report.format());
+        }
+        Ok(())
+    }
+}
+
+/// The routes applied by the daemon, as printed by `debug routes`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct RoutesReport {
+    routes: Vec<RouteReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct RouteReport {
+    destination: String,
+    node: Option<String>,
+    interface: Option<String>,
+    metric: Option<u32>,
+}
+
+impl RoutesReport {
+    fn new(routes: &[Route]) -> Self {
+        let non_empty = |value: &str| Some(value.to_owned()).filter(|value| !value.is_empty());
+        let mut routes: Vec<_> = routes
+            .iter()
+            .map(|route| RouteReport {
+                destination: route.destination.clone(),
+                node: non_empty(&route.gateway),
+                interface: non_empty(&route.interface),
+                metric: Some(route.metric).filter(|metric| *metric != 0),
+            })
+            .collect();
+        routes.sort_by(|a, b| a.destination.cmp(&b.destination));
+        RoutesReport { routes }
+    }
+
+    /// Returns the routes as a human readable table.
+    fn format(&self) -> String {
+        const HEADERS: [&str; 4] = ["Destination", "Node", "Interface", "Metric"];
+        const NONE: &str = "-";
+
+        let rows: Vec<[String; 4]> = self
+            .routes
+            .iter()
+            .map(|route| {
+                [
+                    route.destination.clone(),
+                    route.node.clone().unwrap_or_else(|| NONE.to_owned()),
+                    route.interface.clone().unwrap_or_else(|| NONE.to_owned()),
+                    route
+                        .metric
+                        .map(|metric| metric.to_string())
+                        .unwrap_or_else(|| NONE.to_owned()),
+                ]
+            })
+            .collect();
+
+        let mut widths = [0; 4];
+        for (width, header) in widths.iter_mut().zip(HEADERS.iter()) {
+            *width = header.len();
+        }
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let mut out = String::new();
+        let mut write_row = |cells: &[&str]| {
+            let line = cells
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(out, "{}", line.trim_end()).unwrap();
+        };
+        write_row(&HEADERS);
+        for row in &rows {
+            write_row(&[&row[0], &row[1], &row[2], &row[3]]);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample_routes() -> Vec<Route> {
+        vec![
+            Route {
+                destination: "193.138.218.78/32".to_owned(),
+                gateway: "192.168.1.1".to_owned(),
+                interface: "eth0".to_owned(),
+                metric: 0,
+            },
+            Route {
+                destination: "0.0.0.0/1".to_owned(),
+                gateway: String::new(),
+                interface: "tun0".to_owned(),
+                metric: 0,
+            },
+            Route {
+                destination: "::/0".to_owned(),
+                gateway: "fe80::1".to_owned(),
+                interface: String::new(),
+                metric: 1024,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            RoutesReport::new(&sample_routes()).format(),
+            "Destination        Node         Interface  Metric\n\
+             0.0.0.0/1          -            tun0       -\n\
+             193.138.218.78/32  192.168.1.1  eth0       -\n\
+             ::/0               fe80::1      -          1024\n"
+        );
+        assert_eq!(
+            RoutesReport::new(&[]).format(),
+            "Destination  Node  Interface  Metric\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            serde_json::to_value(&RoutesReport::new(&sample_routes())).unwrap(),
+            json!({
+                "routes": [
+                    {
+                        "destination": "0.0.0.0/1",
+                        "node": null,
+                        "interface": "tun0",
+                        "metric": null,
+                    },
+                    {
+                        "destination": "193.138.218.78/32",
+                        "node": "192.168.1.1",
+                        "interface": "eth0",
+                        "metric": null,
+                    },
+                    {
+                        "destination": "::/0",
+                        "node": "fe80::1",
+                        "interface": null,
+                        "metric": 1024,
+                    },
+                ]
+            })
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{cmds::status::print_state, new_rpc_client, Command, Error, Result};
+
+pub struct LockdownOff;
+
+#[mullvad_management_interface::async_trait]
+impl Command for LockdownOff {
+    fn name(&self) -> &'static str {
+        "lockdown-off"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Disconnect the VPN tunnel and turn off the always require VPN setting, \
+                 allowing network traffic outside the tunnel",
+            )
+            .arg(
+                clap::Arg::with_name("yes")
+                    .long("yes")
+                    .help("Confirm that network traffic should be allowed while disconnected"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        check_confirmed(matches)?;
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.disconnect_tunnel(()).await?;
+        rpc.set_block_when_disconnected(false).await?;
+
+        let state = rpc.get_tunnel_state(()).await?.into_inner();
+        print_state(&state);
+        println!("Network traffic will be allowed when the VPN is disconnected");
+        Ok(())
+    }
+}
+
+/// Returns an error unless the user passed `--yes`, since this command removes the protection
+/// against leaking traffic outside the tunnel.
+fn check_confirmed(matches: &clap::ArgMatches<'_>) -> Result<()> {
+    if matches.is_present("yes") {
+        Ok(())
+    } else {
+        Err(Error::InvalidCommand(
+            "This allows traffic outside the tunnel. Pass --yes to confirm",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_args(args: &[&str]) -> Result<()> {
+        let matches = LockdownOff
+            .clap_subcommand()
+            .get_matches_from_safe(args)
+            .expect("failed to parse arguments");
+        check_confirmed(&matches)
+    }
+
+    #[test]
+    fn test_requires_confirmation() {
+        assert!(matches!(
+            check_args(&["lockdown-off"]),
+            Err(Error::InvalidCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_confirmed() {
+        assert!(check_args(&["lockdown-off", "--yes"]).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_unknown_confirmation() {
+        assert!(LockdownOff
+            .clap_subcommand()
+            .get_matches_from_safe(&["lockdown-off", "-y"])
+            .is_err());
+    }
+}
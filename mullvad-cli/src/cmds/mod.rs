@@ -19,12 +19,18 @@ pub use self::bridge::Bridge;
 mod connect;
 pub use self::connect::Connect;
 
+mod debug;
+pub use self::debug::Debug;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
 mod lan;
 pub use self::lan::Lan;
 
+mod lockdown_off;
+pub use self::lockdown_off::LockdownOff;
+
 mod reconnect;
 pub use self::reconnect::Reconnect;
 
@@ -34,6 +40,9 @@ pub use self::relay::Relay;
 mod reset;
 pub use self::reset::Reset;
 
+mod self_test;
+pub use self::self_test::SelfTest;
+
 #[cfg(target_os = "linux")]
 mod split_tunnel;
 #[cfg(target_os = "linux")]
@@ -57,11 +66,14 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
         Box::new(Connect),
+        Box::new(Debug),
         Box::new(Disconnect),
         Box::new(Reconnect),
         Box::new(Lan),
+        Box::new(LockdownOff),
         Box::new(Relay),
         Box::new(Reset),
+        Box::new(SelfTest),
         #[cfg(target_os = "linux")]
         Box::new(SplitTunnel),
         Box::new(Status),
@@ -76,3 +88,26 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
     }
     map
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reconnect_registered() {
+        let commands = get_commands();
+        let reconnect = commands
+            .get("reconnect")
+            .expect("reconnect is not registered");
+
+        let matches = reconnect
+            .clap_subcommand()
+            .get_matches_from_safe(&["reconnect", "--wait", "--timeout", "10"])
+            .expect("failed to parse arguments");
+        assert_eq!(
+            connect::get_wait_timeout(&matches).unwrap(),
+            Some(Duration::from_secs(10))
+        );
+    }
+}
@@ -1,5 +1,8 @@
-use crate::{new_rpc_client, Command, Result};
-use talpid_types::ErrorExt;
+use super::connect::{
+    get_timeout_arg, get_wait_arg, get_wait_timeout, wait_for_connected, ConnectionAttempt,
+};
+use crate::{new_rpc_client, output::Output, Command, Error, Result};
+use mullvad_management_interface::types::tunnel_state::State;
 
 pub struct Reconnect;
 
@@ -10,14 +13,36 @@ impl Command for Reconnect {
     }
 
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
-        clap::SubCommand::with_name(self.name()).about("Command the client to reconnect")
+        clap::SubCommand::with_name(self.name())
+            .about("Command the client to reconnect, keeping the current relay constraints")
+            .arg(get_wait_arg())
+            .arg(get_timeout_arg())
     }
 
-    async fn run(&self, _: &clap::ArgMatches<'_>) -> Result<()> {
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let output = Output::from_matches(matches);
+        let timeout = get_wait_timeout(matches)?;
         let mut rpc = new_rpc_client().await?;
-        if let Err(e) = rpc.reconnect_tunnel(()).await {
-            eprintln!("{}", e.display_chain());
+
+        let state = rpc.get_tunnel_state(()).await?.into_inner();
+        if let Some(State::Error(_)) = state.state {
+            return Err(Error::TunnelBlocked);
+        }
+
+        if let Some(timeout) = timeout {
+            if let Some(State::Disconnected(_)) = state.state {
+                // Reconnecting a disconnected tunnel does nothing, so there is nothing to wait for
+                return Err(Error::ConnectFailed("The tunnel is disconnected"));
+            }
+            // Subscribe before reconnecting so that no state change is missed
+            let events = rpc.events_listen(()).await?.into_inner();
+            rpc.reconnect_tunnel(()).await?;
+            wait_for_connected(events, ConnectionAttempt::reconnect(), timeout).await?;
+        } else {
+            rpc.reconnect_tunnel(()).await?;
         }
+
+        output.success(None, None);
         Ok(())
     }
 }
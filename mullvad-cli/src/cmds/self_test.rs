@@ -0,0 +1,489 @@
+use crate::{new_rpc_client, output::Output, Command, Error, Result};
+use mullvad_management_interface::{
+    types::{tunnel_state::State, GeoIpLocation},
+    ManagementServiceClient,
+};
+use mullvad_types::location::AmIMullvad;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use talpid_types::ErrorExt;
+use tokio::net::UdpSocket;
+
+/// Location service that reports whether a request exited through a Mullvad relay.
+const EXIT_IP_URI: &str = "https://ipv4.am.i.mullvad.net/json";
+/// Host that is resolved to check that DNS lookups work through the tunnel.
+const DNS_TEST_HOST: &str = "am.i.mullvad.net";
+/// Maximum time to wait for an answer from a DNS server.
+const DNS_TIMEOUT: Duration = Duration::from_secs(10);
+const DNS_PORT: u16 = 53;
+
+const CHECK_CONNECTED: &str = "Tunnel connected";
+const CHECK_EXIT_IP: &str = "Exit IP belongs to the connected relay";
+const CHECK_DNS: &str = "DNS resolves through the tunnel";
+const CHECK_IPV6_LEAK: &str = "No IPv6 leak";
+
+pub struct SelfTest;
+
+#[mullvad_management_interface::async_trait]
+impl Command for SelfTest {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name()).about(
+            "Check that the connected tunnel works: that traffic exits through the relay, \
+             that DNS resolves and that nothing leaks. Fails if any check fails",
+        )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let output = Output::from_matches(matches);
+        let mut rpc = new_rpc_client().await?;
+
+        let results = run_checks(&mut rpc).await?;
+        if !output.is_json() {
+            for result in &results {
+                println!("{}", result);
+            }
+        }
+        summarize(&results)?;
+
+        output.success(None, Some(&format!("All {} checks passed", results.len())));
+        Ok(())
+    }
+}
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Fail(String),
+    /// The check could not run because an earlier check failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CheckResult {
+    name: &'static str,
+    outcome: Outcome,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str) -> Self {
+        CheckResult {
+            name,
+            outcome: Outcome::Pass,
+        }
+    }
+
+    fn fail(name: &'static str, reason: impl Into<String>) -> Self {
+        CheckResult {
+            name,
+            outcome: Outcome::Fail(reason.into()),
+        }
+    }
+
+    fn skipped(name: &'static str) -> Self {
+        CheckResult {
+            name,
+            outcome: Outcome::Skipped,
+        }
+    }
+
+    fn passed(&self) -> bool {
+        self.outcome == Outcome::Pass
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Pass => write!(f, "[PASS] {}", self.name),
+            Outcome::Fail(reason) => write!(f, "[FAIL] {}: {}", self.name, reason),
+            Outcome::Skipped => write!(f, "[SKIP] {}", self.name),
+        }
+    }
+}
+
+/// Returns an error unless every check passed.
+fn summarize(results: &[CheckResult]) -> Result<()> {
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(Error::SelfTestFailed(failed, results.len()))
+    }
+}
+
+async fn run_checks(rpc: &mut ManagementServiceClient) -> Result<Vec<CheckResult>> {
+    let state = rpc.get_tunnel_state(()).await?.into_inner();
+    let relay_hostname = match state.state {
+        Some(State::Connected(connected)) => connected
+            .relay_info
+            .and_then(|relay_info| relay_info.location)
+            .map(|location| location.hostname),
+        _ => {
+            return Ok(vec![
+                CheckResult::fail(CHECK_CONNECTED, "The tunnel is not connected"),
+                CheckResult::skipped(CHECK_EXIT_IP),
+                CheckResult::skipped(CHECK_DNS),
+                CheckResult::skipped(CHECK_IPV6_LEAK),
+            ]);
+        }
+    };
+
+    let dns_servers = rpc
+        .get_tunnel_dns_servers(())
+        .await?
+        .into_inner()
+        .addresses
+        .iter()
+        .filter_map(|address| address.parse().ok())
+        .collect::<Vec<IpAddr>>();
+    let location = match rpc.get_current_location(()).await {
+        Ok(response) => Some(response.into_inner()),
+        Err(status) if status.code() == mullvad_management_interface::Code::NotFound => None,
+        Err(status) => return Err(Error::GrpcClientError(status)),
+    };
+    let ipv6_enabled = rpc
+        .get_settings(())
+        .await?
+        .into_inner()
+        .tunnel_options
+        .and_then(|options| options.generic)
+        .map(|generic| generic.enable_ipv6)
+        .unwrap_or(false);
+
+    let exit_ip = match fetch_exit_location().await {
+        Ok(exit) => check_exit_ip(&exit, relay_hostname.as_deref()),
+        Err(reason) => CheckResult::fail(CHECK_EXIT_IP, reason),
+    };
+
+    Ok(vec![
+        CheckResult::pass(CHECK_CONNECTED),
+        exit_ip,
+        check_dns(&dns_servers).await,
+        check_ipv6_leak(location.as_ref(), ipv6_enabled),
+    ])
+}
+
+/// Asks am.i.mullvad.net where requests from this machine exit. The request is sent by the CLI
+/// itself rather than the daemon, so it takes the same path as any other traffic on the host.
+async fn fetch_exit_location() -> std::result::Result<AmIMullvad, String> {
+    let result = tokio::task::spawn_blocking(|| {
+        let mut rpc_runtime = mullvad_rpc::MullvadRpcRuntime::new()
+            .map_err(|error| error.display_chain_with_msg("Failed to create the HTTP client"))?;
+        let service = rpc_runtime.rest_handle();
+        rpc_runtime
+            .runtime()
+            .block_on(async move {
+                let request = mullvad_rpc::rest::RestRequest::get(EXIT_IP_URI)?;
+                let response = service.request(request).await?;
+                mullvad_rpc::rest::deserialize_body(response).await
+            })
+            .map_err(|error| {
+                error.display_chain_with_msg(&format!("Failed to query {}", EXIT_IP_URI))
+            })
+    })
+    .await;
+    result.unwrap_or_else(|_| Err("The location request panicked".to_owned()))
+}
+
+fn check_exit_ip(exit: &AmIMullvad, relay_hostname: Option<&str>) -> CheckResult {
+    if !exit.mullvad_exit_ip {
+        return CheckResult::fail(
+            CHECK_EXIT_IP,
+            format!("{} is not a Mullvad exit IP", exit.ip),
+        );
+    }
+    match (relay_hostname, exit.mullvad_exit_ip_hostname.as_deref()) {
+        (Some(relay_hostname), Some(exit_hostname))
+            if !relay_hostname.is_empty() && relay_hostname != exit_hostname =>
+        {
+            CheckResult::fail(
+                CHECK_EXIT_IP,
+                format!(
+                    "Traffic exits through {} instead of {}",
+                    exit_hostname, relay_hostname
+                ),
+            )
+        }
+        _ => CheckResult::pass(CHECK_EXIT_IP),
+    }
+}
+
+/// Sends a query for `DNS_TEST_HOST` straight to each DNS server used in the tunnel, bypassing
+/// the system resolver.
+async fn check_dns(servers: &[IpAddr]) -> CheckResult {
+    if servers.is_empty() {
+        return CheckResult::fail(CHECK_DNS, "The tunnel has no DNS servers");
+    }
+    for server in servers {
+        let result = tokio::time::timeout(DNS_TIMEOUT, query_dns_server(*server)).await;
+        let reason = match result {
+            Ok(Ok(())) => continue,
+            Ok(Err(reason)) => reason,
+            Err(_) => "timed out".to_owned(),
+        };
+        return CheckResult::fail(
+            CHECK_DNS,
+            format!(
+                "Failed to resolve {} using {}: {}",
+                DNS_TEST_HOST, server, reason
+            ),
+        );
+    }
+    CheckResult::pass(CHECK_DNS)
+}
+
+async fn query_dns_server(server: IpAddr) -> std::result::Result<(), String> {
+    let bind_addr = match server {
+        IpAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        IpAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+    let mut socket = UdpSocket::bind(bind_addr)
+        .await
+        .map_err(|error| error.to_string())?;
+    socket
+        .connect(SocketAddr::new(server, DNS_PORT))
+        .await
+        .map_err(|error| error.to_string())?;
+
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos() as u16)
+        .unwrap_or(0);
+    socket
+        .send(&dns_query(id, DNS_TEST_HOST))
+        .await
+        .map_err(|error| error.to_string())?;
+
+    let mut response = [0u8; 512];
+    loop {
+        let len = socket
+            .recv(&mut response)
+            .await
+            .map_err(|error| error.to_string())?;
+        match parse_dns_response(id, &response[..len]) {
+            // Not an answer to our query, so keep waiting
+            Err(DnsResponseError::WrongId) => continue,
+            result => return result.map_err(|error| error.to_string()),
+        }
+    }
+}
+
+/// Builds a recursive query for the A records of `host`.
+fn dns_query(id: u16, host: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(18 + host.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Flags: recursion desired
+    query.extend_from_slice(&0x0100u16.to_be_bytes());
+    // One question, no answer, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.split('.').filter(|label| !label.is_empty()) {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    // Type A, class IN
+    query.extend_from_slice(&[0, 1, 0, 1]);
+    query
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum DnsResponseError {
+    Truncated,
+    WrongId,
+    NotAResponse,
+    ErrorCode(u8),
+    NoAnswers,
+}
+
+impl fmt::Display for DnsResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsResponseError::Truncated => write!(f, "the response is too short"),
+            DnsResponseError::WrongId => write!(f, "the response is for another query"),
+            DnsResponseError::NotAResponse => write!(f, "the server sent a query"),
+            DnsResponseError::ErrorCode(code) => {
+                write!(f, "the server returned error code {}", code)
+            }
+            DnsResponseError::NoAnswers => write!(f, "the response has no answers"),
+        }
+    }
+}
+
+/// Checks that `response` successfully answers the query with the given `id`.
+fn parse_dns_response(id: u16, response: &[u8]) -> std::result::Result<(), DnsResponseError> {
+    if response.len() < 12 {
+        return Err(DnsResponseError::Truncated);
+    }
+    if u16::from_be_bytes([response[0], response[1]]) != id {
+        return Err(DnsResponseError::WrongId);
+    }
+    let flags = u16::from_be_bytes([response[2], response[3]]);
+    if flags & 0x8000 == 0 {
+        return Err(DnsResponseError::NotAResponse);
+    }
+    let code = (flags & 0x000f) as u8;
+    if code != 0 {
+        return Err(DnsResponseError::ErrorCode(code));
+    }
+    if u16::from_be_bytes([response[6], response[7]]) == 0 {
+        return Err(DnsResponseError::NoAnswers);
+    }
+    Ok(())
+}
+
+fn check_ipv6_leak(location: Option<&GeoIpLocation>, ipv6_enabled: bool) -> CheckResult {
+    let location = match location {
+        Some(location) => location,
+        None => return CheckResult::fail(CHECK_IPV6_LEAK, "Location data unavailable"),
+    };
+    if !ipv6_enabled && !location.ipv6.is_empty() {
+        return CheckResult::fail(
+            CHECK_IPV6_LEAK,
+            format!(
+                "Traffic exits over IPv6 from {} even though IPv6 is disabled in the tunnel",
+                location.ipv6
+            ),
+        );
+    }
+    CheckResult::pass(CHECK_IPV6_LEAK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(ipv4: &str, ipv6: &str, hostname: &str, mullvad_exit_ip: bool) -> GeoIpLocation {
+        GeoIpLocation {
+            ipv4: ipv4.to_owned(),
+            ipv6: ipv6.to_owned(),
+            hostname: hostname.to_owned(),
+            mullvad_exit_ip,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let all_passed = vec![
+            CheckResult::pass(CHECK_CONNECTED),
+            CheckResult::pass(CHECK_DNS),
+        ];
+        assert!(summarize(&all_passed).is_ok());
+
+        let some_failed = vec![
+            CheckResult::fail(CHECK_CONNECTED, "The tunnel is not connected"),
+            CheckResult::skipped(CHECK_EXIT_IP),
+            CheckResult::skipped(CHECK_DNS),
+            CheckResult::pass(CHECK_IPV6_LEAK),
+        ];
+        assert!(matches!(
+            summarize(&some_failed),
+            Err(Error::SelfTestFailed(3, 4))
+        ));
+    }
+
+    fn exit(ip: &str, hostname: Option<&str>, mullvad_exit_ip: bool) -> AmIMullvad {
+        AmIMullvad {
+            ip: ip.parse().unwrap(),
+            country: "Sweden".to_owned(),
+            city: None,
+            latitude: 0.0,
+            longitude: 0.0,
+            mullvad_exit_ip,
+            mullvad_exit_ip_hostname: hostname.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_check_exit_ip() {
+        let se = exit("185.65.135.117", Some("se-got-001"), true);
+        assert!(check_exit_ip(&se, Some("se-got-001")).passed());
+        assert!(!check_exit_ip(&se, Some("se-sto-002")).passed());
+
+        let not_mullvad = exit("192.0.2.1", None, false);
+        assert!(!check_exit_ip(&not_mullvad, Some("se-got-001")).passed());
+        assert!(!check_exit_ip(&not_mullvad, None).passed());
+    }
+
+    #[test]
+    fn test_dns_query() {
+        let query = dns_query(0x1234, "am.i.mullvad.net");
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[4..12], &[0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            &query[12..],
+            b"\x02am\x01i\x07mullvad\x03net\x00\x00\x01\x00\x01"
+        );
+    }
+
+    #[test]
+    fn test_parse_dns_response() {
+        let mut response = dns_query(0x1234, DNS_TEST_HOST);
+        response[2] |= 0x80;
+        response[7] = 1;
+        assert_eq!(parse_dns_response(0x1234, &response), Ok(()));
+        assert_eq!(
+            parse_dns_response(0x4321, &response),
+            Err(DnsResponseError::WrongId)
+        );
+        assert_eq!(
+            parse_dns_response(0x1234, &response[..11]),
+            Err(DnsResponseError::Truncated)
+        );
+
+        let query = dns_query(0x1234, DNS_TEST_HOST);
+        assert_eq!(
+            parse_dns_response(0x1234, &query),
+            Err(DnsResponseError::NotAResponse)
+        );
+
+        let mut nxdomain = response.clone();
+        nxdomain[3] |= 3;
+        assert_eq!(
+            parse_dns_response(0x1234, &nxdomain),
+            Err(DnsResponseError::ErrorCode(3))
+        );
+
+        let mut no_answers = response;
+        no_answers[7] = 0;
+        assert_eq!(
+            parse_dns_response(0x1234, &no_answers),
+            Err(DnsResponseError::NoAnswers)
+        );
+    }
+
+    #[test]
+    fn test_check_dns_without_servers() {
+        assert!(!futures03::executor::block_on(check_dns(&[])).passed());
+    }
+
+    #[test]
+    fn test_check_ipv6_leak() {
+        let with_ipv6 = location("185.65.135.117", "2a03:1b20:1::1", "se-got-001", true);
+        let without_ipv6 = location("185.65.135.117", "", "se-got-001", true);
+
+        assert!(check_ipv6_leak(Some(&with_ipv6), true).passed());
+        assert!(!check_ipv6_leak(Some(&with_ipv6), false).passed());
+        assert!(check_ipv6_leak(Some(&without_ipv6), false).passed());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            CheckResult::fail(CHECK_DNS, "timed out").to_string(),
+            "[FAIL] DNS resolves through the tunnel: timed out"
+        );
+        assert_eq!(
+            CheckResult::skipped(CHECK_DNS).to_string(),
+            "[SKIP] DNS resolves through the tunnel"
+        );
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{format::print_keygen_event, new_rpc_client, Command, Error, Result};
+use crate::{format::print_keygen_event, new_rpc_client, output::Output, Command, Error, Result};
+use chrono::{DateTime, Utc};
+use futures03::future::Either;
 use mullvad_management_interface::{
     types::{
         daemon_event::Event as EventType,
@@ -11,6 +13,7 @@ use mullvad_management_interface::{
     ManagementServiceClient,
 };
 use mullvad_types::auth_failed::AuthFailed;
+use serde::Serialize;
 use std::fmt::Write;
 
 pub struct Status;
@@ -30,6 +33,11 @@ impl Command for Status {
                     .short("l")
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
+            .arg(
+                clap::Arg::with_name("listen")
+                    .long("listen")
+                    .help("Listen for VPN tunnel state changes. Same as the listen subcommand"),
+            )
             .subcommand(
                 clap::SubCommand::with_name("listen")
                     .about("Listen for VPN tunnel state changes")
@@ -45,13 +53,25 @@ impl Command for Status {
         let mut rpc = new_rpc_client().await?;
         let state = rpc.get_tunnel_state(()).await?.into_inner();
 
+        if Output::from_matches(matches).is_json() {
+            let event = TunnelStateEvent::new(&state, Utc::now());
+            event.print();
+            if matches.is_present("listen") || matches.subcommand_matches("listen").is_some() {
+                listen_json(&mut rpc).await?;
+            }
+            return Ok(());
+        }
+
         print_state(&state);
         if matches.is_present("location") {
             print_location(&mut rpc).await?;
         }
 
-        if let Some(listen_matches) = matches.subcommand_matches("listen") {
-            let verbose = listen_matches.is_present("verbose");
+        let listen_matches = matches.subcommand_matches("listen");
+        if listen_matches.is_some() || matches.is_present("listen") {
+            let verbose = listen_matches
+                .map(|listen_matches| listen_matches.is_present("verbose"))
+                .unwrap_or(false);
 
             let mut events = rpc.events_listen(()).await?.into_inner();
 
@@ -98,7 +118,100 @@ impl Command for Status {
     }
 }
 
-fn print_state(state: &TunnelState) {
+/// Prints every tunnel state change as a JSON object on its own line until the event stream ends
+/// or the user presses Ctrl-C.
+async fn listen_json(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let mut events = rpc.events_listen(()).await?.into_inner();
+    let print_events = async move {
+        while let Some(event) = events.message().await? {
+            if let Some(EventType::TunnelState(new_state)) = event.event {
+                TunnelStateEvent::new(&new_state, Utc::now()).print();
+            }
+        }
+        Ok::<(), Error>(())
+    };
+    let ctrl_c = tokio::signal::ctrl_c();
+    futures03::pin_mut!(print_events, ctrl_c);
+
+    match futures03::future::select(print_events, ctrl_c).await {
+        Either::Left((result, _)) => result,
+        Either::Right((Ok(()), _)) => Ok(()),
+        // Without a signal handler, keep printing until the process is killed.
+        Either::Right((Err(_), print_events)) => print_events.await,
+    }
+}
+
+/// A tunnel state transition, as printed by `status --json`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct TunnelStateEvent {
+    timestamp: DateTime<Utc>,
+    state: &'static str,
+    endpoint: Option<String>,
+    relay: Option<String>,
+    error: Option<String>,
+    /// Set while the VPN client is bringing a tunnel that went down back up on its own.
+    reconnect_attempt: Option<u32>,
+}
+
+impl TunnelStateEvent {
+    fn new(state: &TunnelState, timestamp: DateTime<Utc>) -> Self {
+        use mullvad_management_interface::types::tunnel_state::State::*;
+
+        let mut event = TunnelStateEvent {
+            timestamp,
+            state: "",
+            endpoint: None,
+            relay: None,
+            error: None,
+            reconnect_attempt: None,
+        };
+        let relay_info = match state.state.as_ref().unwrap() {
+            Connected(connected) => {
+                event.state = "connected";
+                connected.relay_info.as_ref()
+            }
+            Connecting(connecting) => {
+                event.state = "connecting";
+                event.reconnect_attempt = connecting
+                    .reconnect_attempt
+                    .as_ref()
+                    .map(|reconnect_attempt| reconnect_attempt.attempt);
+                connecting.relay_info.as_ref()
+            }
+            Disconnected(_) => {
+                event.state = "disconnected";
+                None
+            }
+            Disconnecting(_) => {
+                event.state = "disconnecting";
+                None
+            }
+            Error(error) => {
+                event.state = "error";
+                event.error = error.error_state.as_ref().map(error_state_to_string);
+                None
+            }
+        };
+        if let Some(relay_info) = relay_info {
+            event.endpoint = relay_info.tunnel_endpoint.as_ref().map(format_endpoint);
+            event.relay = relay_info
+                .location
+                .as_ref()
+                .map(|location| location.hostname.clone())
+                .filter(|hostname| !hostname.is_empty());
+        }
+        event
+    }
+
+    fn print(&self) {
+        println!(
+            "{}",
+            serde_json::to_string(self).expect("Failed to serialize tunnel state")
+        );
+    }
+}
+
+pub(super) fn print_state(state: &TunnelState) {
     use mullvad_management_interface::types::{tunnel_state, tunnel_state::State::*};
 
     print!("Tunnel status: ");
@@ -113,14 +226,25 @@ fn print_state(state: &TunnelState) {
                 .unwrap();
             println!("Connected to {}", format_endpoint(&endpoint));
         }
-        Connecting(tunnel_state::Connecting { relay_info }) => {
+        Connecting(tunnel_state::Connecting {
+            relay_info,
+            reconnect_attempt,
+        }) => {
             let endpoint = relay_info
                 .as_ref()
                 .unwrap()
                 .tunnel_endpoint
                 .as_ref()
                 .unwrap();
-            println!("Connecting to {}...", format_endpoint(&endpoint));
+            match reconnect_attempt {
+                Some(reconnect_attempt) => println!(
+                    "Reconnecting to {}, attempt {} in {}s...",
+                    format_endpoint(&endpoint),
+                    reconnect_attempt.attempt,
+                    reconnect_attempt.next_delay_secs
+                ),
+                None => println!("Connecting to {}...", format_endpoint(&endpoint)),
+            }
         }
         Disconnected(_) => println!("Disconnected"),
         Disconnecting(_) => println!("Disconnecting..."),
@@ -147,6 +271,7 @@ fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
             match ProxyType::from_i32(proxy.proxy_type).expect("unknown proxy type") {
                 ProxyType::Shadowsocks => "Shadowsocks",
                 ProxyType::Custom => "custom bridge",
+                ProxyType::Obfuscation => "obfuscation bridge",
             },
             proxy.address,
             format_protocol(
@@ -285,3 +410,94 @@ fn format_protocol(protocol: TransportProtocol) -> &'static str {
         TransportProtocol::Tcp => "TCP",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use mullvad_management_interface::types::{
+        tunnel_state, GeoIpLocation, ReconnectAttempt, TunnelStateRelayInfo,
+    };
+    use serde_json::json;
+
+    fn timestamp() -> DateTime<Utc> {
+        Utc.ymd(2020, 6, 1).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn test_disconnected_event() {
+        let state = TunnelState {
+            state: Some(tunnel_state::State::Disconnected(
+                tunnel_state::Disconnected {},
+            )),
+        };
+        assert_eq!(
+            serde_json::to_value(TunnelStateEvent::new(&state, timestamp())).unwrap(),
+            json!({
+                "timestamp": "2020-06-01T12:00:00Z",
+                "state": "disconnected",
+                "endpoint": null,
+                "relay": null,
+                "error": null,
+                "reconnect_attempt": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_connected_event() {
+        let state = TunnelState {
+            state: Some(tunnel_state::State::Connected(tunnel_state::Connected {
+                relay_info: Some(TunnelStateRelayInfo {
+                    tunnel_endpoint: Some(TunnelEndpoint {
+                        address: "185.65.135.117:1194".to_string(),
+                        protocol: TransportProtocol::Udp as i32,
+                        tunnel_type: TunnelType::Openvpn as i32,
+                        proxy: None,
+                    }),
+                    location: Some(GeoIpLocation {
+                        hostname: "se-got-001".to_string(),
+                        ..Default::default()
+                    }),
+                }),
+            })),
+        };
+        let line = serde_json::to_string(&TunnelStateEvent::new(&state, timestamp())).unwrap();
+        assert!(!line.contains('\n'));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+            json!({
+                "timestamp": "2020-06-01T12:00:00Z",
+                "state": "connected",
+                "endpoint": "OpenVPN 185.65.135.117:1194 over UDP",
+                "relay": "se-got-001",
+                "error": null,
+                "reconnect_attempt": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_reconnecting_event() {
+        let state = TunnelState {
+            state: Some(tunnel_state::State::Connecting(tunnel_state::Connecting {
+                relay_info: None,
+                reconnect_attempt: Some(ReconnectAttempt {
+                    attempt: 2,
+                    next_delay_secs: 5,
+                }),
+            })),
+        };
+        assert_eq!(
+            serde_json::to_value(TunnelStateEvent::new(&state, timestamp())).unwrap(),
+            json!({
+                "timestamp": "2020-06-01T12:00:00Z",
+                "state": "connecting",
+                "endpoint": null,
+                "relay": null,
+                "error": null,
+                "reconnect_attempt": 2,
+            })
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{new_rpc_client, output::Output, Command, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use mullvad_management_interface::types::{AppVersionInfo, VersionCheckStatus};
+use serde::Serialize;
+use std::fmt::Write;
 
 pub struct Version;
 
@@ -11,30 +15,221 @@ impl Command for Version {
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
         clap::SubCommand::with_name(self.name())
             .about("Shows current version, and the currently supported versions")
+            .arg(
+                clap::Arg::with_name("verbose")
+                    .long("verbose")
+                    .short("v")
+                    .help("Also show the outcome of the last check for new versions"),
+            )
     }
 
-    async fn run(&self, _: &clap::ArgMatches<'_>) -> Result<()> {
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let output = Output::from_matches(matches);
+        let verbose = matches.is_present("verbose");
+
         let mut rpc = new_rpc_client().await?;
         let current_version = rpc.get_current_version(()).await?.into_inner();
-        println!("Current version: {}", current_version);
         let version_info = rpc.get_version_info(()).await?.into_inner();
-        println!("\tIs supported: {}", version_info.supported);
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let status = if verbose || output.is_json() {
+            Some(rpc.get_version_check_status(()).await?.into_inner())
+        } else {
+            None
+        };
 
-        if !version_info.suggested_upgrade.is_empty() {
-            println!("\tSuggested update: {}", version_info.suggested_upgrade);
+        let report = VersionReport::new(
+            current_version,
+            &version_info,
+            settings.show_beta_releases,
+            status.as_ref(),
+        );
+        if output.is_json() {
+            println!(
+                "{}",
+                serde_json::to_string(&report).expect("Failed to serialize version report")
+            );
         } else {
-            println!("\tNo newer version is available");
+            print!("{}", report.format());
         }
 
-        if !version_info.latest_stable.is_empty() {
-            println!("\tLatest stable version: {}", version_info.latest_stable);
+        Ok(())
+    }
+}
+
+/// Version information of the running app, as printed by `version`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct VersionReport {
+    current_version: String,
+    supported: bool,
+    suggested_upgrade: Option<String>,
+    latest_stable: Option<String>,
+    latest_beta: Option<String>,
+    /// Outcome of the last version check. Only present in verbose and JSON output.
+    #[serde(flatten)]
+    status: Option<StatusReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct StatusReport {
+    last_check: Option<DateTime<Utc>>,
+    last_check_error: Option<String>,
+}
+
+impl VersionReport {
+    fn new(
+        current_version: String,
+        version_info: &AppVersionInfo,
+        show_beta_releases: bool,
+        status: Option<&VersionCheckStatus>,
+    ) -> Self {
+        let non_empty = |value: &str| Some(value.to_owned()).filter(|value| !value.is_empty());
+        VersionReport {
+            current_version,
+            supported: version_info.supported,
+            suggested_upgrade: non_empty(&version_info.suggested_upgrade),
+            latest_stable: non_empty(&version_info.latest_stable),
+            latest_beta: non_empty(&version_info.latest_beta).filter(|_| show_beta_releases),
+            status: status.map(|status| StatusReport {
+                last_check: status
+                    .last_check
+                    .as_ref()
+                    .map(|last_check| Utc.timestamp(last_check.seconds, last_check.nanos as u32)),
+                last_check_error: non_empty(&status.last_error),
+            }),
         }
+    }
 
-        let settings = rpc.get_settings(()).await?.into_inner();
-        if settings.show_beta_releases {
-            println!("\t Latest beta version: {}", version_info.latest_beta);
-        };
+    /// Returns the human readable form of the report.
+    fn format(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Current version: {}", self.current_version).unwrap();
+        writeln!(out, "\tIs supported: {}", self.supported).unwrap();
+        match &self.suggested_upgrade {
+            Some(suggested_upgrade) => {
+                writeln!(out, "\tSuggested update: {}", suggested_upgrade).unwrap();
+            }
+            None => {
+                writeln!(out, "\tNo newer version is available").unwrap();
+            }
+        }
+        if let Some(latest_stable) = &self.latest_stable {
+            writeln!(out, "\tLatest stable version: {}", latest_stable).unwrap();
+        }
+        if let Some(latest_beta) = &self.latest_beta {
+            writeln!(out, "\t Latest beta version: {}", latest_beta).unwrap();
+        }
+        if let Some(status) = &self.status {
+            match status.last_check {
+                Some(last_check) => {
+                    writeln!(out, "\tLast checked: {}", last_check).unwrap();
+                }
+                None => {
+                    writeln!(out, "\tLast checked: never").unwrap();
+                }
+            }
+            if let Some(error) = &status.last_check_error {
+                writeln!(out, "\tLast check failed: {}", error).unwrap();
+            }
+        }
+        out
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mullvad_management_interface::types::Timestamp;
+    use serde_json::json;
+
+    fn version_info(supported: bool, suggested_upgrade: &str) -> AppVersionInfo {
+        AppVersionInfo {
+            supported,
+            latest_stable: "2020.5".to_owned(),
+            latest_beta: "2020.6-beta1".to_owned(),
+            suggested_upgrade: suggested_upgrade.to_owned(),
+        }
+    }
+
+    fn report(supported: bool, suggested_upgrade: &str) -> VersionReport {
+        VersionReport::new(
+            "2020.4".to_owned(),
+            &version_info(supported, suggested_upgrade),
+            false,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_supported_without_upgrade() {
+        assert_eq!(
+            report(true, "").format(),
+            "Current version: 2020.4\n\
+             \tIs supported: true\n\
+             \tNo newer version is available\n\
+             \tLatest stable version: 2020.5\n"
+        );
+    }
+
+    #[test]
+    fn test_unsupported_with_upgrade() {
+        assert_eq!(
+            report(false, "2020.5").format(),
+            "Current version: 2020.4\n\
+             \tIs supported: false\n\
+             \tSuggested update: 2020.5\n\
+             \tLatest stable version: 2020.5\n"
+        );
+    }
+
+    #[test]
+    fn test_verbose() {
+        let never_checked = VersionReport::new(
+            "2020.4".to_owned(),
+            &version_info(true, ""),
+            true,
+            Some(&VersionCheckStatus::default()),
+        );
+        assert!(never_checked
+            .format()
+            .ends_with("\t Latest beta version: 2020.6-beta1\n\tLast checked: never\n"));
+
+        let failed_check = VersionReport::new(
+            "2020.4".to_owned(),
+            &version_info(true, ""),
+            false,
+            Some(&VersionCheckStatus {
+                last_check: Some(Timestamp {
+                    seconds: 1_590_000_000,
+                    nanos: 0,
+                }),
+                last_error: "Failed to check the latest app version".to_owned(),
+            }),
+        );
+        assert!(failed_check.format().ends_with(
+            "\tLast checked: 2020-05-20 18:40:00 UTC\n\
+             \tLast check failed: Failed to check the latest app version\n"
+        ));
+    }
+
+    #[test]
+    fn test_json() {
+        let report = VersionReport::new(
+            "2020.4".to_owned(),
+            &version_info(true, "2020.5"),
+            false,
+            Some(&VersionCheckStatus::default()),
+        );
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "current_version": "2020.4",
+                "supported": true,
+                "suggested_upgrade": "2020.5",
+                "latest_stable": "2020.5",
+                "latest_beta": null,
+                "last_check": null,
+                "last_check_error": null,
+            })
+        );
     }
 }
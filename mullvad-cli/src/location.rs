@@ -1,25 +1,45 @@
-use mullvad_management_interface::types::RelayLocation;
+use crate::{Error, Result};
+use mullvad_management_interface::{
+    types::{RelayListCountry, RelayLocation},
+    ManagementServiceClient,
+};
 
 pub fn get_subcommand() -> clap::App<'static, 'static> {
-    clap::SubCommand::with_name("location")
-        .arg(
-            clap::Arg::with_name("country")
-                .help("The two letter country code, or 'any' for no preference.")
-                .required(true)
-                .index(1)
-                .validator(country_code_validator),
-        )
-        .arg(
-            clap::Arg::with_name("city")
-                .help("The three letter city code")
-                .index(2)
-                .validator(city_code_validator),
-        )
-        .arg(
-            clap::Arg::with_name("hostname")
-                .help("The hostname")
-                .index(3),
-        )
+    add_location_args(clap::SubCommand::with_name("location"), true)
+}
+
+/// Adds the positional `country`, `city` and `hostname` arguments to `app`.
+pub fn add_location_args(
+    app: clap::App<'static, 'static>,
+    country_required: bool,
+) -> clap::App<'static, 'static> {
+    app.arg(
+        clap::Arg::with_name("country")
+            .help("The two letter country code, or 'any' for no preference.")
+            .required(country_required)
+            .index(1)
+            .validator(country_code_validator),
+    )
+    .arg(
+        clap::Arg::with_name("city")
+            .help("The three letter city code")
+            .index(2)
+            .validator(city_code_validator),
+    )
+    .arg(
+        clap::Arg::with_name("hostname")
+            .help("The hostname")
+            .index(3),
+    )
+}
+
+/// Like [`get_constraint`], but returns `None` if no country was given.
+pub fn get_optional_constraint(matches: &clap::ArgMatches<'_>) -> Option<RelayLocation> {
+    if matches.is_present("country") {
+        Some(get_constraint(matches))
+    } else {
+        None
+    }
 }
 
 pub fn get_constraint(matches: &clap::ArgMatches<'_>) -> RelayLocation {
@@ -57,6 +77,59 @@ pub fn get_constraint(matches: &clap::ArgMatches<'_>) -> RelayLocation {
     }
 }
 
+/// Fails if `location` does not match any country, city or relay in the daemon's relay list.
+pub async fn validate_location(
+    rpc: &mut ManagementServiceClient,
+    location: &RelayLocation,
+) -> Result<()> {
+    if location.country.is_empty() {
+        return Ok(());
+    }
+
+    let mut relay_locations = rpc.get_relay_locations(()).await?.into_inner();
+    let mut countries = Vec::new();
+    while let Some(country) = relay_locations.message().await? {
+        countries.push(country);
+    }
+
+    if location_exists(&countries, location) {
+        Ok(())
+    } else {
+        Err(Error::InvalidCommand(
+            "The given location does not exist in the relay list. Use 'mullvad relay list' to \
+             show available alternatives.",
+        ))
+    }
+}
+
+/// Returns whether `location` matches a country, city or relay in `countries`.
+pub fn location_exists(countries: &[RelayListCountry], location: &RelayLocation) -> bool {
+    let country = match countries
+        .iter()
+        .find(|country| country.code.eq_ignore_ascii_case(&location.country))
+    {
+        Some(country) => country,
+        None => return false,
+    };
+    if location.city.is_empty() {
+        return true;
+    }
+
+    let city = match country
+        .cities
+        .iter()
+        .find(|city| city.code.eq_ignore_ascii_case(&location.city))
+    {
+        Some(city) => city,
+        None => return false,
+    };
+    location.hostname.is_empty()
+        || city
+            .relays
+            .iter()
+            .any(|relay| relay.hostname.eq_ignore_ascii_case(&location.hostname))
+}
+
 pub fn format_location(location: Option<&RelayLocation>) -> String {
     if let Some(location) = location {
         if !location.hostname.is_empty() {
@@ -1,21 +1,39 @@
 #![deny(rust_2018_idioms)]
 
 use clap::{crate_authors, crate_description};
-use mullvad_management_interface::async_trait;
-use std::{collections::HashMap, io};
-use talpid_types::ErrorExt;
+use mullvad_management_interface::{async_trait, ManagementServiceClient, DEFAULT_RPC_TIMEOUT};
+use mullvad_types::version::AppVersion;
+use std::{
+    collections::HashMap,
+    io,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
 
-pub use mullvad_management_interface::{self, new_rpc_client};
+pub use mullvad_management_interface;
 
 mod cmds;
 mod format;
 mod location;
+mod output;
 
 pub const BIN_NAME: &str = "mullvad";
 pub const PRODUCT_VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/product-version.txt"));
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Name of the global argument that bounds how long to wait for the daemon.
+const RPC_TIMEOUT_ARG: &str = "rpc-timeout";
+
+/// Name of the global argument that skips checking that the daemon and the CLI are compatible.
+const SKIP_VERSION_CHECK_ARG: &str = "skip-version-check";
+
+/// How long `new_rpc_client` waits for the daemon, in seconds.
+static RPC_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_RPC_TIMEOUT.as_secs());
+
+/// Whether `new_rpc_client` checks that the daemon runs a compatible version.
+static CHECK_DAEMON_VERSION: AtomicBool = AtomicBool::new(true);
+
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
     #[error(display = "Failed to connect to daemon")]
@@ -24,31 +42,92 @@ pub enum Error {
     #[error(display = "Management interface error")]
     ManagementInterfaceError(#[error(source)] mullvad_management_interface::Error),
 
+    /// The daemon did not accept the connection in time
+    #[error(display = "The daemon is not responding (gave up after {:?})", _0)]
+    DaemonNotResponding(Duration),
+
+    /// The daemon runs a version that the CLI can't talk to
+    #[error(
+        display = "The daemon is running version {}, which is incompatible with this CLI (version \
+                   {}). Upgrade the app so that both are the same version, or pass \
+                   --skip-version-check to try anyway",
+        _0,
+        _1
+    )]
+    IncompatibleDaemon(AppVersion, AppVersion),
+
     #[error(display = "Failed to communicate with mullvad-daemon over RPC")]
     GrpcClientError(#[error(source)] mullvad_management_interface::Status),
 
     /// The given command is not correct in some way
     #[error(display = "Invalid command: {}", _0)]
     InvalidCommand(&'static str),
+
+    /// The tunnel did not reach the connected state
+    #[error(display = "Failed to connect: {}", _0)]
+    ConnectFailed(&'static str),
+
+    /// The tunnel was disconnected, by this or another client, before it connected
+    #[error(display = "The tunnel was disconnected before it connected")]
+    ConnectCancelled,
+
+    /// The tunnel entered the error state while connecting
+    #[error(
+        display = "Failed to connect: the tunnel entered the error state. Run 'mullvad status' \
+                   for more information"
+    )]
+    ConnectBlocked,
+
+    /// The tunnel is blocked due to an error and cannot be reconnected
+    #[error(display = "The tunnel is blocked. Run 'mullvad status' for more information")]
+    TunnelBlocked,
+
+    /// The tunnel did not reach the connected state in time
+    #[error(display = "Timed out waiting for the tunnel to connect")]
+    ConnectTimeout,
+
+    /// The daemon can't list its routes on this platform
+    #[error(display = "Listing the applied routes is not supported on this platform")]
+    RoutesUnsupported,
+
+    /// One or more checks of `mullvad test` did not pass
+    #[error(display = "{} of {} checks failed", _0, _1)]
+    SelfTestFailed(usize, usize),
+}
+
+impl Error {
+    /// Returns the code that the CLI exits with when failing with this error. Waiting for the
+    /// tunnel to connect can end with distinct codes, so that scripts can tell a cancelled
+    /// attempt apart from a tunnel error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ConnectCancelled => 2,
+            Error::ConnectBlocked => 3,
+            _ => 1,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let exit_code = match run().await {
+    env_logger::init();
+
+    let commands = cmds::get_commands();
+    let app_matches = build_app(&commands).get_matches();
+    let output = output::Output::from_matches(&app_matches);
+
+    let exit_code = match run(&commands, &app_matches).await {
         Ok(_) => 0,
         Err(error) => {
-            eprintln!("{}", error.display_chain());
-            1
+            output.error(&error);
+            error.exit_code()
         }
     };
     std::process::exit(exit_code);
 }
 
-async fn run() -> Result<()> {
-    env_logger::init();
-
-    let commands = cmds::get_commands();
-    let app = build_cli(&commands);
+fn build_app(commands: &HashMap<&'static str, Box<dyn Command>>) -> clap::App<'static, 'static> {
+    let app = build_cli(commands);
 
     #[cfg(feature = "shell-completions")]
     let app = app.subcommand(
@@ -67,7 +146,20 @@ async fn run() -> Result<()> {
             ),
     );
 
-    let app_matches = app.get_matches();
+    app
+}
+
+async fn run(
+    commands: &HashMap<&'static str, Box<dyn Command>>,
+    app_matches: &clap::ArgMatches<'_>,
+) -> Result<()> {
+    let rpc_timeout = get_rpc_timeout(app_matches)?;
+    RPC_TIMEOUT_SECS.store(rpc_timeout.as_secs(), Ordering::Relaxed);
+    CHECK_DAEMON_VERSION.store(
+        !app_matches.is_present(SKIP_VERSION_CHECK_ARG),
+        Ordering::Relaxed,
+    );
+
     match app_matches.subcommand() {
         #[cfg(feature = "shell-completions")]
         ("shell-completions", Some(sub_matches)) => {
@@ -77,7 +169,7 @@ async fn run() -> Result<()> {
                 .parse()
                 .expect("Invalid shell");
             let out_dir = sub_matches.value_of_os("DIR").unwrap();
-            build_cli(&commands).gen_completions(BIN_NAME, shell, out_dir);
+            build_cli(commands).gen_completions(BIN_NAME, shell, out_dir);
             Ok(())
         }
         (sub_name, Some(sub_matches)) => {
@@ -103,9 +195,92 @@ fn build_cli(commands: &HashMap<&'static str, Box<dyn Command>>) -> clap::App<'s
             clap::AppSettings::DisableHelpSubcommand,
             clap::AppSettings::VersionlessSubcommands,
         ])
+        .arg(output::get_arg())
+        .arg(
+            clap::Arg::with_name(RPC_TIMEOUT_ARG)
+                .long("rpc-timeout")
+                .global(true)
+                .takes_value(true)
+                .value_name("SECONDS")
+                .env("MULLVAD_RPC_TIMEOUT")
+                .help("How long to wait for the daemon to respond before giving up"),
+        )
+        .arg(
+            clap::Arg::with_name(SKIP_VERSION_CHECK_ARG)
+                .long("skip-version-check")
+                .global(true)
+                .help("Talk to the daemon even if it runs an incompatible version"),
+        )
         .subcommands(commands.values().map(|cmd| cmd.clap_subcommand()))
 }
 
+/// Returns the timeout given by `--rpc-timeout`, or the default one.
+fn get_rpc_timeout(matches: &clap::ArgMatches<'_>) -> Result<Duration> {
+    match matches.value_of(RPC_TIMEOUT_ARG) {
+        Some(secs) => secs
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .ok_or(Error::InvalidCommand(
+                "The RPC timeout must be a positive number of seconds",
+            )),
+        None => Ok(DEFAULT_RPC_TIMEOUT),
+    }
+}
+
+/// Connects to the daemon, giving up once the timeout given by `--rpc-timeout` has passed.
+/// Unless `--skip-version-check` is given, this fails if the daemon runs an incompatible version.
+pub async fn new_rpc_client() -> Result<ManagementServiceClient> {
+    let timeout = Duration::from_secs(RPC_TIMEOUT_SECS.load(Ordering::Relaxed));
+    let mut rpc = mullvad_management_interface::new_rpc_client_with_timeout(timeout)
+        .await
+        .map_err(|error| match error {
+            mullvad_management_interface::Error::DaemonNotResponding(timeout) => {
+                Error::DaemonNotResponding(timeout)
+            }
+            error => Error::ManagementInterfaceError(error),
+        })?;
+
+    if CHECK_DAEMON_VERSION.load(Ordering::Relaxed) {
+        let daemon_version = tokio::time::timeout(timeout, rpc.get_current_version(()))
+            .await
+            .map_err(|_| Error::DaemonNotResponding(timeout))??
+            .into_inner();
+        if !is_compatible_daemon_version(&daemon_version, PRODUCT_VERSION) {
+            return Err(Error::IncompatibleDaemon(
+                daemon_version,
+                PRODUCT_VERSION.to_owned(),
+            ));
+        }
+    }
+
+    Ok(rpc)
+}
+
+/// Returns whether a CLI of version `cli_version` can talk to a daemon of version
+/// `daemon_version`. They are compatible if they belong to the same release, e.g. `2020.5` and
+/// `2020.5-beta2`. Versions that can't be parsed, such as those of development builds, are assumed
+/// to be compatible.
+fn is_compatible_daemon_version(daemon_version: &AppVersion, cli_version: &str) -> bool {
+    match (release(daemon_version), release(cli_version)) {
+        (Some(daemon_release), Some(cli_release)) => daemon_release == cli_release,
+        _ => true,
+    }
+}
+
+/// Returns the year and number of the release that `version` belongs to.
+fn release(version: &str) -> Option<(u32, u32)> {
+    let release = version.split('-').next()?;
+    let mut parts = release.split('.');
+    let year = parts.next()?.parse().ok()?;
+    let number = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((year, number))
+}
+
 #[async_trait]
 pub trait Command {
     fn name(&self) -> &'static str;
@@ -114,3 +289,55 @@ pub trait Command {
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpc_timeout() {
+        let commands = cmds::get_commands();
+        let parse = |args: &[&str]| {
+            get_rpc_timeout(
+                &build_app(&commands)
+                    .get_matches_from_safe(args)
+                    .expect("failed to parse arguments"),
+            )
+        };
+
+        assert_eq!(parse(&["mullvad", "status"]).unwrap(), DEFAULT_RPC_TIMEOUT);
+        assert_eq!(
+            parse(&[
+                "mullvad",
+                "--rpc-timeout",
+                "3",
+                "connect",
+                "--wait",
+                "--timeout",
+                "5"
+            ])
+            .unwrap(),
+            Duration::from_secs(3)
+        );
+        assert!(matches!(
+            parse(&["mullvad", "--rpc-timeout", "0", "status"]),
+            Err(Error::InvalidCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_daemon_version_compatibility() {
+        let compatible =
+            |daemon: &str, cli: &str| is_compatible_daemon_version(&daemon.to_owned(), cli);
+
+        assert!(compatible("2020.5", "2020.5"));
+        assert!(compatible("2020.5-beta2", "2020.5"));
+        assert!(compatible("2020.5", "2020.5-dev-1a2b3c"));
+        assert!(!compatible("2020.4", "2020.5"));
+        assert!(!compatible("2020.6-beta1", "2020.5"));
+        assert!(!compatible("2019.5", "2020.5"));
+        // Versions that can't be parsed don't prevent talking to the daemon
+        assert!(compatible("unknown", "2020.5"));
+        assert!(compatible("2020.5", ""));
+    }
+}
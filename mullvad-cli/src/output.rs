@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::error::Error as StdError;
+use talpid_types::ErrorExt;
+
+/// Name of the global argument that switches the output to JSON.
+pub const JSON_ARG: &str = "json";
+
+/// Returns the global argument that selects machine readable output.
+pub fn get_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name(JSON_ARG)
+        .long("json")
+        .global(true)
+        .help("Print the result as a JSON object instead of human readable text")
+}
+
+/// How the result of a command should be presented to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+/// Outcome of a command, as reported in JSON mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Success,
+    Error,
+}
+
+/// Machine readable description of an error and the chain of errors that caused it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorOutput {
+    pub message: String,
+    pub causes: Vec<String>,
+}
+
+impl ErrorOutput {
+    pub fn new(error: &(dyn StdError + 'static)) -> Self {
+        let mut causes = Vec::new();
+        let mut source = error.source();
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        ErrorOutput {
+            message: error.to_string(),
+            causes,
+        }
+    }
+}
+
+/// The object printed to stdout when a command finishes in JSON mode. All fields are always
+/// present so that scripts can rely on a stable shape.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommandOutput {
+    pub status: Status,
+    pub relay: Option<String>,
+    pub error: Option<ErrorOutput>,
+}
+
+impl CommandOutput {
+    pub fn success(relay: Option<String>) -> Self {
+        CommandOutput {
+            status: Status::Success,
+            relay,
+            error: None,
+        }
+    }
+
+    pub fn failure(error: &(dyn StdError + 'static)) -> Self {
+        CommandOutput {
+            status: Status::Error,
+            relay: None,
+            error: Some(ErrorOutput::new(error)),
+        }
+    }
+}
+
+/// Prints command results in the format selected by the user.
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    format: Format,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Self {
+        Output { format }
+    }
+
+    pub fn from_matches(matches: &clap::ArgMatches<'_>) -> Self {
+        if matches.is_present(JSON_ARG) {
+            Self::new(Format::Json)
+        } else {
+            Self::new(Format::Human)
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == Format::Json
+    }
+
+    /// Prints a successful result. In human readable mode `message` is printed, if any.
+    pub fn success(&self, relay: Option<String>, message: Option<&str>) {
+        match self.format {
+            Format::Human => {
+                if let Some(message) = message {
+                    println!("{}", message);
+                }
+            }
+            Format::Json => print_json(&CommandOutput::success(relay)),
+        }
+    }
+
+    /// Prints an error. Human readable errors are written to stderr, JSON errors to stdout.
+    pub fn error<E: StdError + 'static>(&self, error: &E) {
+        match self.format {
+            Format::Human => eprintln!("{}", error.display_chain()),
+            Format::Json => print_json(&CommandOutput::failure(error)),
+        }
+    }
+}
+
+fn print_json(output: &CommandOutput) {
+    println!(
+        "{}",
+        serde_json::to_string(output).expect("Failed to serialize command output")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(err_derive::Error, Debug)]
+    #[error(display = "Outer error")]
+    struct OuterError(#[error(source)] std::io::Error);
+
+    #[test]
+    fn test_success_shape() {
+        let output = CommandOutput::success(Some("se-got-001".to_string()));
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            json!({
+                "status": "success",
+                "relay": "se-got-001",
+                "error": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_failure_shape() {
+        let error = OuterError(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "inner error",
+        ));
+        let output = CommandOutput::failure(&error);
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            json!({
+                "status": "error",
+                "relay": null,
+                "error": {
+                    "message": "Outer error",
+                    "causes": ["inner error"],
+                },
+            })
+        );
+    }
+}
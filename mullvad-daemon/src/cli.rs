@@ -1,5 +1,6 @@
-use clap::{crate_authors, crate_description, crate_name, App, Arg};
+use clap::{crate_authors, crate_description, crate_name, values_t, App, Arg};
 use log;
+use std::time::Duration;
 
 use crate::version;
 
@@ -8,9 +9,18 @@ pub struct Config {
     pub log_to_file: bool,
     pub log_stdout_timestamps: bool,
     pub run_as_service: bool,
+    pub run_in_console: bool,
     pub register_service: bool,
+    pub unregister_service: bool,
+    pub restart_service: bool,
+    pub service_status: bool,
+    pub service_recovery_delays: Option<Vec<Duration>>,
+    pub restart_after_hibernation: bool,
 }
 
+/// Flag that keeps the system service from restarting itself after hibernation.
+pub const DISABLE_HIBERNATION_RESTART_ARG: &str = "disable-hibernation-restart";
+
 pub fn get_config() -> &'static Config {
     lazy_static::lazy_static! {
         static ref CONFIG: Config = create_config();
@@ -31,14 +41,33 @@ pub fn create_config() -> Config {
     let log_stdout_timestamps = !matches.is_present("disable_stdout_timestamps");
 
     let run_as_service = cfg!(windows) && matches.is_present("run_as_service");
+    let run_in_console = cfg!(windows) && matches.is_present("run_in_console");
     let register_service = cfg!(windows) && matches.is_present("register_service");
+    let unregister_service = cfg!(windows) && matches.is_present("unregister_service");
+    let restart_service = cfg!(windows) && matches.is_present("restart_service");
+    let service_status = cfg!(windows) && matches.is_present("service_status");
+    let service_recovery_delays = if cfg!(windows) {
+        values_t!(matches.values_of("service_recovery_delays"), u64)
+            .ok()
+            .map(|delays| delays.into_iter().map(Duration::from_secs).collect())
+    } else {
+        None
+    };
+    let restart_after_hibernation =
+        !(cfg!(windows) && matches.is_present("disable_hibernation_restart"));
 
     Config {
         log_level,
         log_to_file,
         log_stdout_timestamps,
         run_as_service,
+        run_in_console,
         register_service,
+        unregister_service,
+        restart_service,
+        service_status,
+        service_recovery_delays,
+        restart_after_hibernation,
     }
 }
 
@@ -54,6 +83,26 @@ lazy_static::lazy_static! {
     MULLVAD_RPC_SOCKET_PATH    Location of the management interface device.
                                It refers to Unix domain socket on Unix based platforms, and named pipe on Windows.
                                [Default: {}]
+    MULLVAD_DISABLE_VERSION_CHECK
+                               If set, the daemon never checks for new app versions, such as when an
+                               admin manages updates.
+    MULLVAD_API_FALLBACK_HOSTS Comma-separated list of API hosts to check for app updates against
+                               if the primary API host is unreachable.
+    TALPID_OPENVPN_CA_PATH     Path to the CA certificate used by OpenVPN.
+                               [Default: ca.crt in the resource directory]
+    TALPID_OPENVPN_PLUGIN_FILENAME
+                               Filename of the OpenVPN plugin, which is looked up in the resource
+                               directory.
+    TALPID_OPENVPN_MAX_LOG_SIZE
+                               Size in bytes at which the OpenVPN log is rotated. If unset, it is
+                               never rotated.
+    TALPID_OPENVPN_TEMP_DIR    Directory that OpenVPN credential files are written to.
+                               [Default: the system's temporary directory]
+    TALPID_OPENVPN_USERNAME    OpenVPN username, used if the tunnel parameters lack one.
+    TALPID_OPENVPN_PASSWORD    OpenVPN password, used if the tunnel parameters lack one.
+    TALPID_OPENVPN_ABSTRACT_SOCKET
+                               If set, the OpenVPN event server listens on an abstract socket
+                               instead of a socket file. Linux only.
 
 ",
         mullvad_paths::get_default_resource_dir().display(),
@@ -91,10 +140,43 @@ fn create_app() -> App<'static, 'static> {
             Arg::with_name("run_as_service")
                 .long("run-as-service")
                 .help("Run as a system service. On Windows this option must be used when running a system service"),
+        ).arg(
+            Arg::with_name("run_in_console")
+                .long("run-in-console")
+                .conflicts_with("run_as_service")
+                .help("Run the system service logic in the console, for debugging. Ctrl-C stops the daemon like a service stop request"),
         ).arg(
             Arg::with_name("register_service")
                 .long("register-service")
                 .help("Register itself as a system service"),
+        ).arg(
+            Arg::with_name("unregister_service")
+                .long("unregister-service")
+                .conflicts_with("register_service")
+                .help("Stop and remove the system service"),
+        ).arg(
+            Arg::with_name("restart_service")
+                .long("restart-service")
+                .hidden(true)
+                .help("Restart the system service using the service control manager"),
+        ).arg(
+            Arg::with_name("service_status")
+                .long("service-status")
+                .help("Check whether the system service is installed and up to date"),
+        ).arg(
+            Arg::with_name("service_recovery_delays")
+                .long("service-recovery-delays")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .use_delimiter(true)
+                .number_of_values(3)
+                .requires("register_service")
+                .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Comma separated delays before each of the three restart attempts made by the service recovery"),
+        ).arg(
+            Arg::with_name("disable_hibernation_restart")
+                .long(DISABLE_HIBERNATION_RESTART_ARG)
+                .help("Don't restart the system service when the machine resumes from hibernation. When registering the service, this choice is stored for the service"),
         )
     }
     app
@@ -0,0 +1,178 @@
+//! Reports service lifecycle events to the Windows event log.
+
+use std::{ffi::OsStr, io, iter, os::windows::ffi::OsStrExt, path::Path, ptr};
+use winapi::{
+    shared::minwindef::WORD,
+    um::{
+        winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW},
+        winnt::{
+            EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, HANDLE, LPCWSTR,
+        },
+    },
+};
+use winreg::{
+    enums::{HKEY_LOCAL_MACHINE, REG_EXPAND_SZ},
+    RegKey, RegValue,
+};
+
+/// Name of the event source that entries are reported under.
+const EVENT_SOURCE_NAME: &str = "MullvadVPN";
+
+/// Registry key under which the event sources of the application log are registered.
+const APPLICATION_LOG_SOURCES_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application";
+
+/// Message file that maps every event ID to a message consisting of only the reported string.
+/// It ships with the .NET Framework, which uses it for the event sources it registers.
+const EVENT_MESSAGE_FILE: &str =
+    r"%SystemRoot%\Microsoft.NET\Framework\v4.0.30319\EventLogMessages.dll";
+
+lazy_static::lazy_static! {
+    static ref EVENT_LOG: Option<EventLog> = match EventLog::open(EVENT_SOURCE_NAME) {
+        Ok(event_log) => Some(event_log),
+        Err(error) => {
+            log::warn!("Failed to register event source: {}", error);
+            None
+        }
+    };
+}
+
+/// Service transitions that are reported to the event log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceEvent {
+    /// The service finished starting and is running.
+    Started,
+    /// The service was stopped on request.
+    Stopped,
+    /// The service stopped because of an error or an unexpected daemon exit.
+    Failed,
+    /// The service is being restarted after the machine resumed from hibernation.
+    HibernationRestart,
+}
+
+impl ServiceEvent {
+    /// Returns the event ID that identifies this kind of event in the event log.
+    pub fn event_id(self) -> u32 {
+        match self {
+            ServiceEvent::Started => 1000,
+            ServiceEvent::Stopped => 1001,
+            ServiceEvent::Failed => 1002,
+            ServiceEvent::HibernationRestart => 1003,
+        }
+    }
+
+    fn event_type(self) -> WORD {
+        match self {
+            ServiceEvent::Started | ServiceEvent::Stopped => EVENTLOG_INFORMATION_TYPE,
+            ServiceEvent::Failed => EVENTLOG_ERROR_TYPE,
+            ServiceEvent::HibernationRestart => EVENTLOG_WARNING_TYPE,
+        }
+    }
+}
+
+/// Reports `event` to the event log. Failures are logged but otherwise ignored.
+pub fn report(event: ServiceEvent, message: &str) {
+    if let Some(event_log) = EVENT_LOG.as_ref() {
+        if let Err(error) = event_log.report(event, message) {
+            log::warn!("Failed to report {:?} to the event log: {}", event, error);
+        }
+    }
+}
+
+/// Registers the event source in the application log, so that the event viewer can display the
+/// reported messages. Must be run with administrator privileges.
+pub fn register_event_source() -> io::Result<()> {
+    let (source_key, _) = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .create_subkey(Path::new(APPLICATION_LOG_SOURCES_KEY).join(EVENT_SOURCE_NAME))?;
+    let message_file = RegValue {
+        bytes: to_wide_nul(EVENT_MESSAGE_FILE)
+            .into_iter()
+            .flat_map(|unit| unit.to_le_bytes().to_vec())
+            .collect(),
+        vtype: REG_EXPAND_SZ,
+    };
+    source_key.set_raw_value("EventMessageFile", &message_file)?;
+    let types_supported =
+        u32::from(EVENTLOG_ERROR_TYPE | EVENTLOG_WARNING_TYPE | EVENTLOG_INFORMATION_TYPE);
+    source_key.set_value("TypesSupported", &types_supported)
+}
+
+struct EventLog {
+    handle: HANDLE,
+}
+
+// The event log handle may be used from any thread.
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    fn open(source_name: &str) -> io::Result<Self> {
+        let source_name = to_wide_nul(source_name);
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source_name.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EventLog { handle })
+    }
+
+    fn report(&self, event: ServiceEvent, message: &str) -> io::Result<()> {
+        let message = to_wide_nul(message);
+        let mut strings: [LPCWSTR; 1] = [message.as_ptr()];
+        let result = unsafe {
+            ReportEventW(
+                self.handle,
+                event.event_type(),
+                0,
+                event.event_id(),
+                ptr::null_mut(),
+                strings.len() as WORD,
+                0,
+                strings.as_mut_ptr(),
+                ptr::null_mut(),
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        unsafe { DeregisterEventSource(self.handle) };
+    }
+}
+
+fn to_wide_nul(string: &str) -> Vec<u16> {
+    OsStr::new(string)
+        .encode_wide()
+        .chain(iter::once(0u16))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_ids_are_distinct() {
+        let events = [
+            ServiceEvent::Started,
+            ServiceEvent::Stopped,
+            ServiceEvent::Failed,
+            ServiceEvent::HibernationRestart,
+        ];
+        let ids: std::collections::HashSet<u32> =
+            events.iter().map(|event| event.event_id()).collect();
+        assert_eq!(ids.len(), events.len());
+    }
+
+    #[test]
+    fn test_event_id_mapping() {
+        assert_eq!(ServiceEvent::Started.event_id(), 1000);
+        assert_eq!(ServiceEvent::Stopped.event_id(), 1001);
+        assert_eq!(ServiceEvent::Failed.event_id(), 1002);
+        assert_eq!(ServiceEvent::HibernationRestart.event_id(), 1003);
+        assert_eq!(ServiceEvent::Failed.event_type(), EVENTLOG_ERROR_TYPE);
+    }
+}
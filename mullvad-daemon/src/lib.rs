@@ -41,7 +41,7 @@ use mullvad_types::{
     relay_list::{Relay, RelayList},
     settings::Settings,
     states::{TargetState, TunnelState},
-    version::{AppVersion, AppVersionInfo},
+    version::{AppVersion, AppVersionInfo, VersionCheckStatus},
     wireguard::KeygenEvent,
 };
 use settings::SettingsPersister;
@@ -52,6 +52,7 @@ use std::{
     io,
     marker::PhantomData,
     mem,
+    net::IpAddr,
     path::PathBuf,
     sync::{mpsc, Arc, Weak},
     time::Duration,
@@ -60,6 +61,7 @@ use std::{
 use talpid_core::split_tunnel;
 use talpid_core::{
     mpsc::Sender,
+    routing::Route,
     tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
 };
 #[cfg(target_os = "android")]
@@ -83,12 +85,22 @@ type BoxFuture<T, E> = Box<dyn Future<Item = T, Error = E> + Send>;
 
 const TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Timeout for the version updater to finish writing its cache when the daemon shuts down.
+const VERSION_UPDATER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Timeout for first WireGuard key pushing
 const FIRST_KEY_PUSH_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(30);
 
+/// Comma-separated list of API hosts to check for app updates against if the primary API host is
+/// unreachable.
+const API_FALLBACK_HOSTS_VAR: &str = "MULLVAD_API_FALLBACK_HOSTS";
+
+/// When set, the daemon never checks for new app versions, such as when an admin manages updates.
+const DISABLE_VERSION_CHECK_VAR: &str = "MULLVAD_DISABLE_VERSION_CHECK";
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -148,12 +160,23 @@ pub enum Error {
     OpenCachedTargetState(#[error(source)] io::Error),
 }
 
+/// Reasons why the daemon refused a command that changes the tunnel state.
+#[derive(err_derive::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelCommandError {
+    /// The service has been paused, and does not change the tunnel state until it is resumed.
+    #[error(display = "The daemon is paused")]
+    Paused,
+}
+
 /// Enum representing commands that can be sent to the daemon.
 pub enum DaemonCommand {
     /// Set target state. Does nothing if the daemon already has the state that is being set.
-    SetTargetState(oneshot::Sender<std::result::Result<(), ()>>, TargetState),
+    SetTargetState(
+        oneshot::Sender<std::result::Result<(), TunnelCommandError>>,
+        TargetState,
+    ),
     /// Reconnect the tunnel, if one is connecting/connected.
-    Reconnect,
+    Reconnect(oneshot::Sender<std::result::Result<(), TunnelCommandError>>),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
     /// Get the current geographical location.
@@ -222,8 +245,15 @@ pub enum DaemonCommand {
     VerifyWireguardKey(oneshot::Sender<bool>),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<AppVersionInfo>),
+    /// Get the outcome of the most recent check for new app versions
+    GetVersionCheckStatus(oneshot::Sender<VersionCheckStatus>),
     /// Get current version of the app
     GetCurrentVersion(oneshot::Sender<AppVersion>),
+    /// Get the routes currently applied by the tunnel state machine, or `None` if this isn't
+    /// supported on the platform
+    GetRoutes(oneshot::Sender<Option<Vec<Route>>>),
+    /// Get the DNS servers used inside the tunnel. Empty unless the tunnel is connected.
+    GetTunnelDnsServers(oneshot::Sender<Vec<IpAddr>>),
     /// Remove settings and clear the cache
     #[cfg(not(target_os = "android"))]
     FactoryReset(oneshot::Sender<()>),
@@ -273,6 +303,13 @@ pub(crate) enum InternalDaemonEvent {
     ),
     /// The background job fetching new `AppVersionInfo`s got a new info object.
     NewAppVersionInfo(AppVersionInfo),
+    /// Stop accepting commands that change the tunnel state. Traffic is blocked if the tunnel
+    /// goes down while paused.
+    Pause,
+    /// Accept commands that change the tunnel state again after a `Pause`.
+    Resume,
+    /// Sent by the daemon to itself when the main event loop starts.
+    Ready,
 }
 
 impl From<TunnelStateTransition> for InternalDaemonEvent {
@@ -463,6 +500,8 @@ pub struct Daemon<L: EventListener> {
     rx: Wait<UnboundedReceiver<InternalDaemonEvent>>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    /// Whether commands that change the tunnel state are currently rejected.
+    paused: bool,
     event_listener: L,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
@@ -477,6 +516,7 @@ pub struct Daemon<L: EventListener> {
     last_generated_bridge_relay: Option<Relay>,
     app_version_info: AppVersionInfo,
     shutdown_callbacks: Vec<Box<dyn FnOnce()>>,
+    ready_callbacks: Vec<Box<dyn FnOnce()>>,
     /// oneshot channel that completes once the tunnel state machine has been shut down
     tunnel_state_machine_shutdown_signal: oneshot::Receiver<()>,
     cache_dir: PathBuf,
@@ -525,12 +565,21 @@ where
         }
 
         let app_version_info = version_check::load_cache(&cache_dir);
+        let fallback_handles = std::env::var(API_FALLBACK_HOSTS_VAR)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(|host| rpc_runtime.mullvad_rest_handle_for_host(host.to_owned()))
+            .collect();
         let (version_updater, version_updater_handle) = version_check::VersionUpdater::new(
             rpc_handle.clone(),
+            fallback_handles,
             cache_dir.clone(),
             internal_event_tx.to_specialized_sender(),
             app_version_info.clone(),
             settings.show_beta_releases,
+            std::env::var_os(DISABLE_VERSION_CHECK_VAR).is_some(),
         );
         rpc_runtime.runtime().spawn(version_updater.run());
         let account_history =
@@ -603,6 +652,7 @@ where
             rx: internal_event_rx.wait(),
             tx: internal_event_tx,
             reconnection_job: None,
+            paused: false,
             event_listener,
             settings,
             account_history,
@@ -617,6 +667,7 @@ where
             last_generated_bridge_relay: None,
             app_version_info,
             shutdown_callbacks: vec![],
+            ready_callbacks: vec![],
             tunnel_state_machine_shutdown_signal,
             cache_dir,
         };
@@ -639,12 +690,20 @@ where
         Ok(daemon)
     }
 
+    /// Registers a callback that is called once the daemon has handled the events queued before
+    /// the main event loop started, at which point it is able to respond to management interface
+    /// requests.
+    pub fn add_ready_callback(&mut self, callback: impl FnOnce() + 'static) {
+        self.ready_callbacks.push(Box::new(callback));
+    }
+
     /// Consume the `Daemon` and run the main event loop. Blocks until an error happens or a
     /// shutdown event is received.
     pub fn run(mut self) -> Result<(), Error> {
         if self.target_state == TargetState::Secured {
             self.connect_tunnel();
         }
+        let _ = self.tx.send(InternalDaemonEvent::Ready);
         while let Some(Ok(event)) = self.rx.next() {
             self.handle_event(event);
             if self.state == DaemonExecutionState::Finished {
@@ -656,7 +715,9 @@ where
         Ok(())
     }
 
-    fn finalize(self) {
+    fn finalize(mut self) {
+        self.stop_version_updater();
+
         let (event_listener, shutdown_callbacks, tunnel_state_machine_shutdown_signal) =
             self.shutdown();
         for cb in shutdown_callbacks {
@@ -683,6 +744,16 @@ where
         mem::drop(event_listener);
     }
 
+    /// Stops the version updater, letting it save any version info it has just fetched.
+    fn stop_version_updater(&mut self) {
+        if !self
+            .version_updater_handle
+            .stop_blocking(self.rpc_runtime.runtime(), VERSION_UPDATER_SHUTDOWN_TIMEOUT)
+        {
+            log::error!("Version updater did not shut down in time, shutting down anyway");
+        }
+    }
+
     /// Shuts down the daemon without shutting down the underlying event listener and the shutdown
     /// callbacks
     fn shutdown(self) -> (L, Vec<Box<dyn FnOnce()>>, oneshot::Receiver<()>) {
@@ -714,6 +785,9 @@ where
             NewAppVersionInfo(app_version_info) => {
                 self.handle_new_app_version_info(app_version_info)
             }
+            Pause => self.on_pause(),
+            Resume => self.on_resume(),
+            Ready => self.on_ready(),
         }
     }
 
@@ -734,7 +808,15 @@ where
             TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
                 endpoint,
                 location: self.build_location_from_relay(),
+                reconnect_attempt: None,
             },
+            TunnelStateTransition::Reconnecting(endpoint, reconnect_attempt) => {
+                TunnelState::Connecting {
+                    endpoint,
+                    location: self.build_location_from_relay(),
+                    reconnect_attempt: Some(reconnect_attempt),
+                }
+            }
             TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
                 endpoint,
                 location: self.build_location_from_relay(),
@@ -951,7 +1033,8 @@ where
         let (future, abort_handle) = abortable(Box::pin(async move {
             tokio02::time::delay_for(delay).await;
             log::debug!("Attempting to reconnect");
-            let _ = tunnel_command_tx.send(DaemonCommand::Reconnect);
+            let (tx, _) = oneshot::channel();
+            let _ = tunnel_command_tx.send(DaemonCommand::Reconnect(tx));
         }));
 
         self.spawn_future(future);
@@ -988,7 +1071,7 @@ where
         }
         match command {
             SetTargetState(tx, state) => self.on_set_target_state(tx, state),
-            Reconnect => self.on_reconnect(),
+            Reconnect(tx) => self.on_reconnect(tx),
             GetState(tx) => self.on_get_state(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx),
             CreateNewAccount(tx) => self.on_create_new_account(tx),
@@ -1025,7 +1108,10 @@ where
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx),
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx),
             GetVersionInfo(tx) => self.on_get_version_info(tx),
+            GetVersionCheckStatus(tx) => self.on_get_version_check_status(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
+            GetRoutes(tx) => self.on_get_routes(tx),
+            GetTunnelDnsServers(tx) => self.on_get_tunnel_dns_servers(tx),
             #[cfg(not(target_os = "android"))]
             FactoryReset(tx) => self.on_factory_reset(tx),
             #[cfg(target_os = "linux")]
@@ -1125,6 +1211,12 @@ where
         };
     }
 
+    fn on_ready(&mut self) {
+        for callback in mem::take(&mut self.ready_callbacks) {
+            callback();
+        }
+    }
+
     fn handle_new_app_version_info(&mut self, app_version_info: AppVersionInfo) {
         self.app_version_info = app_version_info.clone();
         self.event_listener.notify_app_version(app_version_info);
@@ -1132,9 +1224,14 @@ where
 
     fn on_set_target_state(
         &mut self,
-        tx: oneshot::Sender<Result<(), ()>>,
+        tx: oneshot::Sender<Result<(), TunnelCommandError>>,
         new_target_state: TargetState,
     ) {
+        if self.paused {
+            warn!("Ignoring target state change request because the daemon is paused");
+            Self::oneshot_send(tx, Err(TunnelCommandError::Paused), "target state");
+            return;
+        }
         if self.state.is_running() {
             self.set_target_state(new_target_state);
         } else {
@@ -1143,12 +1240,18 @@ where
         Self::oneshot_send(tx, Ok(()), "target state");
     }
 
-    fn on_reconnect(&mut self) {
+    fn on_reconnect(&mut self, tx: oneshot::Sender<Result<(), TunnelCommandError>>) {
+        if self.paused {
+            warn!("Ignoring reconnect command because the daemon is paused");
+            Self::oneshot_send(tx, Err(TunnelCommandError::Paused), "reconnect");
+            return;
+        }
         if self.target_state == TargetState::Secured || self.tunnel_state.is_in_error_state() {
             self.connect_tunnel();
         } else {
             debug!("Ignoring reconnect command. Currently not in secured state");
         }
+        Self::oneshot_send(tx, Ok(()), "reconnect");
     }
 
     fn on_get_state(&self, tx: oneshot::Sender<TunnelState>) {
@@ -1169,6 +1272,7 @@ where
                             .map(|fetched_location| GeoIpLocation {
                                 ipv4: fetched_location.ipv4,
                                 ipv6: fetched_location.ipv6,
+                                mullvad_exit_ip: fetched_location.mullvad_exit_ip,
                                 ..relay_location.unwrap_or(fetched_location)
                             })
                             .map(Some),
@@ -1369,6 +1473,14 @@ where
         );
     }
 
+    fn on_get_version_check_status(&mut self, tx: oneshot::Sender<VersionCheckStatus>) {
+        Self::oneshot_send(
+            tx,
+            self.version_updater_handle.status(),
+            "get_version_check_status response",
+        );
+    }
+
     fn on_get_current_version(&mut self, tx: oneshot::Sender<AppVersion>) {
         Self::oneshot_send(
             tx,
@@ -1377,6 +1489,14 @@ where
         );
     }
 
+    fn on_get_routes(&mut self, tx: oneshot::Sender<Option<Vec<Route>>>) {
+        self.send_tunnel_command(TunnelCommand::GetRoutes(tx));
+    }
+
+    fn on_get_tunnel_dns_servers(&mut self, tx: oneshot::Sender<Vec<IpAddr>>) {
+        self.send_tunnel_command(TunnelCommand::GetDnsServers(tx));
+    }
+
     #[cfg(not(target_os = "android"))]
     fn on_factory_reset(&mut self, tx: oneshot::Sender<()>) {
         let mut failed = false;
@@ -1511,9 +1631,12 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
-                        block_when_disconnected,
-                    ));
+                    // Traffic stays blocked while paused. The setting is applied on resume.
+                    if !self.paused {
+                        self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                            block_when_disconnected,
+                        ));
+                    }
                 }
             }
             Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
@@ -1828,6 +1951,26 @@ where
         self.disconnect_tunnel();
     }
 
+    fn on_pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        info!("Pausing the daemon");
+        self.paused = true;
+        self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(true));
+    }
+
+    fn on_resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        info!("Resuming the daemon");
+        self.paused = false;
+        self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+            self.settings.block_when_disconnected,
+        ));
+    }
+
     fn on_prepare_restart(&mut self) {
         // TODO: See if this can be made to also shut down the daemon
         //       without causing the service to be restarted.
@@ -1965,6 +2108,16 @@ impl DaemonShutdownHandle {
     pub fn shutdown(&self) {
         let _ = self.tx.send(InternalDaemonEvent::TriggerShutdown);
     }
+
+    /// Makes the daemon reject tunnel state changes while keeping traffic blocked.
+    pub fn pause(&self) {
+        let _ = self.tx.send(InternalDaemonEvent::Pause);
+    }
+
+    /// Undoes a previous call to `pause`.
+    pub fn resume(&self) {
+        let _ = self.tx.send(InternalDaemonEvent::Resume);
+    }
 }
 
 struct MullvadTunnelParametersGenerator {
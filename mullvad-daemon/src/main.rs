@@ -3,13 +3,15 @@
 use log::{debug, error, info, warn};
 use mullvad_daemon::{
     logging,
-    management_interface::{ManagementInterfaceEventBroadcaster, ManagementInterfaceServer},
+    management_interface::{self, ManagementInterfaceEventBroadcaster, ManagementInterfaceServer},
     rpc_uniqueness_check, version, Daemon, DaemonCommandChannel, DaemonCommandSender,
 };
 use std::{path::PathBuf, thread, time::Duration};
 use talpid_types::ErrorExt;
 
 mod cli;
+#[cfg(windows)]
+mod event_log;
 mod exception_logging;
 mod shutdown;
 #[cfg(windows)]
@@ -67,6 +69,8 @@ fn get_log_dir(config: &cli::Config) -> Result<Option<PathBuf>, String> {
 fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
     if config.run_as_service {
         system_service::run()
+    } else if config.run_in_console {
+        system_service::run_in_console()
     } else {
         if config.register_service {
             let install_result = system_service::install_service().map_err(|e| e.display_chain());
@@ -74,6 +78,30 @@ fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), St
                 println!("Installed the service.");
             }
             install_result
+        } else if config.unregister_service {
+            let uninstall_result =
+                system_service::uninstall_service().map_err(|e| e.display_chain());
+            if uninstall_result.is_ok() {
+                println!("Removed the service.");
+            }
+            uninstall_result
+        } else if config.restart_service {
+            system_service::restart_service().map_err(|e| e.display_chain())
+        } else if config.service_status {
+            use system_service::ServiceInstallState;
+            match system_service::service_status().map_err(|e| e.display_chain())? {
+                ServiceInstallState::NotInstalled => println!("The service is not installed."),
+                ServiceInstallState::Installed {
+                    matches_config: true,
+                } => println!("The service is installed and up to date."),
+                ServiceInstallState::Installed {
+                    matches_config: false,
+                } => println!(
+                    "The service is installed but its configuration is out of date. \
+                     Run with --register-service to update it."
+                ),
+            }
+            Ok(())
         } else {
             run_standalone(log_dir)
         }
@@ -101,7 +129,7 @@ fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
         warn!("Running daemon as a non-administrator user, clients might refuse to connect");
     }
 
-    let daemon = create_daemon(log_dir)?;
+    let daemon = create_daemon(log_dir).map_err(|e| e.display_chain())?;
 
     let shutdown_handle = daemon.shutdown_handle();
     shutdown::set_shutdown_signal_handler(move || shutdown_handle.shutdown())
@@ -114,17 +142,32 @@ fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
     Ok(())
 }
 
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+enum CreateDaemonError {
+    #[error(display = "Unable to get settings dir")]
+    SettingsDir(#[error(source)] mullvad_paths::Error),
+
+    #[error(display = "Unable to get cache dir")]
+    CacheDir(#[error(source)] mullvad_paths::Error),
+
+    #[error(display = "Failed to set up the management interface")]
+    ManagementInterface(#[error(source)] management_interface::Error),
+
+    #[error(display = "Unable to initialize daemon")]
+    Daemon(#[error(source)] mullvad_daemon::Error),
+}
+
 fn create_daemon(
     log_dir: Option<PathBuf>,
-) -> Result<Daemon<ManagementInterfaceEventBroadcaster>, String> {
+) -> Result<Daemon<ManagementInterfaceEventBroadcaster>, CreateDaemonError> {
     let resource_dir = mullvad_paths::get_resource_dir();
-    let settings_dir = mullvad_paths::settings_dir()
-        .map_err(|e| e.display_chain_with_msg("Unable to get settings dir"))?;
-    let cache_dir = mullvad_paths::cache_dir()
-        .map_err(|e| e.display_chain_with_msg("Unable to get cache dir"))?;
+    let settings_dir = mullvad_paths::settings_dir().map_err(CreateDaemonError::SettingsDir)?;
+    let cache_dir = mullvad_paths::cache_dir().map_err(CreateDaemonError::CacheDir)?;
 
     let command_channel = DaemonCommandChannel::new();
-    let event_listener = spawn_management_interface(command_channel.sender())?;
+    let event_listener = spawn_management_interface(command_channel.sender())
+        .map_err(CreateDaemonError::ManagementInterface)?;
 
     Daemon::start(
         log_dir,
@@ -134,15 +177,13 @@ fn create_daemon(
         event_listener,
         command_channel,
     )
-    .map_err(|e| e.display_chain_with_msg("Unable to initialize daemon"))
+    .map_err(CreateDaemonError::Daemon)
 }
 
 fn spawn_management_interface(
     command_sender: DaemonCommandSender,
-) -> Result<ManagementInterfaceEventBroadcaster, String> {
-    let server = ManagementInterfaceServer::start(command_sender).map_err(|error| {
-        error.display_chain_with_msg("Unable to start management interface server")
-    })?;
+) -> Result<ManagementInterfaceEventBroadcaster, management_interface::Error> {
+    let server = ManagementInterfaceServer::start(command_sender)?;
     let event_broadcaster = server.event_broadcaster();
 
     info!("Management interface listening on {}", server.socket_path());
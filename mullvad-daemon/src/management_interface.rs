@@ -1,4 +1,4 @@
-use crate::{DaemonCommand, DaemonCommandSender, EventListener};
+use crate::{DaemonCommand, DaemonCommandSender, EventListener, TunnelCommandError};
 use futures::compat::Future01CompatExt;
 use futures01::{future, sync, Future};
 use mullvad_management_interface::{
@@ -53,7 +53,6 @@ type EventsListenerReceiver =
 type EventsListenerSender =
     tokio02::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
 
-const INVALID_ACCOUNT_TOKEN_MESSAGE: &str = "No valid account token configured";
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 
@@ -74,13 +73,7 @@ impl ManagementService for ManagementServiceImpl {
         let (tx, rx) = sync::oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::SetTargetState(tx, TargetState::Secured))
             .and_then(|_| rx.map_err(|_| Status::internal("internal error")))
-            .and_then(|result| match result {
-                Ok(()) => Ok(Response::new(())),
-                Err(()) => Err(Status::new(
-                    Code::Unauthenticated,
-                    INVALID_ACCOUNT_TOKEN_MESSAGE,
-                )),
-            })
+            .and_then(|result| result.map(Response::new).map_err(map_tunnel_command_error))
             .compat()
             .await
     }
@@ -97,8 +90,10 @@ impl ManagementService for ManagementServiceImpl {
 
     async fn reconnect_tunnel(&self, _: Request<()>) -> ServiceResult<()> {
         log::debug!("reconnect_tunnel");
-        self.send_command_to_daemon(DaemonCommand::Reconnect)
-            .map(Response::new)
+        let (tx, rx) = sync::oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::Reconnect(tx))
+            .and_then(|_| rx.map_err(|_| Status::internal("internal error")))
+            .and_then(|result| result.map(Response::new).map_err(map_tunnel_command_error))
             .compat()
             .await
     }
@@ -181,6 +176,22 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(convert_version_info(&app_version_info)))
     }
 
+    async fn get_version_check_status(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::VersionCheckStatus> {
+        log::debug!("get_version_check_status");
+
+        let (tx, rx) = sync::oneshot::channel();
+        let status = self
+            .send_command_to_daemon(DaemonCommand::GetVersionCheckStatus(tx))
+            .and_then(|_| rx.map_err(|_| Status::internal("internal error")))
+            .compat()
+            .await?;
+
+        Ok(Response::new(convert_version_check_status(&status)))
+    }
+
     // Relays and tunnel constraints
     //
 
@@ -308,6 +319,22 @@ impl ManagementService for ManagementServiceImpl {
                     });
                 BridgeSettings::Custom(proxy_settings)
             }
+            BridgeSettingType::Http(proxy_settings) => {
+                let address = proxy_settings
+                    .address
+                    .parse()
+                    .map_err(|_| Status::invalid_argument("failed to parse IP address"))?;
+                let auth = proxy_settings.auth.map(|auth| net::openvpn::ProxyAuth {
+                    username: auth.username,
+                    password: auth.password,
+                });
+                let proxy_settings =
+                    net::openvpn::ProxySettings::Http(net::openvpn::HttpProxySettings {
+                        address,
+                        auth,
+                    });
+                BridgeSettings::Custom(proxy_settings)
+            }
             BridgeSettingType::Shadowsocks(proxy_settings) => {
                 let peer = proxy_settings
                     .peer
@@ -322,6 +349,19 @@ impl ManagementService for ManagementServiceImpl {
                 );
                 BridgeSettings::Custom(proxy_settings)
             }
+            BridgeSettingType::Obfuscation(proxy_settings) => {
+                let peer = proxy_settings
+                    .peer
+                    .parse()
+                    .map_err(|_| Status::invalid_argument("failed to parse peer address"))?;
+                let proxy_settings = net::openvpn::ProxySettings::Obfuscation(
+                    net::openvpn::ObfuscationProxySettings {
+                        peer,
+                        key: proxy_settings.key,
+                    },
+                );
+                BridgeSettings::Custom(proxy_settings)
+            }
         };
 
         log::debug!("set_bridge_settings({:?})", settings);
@@ -762,6 +802,40 @@ impl ManagementService for ManagementServiceImpl {
             Ok(Response::new(()))
         }
     }
+
+    // Debugging
+    //
+
+    async fn get_routes(&self, _: Request<()>) -> ServiceResult<types::RouteList> {
+        log::debug!("get_routes");
+        let (tx, rx) = sync::oneshot::channel();
+        let routes = self
+            .send_command_to_daemon(DaemonCommand::GetRoutes(tx))
+            .and_then(|_| rx.map_err(|_| Status::internal("internal error")))
+            .compat()
+            .await?
+            .ok_or_else(|| {
+                Status::unimplemented(
+                    "listing the applied routes is not supported on this platform",
+                )
+            })?;
+
+        Ok(Response::new(convert_routes(&routes)))
+    }
+
+    async fn get_tunnel_dns_servers(&self, _: Request<()>) -> ServiceResult<types::DnsServerList> {
+        log::debug!("get_tunnel_dns_servers");
+        let (tx, rx) = sync::oneshot::channel();
+        let servers = self
+            .send_command_to_daemon(DaemonCommand::GetTunnelDnsServers(tx))
+            .and_then(|_| rx.map_err(|_| Status::internal("internal error")))
+            .compat()
+            .await?;
+
+        Ok(Response::new(types::DnsServerList {
+            addresses: servers.iter().map(|server| server.to_string()).collect(),
+        }))
+    }
 }
 
 impl ManagementServiceImpl {
@@ -837,6 +911,8 @@ fn convert_relay_settings_update(
                         },
                         username: config.username.clone(),
                         password: config.password.clone(),
+                        tls_crypt_v2_key: None,
+                        verify_x509_name: None,
                     })
                 }
                 ProtoConnectionConfig::Wireguard(config) => {
@@ -1119,6 +1195,17 @@ fn convert_bridge_settings(settings: &BridgeSettings) -> types::BridgeSettings {
                     }),
                 })
             }
+            net::openvpn::ProxySettings::Http(proxy_settings) => {
+                BridgeSettingType::Http(bridge_settings::HttpProxySettings {
+                    address: proxy_settings.address.to_string(),
+                    auth: proxy_settings.auth.as_ref().map(|auth| {
+                        bridge_settings::RemoteProxyAuth {
+                            username: auth.username.clone(),
+                            password: auth.password.clone(),
+                        }
+                    }),
+                })
+            }
             net::openvpn::ProxySettings::Shadowsocks(proxy_settings) => {
                 BridgeSettingType::Shadowsocks(bridge_settings::ShadowsocksProxySettings {
                     peer: proxy_settings.peer.to_string(),
@@ -1126,6 +1213,12 @@ fn convert_bridge_settings(settings: &BridgeSettings) -> types::BridgeSettings {
                     cipher: proxy_settings.cipher.clone(),
                 })
             }
+            net::openvpn::ProxySettings::Obfuscation(proxy_settings) => {
+                BridgeSettingType::Obfuscation(bridge_settings::ObfuscationProxySettings {
+                    peer: proxy_settings.peer.to_string(),
+                    key: proxy_settings.key.clone(),
+                })
+            }
         },
     };
 
@@ -1357,11 +1450,19 @@ fn convert_state(state: TunnelState) -> types::TunnelState {
 
     let state = match state {
         Disconnected => ProtoState::Disconnected(tunnel_state::Disconnected {}),
-        Connecting { endpoint, location } => ProtoState::Connecting(tunnel_state::Connecting {
+        Connecting {
+            endpoint,
+            location,
+            reconnect_attempt,
+        } => ProtoState::Connecting(tunnel_state::Connecting {
             relay_info: Some(types::TunnelStateRelayInfo {
                 tunnel_endpoint: Some(convert_endpoint(endpoint)),
                 location: location.map(convert_geoip_location),
             }),
+            reconnect_attempt: reconnect_attempt.map(|reconnect_attempt| types::ReconnectAttempt {
+                attempt: reconnect_attempt.attempt,
+                next_delay_secs: reconnect_attempt.next_delay.as_secs(),
+            }),
         }),
         Connected { endpoint, location } => ProtoState::Connected(tunnel_state::Connected {
             relay_info: Some(types::TunnelStateRelayInfo {
@@ -1462,6 +1563,7 @@ fn convert_endpoint(endpoint: talpid_types::net::TunnelEndpoint) -> types::Tunne
             proxy_type: match proxy_ep.proxy_type {
                 net::proxy::ProxyType::Shadowsocks => i32::from(types::ProxyType::Shadowsocks),
                 net::proxy::ProxyType::Custom => i32::from(types::ProxyType::Custom),
+                net::proxy::ProxyType::Obfuscation => i32::from(types::ProxyType::Obfuscation),
             },
         }),
     }
@@ -1481,15 +1583,43 @@ fn convert_geoip_location(geoip: GeoIpLocation) -> types::GeoIpLocation {
     }
 }
 
+fn convert_version_check_status(status: &version::VersionCheckStatus) -> types::VersionCheckStatus {
+    types::VersionCheckStatus {
+        last_check: status.last_check.map(|last_check| types::Timestamp {
+            seconds: last_check.timestamp(),
+            nanos: last_check.timestamp_subsec_nanos() as i32,
+        }),
+        last_error: status.last_error.clone().unwrap_or_default(),
+    }
+}
+
 fn convert_version_info(version_info: &version::AppVersionInfo) -> types::AppVersionInfo {
     types::AppVersionInfo {
         supported: version_info.supported,
-        latest_stable: version_info.latest_stable.clone(),
-        latest_beta: version_info.latest_beta.clone(),
+        latest_stable: version_info.latest_stable.clone().unwrap_or_default(),
+        latest_beta: version_info.latest_beta.clone().unwrap_or_default(),
         suggested_upgrade: version_info.suggested_upgrade.clone().unwrap_or_default(),
     }
 }
 
+fn convert_routes(routes: &[talpid_core::routing::Route]) -> types::RouteList {
+    types::RouteList {
+        routes: routes
+            .iter()
+            .map(|route| types::Route {
+                destination: route.prefix().to_string(),
+                gateway: route
+                    .node()
+                    .get_address()
+                    .map(|address| address.to_string())
+                    .unwrap_or_default(),
+                interface: route.node().get_device().unwrap_or_default().to_string(),
+                metric: route.metric().unwrap_or(0),
+            })
+            .collect(),
+    }
+}
+
 fn convert_proto_location(location: types::RelayLocation) -> Constraint<LocationConstraint> {
     if !location.hostname.is_empty() {
         Constraint::Only(LocationConstraint::Hostname(
@@ -1654,6 +1784,13 @@ impl Drop for ManagementInterfaceEventBroadcaster {
     }
 }
 
+// Converts a refused tunnel state change into a tonic status.
+fn map_tunnel_command_error(error: TunnelCommandError) -> Status {
+    match error {
+        TunnelCommandError::Paused => Status::new(Code::FailedPrecondition, error.to_string()),
+    }
+}
+
 // Converts a REST API error for an account into a tonic status.
 fn map_rest_account_error(error: RestError) -> Status {
     match error {
@@ -1,8 +1,16 @@
-use crate::cli;
+use crate::{
+    cli,
+    event_log::{self, ServiceEvent},
+};
 use mullvad_daemon::DaemonShutdownHandle;
 use std::{
+    cmp,
+    collections::{HashMap, HashSet},
     env,
-    ffi::OsString,
+    ffi::{OsStr, OsString},
+    io,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
     ptr, slice,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
@@ -18,13 +26,23 @@ use winapi::{
         minwindef::ULONG,
         ntdef::{LUID, PVOID, WCHAR},
         ntstatus::STATUS_SUCCESS,
+        winerror::{
+            ERROR_SERVICE_DOES_NOT_EXIST, ERROR_SERVICE_MARKED_FOR_DELETE, ERROR_SERVICE_NOT_ACTIVE,
+        },
     },
     um::{
         ntlsa::{
             LsaEnumerateLogonSessions, LsaFreeReturnBuffer, LsaGetLogonSessionData,
             SECURITY_LOGON_SESSION_DATA,
         },
+        shellapi::CommandLineToArgvW,
         sysinfoapi::GetSystemDirectoryW,
+        winbase::LocalFree,
+        winsvc::{
+            CloseServiceHandle, OpenSCManagerW, OpenServiceW, QueryServiceConfig2W, SC_HANDLE,
+            SC_MANAGER_CONNECT, SERVICE_CONFIG_SERVICE_SID_INFO, SERVICE_QUERY_CONFIG,
+            SERVICE_SID_INFO,
+        },
     },
 };
 use windows_service::{
@@ -38,14 +56,55 @@ use windows_service::{
     service_dispatcher,
     service_manager::{ServiceManager, ServiceManagerAccess},
 };
+use winreg::{
+    enums::{HKEY_LOCAL_MACHINE, KEY_READ},
+    RegKey,
+};
 
 static SERVICE_NAME: &'static str = "MullvadVPN";
 static SERVICE_DISPLAY_NAME: &'static str = "Mullvad VPN Service";
 static SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
 
+/// Services that must be running before the SCM starts the daemon.
+const SERVICE_DEPENDENCIES: &[&str] = &[
+    // Base Filter Engine
+    "BFE",
+    // Network Store Interface Service
+    // This service delivers network notifications (e.g. interface addition/deleting etc).
+    "NSI",
+    // TCP/IP Protocol Driver
+    // Dependencies are hard, so the DNS Client (Dnscache) is left out. The daemon would otherwise
+    // fail to start whenever that service is disabled.
+    "Tcpip",
+];
+
+/// Registry key under which the services are registered.
+const SERVICES_KEY: &str = r"SYSTEM\CurrentControlSet\Services";
+/// Registry value, under the service's `Parameters` key, that holds whether the service restarts
+/// after hibernation. Restarts are enabled if it is missing.
+const RESTART_AFTER_HIBERNATION_VALUE: &str = "RestartAfterHibernation";
+
 const SERVICE_RECOVERY_LAST_RESTART_DELAY: Duration = Duration::from_secs(60 * 10);
+/// Delays before each restart attempt, used unless overridden with `--service-recovery-delays`.
+const DEFAULT_SERVICE_RECOVERY_DELAYS: [Duration; 3] = [
+    Duration::from_secs(3),
+    Duration::from_secs(30),
+    SERVICE_RECOVERY_LAST_RESTART_DELAY,
+];
 const SERVICE_FAILURE_RESET_PERIOD: Duration = Duration::from_secs(60 * 15);
 
+/// How often the pending start state is re-reported while waiting for the daemon to be ready.
+const SERVICE_START_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Wait hint given to the service control manager while the daemon is starting.
+const SERVICE_START_WAIT_HINT: Duration = Duration::from_secs(3);
+
+/// How often the pending stop state is re-reported while the daemon is shutting down.
+const SERVICE_STOP_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+/// Wait hint given to the service control manager while the daemon is shutting down.
+const SERVICE_STOP_WAIT_HINT: Duration = Duration::from_secs(10);
+/// Maximum time the daemon is given to shut down before the process is terminated.
+const SERVICE_STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
 lazy_static::lazy_static! {
     static ref SERVICE_ACCESS: ServiceAccess = ServiceAccess::QUERY_CONFIG
     | ServiceAccess::CHANGE_CONFIG
@@ -81,10 +140,7 @@ fn run_service() -> Result<(), String> {
             // control manager. Always return NO_ERROR even if not implemented.
             ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
 
-            ServiceControl::Stop
-            | ServiceControl::Preshutdown
-            | ServiceControl::PowerEvent(_)
-            | ServiceControl::SessionChange(_) => {
+            control_event if translate_control(&control_event).is_some() => {
                 event_tx.send(control_event).unwrap();
                 ServiceControlHandlerResult::NoError
             }
@@ -94,46 +150,186 @@ fn run_service() -> Result<(), String> {
     };
     let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)
         .map_err(|e| e.display_chain_with_msg("Failed to register a service control handler"))?;
-    let mut persistent_service_status = PersistentServiceStatus::new(status_handle);
+
+    run_daemon(PersistentServiceStatus::new(status_handle), event_rx)
+}
+
+/// Runs the daemon in the current console with the same lifecycle handling as the system
+/// service. Ctrl-C and closing the console are treated like a service stop request.
+pub fn run_in_console() -> Result<(), String> {
+    let (event_tx, event_rx) = mpsc::channel();
+    crate::shutdown::set_shutdown_signal_handler(move || {
+        let _ = event_tx.send(ServiceControl::Stop);
+    })
+    .map_err(|e| e.display_chain())?;
+
+    let result = run_daemon(PersistentServiceStatus::detached(), event_rx);
+    if result.is_ok() {
+        log::info!("Console service stopped.");
+    }
+    result
+}
+
+fn run_daemon(
+    mut persistent_service_status: PersistentServiceStatus,
+    event_rx: mpsc::Receiver<ServiceControl>,
+) -> Result<(), String> {
     persistent_service_status
         .set_pending_start(Duration::from_secs(1))
         .unwrap();
 
     let clean_shutdown = Arc::new(AtomicBool::new(false));
+    let (stopped_tx, stopped_rx) = mpsc::channel();
 
     let log_dir = crate::get_log_dir(cli::get_config()).expect("Log dir should be available here");
-    let result = crate::create_daemon(log_dir).and_then(|daemon| {
-        let shutdown_handle = daemon.shutdown_handle();
-
-        // Register monitor that translates `ServiceControl` to Daemon events
-        start_event_monitor(
-            persistent_service_status.clone(),
-            shutdown_handle,
-            event_rx,
-            clean_shutdown.clone(),
-        );
+    let result = crate::create_daemon(log_dir)
+        .map_err(ServiceError::CreateDaemon)
+        .and_then(|mut daemon| {
+            let shutdown_handle = daemon.shutdown_handle();
+
+            // Register monitor that translates `ServiceControl` to Daemon events
+            start_event_monitor(
+                persistent_service_status.clone(),
+                shutdown_handle,
+                event_rx,
+                clean_shutdown.clone(),
+                stopped_rx,
+            );
 
-        persistent_service_status.set_running().unwrap();
+            // Remain in the pending start state until the daemon can serve RPC requests
+            let (ready_tx, ready_rx) = mpsc::channel();
+            daemon.add_ready_callback(move || {
+                let _ = ready_tx.send(());
+            });
+            start_progress_reporter(persistent_service_status.clone(), ready_rx);
 
-        daemon.run().map_err(|e| e.display_chain())
-    });
+            daemon.run().map_err(ServiceError::RunDaemon)
+        });
 
-    let exit_code = match result {
+    // Stop reporting shutdown progress
+    let _ = stopped_tx.send(());
+
+    let exit_code = match &result {
         Ok(()) => {
             // check if shutdown signal was sent from the system
             if clean_shutdown.load(Ordering::Acquire) {
                 ServiceExitCode::default()
             } else {
                 // otherwise return a non-zero code so that the daemon gets restarted
-                ServiceExitCode::ServiceSpecific(1)
+                ServiceExitCode::ServiceSpecific(exit_code::UNEXPECTED_SHUTDOWN)
             }
         }
-        Err(_) => ServiceExitCode::ServiceSpecific(1),
+        Err(error) => ServiceExitCode::ServiceSpecific(error.exit_code()),
     };
 
+    let unexpected_shutdown = result.is_ok() && !clean_shutdown.load(Ordering::Acquire);
     persistent_service_status.set_stopped(exit_code).unwrap();
 
-    result.map(|_| ())
+    let result = result.map_err(|e| e.display_chain());
+    match &result {
+        Ok(()) if unexpected_shutdown => {
+            event_log::report(ServiceEvent::Failed, "The service stopped unexpectedly")
+        }
+        Ok(()) => event_log::report(ServiceEvent::Stopped, "The service stopped"),
+        Err(error) => event_log::report(ServiceEvent::Failed, error),
+    }
+    result
+}
+
+/// Start a thread that reports the service as pending start until `ready_rx` signals that the
+/// daemon is ready, and then reports it as running.
+fn start_progress_reporter(
+    mut persistent_service_status: PersistentServiceStatus,
+    ready_rx: mpsc::Receiver<()>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let ready = wait_until_ready(&ready_rx, SERVICE_START_PROGRESS_INTERVAL, || {
+            persistent_service_status
+                .set_pending_start(SERVICE_START_WAIT_HINT)
+                .unwrap();
+        });
+        if ready {
+            persistent_service_status.set_running().unwrap();
+            event_log::report(ServiceEvent::Started, "The service started");
+        } else {
+            log::warn!("The daemon stopped before it became ready");
+        }
+    })
+}
+
+/// Blocks until `ready_rx` receives a value, calling `on_pending` every `interval` until then.
+/// Returns `false` if the sender is dropped before the daemon became ready.
+fn wait_until_ready(
+    ready_rx: &mpsc::Receiver<()>,
+    interval: Duration,
+    mut on_pending: impl FnMut(),
+) -> bool {
+    loop {
+        match ready_rx.recv_timeout(interval) {
+            Ok(()) => return true,
+            Err(mpsc::RecvTimeoutError::Timeout) => on_pending(),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Service specific exit codes reported to the service control manager when the service stops
+/// for any other reason than being asked to.
+mod exit_code {
+    /// The daemon stopped without being asked to.
+    pub const UNEXPECTED_SHUTDOWN: u32 = 1;
+    /// The settings or cache directory could not be resolved or created.
+    pub const PATH_ERROR: u32 = 2;
+    /// The management interface could not be started.
+    pub const MANAGEMENT_INTERFACE_ERROR: u32 = 3;
+    /// The tunnel state machine, including the firewall, failed to initialize.
+    pub const TUNNEL_STATE_MACHINE_ERROR: u32 = 4;
+    /// Cached state, such as the account history, could not be read.
+    pub const CACHE_ERROR: u32 = 5;
+    /// Any other error that occurred while initializing or running the daemon.
+    pub const DAEMON_ERROR: u32 = 6;
+    /// The daemon did not shut down in time and the process was terminated.
+    pub const STOP_TIMEOUT: u32 = 7;
+}
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+enum ServiceError {
+    #[error(display = "Failed to create the daemon")]
+    CreateDaemon(#[error(source)] crate::CreateDaemonError),
+
+    #[error(display = "The daemon failed")]
+    RunDaemon(#[error(source)] mullvad_daemon::Error),
+}
+
+impl ServiceError {
+    fn exit_code(&self) -> u32 {
+        use crate::CreateDaemonError;
+        match self {
+            ServiceError::CreateDaemon(CreateDaemonError::SettingsDir(_))
+            | ServiceError::CreateDaemon(CreateDaemonError::CacheDir(_)) => exit_code::PATH_ERROR,
+            ServiceError::CreateDaemon(CreateDaemonError::ManagementInterface(_)) => {
+                exit_code::MANAGEMENT_INTERFACE_ERROR
+            }
+            ServiceError::CreateDaemon(CreateDaemonError::Daemon(error))
+            | ServiceError::RunDaemon(error) => daemon_error_exit_code(error),
+        }
+    }
+}
+
+fn daemon_error_exit_code(error: &mullvad_daemon::Error) -> u32 {
+    use mullvad_daemon::Error;
+    match error {
+        Error::PathError(_) | Error::CreateDirError(..) | Error::RemoveDirError(..) => {
+            exit_code::PATH_ERROR
+        }
+        Error::TunnelError(_) => exit_code::TUNNEL_STATE_MACHINE_ERROR,
+        Error::LoadAccountHistory(_)
+        | Error::AccountHistory(_)
+        | Error::ReadCachedTargetState(_)
+        | Error::OpenCachedTargetState(_) => exit_code::CACHE_ERROR,
+        _ => exit_code::DAEMON_ERROR,
+    }
 }
 
 /// Start event monitor thread that polls for `ServiceControl` and translates them into calls to
@@ -143,51 +339,199 @@ fn start_event_monitor(
     shutdown_handle: DaemonShutdownHandle,
     event_rx: mpsc::Receiver<ServiceControl>,
     clean_shutdown: Arc<AtomicBool>,
+    stopped_rx: mpsc::Receiver<()>,
 ) -> thread::JoinHandle<()> {
     thread::spawn(move || {
-        let mut hibernation_detector = HibernationDetector::default();
+        let mut stopped_rx = Some(stopped_rx);
+        let mut hibernation_detector =
+            HibernationDetector::default().restart_on_resume(restart_after_hibernation());
+        let mut service_state = ServiceState::Running;
         for event in event_rx {
-            match event {
-                ServiceControl::Stop | ServiceControl::Preshutdown => {
+            let monitor_event = match translate_control(&event) {
+                Some(monitor_event) => monitor_event,
+                None => continue,
+            };
+            match monitor_event {
+                MonitorEvent::Pause | MonitorEvent::Continue => {
+                    match pause_transition(service_state, &event) {
+                        Some(ServiceState::Paused) => {
+                            shutdown_handle.pause();
+                            persistent_service_status.set_paused().unwrap();
+                            service_state = ServiceState::Paused;
+                        }
+                        Some(ServiceState::Running) => {
+                            shutdown_handle.resume();
+                            persistent_service_status.set_running().unwrap();
+                            service_state = ServiceState::Running;
+                        }
+                        _ => log::warn!(
+                            "Ignoring {:?} control in service state {:?}",
+                            event,
+                            service_state
+                        ),
+                    }
+                }
+                MonitorEvent::Shutdown => {
                     persistent_service_status
-                        .set_pending_stop(Duration::from_secs(10))
+                        .set_pending_stop(SERVICE_STOP_WAIT_HINT)
                         .unwrap();
 
                     clean_shutdown.store(true, Ordering::Release);
                     shutdown_handle.shutdown();
-                }
-                ServiceControl::PowerEvent(details) => match details {
-                    PowerEventParam::Suspend => {
-                        hibernation_detector.register_suspend();
-                    }
-                    PowerEventParam::ResumeAutomatic | PowerEventParam::ResumeSuspend => {
-                        hibernation_detector.register_resume();
-                    }
-                    _ => (),
-                },
-                ServiceControl::SessionChange(details) => {
-                    if details.reason == SessionChangeReason::SessionLogoff {
-                        hibernation_detector.register_logoff(details.notification.session_id);
+
+                    if let Some(stopped_rx) = stopped_rx.take() {
+                        start_stop_progress_reporter(persistent_service_status.clone(), stopped_rx);
                     }
                 }
-                _ => (),
+                MonitorEvent::Suspend => hibernation_detector.register_suspend(),
+                MonitorEvent::Resume => hibernation_detector.register_resume(),
+                MonitorEvent::Logon(session_id) => hibernation_detector.register_logon(session_id),
+                MonitorEvent::Logoff(session_id) => {
+                    hibernation_detector.register_logoff(session_id)
+                }
+                MonitorEvent::Disconnect(session_id) => {
+                    hibernation_detector.register_disconnect(session_id)
+                }
             }
         }
     })
 }
 
+/// Start a thread that keeps reporting the service as pending stop until `stopped_rx` signals that
+/// the daemon has stopped. If the daemon does not stop in time, the process is terminated.
+fn start_stop_progress_reporter(
+    mut persistent_service_status: PersistentServiceStatus,
+    stopped_rx: mpsc::Receiver<()>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let stopped = wait_until_stopped(
+            &stopped_rx,
+            SERVICE_STOP_PROGRESS_INTERVAL,
+            SERVICE_STOP_TIMEOUT,
+            || {
+                persistent_service_status
+                    .set_pending_stop(SERVICE_STOP_WAIT_HINT)
+                    .unwrap();
+            },
+        );
+        if !stopped {
+            log::error!(
+                "The daemon did not shut down within {} seconds. Terminating",
+                SERVICE_STOP_TIMEOUT.as_secs()
+            );
+            event_log::report(
+                ServiceEvent::Failed,
+                "The service did not stop in time and was terminated",
+            );
+            let _ = persistent_service_status
+                .set_stopped(ServiceExitCode::ServiceSpecific(exit_code::STOP_TIMEOUT));
+            std::process::exit(1);
+        }
+    })
+}
+
+/// Blocks until `stopped_rx` receives a value or is dropped, calling `on_pending` every `interval`
+/// until then. Returns `false` if this takes longer than `timeout`.
+fn wait_until_stopped(
+    stopped_rx: &mpsc::Receiver<()>,
+    interval: Duration,
+    timeout: Duration,
+    mut on_pending: impl FnMut(),
+) -> bool {
+    let start = Instant::now();
+    loop {
+        let remaining = match timeout.checked_sub(start.elapsed()) {
+            Some(remaining) => remaining,
+            None => return false,
+        };
+        match stopped_rx.recv_timeout(cmp::min(interval, remaining)) {
+            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => return true,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if start.elapsed() >= timeout {
+                    return false;
+                }
+                on_pending();
+            }
+        }
+    }
+}
+
+/// What a `ServiceControl` means to the event monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MonitorEvent {
+    Shutdown,
+    Pause,
+    Continue,
+    Suspend,
+    Resume,
+    Logon(u32),
+    Logoff(u32),
+    /// A session was disconnected but remains logged on, such as when switching users.
+    Disconnect(u32),
+}
+
+/// Translates a control event, sent by either the service control manager or the console, into
+/// an event for the event monitor. Returns `None` for controls that the monitor does not handle.
+fn translate_control(control: &ServiceControl) -> Option<MonitorEvent> {
+    match control {
+        ServiceControl::Stop | ServiceControl::Preshutdown => Some(MonitorEvent::Shutdown),
+        ServiceControl::Pause => Some(MonitorEvent::Pause),
+        ServiceControl::Continue => Some(MonitorEvent::Continue),
+        ServiceControl::PowerEvent(PowerEventParam::Suspend) => Some(MonitorEvent::Suspend),
+        ServiceControl::PowerEvent(PowerEventParam::ResumeAutomatic)
+        | ServiceControl::PowerEvent(PowerEventParam::ResumeSuspend) => Some(MonitorEvent::Resume),
+        ServiceControl::PowerEvent(_) => None,
+        ServiceControl::SessionChange(details) => {
+            translate_session_change(details.reason, details.notification.session_id)
+        }
+        _ => None,
+    }
+}
+
+/// Translates a session change. Only logoffs end a session, while disconnects, such as with fast
+/// user switching, leave it logged on.
+fn translate_session_change(reason: SessionChangeReason, session_id: u32) -> Option<MonitorEvent> {
+    match reason {
+        SessionChangeReason::SessionLogon => Some(MonitorEvent::Logon(session_id)),
+        SessionChangeReason::SessionLogoff => Some(MonitorEvent::Logoff(session_id)),
+        SessionChangeReason::ConsoleDisconnect | SessionChangeReason::RemoteDisconnect => {
+            Some(MonitorEvent::Disconnect(session_id))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the state that the service should enter when receiving a pause or continue control in
+/// `current_state`, or `None` if the control does not apply to that state.
+fn pause_transition(current_state: ServiceState, control: &ServiceControl) -> Option<ServiceState> {
+    match (current_state, control) {
+        (ServiceState::Running, ServiceControl::Pause) => Some(ServiceState::Paused),
+        (ServiceState::Paused, ServiceControl::Continue) => Some(ServiceState::Running),
+        _ => None,
+    }
+}
 
 /// Service status helper with persistent checkpoint counter.
 #[derive(Debug, Clone)]
 struct PersistentServiceStatus {
-    status_handle: ServiceStatusHandle,
+    /// Handle used to report the status. When running in a console there is none, and status
+    /// updates are only logged.
+    status_handle: Option<ServiceStatusHandle>,
     checkpoint_counter: Arc<AtomicUsize>,
 }
 
 impl PersistentServiceStatus {
     fn new(status_handle: ServiceStatusHandle) -> Self {
         PersistentServiceStatus {
-            status_handle,
+            status_handle: Some(status_handle),
+            checkpoint_counter: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// Create a status helper that is not connected to the service control manager.
+    fn detached() -> Self {
+        PersistentServiceStatus {
+            status_handle: None,
             checkpoint_counter: Arc::new(AtomicUsize::new(1)),
         }
     }
@@ -211,6 +555,15 @@ impl PersistentServiceStatus {
         )
     }
 
+    /// Tell the system that the service is paused.
+    fn set_paused(&mut self) -> windows_service::Result<()> {
+        self.report_status(
+            ServiceState::Paused,
+            Duration::default(),
+            ServiceExitCode::default(),
+        )
+    }
+
     /// Tell the system that the service is pending stop and provide the time estimate until the
     /// service is stopped.
     fn set_pending_stop(&mut self, wait_hint: Duration) -> windows_service::Result<()> {
@@ -261,7 +614,10 @@ impl PersistentServiceStatus {
             service_status.wait_hint
         );
 
-        self.status_handle.set_service_status(service_status)
+        match &self.status_handle {
+            Some(status_handle) => status_handle.set_service_status(service_status),
+            None => Ok(()),
+        }
     }
 }
 
@@ -272,11 +628,11 @@ fn accepted_controls_by_state(state: ServiceState) -> ServiceControlAccept {
         ServiceState::StartPending | ServiceState::PausePending | ServiceState::ContinuePending => {
             ServiceControlAccept::empty()
         }
-        ServiceState::Running => {
-            always_accepted | ServiceControlAccept::STOP | ServiceControlAccept::PRESHUTDOWN
-        }
-        ServiceState::Paused => {
-            always_accepted | ServiceControlAccept::STOP | ServiceControlAccept::PRESHUTDOWN
+        ServiceState::Running | ServiceState::Paused => {
+            always_accepted
+                | ServiceControlAccept::STOP
+                | ServiceControlAccept::PRESHUTDOWN
+                | ServiceControlAccept::PAUSE_CONTINUE
         }
         ServiceState::StopPending | ServiceState::Stopped => ServiceControlAccept::empty(),
     }
@@ -290,6 +646,202 @@ pub enum InstallError {
 
     #[error(display = "Unable to create a service")]
     CreateService(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to open the service")]
+    OpenService(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to query the service configuration")]
+    QueryServiceConfig(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to register the event log source")]
+    RegisterEventSource(#[error(source)] io::Error),
+
+    #[error(display = "Unable to store the service parameters")]
+    StoreParameters(#[error(source)] io::Error),
+}
+
+/// Whether the system service is installed, as reported by [`service_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceInstallState {
+    NotInstalled,
+    /// `matches_config` is false if the configuration differs from what [`install_service`]
+    /// would set, e.g. after an upgrade that changed it.
+    Installed {
+        matches_config: bool,
+    },
+}
+
+/// The parts of an installed service's configuration that [`install_service`] sets and that
+/// affect how the daemon runs.
+#[derive(Debug, Clone)]
+struct InstalledServiceConfig {
+    start_type: ServiceStartType,
+    executable_path: PathBuf,
+    launch_arguments: Vec<OsString>,
+    dependencies: Vec<ServiceDependency>,
+    sid_type: u32,
+    /// The type and delay of each recovery action.
+    recovery_actions: Vec<(ServiceActionType, Duration)>,
+}
+
+/// Checks whether the system service is installed and configured the way [`install_service`]
+/// would configure it.
+pub fn service_status() -> Result<ServiceInstallState, InstallError> {
+    let service_manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .map_err(InstallError::ConnectServiceManager)?;
+    let service = match service_manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_CONFIG) {
+        Ok(service) => service,
+        Err(error) if is_winapi_error(&error, ERROR_SERVICE_DOES_NOT_EXIST) => {
+            return Ok(ServiceInstallState::NotInstalled);
+        }
+        Err(error) => return Err(InstallError::OpenService(error)),
+    };
+
+    let config = service
+        .query_config()
+        .map_err(InstallError::QueryServiceConfig)?;
+    let failure_actions = service
+        .get_failure_actions()
+        .map_err(InstallError::QueryServiceConfig)?;
+    let sid_type = query_service_sid_type()
+        .map_err(|error| InstallError::QueryServiceConfig(windows_service::Error::Winapi(error)))?;
+
+    // The binary path of the installed service is the full command line, arguments included.
+    let mut command_line = split_command_line(config.executable_path.as_os_str())
+        .map_err(|error| InstallError::QueryServiceConfig(windows_service::Error::Winapi(error)))?
+        .into_iter();
+    let executable_path = command_line.next().map(PathBuf::from).unwrap_or_default();
+    let launch_arguments: Vec<OsString> = command_line.collect();
+
+    let installed = InstalledServiceConfig {
+        start_type: config.start_type,
+        executable_path,
+        launch_arguments,
+        dependencies: config.dependencies,
+        sid_type,
+        recovery_actions: action_types_and_delays(&failure_actions.actions.unwrap_or_default()),
+    };
+
+    Ok(ServiceInstallState::Installed {
+        matches_config: config_matches(&installed, &get_service_info()),
+    })
+}
+
+/// Splits a command line into its arguments the same way the service control manager does.
+fn split_command_line(command_line: &OsStr) -> io::Result<Vec<OsString>> {
+    let command_line: Vec<u16> = command_line
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut argc = 0;
+    let argv = unsafe { CommandLineToArgvW(command_line.as_ptr(), &mut argc) };
+    if argv.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    let arguments = unsafe { slice::from_raw_parts(argv, argc as usize) }
+        .iter()
+        .map(|&argument| {
+            let len = (0..)
+                .take_while(|&i| unsafe { *argument.offset(i) } != 0)
+                .count();
+            OsString::from_wide(unsafe { slice::from_raw_parts(argument, len) })
+        })
+        .collect();
+    unsafe { LocalFree(argv as *mut _) };
+    Ok(arguments)
+}
+
+fn is_winapi_error(error: &windows_service::Error, code: u32) -> bool {
+    match error {
+        windows_service::Error::Winapi(error) => error.raw_os_error() == Some(code as i32),
+        _ => false,
+    }
+}
+
+/// Returns whether `installed` is what [`install_service`] would configure given `expected`. The
+/// order of the dependencies does not matter. Since the recovery delays can be chosen at
+/// registration time, any restart actions with delays that [`get_recovery_delays`] would accept
+/// are considered a match.
+fn config_matches(installed: &InstalledServiceConfig, expected: &ServiceInfo) -> bool {
+    installed.start_type == expected.start_type
+        && paths_equal(&installed.executable_path, &expected.executable_path)
+        && installed.launch_arguments == expected.launch_arguments
+        && installed.dependencies.len() == expected.dependencies.len()
+        && expected
+            .dependencies
+            .iter()
+            .all(|dependency| installed.dependencies.contains(dependency))
+        && installed.sid_type == ServiceSidType::Unrestricted as u32
+        && recovery_actions_valid(&installed.recovery_actions)
+}
+
+/// Paths are compared case-insensitively, like the file system does.
+fn paths_equal(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+fn recovery_actions_valid(actions: &[(ServiceActionType, Duration)]) -> bool {
+    !actions.is_empty()
+        && actions
+            .iter()
+            .all(|(action_type, _)| *action_type == ServiceActionType::Restart)
+        && actions.windows(2).all(|pair| pair[0].1 <= pair[1].1)
+}
+
+fn action_types_and_delays(actions: &[ServiceAction]) -> Vec<(ServiceActionType, Duration)> {
+    actions
+        .iter()
+        .map(|action| (action.action_type, action.delay))
+        .collect()
+}
+
+/// Queries how the service SID is added to the service process token. `windows_service` has no
+/// getter for this.
+fn query_service_sid_type() -> std::io::Result<u32> {
+    struct ScHandle(SC_HANDLE);
+
+    impl Drop for ScHandle {
+        fn drop(&mut self) {
+            unsafe { CloseServiceHandle(self.0) };
+        }
+    }
+
+    fn check_handle(handle: SC_HANDLE) -> std::io::Result<ScHandle> {
+        if handle.is_null() {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(ScHandle(handle))
+        }
+    }
+
+    let service_name: Vec<u16> = SERVICE_NAME
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+    unsafe {
+        let manager = check_handle(OpenSCManagerW(ptr::null(), ptr::null(), SC_MANAGER_CONNECT))?;
+        let service = check_handle(OpenServiceW(
+            manager.0,
+            service_name.as_ptr(),
+            SERVICE_QUERY_CONFIG,
+        ))?;
+
+        let mut sid_info: SERVICE_SID_INFO = std::mem::zeroed();
+        let mut bytes_needed = 0;
+        if QueryServiceConfig2W(
+            service.0,
+            SERVICE_CONFIG_SERVICE_SID_INFO,
+            &mut sid_info as *mut _ as *mut u8,
+            std::mem::size_of::<SERVICE_SID_INFO>() as u32,
+            &mut bytes_needed,
+        ) == 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(sid_info.dwServiceSidType)
+    }
 }
 
 pub fn install_service() -> Result<(), InstallError> {
@@ -302,20 +854,8 @@ pub fn install_service() -> Result<(), InstallError> {
         .or(open_update_service(&service_manager))
         .map_err(InstallError::CreateService)?;
 
-    let recovery_actions = vec![
-        ServiceAction {
-            action_type: ServiceActionType::Restart,
-            delay: Duration::from_secs(3),
-        },
-        ServiceAction {
-            action_type: ServiceActionType::Restart,
-            delay: Duration::from_secs(30),
-        },
-        ServiceAction {
-            action_type: ServiceActionType::Restart,
-            delay: SERVICE_RECOVERY_LAST_RESTART_DELAY,
-        },
-    ];
+    let recovery_delays = get_recovery_delays(cli::get_config().service_recovery_delays.as_deref());
+    let recovery_actions = recovery_actions(&recovery_delays);
 
     let failure_actions = ServiceFailureActions {
         reset_period: ServiceFailureResetPeriod::After(SERVICE_FAILURE_RESET_PERIOD),
@@ -337,9 +877,85 @@ pub fn install_service() -> Result<(), InstallError> {
         .set_config_service_sid_info(ServiceSidType::Unrestricted)
         .map_err(InstallError::CreateService)?;
 
+    let (parameters_key, _) = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .create_subkey(service_parameters_key())
+        .map_err(InstallError::StoreParameters)?;
+    store_restart_after_hibernation(&parameters_key, cli::get_config().restart_after_hibernation)
+        .map_err(InstallError::StoreParameters)?;
+
+    event_log::register_event_source().map_err(InstallError::RegisterEventSource)?;
+
     Ok(())
 }
 
+/// Returns the path of the registry key that holds the options the service was registered with.
+fn service_parameters_key() -> PathBuf {
+    Path::new(SERVICES_KEY)
+        .join(SERVICE_NAME)
+        .join("Parameters")
+}
+
+fn store_restart_after_hibernation(parameters_key: &RegKey, restart: bool) -> io::Result<()> {
+    parameters_key.set_value(RESTART_AFTER_HIBERNATION_VALUE, &u32::from(restart))
+}
+
+/// Returns whether restarts after hibernation are enabled in `parameters_key`. Fails with
+/// `io::ErrorKind::NotFound` if this was never stored.
+fn load_restart_after_hibernation(parameters_key: &RegKey) -> io::Result<bool> {
+    let restart: u32 = parameters_key.get_value(RESTART_AFTER_HIBERNATION_VALUE)?;
+    Ok(restart != 0)
+}
+
+/// Returns whether the service should restart after hibernation. Restarts are disabled by running
+/// with `--disable-hibernation-restart`, or by having registered the service with it.
+fn restart_after_hibernation() -> bool {
+    if !cli::get_config().restart_after_hibernation {
+        return false;
+    }
+    let stored = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey_with_flags(service_parameters_key(), KEY_READ)
+        .and_then(|parameters_key| load_restart_after_hibernation(&parameters_key));
+    match stored {
+        Ok(restart) => restart,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => true,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to read the service parameters")
+            );
+            true
+        }
+    }
+}
+
+/// Returns the configured restart delays, or the default ones if none or invalid delays were
+/// given. The delays must not decrease.
+fn get_recovery_delays(configured_delays: Option<&[Duration]>) -> Vec<Duration> {
+    match configured_delays {
+        Some(delays) if !delays.is_empty() && delays.windows(2).all(|pair| pair[0] <= pair[1]) => {
+            delays.to_vec()
+        }
+        Some(delays) => {
+            log::warn!(
+                "Ignoring service recovery delays {:?}, they must be in increasing order",
+                delays
+            );
+            DEFAULT_SERVICE_RECOVERY_DELAYS.to_vec()
+        }
+        None => DEFAULT_SERVICE_RECOVERY_DELAYS.to_vec(),
+    }
+}
+
+fn recovery_actions(delays: &[Duration]) -> Vec<ServiceAction> {
+    delays
+        .iter()
+        .map(|&delay| ServiceAction {
+            action_type: ServiceActionType::Restart,
+            delay,
+        })
+        .collect()
+}
+
 fn open_update_service(
     service_manager: &ServiceManager,
 ) -> Result<Service, windows_service::Error> {
@@ -348,6 +964,201 @@ fn open_update_service(
     Ok(service)
 }
 
+/// Argument that makes the daemon restart the system service and exit.
+const RESTART_SERVICE_ARG: &str = "--restart-service";
+
+/// Maximum time to wait for the service to stop before starting it again.
+const SERVICE_RESTART_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+const SERVICE_RESTART_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum RestartError {
+    #[error(display = "Unable to connect to service manager")]
+    ConnectServiceManager(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to open the service")]
+    OpenService(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to stop the service")]
+    StopService(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to query the service status")]
+    QueryServiceStatus(#[error(source)] windows_service::Error),
+
+    #[error(display = "Timed out waiting for the service to stop")]
+    StopTimeout,
+
+    #[error(display = "Unable to start the service")]
+    StartService(#[error(source)] windows_service::Error),
+}
+
+/// Stops and starts the system service using the service control manager.
+pub fn restart_service() -> Result<(), RestartError> {
+    let service_manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .map_err(RestartError::ConnectServiceManager)?;
+    let service = service_manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::START,
+        )
+        .map_err(RestartError::OpenService)?;
+
+    restart_service_inner(&service, SERVICE_RESTART_STOP_TIMEOUT)
+}
+
+/// The subset of service operations needed to restart a service.
+trait RestartableService {
+    fn stop(&self) -> windows_service::Result<ServiceStatus>;
+    fn query_status(&self) -> windows_service::Result<ServiceStatus>;
+    fn start(&self) -> windows_service::Result<()>;
+}
+
+impl RestartableService for Service {
+    fn stop(&self) -> windows_service::Result<ServiceStatus> {
+        Service::stop(self)
+    }
+
+    fn query_status(&self) -> windows_service::Result<ServiceStatus> {
+        Service::query_status(self)
+    }
+
+    fn start(&self) -> windows_service::Result<()> {
+        Service::start::<OsString>(self, &[])
+    }
+}
+
+fn restart_service_inner(
+    service: &impl RestartableService,
+    stop_timeout: Duration,
+) -> Result<(), RestartError> {
+    let mut status = service.stop().map_err(RestartError::StopService)?;
+
+    let start = Instant::now();
+    while status.current_state != ServiceState::Stopped {
+        if start.elapsed() >= stop_timeout {
+            return Err(RestartError::StopTimeout);
+        }
+        thread::sleep(SERVICE_RESTART_POLL_INTERVAL);
+        status = service
+            .query_status()
+            .map_err(RestartError::QueryServiceStatus)?;
+    }
+
+    service.start().map_err(RestartError::StartService)
+}
+
+/// Maximum time to wait for the service to stop before deleting it.
+const SERVICE_UNINSTALL_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum UninstallError {
+    #[error(display = "Unable to connect to service manager")]
+    ConnectServiceManager(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to open the service")]
+    OpenService(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to stop the service")]
+    StopService(#[error(source)] windows_service::Error),
+
+    #[error(display = "Unable to query the service status")]
+    QueryServiceStatus(#[error(source)] windows_service::Error),
+
+    #[error(display = "Timed out waiting for the service to stop")]
+    StopTimeout,
+
+    #[error(display = "Unable to delete the service")]
+    DeleteService(#[error(source)] windows_service::Error),
+}
+
+/// Stops and removes the system service. Succeeds if the service is not installed.
+pub fn uninstall_service() -> Result<(), UninstallError> {
+    let service_manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .map_err(UninstallError::ConnectServiceManager)?;
+    let service = service_manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    );
+
+    match check_service_opened(service)? {
+        Some(service) => uninstall_service_inner(service, SERVICE_UNINSTALL_STOP_TIMEOUT),
+        None => Ok(()),
+    }
+}
+
+/// Maps the result of opening the service to `None` if the service is not installed.
+fn check_service_opened<S>(
+    result: windows_service::Result<S>,
+) -> Result<Option<S>, UninstallError> {
+    match result {
+        Ok(service) => Ok(Some(service)),
+        Err(error) if is_winapi_error(&error, ERROR_SERVICE_DOES_NOT_EXIST) => {
+            log::debug!("The service is not installed");
+            Ok(None)
+        }
+        Err(error) => Err(UninstallError::OpenService(error)),
+    }
+}
+
+/// The subset of service operations needed to uninstall a service.
+trait UninstallableService {
+    fn stop(&self) -> windows_service::Result<ServiceStatus>;
+    fn query_status(&self) -> windows_service::Result<ServiceStatus>;
+    fn delete(self) -> windows_service::Result<()>;
+}
+
+impl UninstallableService for Service {
+    fn stop(&self) -> windows_service::Result<ServiceStatus> {
+        Service::stop(self)
+    }
+
+    fn query_status(&self) -> windows_service::Result<ServiceStatus> {
+        Service::query_status(self)
+    }
+
+    fn delete(self) -> windows_service::Result<()> {
+        Service::delete(self)
+    }
+}
+
+fn uninstall_service_inner(
+    service: impl UninstallableService,
+    stop_timeout: Duration,
+) -> Result<(), UninstallError> {
+    let mut status =
+        match service.stop() {
+            Ok(status) => status,
+            Err(error) if is_winapi_error(&error, ERROR_SERVICE_NOT_ACTIVE) => service
+                .query_status()
+                .map_err(UninstallError::QueryServiceStatus)?,
+            Err(error) => return Err(UninstallError::StopService(error)),
+        };
+
+    let start = Instant::now();
+    while status.current_state != ServiceState::Stopped {
+        if start.elapsed() >= stop_timeout {
+            return Err(UninstallError::StopTimeout);
+        }
+        thread::sleep(SERVICE_RESTART_POLL_INTERVAL);
+        status = service
+            .query_status()
+            .map_err(UninstallError::QueryServiceStatus)?;
+    }
+
+    match service.delete() {
+        Err(error) if !is_winapi_error(&error, ERROR_SERVICE_MARKED_FOR_DELETE) => {
+            Err(UninstallError::DeleteService(error))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns how the service should be registered. Options chosen at registration time are stored
+/// under [`service_parameters_key`] rather than passed as arguments.
 fn get_service_info() -> ServiceInfo {
     ServiceInfo {
         name: OsString::from(SERVICE_NAME),
@@ -357,13 +1168,10 @@ fn get_service_info() -> ServiceInfo {
         error_control: ServiceErrorControl::Normal,
         executable_path: env::current_exe().unwrap(),
         launch_arguments: vec![OsString::from("--run-as-service"), OsString::from("-v")],
-        dependencies: vec![
-            // Base Filter Engine
-            ServiceDependency::Service(OsString::from("BFE")),
-            // Network Store Interface Service
-            // This service delivers network notifications (e.g. interface addition/deleting etc).
-            ServiceDependency::Service(OsString::from("NSI")),
-        ],
+        dependencies: SERVICE_DEPENDENCIES
+            .iter()
+            .map(|name| ServiceDependency::Service(OsString::from(name)))
+            .collect(),
         account_name: None, // run as System
         account_password: None,
     }
@@ -372,33 +1180,106 @@ fn get_service_info() -> ServiceInfo {
 /// Used to track events that taken together would mean the machine is heading towards being
 /// hibernated. Typically, the user's session if first terminated. Moments later we should receive a
 /// suspension event corresponding to the hibernation of session 0 (kernel and services).
-#[derive(Default)]
+///
+/// On machines with several interactive sessions, the detector is only armed once the last of them
+/// has been logged off.
 struct HibernationDetector {
+    /// Interactive sessions that have been logged off, and when.
+    session_logoff_times: HashMap<u32, Instant>,
+    /// The time at which the last remaining interactive session was logged off.
     logoff_time: Option<Instant>,
+    /// The maximum time between the last logoff and a suspend for it to be treated as hibernation.
+    suspend_window: Duration,
     should_restart: bool,
+    /// Whether to restart the service when resuming from hibernation.
+    restart_on_resume: bool,
 }
 
 const SECURITY_LOGON_TYPE_INTERACTIVE: u32 = 2;
 
+/// Default maximum time between a logoff and a suspend event for them to be treated as a
+/// hibernation.
+const DEFAULT_HIBERNATION_SUSPEND_WINDOW: Duration = Duration::from_secs(5);
+
+impl Default for HibernationDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_HIBERNATION_SUSPEND_WINDOW)
+    }
+}
+
 impl HibernationDetector {
+    fn new(suspend_window: Duration) -> Self {
+        HibernationDetector {
+            session_logoff_times: HashMap::new(),
+            logoff_time: None,
+            suspend_window,
+            should_restart: false,
+            restart_on_resume: true,
+        }
+    }
+
+    /// Sets whether to restart the service when resuming from hibernation. Hibernation is logged
+    /// either way.
+    fn restart_on_resume(mut self, restart_on_resume: bool) -> Self {
+        self.restart_on_resume = restart_on_resume;
+        self
+    }
+
+    /// Register a session logon. This forgets any earlier logoff of the same session.
+    fn register_logon(&mut self, session_id: u32) {
+        self.session_logoff_times.remove(&session_id);
+        self.logoff_time = None;
+    }
+
+    /// Register a session disconnect. The session stays logged on, so this is never taken as a sign
+    /// of hibernation. Sessions are also disconnected while being logged off, so this leaves any
+    /// earlier logoff in place.
+    fn register_disconnect(&mut self, session_id: u32) {
+        log::debug!("Session {} disconnected", session_id);
+    }
+
     /// Register a session logoff.
     /// The logoff event is discarded unless the session was/is interactive.
     fn register_logoff(&mut self, session_id: u32) {
-        if unsafe { Self::interactive_session(session_id) } {
-            self.logoff_time = Some(Instant::now());
+        let interactive_sessions = unsafe { Self::interactive_sessions() };
+        self.handle_logoff(session_id, &interactive_sessions, Instant::now());
+    }
+
+    fn handle_logoff(
+        &mut self,
+        session_id: u32,
+        interactive_sessions: &HashSet<u32>,
+        logoff_time: Instant,
+    ) {
+        if !interactive_sessions.contains(&session_id) {
+            return;
+        }
+        self.session_logoff_times.insert(session_id, logoff_time);
+
+        let last_session = interactive_sessions
+            .iter()
+            .all(|session| self.session_logoff_times.contains_key(session));
+        if last_session {
+            self.logoff_time = Some(logoff_time);
+        } else {
+            log::debug!(
+                "Session {} logged off, but other interactive sessions remain",
+                session_id
+            );
         }
     }
 
-    unsafe fn interactive_session(session_id: u32) -> bool {
+    /// Returns the ids of all sessions that have an interactive logon session.
+    unsafe fn interactive_sessions() -> HashSet<u32> {
+        let mut sessions = HashSet::new();
         let mut logon_session_count: ULONG = 0;
         let mut logon_session_list: *mut LUID = ptr::null_mut();
         let status = LsaEnumerateLogonSessions(&mut logon_session_count, &mut logon_session_list);
         if status != STATUS_SUCCESS {
             log::warn!("LsaEnumerateLogonSessions() failed, error code: {}", status);
-            return false;
+            return sessions;
         }
         let logons = slice::from_raw_parts(logon_session_list, logon_session_count as usize);
-        let mut interactive = false;
         for logon in logons {
             let mut session_data: *mut SECURITY_LOGON_SESSION_DATA = ptr::null_mut();
             let status = LsaGetLogonSessionData(logon as *const _ as *mut LUID, &mut session_data);
@@ -406,23 +1287,23 @@ impl HibernationDetector {
                 log::warn!("LsaGetLogonSessionData() failed, error code: {}", status);
                 continue;
             }
-            let candidate_correct_session = (*session_data).Session == session_id;
-            let candidate_interactive =
-                (*session_data).LogonType == SECURITY_LOGON_TYPE_INTERACTIVE;
-            LsaFreeReturnBuffer(session_data as *mut c_void as PVOID);
-            if candidate_correct_session {
-                interactive = candidate_interactive;
-                break;
+            if (*session_data).LogonType == SECURITY_LOGON_TYPE_INTERACTIVE {
+                sessions.insert((*session_data).Session);
             }
+            LsaFreeReturnBuffer(session_data as *mut c_void as PVOID);
         }
         LsaFreeReturnBuffer(logon_session_list as *mut c_void as PVOID);
-        interactive
+        sessions
     }
 
     /// Register a machine suspend event.
     fn register_suspend(&mut self) {
-        if let Some(logoff_time) = &self.logoff_time {
-            if logoff_time.elapsed() < Duration::from_secs(5) {
+        self.handle_suspend(Instant::now());
+    }
+
+    fn handle_suspend(&mut self, suspend_time: Instant) {
+        if let Some(logoff_time) = self.logoff_time {
+            if suspend_time.saturating_duration_since(logoff_time) < self.suspend_window {
                 log::info!("Pending hibernation detected");
                 self.should_restart = true;
             }
@@ -430,19 +1311,65 @@ impl HibernationDetector {
     }
 
     /// Register a machine resume event.
-    /// This will restart the service if we are coming back from hibernation.
+    /// This will restart the service if we are coming back from hibernation, unless restarting has
+    /// been disabled.
     fn register_resume(&mut self) {
-        if self.should_restart {
-            self.should_restart = false;
-            log::info!("System is being restored from hibernation. Restarting daemon service");
-            if let Err(err) = Self::restart_daemon() {
-                log::error!("{}", err);
-            }
+        self.handle_resume(Self::restart_daemon);
+    }
+
+    fn handle_resume(&mut self, restart_daemon: impl FnOnce() -> Result<(), String>) {
+        if !self.should_restart {
+            return;
+        }
+        self.should_restart = false;
+        if !self.restart_on_resume {
+            log::info!(
+                "System is being restored from hibernation. Not restarting daemon service since \
+                 that is disabled"
+            );
+            return;
+        }
+        log::info!("System is being restored from hibernation. Restarting daemon service");
+        event_log::report(
+            ServiceEvent::HibernationRestart,
+            "Restarting the service after hibernation",
+        );
+        if let Err(err) = restart_daemon() {
+            log::error!("{}", err);
         }
     }
 
     /// Performs a clean shutdown and restart of the daemon.
+    /// The restart is carried out by a detached instance of the daemon executable, since this
+    /// process exits when the service is stopped. Falls back on `net stop` and `net start` if the
+    /// helper process cannot be started.
     fn restart_daemon() -> Result<(), String> {
+        match Self::start_restart_helper() {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start service restart helper")
+                );
+                Self::restart_daemon_with_net_command()
+            }
+        }
+    }
+
+    fn start_restart_helper() -> Result<(), std::io::Error> {
+        // The helper must not rotate the log file that the service is still writing to.
+        duct::cmd(
+            env::current_exe()?,
+            &[RESTART_SERVICE_ARG, "--disable-log-to-file"],
+        )
+        .stdin_null()
+        .stdout_null()
+        .stderr_null()
+        .start()
+        .map(|_| ())
+    }
+
+    fn restart_daemon_with_net_command() -> Result<(), String> {
         let sysdir = unsafe { Self::get_system_directory() }?;
         let cmd_path = format!("{}cmd.exe", sysdir);
         let commands = vec!["net stop", SERVICE_NAME, "& net start", SERVICE_NAME];
@@ -480,3 +1407,555 @@ impl HibernationDetector {
         Err("Failed to resolve system directory".into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_dependencies() {
+        let dependencies = get_service_info().dependencies;
+        for name in &["BFE", "NSI", "Tcpip"] {
+            assert!(
+                dependencies.contains(&ServiceDependency::Service(OsString::from(name))),
+                "missing dependency on {}",
+                name
+            );
+        }
+        assert_eq!(dependencies.len(), SERVICE_DEPENDENCIES.len());
+    }
+
+    fn expected_config() -> InstalledServiceConfig {
+        let service_info = get_service_info();
+        InstalledServiceConfig {
+            start_type: ServiceStartType::AutoStart,
+            executable_path: service_info.executable_path,
+            launch_arguments: service_info.launch_arguments,
+            dependencies: service_info.dependencies,
+            sid_type: ServiceSidType::Unrestricted as u32,
+            recovery_actions: action_types_and_delays(&recovery_actions(
+                &DEFAULT_SERVICE_RECOVERY_DELAYS,
+            )),
+        }
+    }
+
+    #[test]
+    fn test_config_matches() {
+        let service_info = get_service_info();
+        assert!(config_matches(&expected_config(), &service_info));
+
+        let mut reordered = expected_config();
+        reordered.dependencies.reverse();
+        assert!(config_matches(&reordered, &service_info));
+
+        let mut other_case = expected_config();
+        other_case.executable_path =
+            PathBuf::from(other_case.executable_path.to_string_lossy().to_uppercase());
+        assert!(config_matches(&other_case, &service_info));
+
+        let mut custom_delays = expected_config();
+        custom_delays.recovery_actions =
+            action_types_and_delays(&recovery_actions(&[Duration::from_secs(1); 2]));
+        assert!(config_matches(&custom_delays, &service_info));
+    }
+
+    #[test]
+    fn test_config_mismatches() {
+        let service_info = get_service_info();
+
+        let mut manual_start = expected_config();
+        manual_start.start_type = ServiceStartType::OnDemand;
+        assert!(!config_matches(&manual_start, &service_info));
+
+        let mut other_executable = expected_config();
+        other_executable.executable_path = PathBuf::from(r"C:\other\mullvad-daemon.exe");
+        assert!(!config_matches(&other_executable, &service_info));
+
+        let mut other_arguments = expected_config();
+        other_arguments.launch_arguments.pop();
+        assert!(!config_matches(&other_arguments, &service_info));
+
+        let mut missing_dependency = expected_config();
+        missing_dependency.dependencies.pop();
+        assert!(!config_matches(&missing_dependency, &service_info));
+
+        let mut restricted_sid = expected_config();
+        restricted_sid.sid_type = ServiceSidType::None as u32;
+        assert!(!config_matches(&restricted_sid, &service_info));
+
+        let mut no_recovery = expected_config();
+        no_recovery.recovery_actions.clear();
+        assert!(!config_matches(&no_recovery, &service_info));
+
+        let mut decreasing_delays = expected_config();
+        decreasing_delays.recovery_actions.reverse();
+        assert!(!config_matches(&decreasing_delays, &service_info));
+
+        let mut no_restart = expected_config();
+        no_restart.recovery_actions[0].0 = ServiceActionType::None;
+        assert!(!config_matches(&no_restart, &service_info));
+    }
+
+    #[test]
+    fn test_split_command_line() {
+        let arguments = split_command_line(OsStr::new(
+            r#""C:\Program Files\daemon.exe" --run-as-service -v"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            arguments,
+            vec![
+                OsString::from(r"C:\Program Files\daemon.exe"),
+                OsString::from("--run-as-service"),
+                OsString::from("-v"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hibernation_requires_last_interactive_logoff() {
+        let mut detector = HibernationDetector::default();
+        let sessions: HashSet<u32> = [1, 2].iter().cloned().collect();
+
+        detector.handle_logoff(1, &sessions, Instant::now());
+        detector.register_suspend();
+        assert!(!detector.should_restart);
+
+        detector.handle_logoff(2, &sessions, Instant::now());
+        detector.register_suspend();
+        assert!(detector.should_restart);
+    }
+
+    #[test]
+    fn test_hibernation_suspend_window() {
+        let window = Duration::from_secs(10);
+        let sessions: HashSet<u32> = [1].iter().cloned().collect();
+        let logoff_time = Instant::now();
+
+        let mut detector = HibernationDetector::new(window);
+        detector.handle_logoff(1, &sessions, logoff_time);
+        detector.handle_suspend(logoff_time + window - Duration::from_millis(1));
+        assert!(detector.should_restart);
+
+        let mut detector = HibernationDetector::new(window);
+        detector.handle_logoff(1, &sessions, logoff_time);
+        detector.handle_suspend(logoff_time + window + Duration::from_millis(1));
+        assert!(!detector.should_restart);
+    }
+
+    #[test]
+    fn test_service_exit_codes() {
+        use std::io;
+
+        let io_error = || io::Error::new(io::ErrorKind::Other, "test");
+
+        assert_eq!(
+            ServiceError::CreateDaemon(crate::CreateDaemonError::SettingsDir(
+                mullvad_paths::Error::NoProgramDataDir
+            ))
+            .exit_code(),
+            exit_code::PATH_ERROR
+        );
+        assert_eq!(
+            ServiceError::CreateDaemon(crate::CreateDaemonError::Daemon(
+                mullvad_daemon::Error::OpenCachedTargetState(io_error())
+            ))
+            .exit_code(),
+            exit_code::CACHE_ERROR
+        );
+        assert_eq!(
+            ServiceError::CreateDaemon(crate::CreateDaemonError::Daemon(
+                mullvad_daemon::Error::InitIoEventLoop(io_error())
+            ))
+            .exit_code(),
+            exit_code::DAEMON_ERROR
+        );
+        assert_eq!(
+            ServiceError::RunDaemon(mullvad_daemon::Error::DaemonUnavailable).exit_code(),
+            exit_code::DAEMON_ERROR
+        );
+    }
+
+    #[test]
+    fn test_wait_until_ready_reports_progress() {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let mut pending_reports = 0;
+        let ready = wait_until_ready(&ready_rx, Duration::from_millis(1), || {
+            pending_reports += 1;
+            if pending_reports == 3 {
+                ready_tx.send(()).unwrap();
+            }
+        });
+        assert!(ready);
+        assert_eq!(pending_reports, 3);
+    }
+
+    #[test]
+    fn test_wait_until_ready_aborted() {
+        let (ready_tx, ready_rx) = mpsc::channel::<()>();
+        drop(ready_tx);
+        let mut pending_reports = 0;
+        assert!(!wait_until_ready(
+            &ready_rx,
+            Duration::from_millis(1),
+            || pending_reports += 1
+        ));
+        assert_eq!(pending_reports, 0);
+    }
+
+    #[test]
+    fn test_wait_until_stopped_reports_progress() {
+        let (stopped_tx, stopped_rx) = mpsc::channel();
+        let mut pending_reports = 0;
+        let stopped = wait_until_stopped(
+            &stopped_rx,
+            Duration::from_millis(1),
+            Duration::from_secs(60),
+            || {
+                pending_reports += 1;
+                if pending_reports == 3 {
+                    stopped_tx.send(()).unwrap();
+                }
+            },
+        );
+        assert!(stopped);
+        assert_eq!(pending_reports, 3);
+    }
+
+    #[test]
+    fn test_wait_until_stopped_times_out() {
+        let (_stopped_tx, stopped_rx) = mpsc::channel::<()>();
+        let mut pending_reports = 0;
+        let stopped = wait_until_stopped(
+            &stopped_rx,
+            Duration::from_millis(5),
+            Duration::from_millis(50),
+            || pending_reports += 1,
+        );
+        assert!(!stopped);
+        assert!(pending_reports > 0);
+    }
+
+    #[test]
+    fn test_translate_control() {
+        assert_eq!(
+            translate_control(&ServiceControl::Stop),
+            Some(MonitorEvent::Shutdown)
+        );
+        assert_eq!(
+            translate_control(&ServiceControl::Preshutdown),
+            Some(MonitorEvent::Shutdown)
+        );
+        assert_eq!(
+            translate_control(&ServiceControl::Pause),
+            Some(MonitorEvent::Pause)
+        );
+        assert_eq!(
+            translate_control(&ServiceControl::Continue),
+            Some(MonitorEvent::Continue)
+        );
+        assert_eq!(
+            translate_control(&ServiceControl::PowerEvent(PowerEventParam::Suspend)),
+            Some(MonitorEvent::Suspend)
+        );
+        assert_eq!(
+            translate_control(&ServiceControl::PowerEvent(
+                PowerEventParam::ResumeAutomatic
+            )),
+            Some(MonitorEvent::Resume)
+        );
+        assert_eq!(translate_control(&ServiceControl::Interrogate), None);
+        assert_eq!(translate_control(&ServiceControl::Shutdown), None);
+    }
+
+    #[test]
+    fn test_pause_transition() {
+        assert_eq!(
+            pause_transition(ServiceState::Running, &ServiceControl::Pause),
+            Some(ServiceState::Paused)
+        );
+        assert_eq!(
+            pause_transition(ServiceState::Paused, &ServiceControl::Continue),
+            Some(ServiceState::Running)
+        );
+        assert_eq!(
+            pause_transition(ServiceState::Running, &ServiceControl::Continue),
+            None
+        );
+        assert_eq!(
+            pause_transition(ServiceState::Paused, &ServiceControl::Pause),
+            None
+        );
+        assert_eq!(
+            pause_transition(ServiceState::StopPending, &ServiceControl::Pause),
+            None
+        );
+    }
+
+    #[test]
+    fn test_recovery_actions() {
+        let delays = [
+            Duration::from_secs(1),
+            Duration::from_secs(10),
+            Duration::from_secs(100),
+        ];
+        let actions = recovery_actions(&get_recovery_delays(Some(&delays)));
+        assert_eq!(actions.len(), 3);
+        for (action, delay) in actions.iter().zip(delays.iter()) {
+            assert_eq!(action.action_type, ServiceActionType::Restart);
+            assert_eq!(action.delay, *delay);
+        }
+    }
+
+    #[test]
+    fn test_recovery_delays_fallback() {
+        let unordered = [Duration::from_secs(10), Duration::from_secs(1)];
+        assert_eq!(
+            get_recovery_delays(Some(&unordered)),
+            DEFAULT_SERVICE_RECOVERY_DELAYS.to_vec()
+        );
+        assert_eq!(
+            get_recovery_delays(Some(&[])),
+            DEFAULT_SERVICE_RECOVERY_DELAYS.to_vec()
+        );
+        assert_eq!(
+            get_recovery_delays(None),
+            DEFAULT_SERVICE_RECOVERY_DELAYS.to_vec()
+        );
+    }
+
+    struct MockService {
+        stop_result: fn() -> windows_service::Result<ServiceStatus>,
+        query_state: ServiceState,
+        start_result: fn() -> windows_service::Result<()>,
+    }
+
+    impl RestartableService for MockService {
+        fn stop(&self) -> windows_service::Result<ServiceStatus> {
+            (self.stop_result)()
+        }
+
+        fn query_status(&self) -> windows_service::Result<ServiceStatus> {
+            Ok(mock_status(self.query_state))
+        }
+
+        fn start(&self) -> windows_service::Result<()> {
+            (self.start_result)()
+        }
+    }
+
+    fn mock_status(current_state: ServiceState) -> ServiceStatus {
+        ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state,
+            controls_accepted: ServiceControlAccept::empty(),
+            exit_code: ServiceExitCode::default(),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
+
+    fn mock_error() -> windows_service::Error {
+        windows_service::Error::Winapi(std::io::Error::from_raw_os_error(5))
+    }
+
+    #[test]
+    fn test_restart_service_error_mapping() {
+        let service = MockService {
+            stop_result: || Err(mock_error()),
+            query_state: ServiceState::Stopped,
+            start_result: || Ok(()),
+        };
+        assert!(matches!(
+            restart_service_inner(&service, Duration::from_secs(1)),
+            Err(RestartError::StopService(_))
+        ));
+
+        let service = MockService {
+            stop_result: || Ok(mock_status(ServiceState::StopPending)),
+            query_state: ServiceState::StopPending,
+            start_result: || Ok(()),
+        };
+        assert!(matches!(
+            restart_service_inner(&service, Duration::from_millis(0)),
+            Err(RestartError::StopTimeout)
+        ));
+
+        let service = MockService {
+            stop_result: || Ok(mock_status(ServiceState::StopPending)),
+            query_state: ServiceState::Stopped,
+            start_result: || Err(mock_error()),
+        };
+        assert!(matches!(
+            restart_service_inner(&service, Duration::from_secs(1)),
+            Err(RestartError::StartService(_))
+        ));
+
+        let service = MockService {
+            stop_result: || Ok(mock_status(ServiceState::Stopped)),
+            query_state: ServiceState::Stopped,
+            start_result: || Ok(()),
+        };
+        assert!(restart_service_inner(&service, Duration::from_secs(1)).is_ok());
+    }
+
+    struct MockUninstallService {
+        stop_result: fn() -> windows_service::Result<ServiceStatus>,
+        query_state: ServiceState,
+        delete_result: fn() -> windows_service::Result<()>,
+    }
+
+    impl UninstallableService for MockUninstallService {
+        fn stop(&self) -> windows_service::Result<ServiceStatus> {
+            (self.stop_result)()
+        }
+
+        fn query_status(&self) -> windows_service::Result<ServiceStatus> {
+            Ok(mock_status(self.query_state))
+        }
+
+        fn delete(self) -> windows_service::Result<()> {
+            (self.delete_result)()
+        }
+    }
+
+    fn winapi_error(code: u32) -> windows_service::Error {
+        windows_service::Error::Winapi(std::io::Error::from_raw_os_error(code as i32))
+    }
+
+    #[test]
+    fn test_uninstall_not_installed() {
+        let result = check_service_opened::<()>(Err(winapi_error(ERROR_SERVICE_DOES_NOT_EXIST)));
+        assert!(matches!(result, Ok(None)));
+
+        let result = check_service_opened::<()>(Err(mock_error()));
+        assert!(matches!(result, Err(UninstallError::OpenService(_))));
+
+        assert!(matches!(check_service_opened(Ok(())), Ok(Some(()))));
+    }
+
+    #[test]
+    fn test_uninstall_service_error_mapping() {
+        let service = MockUninstallService {
+            stop_result: || Err(winapi_error(ERROR_SERVICE_NOT_ACTIVE)),
+            query_state: ServiceState::Stopped,
+            delete_result: || Ok(()),
+        };
+        assert!(uninstall_service_inner(service, Duration::from_secs(1)).is_ok());
+
+        let service = MockUninstallService {
+            stop_result: || Err(mock_error()),
+            query_state: ServiceState::Stopped,
+            delete_result: || Ok(()),
+        };
+        assert!(matches!(
+            uninstall_service_inner(service, Duration::from_secs(1)),
+            Err(UninstallError::StopService(_))
+        ));
+
+        let service = MockUninstallService {
+            stop_result: || Ok(mock_status(ServiceState::StopPending)),
+            query_state: ServiceState::StopPending,
+            delete_result: || Ok(()),
+        };
+        assert!(matches!(
+            uninstall_service_inner(service, Duration::from_millis(0)),
+            Err(UninstallError::StopTimeout)
+        ));
+
+        let service = MockUninstallService {
+            stop_result: || Ok(mock_status(ServiceState::Stopped)),
+            query_state: ServiceState::Stopped,
+            delete_result: || Err(mock_error()),
+        };
+        assert!(matches!(
+            uninstall_service_inner(service, Duration::from_secs(1)),
+            Err(UninstallError::DeleteService(_))
+        ));
+
+        let service = MockUninstallService {
+            stop_result: || Ok(mock_status(ServiceState::Stopped)),
+            query_state: ServiceState::Stopped,
+            delete_result: || Err(winapi_error(ERROR_SERVICE_MARKED_FOR_DELETE)),
+        };
+        assert!(uninstall_service_inner(service, Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_hibernation_restart_disabled() {
+        let sessions: HashSet<u32> = [1].iter().cloned().collect();
+        let mut detector = HibernationDetector::default().restart_on_resume(false);
+        detector.handle_logoff(1, &sessions, Instant::now());
+        detector.register_suspend();
+        assert!(detector.should_restart);
+
+        let mut restarted = false;
+        detector.handle_resume(|| {
+            restarted = true;
+            Ok(())
+        });
+        assert!(!restarted);
+        assert!(!detector.should_restart);
+    }
+
+    #[test]
+    fn test_restart_after_hibernation_parameter() {
+        let key_path = Path::new(r"Software\MullvadVPN-tests").join("service-parameters");
+        let hkcu = RegKey::predef(winreg::enums::HKEY_CURRENT_USER);
+        let (parameters_key, _) = hkcu.create_subkey(&key_path).unwrap();
+
+        let missing = load_restart_after_hibernation(&parameters_key);
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        store_restart_after_hibernation(&parameters_key, false).unwrap();
+        assert!(!load_restart_after_hibernation(&parameters_key).unwrap());
+        store_restart_after_hibernation(&parameters_key, true).unwrap();
+        assert!(load_restart_after_hibernation(&parameters_key).unwrap());
+
+        hkcu.delete_subkey_all(&key_path).unwrap();
+
+        assert_eq!(
+            get_service_info().launch_arguments,
+            [OsString::from("--run-as-service"), OsString::from("-v")]
+        );
+    }
+
+    #[test]
+    fn test_hibernation_ignores_disconnect() {
+        assert_eq!(
+            translate_session_change(SessionChangeReason::ConsoleDisconnect, 1),
+            Some(MonitorEvent::Disconnect(1))
+        );
+        assert_eq!(
+            translate_session_change(SessionChangeReason::RemoteDisconnect, 1),
+            Some(MonitorEvent::Disconnect(1))
+        );
+        assert_eq!(
+            translate_session_change(SessionChangeReason::SessionLogoff, 1),
+            Some(MonitorEvent::Logoff(1))
+        );
+
+        let sessions: HashSet<u32> = [1].iter().cloned().collect();
+        let mut detector = HibernationDetector::default();
+        detector.register_disconnect(1);
+        detector.register_suspend();
+        assert!(!detector.should_restart);
+
+        // A session is disconnected as it is logged off, which must not disarm the detector.
+        detector.handle_logoff(1, &sessions, Instant::now());
+        detector.register_disconnect(1);
+        detector.register_suspend();
+        assert!(detector.should_restart);
+    }
+
+    #[test]
+    fn test_hibernation_ignores_non_interactive_logoff() {
+        let mut detector = HibernationDetector::default();
+        let sessions: HashSet<u32> = [1].iter().cloned().collect();
+
+        detector.handle_logoff(3, &sessions, Instant::now());
+        detector.register_suspend();
+        assert!(!detector.should_restart);
+    }
+}
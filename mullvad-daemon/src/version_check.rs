@@ -2,9 +2,14 @@ use crate::{
     version::{is_beta_version, PRODUCT_VERSION},
     DaemonEventSender,
 };
-use futures::{channel::mpsc, stream::FusedStream, FutureExt, SinkExt, StreamExt, TryFutureExt};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+    stream::{self, FusedStream},
+    FutureExt, SinkExt, StreamExt, TryFutureExt,
+};
 use mullvad_rpc::{rest::MullvadRestHandle, AppVersionProxy};
-use mullvad_types::version::AppVersionInfo;
+use mullvad_types::version::{AppVersionInfo, VersionCheckStatus};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -13,6 +18,7 @@ use std::{
     future::Future,
     io,
     path::{Path, PathBuf},
+    sync::Arc,
     time::{Duration, Instant},
 };
 use talpid_core::mpsc::Sender;
@@ -22,8 +28,8 @@ use tokio02::fs::File;
 const VERSION_INFO_FILENAME: &str = "version-info.json";
 
 lazy_static::lazy_static! {
-    static ref STABLE_REGEX: Regex = Regex::new(r"^(\d{4})\.(\d+)$").unwrap();
-    static ref BETA_REGEX: Regex = Regex::new(r"^(\d{4})\.(\d+)-beta(\d+)$").unwrap();
+    static ref STABLE_REGEX: Regex = Regex::new(r"^[vV]?(\d{4})\.(\d+)$").unwrap();
+    static ref BETA_REGEX: Regex = Regex::new(r"^[vV]?(\d{4})\.(\d+)-beta(\d+)$").unwrap();
     static ref APP_VERSION: Option<AppVersion> = AppVersion::from_str(PRODUCT_VERSION);
     static ref IS_DEV_BUILD: bool = APP_VERSION.is_some();
 }
@@ -38,6 +44,10 @@ const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 5);
 const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
 /// Wait this long until next try if an update failed
 const UPDATE_INTERVAL_ERROR: Duration = Duration::from_secs(60 * 60 * 6);
+/// Number of times each API host is tried in one check before moving on to the next host.
+const ATTEMPTS_PER_HOST: usize = 2;
+/// Wait this long between attempts against the same API host.
+const HOST_RETRY_DELAY: Duration = Duration::from_secs(10);
 
 #[cfg(target_os = "linux")]
 const PLATFORM: &str = "linux";
@@ -85,72 +95,159 @@ pub enum Error {
 }
 
 
+/// Source of the current time and of delays. Lets tests simulate the passage of time.
+pub(crate) trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn delay_for(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The real clock, backed by tokio timers.
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay_for(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio02::time::delay_for(duration).boxed()
+    }
+}
+
 pub(crate) struct VersionUpdater {
-    version_proxy: AppVersionProxy,
+    /// Proxies for the primary API host followed by the fallback hosts, in the order to try them.
+    version_proxies: Vec<AppVersionProxy>,
     cache_path: PathBuf,
     update_sender: DaemonEventSender<AppVersionInfo>,
     last_app_version_info: AppVersionInfo,
     next_update_time: Instant,
     show_beta_releases: bool,
-    rx: Option<mpsc::Receiver<bool>>,
+    /// Whether the API should never be contacted, such as when updates are managed by an admin.
+    disabled: bool,
+    rx: Option<mpsc::Receiver<UpdaterCommand>>,
+    clock: Arc<dyn Clock>,
+    status: Arc<parking_lot::Mutex<VersionCheckStatus>>,
+}
+
+/// Commands sent to a running `VersionUpdater` through its handle.
+#[derive(Debug)]
+enum UpdaterCommand {
+    SetShowBetaReleases(bool),
+    /// Stops the updater once it is done writing the cache, and signals the sender when it has.
+    Stop(oneshot::Sender<()>),
 }
 
 #[derive(Clone)]
 pub(crate) struct VersionUpdaterHandle {
-    tx: mpsc::Sender<bool>,
+    tx: mpsc::Sender<UpdaterCommand>,
+    status: Arc<parking_lot::Mutex<VersionCheckStatus>>,
 }
 
 impl VersionUpdaterHandle {
     pub async fn set_show_beta_releases(&mut self, show_beta_releases: bool) {
-        if self.tx.send(show_beta_releases).await.is_err() {
+        let command = UpdaterCommand::SetShowBetaReleases(show_beta_releases);
+        if self.tx.send(command).await.is_err() {
             log::error!("Version updater already down, can't send new `show_beta_releases` state");
         }
     }
+
+    /// Stops the version updater. Returns once any cache write that is in progress has finished,
+    /// so that freshly fetched version info isn't lost.
+    pub async fn stop(&mut self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(UpdaterCommand::Stop(done_tx)).await.is_err() {
+            log::debug!("Version updater already down");
+            return;
+        }
+        if done_rx.await.is_err() {
+            log::error!("Version updater stopped without acknowledging the stop command");
+        }
+    }
+
+    /// Stops the version updater like `stop`, blocking on `runtime` for at most `timeout`. Returns
+    /// `false` if the updater didn't stop in time. Must not be called from within a runtime.
+    pub fn stop_blocking(
+        &self,
+        runtime: &mut tokio02::runtime::Runtime,
+        timeout: Duration,
+    ) -> bool {
+        let mut handle = self.clone();
+        // The timeout needs the runtime's timer, so it must be created inside the runtime.
+        runtime
+            .block_on(async move { tokio02::time::timeout(timeout, handle.stop()).await })
+            .is_ok()
+    }
+
+    /// Returns the outcome of the most recent version check.
+    pub fn status(&self) -> VersionCheckStatus {
+        self.status.lock().clone()
+    }
 }
 
 impl VersionUpdater {
+    /// Creates a version updater that checks for updates using `rpc_handle`, and using each of
+    /// `fallback_handles` in turn if that fails. If `disabled` is set, the API is never contacted
+    /// and `last_app_version_info` is reported as is.
     pub fn new(
-        mut rpc_handle: MullvadRestHandle,
+        rpc_handle: MullvadRestHandle,
+        fallback_handles: Vec<MullvadRestHandle>,
         cache_dir: PathBuf,
         update_sender: DaemonEventSender<AppVersionInfo>,
         last_app_version_info: AppVersionInfo,
         show_beta_releases: bool,
+        disabled: bool,
     ) -> (Self, VersionUpdaterHandle) {
-        rpc_handle.factory.timeout = DOWNLOAD_TIMEOUT;
-        let version_proxy = AppVersionProxy::new(rpc_handle);
+        let version_proxies = std::iter::once(rpc_handle)
+            .chain(fallback_handles)
+            .map(|mut handle| {
+                handle.factory.timeout = DOWNLOAD_TIMEOUT;
+                AppVersionProxy::new(handle)
+            })
+            .collect();
         let cache_path = cache_dir.join(VERSION_INFO_FILENAME);
         let (tx, rx) = mpsc::channel(1);
+        let clock = Arc::new(SystemClock);
+        let status = Arc::new(parking_lot::Mutex::new(VersionCheckStatus::default()));
 
         (
             Self {
-                version_proxy,
+                version_proxies,
                 cache_path,
                 update_sender,
                 last_app_version_info,
-                next_update_time: Instant::now(),
+                next_update_time: clock.now(),
                 show_beta_releases,
+                disabled,
                 rx: Some(rx),
+                clock,
+                status: status.clone(),
             },
-            VersionUpdaterHandle { tx },
+            VersionUpdaterHandle { tx, status },
         )
     }
 
-    fn create_update_future(
+    /// Returns a function that downloads the latest version info once, trying each API host.
+    fn version_check_factory(
         &self,
-    ) -> impl Future<Output = Result<mullvad_rpc::AppVersionResponse, Error>> + Send + 'static {
-        let version_proxy = self.version_proxy.clone();
-        let download_future_factory = move || {
-            let response = version_proxy.version_check(PRODUCT_VERSION.to_owned(), PLATFORM);
-            response.map_err(Error::Download)
-        };
-
-        let should_retry = |result: &Result<_, _>| -> bool { result.is_err() };
-
-        Box::pin(talpid_core::future_retry::retry_future_with_backoff(
-            download_future_factory,
-            should_retry,
-            std::iter::repeat(UPDATE_INTERVAL_ERROR),
-        ))
+    ) -> impl Fn() -> BoxFuture<'static, Result<mullvad_rpc::AppVersionResponse, Error>>
+           + Clone
+           + Send
+           + 'static {
+        let version_proxies = self.version_proxies.clone();
+        let clock = self.clock.clone();
+        move || {
+            let version_proxies = version_proxies.clone();
+            let clock = clock.clone();
+            async move {
+                check_hosts(&*clock, version_proxies.len(), HOST_RETRY_DELAY, |index| {
+                    version_proxies[index]
+                        .version_check(PRODUCT_VERSION.to_owned(), PLATFORM)
+                        .map_err(Error::Download)
+                })
+                .await
+            }
+            .boxed()
+        }
     }
 
     async fn write_cache(&self) -> Result<(), Error> {
@@ -175,18 +272,30 @@ impl VersionUpdater {
         &mut self,
         response: mullvad_rpc::AppVersionResponse,
     ) -> AppVersionInfo {
-        let suggested_upgrade = APP_VERSION.and_then(|current_version| {
-            Self::suggested_upgrade(
-                &current_version,
-                &response,
-                self.show_beta_releases || is_beta_version(),
-            )
+        Self::version_info(
+            APP_VERSION.as_ref(),
+            response,
+            self.show_beta_releases || is_beta_version(),
+        )
+    }
+
+    /// Builds the version info to report for `response`. A version that the API left out or sent
+    /// as an empty string is reported as missing.
+    fn version_info(
+        current_version: Option<&AppVersion>,
+        response: mullvad_rpc::AppVersionResponse,
+        show_beta: bool,
+    ) -> AppVersionInfo {
+        let suggested_upgrade = current_version.and_then(|current_version| {
+            Self::suggested_upgrade(current_version, &response, show_beta)
         });
+        let non_empty =
+            |version: String| Some(version).filter(|version| !version.trim().is_empty());
 
         AppVersionInfo {
             supported: response.supported,
-            latest_stable: response.latest_stable.unwrap_or_else(|| "".to_owned()),
-            latest_beta: response.latest_beta,
+            latest_stable: response.latest_stable.and_then(non_empty),
+            latest_beta: non_empty(response.latest_beta),
             suggested_upgrade,
         }
     }
@@ -216,26 +325,77 @@ impl VersionUpdater {
         }
     }
 
+    /// Sends the last known version info to the daemon, so that it has something to show while
+    /// the updater is disabled. Returns `false` if the daemon can't be reached.
+    fn report_cached_version_info(&self) -> bool {
+        self.update_sender
+            .send(self.last_app_version_info.clone())
+            .is_ok()
+    }
+
     pub async fn run(mut self) {
-        let mut rx = self.rx.take().unwrap().fuse();
-        let next_delay = || tokio02::time::delay_for(UPDATE_CHECK_INTERVAL).fuse();
-        let mut check_delay = next_delay();
-        let mut version_check = futures::future::Fuse::terminated();
+        let rx = self.rx.take().unwrap().fuse();
 
         // If this is a dev build ,there's no need to pester the API for version checks.
         if *IS_DEV_BUILD {
-            while let Some(_) = rx.next().await {}
+            let mut rx = rx;
+            while let Some(command) = rx.next().await {
+                if let UpdaterCommand::Stop(done_tx) = command {
+                    let _ = done_tx.send(());
+                    return;
+                }
+            }
+            return;
+        }
+
+        let version_check = self.version_check_factory();
+        self.run_checks(rx, version_check).await
+    }
+
+    /// Wakes up every `UPDATE_CHECK_INTERVAL` and runs `version_check` if `UPDATE_INTERVAL` has
+    /// passed since the last check. Checks that fail for transient reasons are retried every
+    /// `UPDATE_INTERVAL_ERROR`. No checks are run while the updater is disabled.
+    async fn run_checks<F>(
+        &mut self,
+        mut rx: stream::Fuse<mpsc::Receiver<UpdaterCommand>>,
+        version_check: F,
+    ) where
+        F: Fn() -> BoxFuture<'static, Result<mullvad_rpc::AppVersionResponse, Error>>
+            + Clone
+            + Send
+            + 'static,
+    {
+        let clock = self.clock.clone();
+        let status = self.status.clone();
+        let version_check = move || {
+            let status = status.clone();
+            version_check()
+                .inspect(move |result| record_check(&mut status.lock(), result))
+                .boxed()
+        };
+        let next_delay = || clock.delay_for(UPDATE_CHECK_INTERVAL).fuse();
+        let mut check_delay = next_delay();
+        let mut pending_check = futures::future::Fuse::terminated();
+
+        if self.disabled && !self.report_cached_version_info() {
             return;
         }
 
         loop {
             futures::select! {
-                show_beta_releases = rx.next() => {
-                    match show_beta_releases {
-                        Some(show_beta_releases ) => {
+                command = rx.next() => {
+                    match command {
+                        Some(UpdaterCommand::SetShowBetaReleases(show_beta_releases)) => {
                             self.show_beta_releases = show_beta_releases;
                         },
-                        // time to shut down
+                        // Cache writes are awaited before the next command is received, so by now
+                        // any fetched version info has been saved.
+                        Some(UpdaterCommand::Stop(done_tx)) => {
+                            let _ = done_tx.send(());
+                            return;
+                        },
+                        // Time to shut down. Returning drops `pending_check`, which cancels any
+                        // request that is still in flight instead of waiting for it to time out.
                         None => {
                             return;
                         },
@@ -247,20 +407,23 @@ impl VersionUpdater {
                         return;
                     }
 
-                    if Instant::now() > self.next_update_time {
-                        let download_future = self.create_update_future().fuse();
-                        version_check = download_future;
+                    if self.disabled {
+                        check_delay = next_delay();
+                    } else if clock.now() > self.next_update_time {
+                        pending_check = check_with_retries(clock.clone(), version_check.clone())
+                            .boxed()
+                            .fuse();
                     } else {
                         check_delay = next_delay();
                     }
 
                 },
 
-                response = version_check => {
+                response = pending_check => {
                     if rx.is_terminated() || self.update_sender.is_closed() {
                         return;
                     }
-                    self.next_update_time = Instant::now() + UPDATE_INTERVAL;
+                    self.next_update_time = clock.now() + UPDATE_INTERVAL;
 
                     match response {
                         Ok(version_info_response) => {
@@ -288,6 +451,33 @@ impl VersionUpdater {
     }
 }
 
+/// Updates `status` with the outcome of a single version check.
+fn record_check<T>(status: &mut VersionCheckStatus, result: &Result<T, Error>) {
+    status.last_check = Some(chrono::Utc::now());
+    status.last_error = result.as_ref().err().map(|error| error.display_chain());
+}
+
+/// Runs `version_check` until it succeeds or fails for a reason that retrying won't fix, waiting
+/// `UPDATE_INTERVAL_ERROR` between attempts.
+async fn check_with_retries<T, F, O>(clock: Arc<dyn Clock>, version_check: F) -> Result<T, Error>
+where
+    F: Fn() -> O,
+    O: Future<Output = Result<T, Error>>,
+{
+    loop {
+        match version_check().await {
+            Err(error) if is_retryable_error(&error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Version check failed, retrying later")
+                );
+                clock.delay_for(UPDATE_INTERVAL_ERROR).await;
+            }
+            result => return result,
+        }
+    }
+}
+
 fn try_load_cache(cache_dir: &Path) -> Result<AppVersionInfo, Error> {
     let path = cache_dir.join(VERSION_INFO_FILENAME);
     log::debug!("Loading version check cache from {}", path.display());
@@ -313,8 +503,8 @@ pub fn load_cache(cache_dir: &Path) -> AppVersionInfo {
             // If we don't have a cache, start out with sane defaults.
             AppVersionInfo {
                 supported: *IS_DEV_BUILD,
-                latest_stable: PRODUCT_VERSION.to_owned(),
-                latest_beta: PRODUCT_VERSION.to_owned(),
+                latest_stable: Some(PRODUCT_VERSION.to_owned()),
+                latest_beta: Some(PRODUCT_VERSION.to_owned()),
                 suggested_upgrade: None,
             }
         }
@@ -328,7 +518,9 @@ enum AppVersion {
 }
 
 impl AppVersion {
+    /// Parses a stable or beta version. Surrounding whitespace and a leading `v` are ignored.
     fn from_str(version: &str) -> Option<Self> {
+        let version = version.trim();
         let get_int = |cap: &regex::Captures<'_>, idx| cap.get(idx)?.as_str().parse().ok();
 
         if let Some(caps) = STABLE_REGEX.captures(version) {
@@ -390,9 +582,83 @@ impl ToString for AppVersion {
     }
 }
 
+/// Runs `check` against each of the `host_count` API hosts in turn until one succeeds. Each host
+/// is tried `ATTEMPTS_PER_HOST` times, `retry_delay` apart. Errors that retrying won't fix are
+/// returned immediately. If every host fails, the last error is returned.
+async fn check_hosts<T, F, O>(
+    clock: &dyn Clock,
+    host_count: usize,
+    retry_delay: Duration,
+    mut check: F,
+) -> Result<T, Error>
+where
+    F: FnMut(usize) -> O,
+    O: Future<Output = Result<T, Error>>,
+{
+    let mut last_error = None;
+    for host in 0..host_count {
+        for attempt in 0..ATTEMPTS_PER_HOST {
+            if attempt > 0 {
+                clock.delay_for(retry_delay).await;
+            }
+            match check(host).await {
+                Ok(value) => return Ok(value),
+                Err(error) if is_retryable_error(&error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Version check against API host {} failed",
+                            host
+                        ))
+                    );
+                    last_error = Some(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+    Err(last_error.expect("No API hosts to check the version against"))
+}
+
+fn is_retryable_error(error: &Error) -> bool {
+    match error {
+        Error::Download(error) => is_retryable(error),
+        _ => true,
+    }
+}
+
+/// Returns whether a failed version check request may succeed if it is retried. Network errors,
+/// timeouts and server errors are transient, but a request that the API rejected will be rejected
+/// again.
+fn is_retryable(error: &mullvad_rpc::rest::Error) -> bool {
+    use mullvad_rpc::rest::{Error as RestError, StatusCode};
+
+    match error {
+        RestError::ApiError(status, _) => {
+            status.is_server_error()
+                || *status == StatusCode::REQUEST_TIMEOUT
+                || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        RestError::InvalidHeaderError(_) | RestError::HttpError(_) | RestError::UriError(_) => {
+            false
+        }
+        // The response may have been mangled by a captive portal or a proxy.
+        RestError::DeserializeError(_) => true,
+        RestError::Aborted(_)
+        | RestError::HyperError(_)
+        | RestError::TimeoutError(_)
+        | RestError::SendError
+        | RestError::ReceiveError => true,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::{
+        mem,
+        sync::atomic::{self, AtomicBool},
+    };
 
     #[test]
     fn test_version_regex() {
@@ -404,6 +670,11 @@ mod test {
         assert!(!BETA_REGEX.is_match("2020.5-beta1-dev-f16be4"));
         assert!(!BETA_REGEX.is_match("2020.5-dev-f16be4"));
         assert!(!BETA_REGEX.is_match("2020.4"));
+        assert!(STABLE_REGEX.is_match("v2020.4"));
+        assert!(STABLE_REGEX.is_match("V2020.4"));
+        assert!(BETA_REGEX.is_match("v2020.4-beta3"));
+        assert!(!STABLE_REGEX.is_match("vv2020.4"));
+        assert!(!BETA_REGEX.is_match("v2020.5-beta1-dev-f16be4"));
     }
 
     #[test]
@@ -414,6 +685,16 @@ mod test {
             ("2020.15-beta1-dev-f16be4", None),
             ("2020.15-dev-f16be4", None),
             ("", None),
+            ("v2020.4", Some(AppVersion::Stable(2020, 4))),
+            ("V2020.4-beta3", Some(AppVersion::Beta(2020, 4, 3))),
+            ("2020.4\n", Some(AppVersion::Stable(2020, 4))),
+            (" v2020.4-beta3 \r\n", Some(AppVersion::Beta(2020, 4, 3))),
+            ("v2020.15-dev-f16be4", None),
+            ("v2020.15-beta1-dev-f16be4 ", None),
+            ("version 2020.4", None),
+            ("v 2020.4", None),
+            ("v", None),
+            ("   ", None),
         ];
 
         for (input, expected_output) in tests {
@@ -421,6 +702,18 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_version_to_string_is_canonical() {
+        for (input, expected_output) in
+            &[("v2020.4", "2020.4"), (" V2020.4-beta3\n", "2020.4-beta3")]
+        {
+            assert_eq!(
+                AppVersion::from_str(input).unwrap().to_string(),
+                *expected_output
+            );
+        }
+    }
+
     #[test]
     fn test_version_upgrade_suggestions() {
         let app_version_info = mullvad_rpc::AppVersionResponse {
@@ -487,4 +780,555 @@ mod test {
             None
         );
     }
+
+    #[test]
+    fn test_missing_stable_version() {
+        let response = || mullvad_rpc::AppVersionResponse {
+            supported: true,
+            latest: "2020.5-beta3".to_owned(),
+            latest_stable: None,
+            latest_beta: "2020.5-beta3".to_owned(),
+        };
+        let current_stable = AppVersion::from_str("2020.4").unwrap();
+
+        let version_info = VersionUpdater::version_info(Some(&current_stable), response(), false);
+        assert_eq!(version_info.latest_stable, None);
+        assert_eq!(version_info.latest_beta, Some("2020.5-beta3".to_owned()));
+        assert_eq!(version_info.suggested_upgrade, None);
+
+        let version_info = VersionUpdater::version_info(Some(&current_stable), response(), true);
+        assert_eq!(
+            version_info.suggested_upgrade,
+            Some("2020.5-beta3".to_owned())
+        );
+
+        let empty_stable = mullvad_rpc::AppVersionResponse {
+            latest_stable: Some("".to_owned()),
+            ..response()
+        };
+        let version_info = VersionUpdater::version_info(Some(&current_stable), empty_stable, false);
+        assert_eq!(version_info.latest_stable, None);
+    }
+
+    #[test]
+    fn test_missing_beta_version() {
+        let response = mullvad_rpc::AppVersionResponse {
+            supported: true,
+            latest: "2020.5".to_owned(),
+            latest_stable: Some("2020.5".to_owned()),
+            latest_beta: "".to_owned(),
+        };
+        let current_beta = AppVersion::from_str("2020.5-beta3").unwrap();
+
+        let version_info = VersionUpdater::version_info(Some(&current_beta), response, true);
+        assert_eq!(version_info.latest_stable, Some("2020.5".to_owned()));
+        assert_eq!(version_info.latest_beta, None);
+        assert_eq!(version_info.suggested_upgrade, Some("2020.5".to_owned()));
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        use mullvad_rpc::rest::{Error as RestError, StatusCode};
+
+        let api_error = |status| RestError::ApiError(status, String::new());
+
+        assert!(is_retryable(&api_error(StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(is_retryable(&api_error(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_retryable(&api_error(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(is_retryable(&RestError::SendError));
+        assert!(is_retryable(&RestError::ReceiveError));
+        assert!(is_retryable(&RestError::Aborted(futures::future::Aborted)));
+        assert!(is_retryable(&RestError::DeserializeError(
+            serde_json::from_str::<u32>("<html>").unwrap_err()
+        )));
+    }
+
+    #[test]
+    fn test_permanent_errors() {
+        use mullvad_rpc::rest::{Error as RestError, StatusCode};
+
+        let api_error = |status| RestError::ApiError(status, String::new());
+
+        assert!(!is_retryable(&api_error(StatusCode::BAD_REQUEST)));
+        assert!(!is_retryable(&api_error(StatusCode::NOT_FOUND)));
+        assert!(!is_retryable(&api_error(StatusCode::FORBIDDEN)));
+        assert!(!is_retryable(&api_error(StatusCode::UNAUTHORIZED)));
+    }
+
+    fn check_results(results: Vec<Vec<Result<u32, Error>>>) -> (Result<u32, Error>, Vec<usize>) {
+        let results = std::cell::RefCell::new(results);
+        let attempts = std::cell::RefCell::new(vec![]);
+        let mut runtime = tokio02::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(check_hosts(
+            &SystemClock,
+            results.borrow().len(),
+            Duration::from_millis(1),
+            |host| {
+                attempts.borrow_mut().push(host);
+                let result = results.borrow_mut()[host].remove(0);
+                async move { result }
+            },
+        ));
+        (result, attempts.into_inner())
+    }
+
+    fn unavailable() -> Result<u32, Error> {
+        Err(Error::Download(mullvad_rpc::rest::Error::ApiError(
+            mullvad_rpc::rest::StatusCode::SERVICE_UNAVAILABLE,
+            String::new(),
+        )))
+    }
+
+    #[test]
+    fn test_fallback_host_used() {
+        let (result, attempts) =
+            check_results(vec![vec![unavailable(), unavailable()], vec![Ok(1)]]);
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(attempts, vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn test_primary_host_preferred() {
+        let (result, attempts) = check_results(vec![vec![unavailable(), Ok(0)], vec![Ok(1)]]);
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(attempts, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_permanent_error_skips_fallback() {
+        let rejected = Err(Error::Download(mullvad_rpc::rest::Error::ApiError(
+            mullvad_rpc::rest::StatusCode::BAD_REQUEST,
+            String::new(),
+        )));
+        let (result, attempts) = check_results(vec![vec![rejected], vec![Ok(1)]]);
+        assert!(matches!(result, Err(Error::Download(_))));
+        assert_eq!(attempts, vec![0]);
+    }
+
+    #[test]
+    fn test_all_hosts_failing() {
+        let (result, attempts) = check_results(vec![
+            vec![unavailable(), unavailable()],
+            vec![unavailable(), unavailable()],
+        ]);
+        assert!(matches!(result, Err(Error::Download(_))));
+        assert_eq!(attempts, vec![0, 0, 1, 1]);
+    }
+
+    #[derive(Clone)]
+    struct FakeClock(Arc<std::sync::Mutex<FakeClockState>>);
+
+    struct FakeClockState {
+        now: Instant,
+        timers: Vec<(Instant, futures::channel::oneshot::Sender<()>)>,
+    }
+
+    impl FakeClock {
+        fn new() -> Self {
+            FakeClock(Arc::new(std::sync::Mutex::new(FakeClockState {
+                now: Instant::now(),
+                timers: vec![],
+            })))
+        }
+
+        /// Moves time forward, firing all timers that expire.
+        fn advance(&self, duration: Duration) {
+            let mut state = self.0.lock().unwrap();
+            state.now += duration;
+            let now = state.now;
+            let (expired, pending) = state
+                .timers
+                .drain(..)
+                .partition(|(deadline, _)| *deadline <= now);
+            state.timers = pending;
+            for (_, timer) in expired {
+                let _ = timer.send(());
+            }
+        }
+
+        /// Moves time forward without letting any timers progress, like a suspended machine.
+        fn suspend(&self, duration: Duration) {
+            let mut state = self.0.lock().unwrap();
+            state.now += duration;
+            for (deadline, _) in state.timers.iter_mut() {
+                *deadline += duration;
+            }
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.0.lock().unwrap().now
+        }
+
+        fn delay_for(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            let mut state = self.0.lock().unwrap();
+            let deadline = state.now + duration;
+            state.timers.push((deadline, tx));
+            rx.map(|_| ()).boxed()
+        }
+    }
+
+    const TICK: Duration = Duration::from_secs(60);
+
+    /// Runs a version updater against a fake clock and a fake API that returns `results` in
+    /// order, and permanent errors once those run out.
+    struct UpdaterTest {
+        clock: FakeClock,
+        start: Instant,
+        checks: Arc<std::sync::Mutex<Vec<Instant>>>,
+        status: Arc<parking_lot::Mutex<VersionCheckStatus>>,
+        pool: futures::executor::LocalPool,
+        daemon_tx: Arc<futures01::sync::mpsc::UnboundedSender<crate::InternalDaemonEvent>>,
+        daemon_rx: futures01::sync::mpsc::UnboundedReceiver<crate::InternalDaemonEvent>,
+        updater_tx: Option<mpsc::Sender<UpdaterCommand>>,
+        finished: Arc<AtomicBool>,
+    }
+
+    impl UpdaterTest {
+        fn new(results: Vec<Result<mullvad_rpc::AppVersionResponse, Error>>) -> Self {
+            let results = Arc::new(std::sync::Mutex::new(
+                results
+                    .into_iter()
+                    .collect::<std::collections::VecDeque<_>>(),
+            ));
+            Self::with_api(move || {
+                let result = results.lock().unwrap().pop_front().unwrap_or_else(rejected);
+                async move { result }.boxed()
+            })
+        }
+
+        /// Runs a version updater against a fake API that answers each check with `api`.
+        fn with_api<F>(api: F) -> Self
+        where
+            F: Fn() -> BoxFuture<'static, Result<mullvad_rpc::AppVersionResponse, Error>>
+                + Clone
+                + Send
+                + 'static,
+        {
+            Self::start(api, false)
+        }
+
+        fn start<F>(api: F, disabled: bool) -> Self
+        where
+            F: Fn() -> BoxFuture<'static, Result<mullvad_rpc::AppVersionResponse, Error>>
+                + Clone
+                + Send
+                + 'static,
+        {
+            Self::start_with_cache(api, disabled, PathBuf::new())
+        }
+
+        /// Like `start`, but writes fetched version info to `cache_path`.
+        fn start_with_cache<F>(api: F, disabled: bool, cache_path: PathBuf) -> Self
+        where
+            F: Fn() -> BoxFuture<'static, Result<mullvad_rpc::AppVersionResponse, Error>>
+                + Clone
+                + Send
+                + 'static,
+        {
+            use futures::task::LocalSpawnExt;
+
+            let clock = FakeClock::new();
+            let start = clock.now();
+            let checks = Arc::new(std::sync::Mutex::new(vec![]));
+            let status = Arc::new(parking_lot::Mutex::new(VersionCheckStatus::default()));
+            let finished = Arc::new(AtomicBool::new(false));
+
+            let (daemon_tx, daemon_rx) = futures01::sync::mpsc::unbounded();
+            let daemon_tx = Arc::new(daemon_tx);
+            let (updater_tx, updater_rx) = mpsc::channel(1);
+            let mut updater = VersionUpdater {
+                version_proxies: vec![],
+                cache_path,
+                update_sender: crate::DaemonEventSender::new(Arc::downgrade(&daemon_tx))
+                    .to_specialized_sender(),
+                last_app_version_info: AppVersionInfo {
+                    supported: true,
+                    latest_stable: None,
+                    latest_beta: None,
+                    suggested_upgrade: None,
+                },
+                next_update_time: start,
+                show_beta_releases: false,
+                disabled,
+                rx: None,
+                clock: Arc::new(clock.clone()),
+                status: status.clone(),
+            };
+
+            let check_clock = clock.clone();
+            let check_times = checks.clone();
+            let version_check = move || {
+                check_times.lock().unwrap().push(check_clock.now());
+                api()
+            };
+
+            let pool = futures::executor::LocalPool::new();
+            let updater_finished = finished.clone();
+            pool.spawner()
+                .spawn_local(async move {
+                    updater.run_checks(updater_rx.fuse(), version_check).await;
+                    updater_finished.store(true, atomic::Ordering::SeqCst);
+                })
+                .unwrap();
+
+            let mut test = UpdaterTest {
+                clock,
+                start,
+                checks,
+                status,
+                pool,
+                daemon_tx,
+                daemon_rx,
+                updater_tx: Some(updater_tx),
+                finished,
+            };
+            test.pool.run_until_stalled();
+            test
+        }
+
+        /// Advances the clock one tick at a time, running the updater in between.
+        fn run_for(&mut self, duration: Duration) {
+            let mut elapsed = Duration::from_secs(0);
+            while elapsed < duration {
+                self.clock.advance(TICK);
+                self.pool.run_until_stalled();
+                elapsed += TICK;
+            }
+        }
+
+        /// Closes the channel to the updater, like the daemon does when it shuts down.
+        fn shut_down(&mut self) {
+            self.updater_tx = None;
+            self.pool.run_until_stalled();
+        }
+
+        /// Stops the updater through its handle and waits for it to acknowledge.
+        fn stop(&mut self) {
+            let mut handle = VersionUpdaterHandle {
+                tx: self.updater_tx.clone().expect("The updater is shut down"),
+                status: self.status.clone(),
+            };
+            self.pool.run_until(handle.stop());
+        }
+
+        /// Sends `command` to the updater and lets it handle it.
+        fn send(&mut self, command: UpdaterCommand) {
+            use futures::task::LocalSpawnExt;
+
+            let mut updater_tx = self.updater_tx.clone().expect("The updater is shut down");
+            self.pool
+                .spawner()
+                .spawn_local(async move {
+                    let _ = updater_tx.send(command).await;
+                })
+                .unwrap();
+            self.pool.run_until_stalled();
+        }
+
+        /// Shuts the updater down and returns the version info it sent to the daemon.
+        fn into_version_updates(mut self) -> Vec<AppVersionInfo> {
+            use futures01::Stream;
+
+            self.shut_down();
+            drop(self.daemon_tx);
+            self.daemon_rx
+                .wait()
+                .filter_map(|event| match event {
+                    Ok(crate::InternalDaemonEvent::NewAppVersionInfo(info)) => Some(info),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn suspend(&mut self, duration: Duration) {
+            self.clock.suspend(duration);
+            self.pool.run_until_stalled();
+        }
+
+        fn elapsed(&self) -> Duration {
+            self.clock.now() - self.start
+        }
+
+        /// Returns the times of all checks so far, relative to the start of the test.
+        fn check_times(&self) -> Vec<Duration> {
+            self.checks
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|time| *time - self.start)
+                .collect()
+        }
+    }
+
+    fn rejected() -> Result<mullvad_rpc::AppVersionResponse, Error> {
+        Err(Error::Download(mullvad_rpc::rest::Error::ApiError(
+            mullvad_rpc::rest::StatusCode::BAD_REQUEST,
+            String::new(),
+        )))
+    }
+
+    fn retryable() -> Result<mullvad_rpc::AppVersionResponse, Error> {
+        Err(Error::Download(mullvad_rpc::rest::Error::ApiError(
+            mullvad_rpc::rest::StatusCode::SERVICE_UNAVAILABLE,
+            String::new(),
+        )))
+    }
+
+    #[test]
+    fn test_checks_at_update_interval() {
+        let mut test = UpdaterTest::new(vec![]);
+        test.run_for(2 * UPDATE_INTERVAL);
+
+        let first_check = UPDATE_CHECK_INTERVAL;
+        assert_eq!(
+            test.check_times(),
+            vec![
+                first_check,
+                first_check + UPDATE_INTERVAL + UPDATE_CHECK_INTERVAL
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_retried_at_error_interval() {
+        let mut test = UpdaterTest::new(vec![retryable(), retryable()]);
+        test.run_for(UPDATE_INTERVAL);
+
+        let first_check = UPDATE_CHECK_INTERVAL;
+        assert_eq!(
+            test.check_times(),
+            vec![
+                first_check,
+                first_check + UPDATE_INTERVAL_ERROR,
+                first_check + 2 * UPDATE_INTERVAL_ERROR,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shutdown_cancels_pending_check() {
+        // The check holds `in_flight` until it is dropped, and never completes on its own.
+        let (in_flight, mut cancelled) = futures::channel::oneshot::channel::<()>();
+        let in_flight = Arc::new(std::sync::Mutex::new(Some(in_flight)));
+        let mut test = UpdaterTest::with_api(move || {
+            let in_flight = in_flight.lock().unwrap().take();
+            async move {
+                let _in_flight = in_flight;
+                futures::future::pending().await
+            }
+            .boxed()
+        });
+        test.run_for(UPDATE_CHECK_INTERVAL);
+        assert_eq!(test.check_times().len(), 1);
+        assert!(!test.finished.load(atomic::Ordering::SeqCst));
+
+        let shut_down_at = test.elapsed();
+        test.shut_down();
+        assert!(test.finished.load(atomic::Ordering::SeqCst));
+        assert_eq!(
+            cancelled.try_recv(),
+            Err(futures::channel::oneshot::Canceled)
+        );
+        assert_eq!(test.elapsed(), shut_down_at);
+    }
+
+    #[test]
+    fn test_status_records_last_check() {
+        let mut test = UpdaterTest::new(vec![retryable()]);
+        assert_eq!(*test.status.lock(), VersionCheckStatus::default());
+
+        test.run_for(UPDATE_CHECK_INTERVAL);
+        let status = test.status.lock().clone();
+        assert!(status.last_check.is_some());
+        assert!(status.last_error.is_some());
+    }
+
+    fn version_response() -> Result<mullvad_rpc::AppVersionResponse, Error> {
+        Ok(mullvad_rpc::AppVersionResponse {
+            supported: true,
+            latest: "2020.5".to_owned(),
+            latest_stable: Some("2020.5".to_owned()),
+            latest_beta: "2020.5".to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_stop_blocking_outside_runtime() {
+        let mut runtime = tokio02::runtime::Runtime::new().unwrap();
+        let timeout = Duration::from_millis(100);
+
+        // An updater that never handles its commands
+        let (tx, _rx) = mpsc::channel(1);
+        let handle = VersionUpdaterHandle {
+            tx,
+            status: Default::default(),
+        };
+        let start = Instant::now();
+        assert!(!handle.stop_blocking(&mut runtime, timeout));
+        assert!(start.elapsed() >= timeout);
+
+        // An updater that is already down
+        let (tx, rx) = mpsc::channel(1);
+        mem::drop(rx);
+        let handle = VersionUpdaterHandle {
+            tx,
+            status: Default::default(),
+        };
+        assert!(handle.stop_blocking(&mut runtime, timeout));
+    }
+
+    #[test]
+    fn test_stop_persists_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        // Writing the cache requires a Tokio context.
+        let runtime = tokio02::runtime::Runtime::new().unwrap();
+        runtime.enter(|| {
+            let mut test = UpdaterTest::start_with_cache(
+                || async { version_response() }.boxed(),
+                false,
+                cache_dir.path().join(VERSION_INFO_FILENAME),
+            );
+            test.run_for(UPDATE_CHECK_INTERVAL);
+            assert_eq!(test.check_times().len(), 1);
+
+            test.stop();
+            assert!(test.finished.load(atomic::Ordering::SeqCst));
+        });
+
+        let cached = try_load_cache(cache_dir.path()).expect("Version cache was not written");
+        assert_eq!(cached.latest_stable, Some("2020.5".to_owned()));
+    }
+
+    #[test]
+    fn test_disabled_never_checks() {
+        let mut test = UpdaterTest::start(|| async { version_response() }.boxed(), true);
+        test.run_for(2 * UPDATE_INTERVAL);
+        assert!(test.check_times().is_empty());
+        assert!(test.status.lock().last_check.is_none());
+
+        // Only the cached version info is reported.
+        let updates = test.into_version_updates();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].latest_stable, None);
+    }
+
+    #[test]
+    fn test_check_after_suspend() {
+        let mut test = UpdaterTest::new(vec![]);
+        test.run_for(2 * UPDATE_CHECK_INTERVAL);
+        assert_eq!(test.check_times(), vec![UPDATE_CHECK_INTERVAL]);
+
+        test.suspend(3 * UPDATE_INTERVAL);
+        assert_eq!(test.check_times().len(), 1);
+
+        let resumed = test.elapsed();
+        test.run_for(UPDATE_CHECK_INTERVAL);
+        let check_times = test.check_times();
+        assert_eq!(check_times.len(), 2);
+        assert!(check_times[1] > resumed);
+        assert!(check_times[1] <= resumed + UPDATE_CHECK_INTERVAL);
+    }
 }
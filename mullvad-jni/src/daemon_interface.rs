@@ -154,7 +154,9 @@ impl DaemonInterface {
     }
 
     pub fn reconnect(&self) -> Result<()> {
-        self.send_command(DaemonCommand::Reconnect)?;
+        let (tx, _) = oneshot::channel();
+
+        self.send_command(DaemonCommand::Reconnect(tx))?;
 
         Ok(())
     }
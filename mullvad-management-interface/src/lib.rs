@@ -11,12 +11,13 @@ use std::{
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
 use tonic::transport::{server::Connected, Endpoint, Server, Uri};
-use tower::service_fn;
+use tower::{make::MakeConnection, service_fn};
 
-pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status};
+pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status, Streaming};
 
 pub type ManagementServiceClient =
     types::management_service_client::ManagementServiceClient<Channel>;
@@ -36,20 +37,53 @@ pub enum Error {
 
     #[error(display = "Unable to set permissions for IPC endpoint")]
     PermissionsError(#[error(source)] io::Error),
+
+    #[error(display = "The daemon did not respond within {:?}", _0)]
+    DaemonNotResponding(Duration),
 }
 
+/// How long `new_rpc_client` waits for the daemon to accept the connection, and for each call to
+/// be answered.
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[cfg(target_os = "windows")]
 mod windows_permissions;
 
 pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
+    new_rpc_client_with_timeout(DEFAULT_RPC_TIMEOUT).await
+}
+
+/// Connects to the daemon, failing with `Error::DaemonNotResponding` if the connection can't be
+/// established within `timeout`. Calls on the returned client fail if the daemon doesn't respond
+/// to them within `timeout`. This doesn't limit how long a response may be streamed for.
+pub async fn new_rpc_client_with_timeout(
+    timeout: Duration,
+) -> Result<ManagementServiceClient, Error> {
     let ipc_path = mullvad_paths::get_rpc_socket_path();
+    connect_with_timeout(
+        service_fn(move |_: Uri| IpcEndpoint::connect(ipc_path.clone())),
+        timeout,
+    )
+    .await
+}
 
+async fn connect_with_timeout<C>(
+    connector: C,
+    timeout: Duration,
+) -> Result<ManagementServiceClient, Error>
+where
+    C: MakeConnection<Uri> + Send + 'static,
+    C::Connection: Unpin + Send + 'static,
+    C::Future: Send + 'static,
+    Box<dyn std::error::Error + Send + Sync>: From<C::Error> + Send + 'static,
+{
     // The URI will be ignored
-    let channel = Endpoint::from_static("lttp://[::]:50051")
-        .connect_with_connector(service_fn(move |_: Uri| {
-            IpcEndpoint::connect(ipc_path.clone())
-        }))
+    let connect = Endpoint::from_static("lttp://[::]:50051")
+        .timeout(timeout)
+        .connect_with_connector(connector);
+    let channel = tokio::time::timeout(timeout, connect)
         .await
+        .map_err(|_| Error::DaemonNotResponding(timeout))?
         .map_err(Error::GrpcTransportError)?;
 
     Ok(ManagementServiceClient::new(channel))
@@ -121,3 +155,76 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for StreamBox<T> {
         Pin::new(&mut self.0).poll_shutdown(cx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_unresponsive_daemon_times_out() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let timeout = Duration::from_millis(100);
+        // An endpoint that never completes the connection
+        let connector = service_fn(|_: Uri| {
+            futures::future::pending::<io::Result<parity_tokio_ipc::Connection>>()
+        });
+
+        let start = Instant::now();
+        let result = runtime.block_on(connect_with_timeout(connector, timeout));
+
+        assert!(matches!(result, Err(Error::DaemonNotResponding(t)) if t == timeout));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    /// A connection that accepts everything written to it but never answers.
+    struct SilentConnection;
+
+    impl AsyncRead for SilentConnection {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for SilentConnection {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn test_unanswered_call_times_out() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let timeout = Duration::from_millis(100);
+        // An endpoint that accepts the connection but never answers any call
+        let connector = service_fn(|_: Uri| futures::future::ok::<_, io::Error>(SilentConnection));
+
+        let start = Instant::now();
+        let result = runtime.block_on(async move {
+            let mut client = connect_with_timeout(connector, timeout).await?;
+            Ok::<_, Error>(client.get_current_version(()).await)
+        });
+
+        // Depending on whether the connection setup waits for the daemon, either the connection
+        // or the call times out.
+        assert!(!matches!(result, Ok(Ok(_))));
+        assert!(start.elapsed() >= timeout);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -96,6 +96,15 @@ impl MullvadRpcRuntime {
         rest::MullvadRestHandle { service, factory }
     }
 
+    /// Returns a request factory initialized to create requests for the API at `host`, which is
+    /// resolved using DNS. Useful as a fallback when the master API is unreachable.
+    pub fn mullvad_rest_handle_for_host(&mut self, host: String) -> rest::MullvadRestHandle {
+        let service = self.new_request_service(Some(host.clone()));
+        let factory = rest::RequestFactory::new(host, None, Some("app".to_owned()));
+
+        rest::MullvadRestHandle { service, factory }
+    }
+
     /// Returns a new request service handle
     pub fn rest_handle(&mut self) -> rest::RequestServiceHandle {
         self.new_request_service(None)
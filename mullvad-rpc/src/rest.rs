@@ -1,6 +1,6 @@
 use futures::{
     channel::{mpsc, oneshot},
-    future::{abortable, AbortHandle, Aborted},
+    future::{self, abortable, AbortHandle, Aborted, Either},
     sink::SinkExt,
     stream::StreamExt,
     TryFutureExt,
@@ -102,7 +102,7 @@ impl<C: Connect + Clone + Send + Sync + 'static> RequestService<C> {
 
     fn process_command(&mut self, command: RequestCommand) {
         match command {
-            RequestCommand::NewRequest(request, completion_tx) => {
+            RequestCommand::NewRequest(request, mut completion_tx) => {
                 let id = self.id();
                 let mut tx = self.command_tx.clone();
                 let timeout = request.timeout();
@@ -115,16 +115,29 @@ impl<C: Connect + Clone + Send + Sync + 'static> RequestService<C> {
 
                 let future = async move {
                     let response =
-                        tokio::time::timeout(timeout, request_future.map_err(Error::Aborted))
-                            .await
-                            .map_err(Error::TimeoutError);
+                        tokio::time::timeout(timeout, request_future.map_err(Error::Aborted));
+                    futures::pin_mut!(response);
 
-                    let response = flatten_result(flatten_result(response));
-
-                    if completion_tx.send(response).is_err() {
-                        log::trace!(
-                            "Failed to send response to caller, caller channel is shut down"
-                        );
+                    // Stop waiting for the response as soon as the caller is gone, rather than
+                    // keeping the request alive until it times out.
+                    let response =
+                        match future::select(completion_tx.cancellation(), response).await {
+                            Either::Left(((), _)) => None,
+                            Either::Right((response, _)) => Some(response),
+                        };
+
+                    match response {
+                        Some(response) => {
+                            let response = flatten_result(flatten_result(
+                                response.map_err(Error::TimeoutError),
+                            ));
+                            if completion_tx.send(response).is_err() {
+                                log::trace!(
+                                    "Failed to send response to caller, caller channel is shut down"
+                                );
+                            }
+                        }
+                        None => log::trace!("Dropping request, the caller is no longer waiting"),
                     }
                     let _ = tx.send(RequestCommand::RequestFinished(id)).await;
                 };
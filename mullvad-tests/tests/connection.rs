@@ -70,6 +70,7 @@ fn changes_to_connecting_state() {
         TunnelState::Connecting {
             endpoint: get_default_endpoint(),
             location: get_default_location(),
+            reconnect_attempt: None,
         },
     );
     assert_eq!(
@@ -77,6 +78,7 @@ fn changes_to_connecting_state() {
         TunnelState::Connecting {
             endpoint: get_default_endpoint(),
             location: get_default_location(),
+            reconnect_attempt: None,
         },
     );
 }
@@ -97,6 +99,7 @@ fn changes_to_connected_state() {
         TunnelState::Connecting {
             endpoint: get_default_endpoint(),
             location: get_default_location(),
+            reconnect_attempt: None,
         },
     );
     openvpn_args_file_events.assert_create_write_close_sequence();
@@ -137,6 +140,7 @@ fn returns_to_connecting_state() {
         TunnelState::Connecting {
             endpoint: get_default_endpoint(),
             location: get_default_location(),
+            reconnect_attempt: None,
         },
     );
     openvpn_args_file_events.assert_create_write_close_sequence();
@@ -181,6 +185,7 @@ fn disconnects() {
         TunnelState::Connecting {
             endpoint: get_default_endpoint(),
             location: get_default_location(),
+            reconnect_attempt: None,
         },
     );
     openvpn_args_file_events.assert_create_write_close_sequence();
@@ -63,6 +63,9 @@ pub struct AmIMullvad {
     pub latitude: f64,
     pub longitude: f64,
     pub mullvad_exit_ip: bool,
+    /// Hostname of the relay that the request exited through, if it was a Mullvad relay.
+    #[serde(default)]
+    pub mullvad_exit_ip_hostname: Option<String>,
 }
 
 /// GeoIP information exposed from the daemon to frontends.
@@ -100,7 +103,7 @@ impl From<AmIMullvad> for GeoIpLocation {
             latitude: location.latitude,
             longitude: location.longitude,
             mullvad_exit_ip: location.mullvad_exit_ip,
-            hostname: None,
+            hostname: location.mullvad_exit_ip_hostname,
             bridge_hostname: None,
         }
     }
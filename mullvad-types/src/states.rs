@@ -4,7 +4,7 @@ use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
 use talpid_types::{
     net::TunnelEndpoint,
-    tunnel::{ActionAfterDisconnect, ErrorState},
+    tunnel::{ActionAfterDisconnect, ErrorState, ReconnectAttempt},
 };
 
 /// Represents the state the client strives towards.
@@ -28,6 +28,10 @@ pub enum TunnelState {
     Connecting {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// Set while the VPN client is bringing a tunnel that went down back up on its own.
+        #[serde(default)]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        reconnect_attempt: Option<ReconnectAttempt>,
     },
     Connected {
         endpoint: TunnelEndpoint,
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -15,15 +16,24 @@ pub struct AppVersionInfo {
     ///   issues, so using it is no longer recommended.
     /// The user should really upgrade when this is false.
     pub supported: bool,
-    /// Latest stable version
+    /// Latest stable version. `None` if the API did not report one.
     #[cfg_attr(target_os = "android", jnix(skip))]
-    pub latest_stable: AppVersion,
+    pub latest_stable: Option<AppVersion>,
     /// Equal to `latest_stable` when the newest release is a stable release. But will contain
-    /// beta versions when those are out for testing.
+    /// beta versions when those are out for testing. `None` if the API did not report one.
     #[cfg_attr(target_os = "android", jnix(skip))]
-    pub latest_beta: AppVersion,
+    pub latest_beta: Option<AppVersion>,
     /// Whether should update to newer version
     pub suggested_upgrade: Option<AppVersion>,
 }
 
 pub type AppVersion = String;
+
+/// Outcome of the most recent attempt to fetch the latest app versions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct VersionCheckStatus {
+    /// When the last check finished. `None` if no check has finished since the daemon started.
+    pub last_check: Option<DateTime<Utc>>,
+    /// Why the last check failed, if it did.
+    pub last_error: Option<String>,
+}
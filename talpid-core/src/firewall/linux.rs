@@ -542,11 +542,10 @@ impl<'a> PolicyBatch<'a> {
         tunnel: &tunnel::TunnelMetadata,
         protocol: TransportProtocol,
     ) -> Result<()> {
-        // allow DNS traffic to the tunnel gateway(s)
-        self.add_allow_dns_rule(&tunnel.interface, protocol, tunnel.ipv4_gateway.into())?;
-        if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
-            self.add_allow_dns_rule(&tunnel.interface, protocol, ipv6_gateway.into())?;
-        };
+        // allow DNS traffic to the DNS servers in the tunnel
+        for server in tunnel.tunnel_dns_servers() {
+            self.add_allow_dns_rule(&tunnel.interface, protocol, server)?;
+        }
         Ok(())
     }
 
@@ -116,29 +116,8 @@ impl Firewall {
                 allow_lan,
             } => {
                 let mut rules = vec![];
-                let allow_tcp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Tcp)
-                    .keep_state(pfctl::StatePolicy::Keep)
-                    .tcp_flags(Self::get_tcp_flags())
-                    .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
-                    .build()?;
-                rules.push(allow_tcp_dns_to_relay_rule);
-                let allow_udp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Udp)
-                    .to(pfctl::Endpoint::new(tunnel.ipv4_gateway, 53))
-                    .build()?;
-                rules.push(allow_udp_dns_to_relay_rule);
-
-                if let Some(ipv6_gateway) = tunnel.ipv6_gateway {
-                    let v6_dns_rule_tcp = self
+                for dns_server in tunnel.tunnel_dns_servers() {
+                    let allow_tcp_dns_rule = self
                         .create_rule_builder(FilterRuleAction::Pass)
                         .direction(pfctl::Direction::Out)
                         .quick(true)
@@ -146,18 +125,18 @@ impl Firewall {
                         .proto(pfctl::Proto::Tcp)
                         .keep_state(pfctl::StatePolicy::Keep)
                         .tcp_flags(Self::get_tcp_flags())
-                        .to(pfctl::Endpoint::new(ipv6_gateway, 53))
+                        .to(pfctl::Endpoint::new(dns_server, 53))
                         .build()?;
-                    rules.push(v6_dns_rule_tcp);
-                    let v6_dns_rule_udp = self
+                    rules.push(allow_tcp_dns_rule);
+                    let allow_udp_dns_rule = self
                         .create_rule_builder(FilterRuleAction::Pass)
                         .direction(pfctl::Direction::Out)
                         .quick(true)
                         .interface(&tunnel.interface)
                         .proto(pfctl::Proto::Udp)
-                        .to(pfctl::Endpoint::new(ipv6_gateway, 53))
+                        .to(pfctl::Endpoint::new(dns_server, 53))
                         .build()?;
-                    rules.push(v6_dns_rule_udp);
+                    rules.push(allow_udp_dns_rule);
                 }
 
                 rules.push(self.get_allow_relay_rule(peer_endpoint)?);
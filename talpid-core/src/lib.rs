@@ -30,7 +30,7 @@ pub mod process;
 /// Abstracts over different VPN tunnel technologies
 pub mod tunnel;
 
-/// Helper function to preserve previous log files.
+/// Helper functions for managing log files.
 pub mod logging;
 
 /// Abstractions and extra features on `std::mpsc`
@@ -1,4 +1,7 @@
-use std::{fs, io, path::Path};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 /// Types/implementations for logging through a callback.
 #[cfg(windows)]
@@ -9,21 +12,78 @@ pub mod windows;
 #[error(display = "Unable to create new log file")]
 pub struct RotateLogError(#[error(source)] io::Error);
 
-/// Create a new log file while backing up a previous version of it.
+/// How many earlier versions of a log `rotate_log` keeps.
+const MAX_LOG_BACKUPS: usize = 3;
+
+/// Create a new log file while backing up previous versions of it.
 ///
 /// A new log file is created with the given file name, but if a file with that name already exists
-/// it is backed up with the extension changed to `.old.log`.
+/// it is backed up with the extension changed to `.old.log`. Earlier backups are shifted to
+/// `.old.2.log`, `.old.3.log` and so on, and the oldest is removed once there are
+/// `MAX_LOG_BACKUPS` of them.
 pub fn rotate_log(file: &Path) -> Result<(), RotateLogError> {
-    let backup = file.with_extension("old.log");
-    if let Err(error) = fs::rename(file, &backup) {
+    for index in (1..MAX_LOG_BACKUPS).rev() {
+        rename_backup(&backup_path(file, index), &backup_path(file, index + 1));
+    }
+    rename_backup(file, &backup_path(file, 1));
+
+    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+}
+
+/// Returns where the `index`th most recent backup of the log at `file` is kept, counting from 1.
+fn backup_path(file: &Path, index: usize) -> PathBuf {
+    if index == 1 {
+        file.with_extension("old.log")
+    } else {
+        file.with_extension(format!("old.{}.log", index))
+    }
+}
+
+fn rename_backup(from: &Path, to: &Path) {
+    if let Err(error) = fs::rename(from, to) {
         if error.kind() != io::ErrorKind::NotFound {
-            log::warn!(
-                "Failed to rotate log file to {}: {}",
-                backup.display(),
-                error
-            );
+            log::warn!("Failed to rotate log file to {}: {}", to.display(), error);
         }
     }
+}
 
-    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+/// Returns the last `lines` lines of a log file. A log file that does not exist yet is treated as
+/// being empty. Invalid UTF-8 is replaced rather than treated as an error.
+pub fn tail(file: &Path, lines: usize) -> io::Result<Vec<String>> {
+    let contents = match fs::read(file) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let contents = String::from_utf8_lossy(&contents);
+    let all_lines: Vec<&str> = contents.lines().collect();
+    let start = all_lines.len().saturating_sub(lines);
+    Ok(all_lines[start..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_log() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let log_file = temp_dir.path().join("daemon.log");
+        let read = |name: &str| fs::read_to_string(temp_dir.path().join(name)).ok();
+
+        for session in 1..=MAX_LOG_BACKUPS + 2 {
+            rotate_log(&log_file).unwrap();
+            fs::write(&log_file, session.to_string()).unwrap();
+        }
+
+        assert_eq!(read("daemon.log"), Some("5".to_owned()));
+        assert_eq!(read("daemon.old.log"), Some("4".to_owned()));
+        assert_eq!(read("daemon.old.2.log"), Some("3".to_owned()));
+        assert_eq!(read("daemon.old.3.log"), Some("2".to_owned()));
+        assert_eq!(read("daemon.old.4.log"), None);
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 4);
+    }
 }
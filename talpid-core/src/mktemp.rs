@@ -13,8 +13,13 @@ pub struct TempFile {
 impl TempFile {
     /// Create a new unique `TempFile`. The file will not exist after this.
     pub fn new() -> Self {
+        Self::new_in(&env::temp_dir())
+    }
+
+    /// Create a new unique `TempFile` in `dir`. The file will not exist after this.
+    pub fn new_in(dir: &Path) -> Self {
         TempFile {
-            path: generate_path(),
+            path: dir.join(Uuid::new_v4().to_string()),
         }
     }
 
@@ -42,7 +47,3 @@ impl Drop for TempFile {
         }
     }
 }
-
-fn generate_path() -> PathBuf {
-    env::temp_dir().join(Uuid::new_v4().to_string())
-}
@@ -8,6 +8,7 @@ use shell_escape;
 use std::{
     ffi::{OsStr, OsString},
     fmt, io,
+    net::Ipv4Addr,
     path::{Path, PathBuf},
 };
 use talpid_types::net;
@@ -20,18 +21,13 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
     &["--dev", "tun"],
     #[cfg(windows)]
     &["--dev-type", "tun"],
-    &["--ping", "4"],
-    &["--ping-exit", "25"],
     &["--connect-timeout", "30"],
-    &["--connect-retry", "0", "0"],
-    &["--connect-retry-max", "1"],
     &["--remote-cert-tls", "server"],
     &["--rcvbuf", "1048576"],
     &["--sndbuf", "1048576"],
     &["--fast-io"],
     &["--cipher", "AES-256-CBC"],
     &["--tls-version-min", "1.2"],
-    &["--verb", "3"],
     #[cfg(windows)]
     &[
         "--route-gateway",
@@ -44,6 +40,16 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
     ],
 ];
 
+/// Seconds between connection attempts, unless set in the tunnel options.
+pub const DEFAULT_CONNECT_RETRY: u32 = 0;
+/// Number of connection attempts before OpenVPN exits, unless set in the tunnel options.
+const DEFAULT_CONNECT_RETRY_MAX: u32 = 1;
+/// Log verbosity passed with `--verb`, unless set in the tunnel options.
+const DEFAULT_VERBOSITY: u8 = 3;
+
+/// Placeholder shown instead of credential file paths when logging the command line.
+const REDACTED: &str = "[REDACTED]";
+
 static ALLOWED_TLS1_2_CIPHERS: &[&str] = &[
     "TLS-DHE-RSA-WITH-AES-256-GCM-SHA384",
     "TLS-DHE-RSA-WITH-AES-256-CBC-SHA",
@@ -62,9 +68,12 @@ pub struct OpenVpnCommand {
     proxy_auth_path: Option<PathBuf>,
     ca: Option<PathBuf>,
     crl: Option<PathBuf>,
+    tls_crypt_v2: Option<PathBuf>,
+    verify_x509_name: Option<String>,
     iproute_bin: Option<OsString>,
     plugin: Option<(PathBuf, Vec<String>)>,
     log: Option<PathBuf>,
+    log_append: bool,
     tunnel_options: net::openvpn::TunnelOptions,
     proxy_settings: Option<net::openvpn::ProxySettings>,
     tunnel_alias: Option<OsString>,
@@ -84,9 +93,12 @@ impl OpenVpnCommand {
             proxy_auth_path: None,
             ca: None,
             crl: None,
+            tls_crypt_v2: None,
+            verify_x509_name: None,
             iproute_bin: None,
             plugin: None,
             log: None,
+            log_append: false,
             tunnel_options: net::openvpn::TunnelOptions::default(),
             proxy_settings: None,
             tunnel_alias: None,
@@ -133,6 +145,21 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Sets the path to the tls-crypt-v2 client key file. See the `--tls-crypt-v2` OpenVPN
+    /// documentation for details.
+    pub fn tls_crypt_v2(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.tls_crypt_v2 = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Only accepts a server certificate with this common name, in addition to it being signed by
+    /// the CA and having the TLS server extended key usage. See the `--verify-x509-name` OpenVPN
+    /// documentation for details.
+    pub fn verify_x509_name(&mut self, name: impl Into<String>) -> &mut Self {
+        self.verify_x509_name = Some(name.into());
+        self
+    }
+
     /// Sets the path to the ip route command.
     pub fn iproute_bin(&mut self, iproute_bin: impl Into<OsString>) -> &mut Self {
         self.iproute_bin = Some(iproute_bin.into());
@@ -151,6 +178,13 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Makes OpenVPN append to the log file instead of truncating it when starting. Writes then
+    /// always go to the end of the file, so the log can be truncated while OpenVPN is running.
+    pub fn log_append(&mut self, log_append: bool) -> &mut Self {
+        self.log_append = log_append;
+        self
+    }
+
     /// Sets extra options
     pub fn tunnel_options(&mut self, tunnel_options: &net::openvpn::TunnelOptions) -> &mut Self {
         self.tunnel_options = tunnel_options.clone();
@@ -214,6 +248,15 @@ impl OpenVpnCommand {
             args.push(OsString::from("--crl-verify"));
             args.push(OsString::from(crl.as_os_str()));
         }
+        if let Some(ref tls_crypt_v2) = self.tls_crypt_v2 {
+            args.push(OsString::from("--tls-crypt-v2"));
+            args.push(OsString::from(tls_crypt_v2.as_os_str()));
+        }
+        if let Some(ref name) = self.verify_x509_name {
+            args.push(OsString::from("--verify-x509-name"));
+            args.push(OsString::from(name));
+            args.push(OsString::from("name"));
+        }
 
         if let Some((ref path, ref plugin_args)) = self.plugin {
             args.push(OsString::from("--plugin"));
@@ -222,15 +265,74 @@ impl OpenVpnCommand {
         }
 
         if let Some(ref path) = self.log {
-            args.push(OsString::from("--log"));
+            args.push(OsString::from(if self.log_append {
+                "--log-append"
+            } else {
+                "--log"
+            }));
             args.push(OsString::from(path))
         }
 
+        args.push(OsString::from("--verb"));
+        args.push(OsString::from(
+            self.tunnel_options
+                .verbosity
+                .unwrap_or(DEFAULT_VERBOSITY)
+                .to_string(),
+        ));
+
         if let Some(mssfix) = self.tunnel_options.mssfix {
             args.push(OsString::from("--mssfix"));
             args.push(OsString::from(mssfix.to_string()));
         }
 
+        if let Some(fragment) = self.tunnel_options.fragment {
+            args.push(OsString::from("--fragment"));
+            args.push(OsString::from(fragment.to_string()));
+        }
+
+        if let Some(tun_mtu) = self.tunnel_options.tun_mtu {
+            args.push(OsString::from("--tun-mtu"));
+            args.push(OsString::from(tun_mtu.to_string()));
+        }
+
+        let ping = self
+            .tunnel_options
+            .ping
+            .unwrap_or(net::openvpn::DEFAULT_PING);
+        args.push(OsString::from("--ping"));
+        args.push(OsString::from(ping.to_string()));
+
+        // `--ping-exit` and `--ping-restart` are mutually exclusive.
+        match self.tunnel_options.ping_restart {
+            Some(ping_restart) => {
+                args.push(OsString::from("--ping-restart"));
+                args.push(OsString::from(ping_restart.to_string()));
+            }
+            None => {
+                args.push(OsString::from("--ping-exit"));
+                args.push(OsString::from(net::openvpn::DEFAULT_PING_EXIT.to_string()));
+            }
+        }
+
+        // The delay is passed as both the initial and the maximum delay, to keep it from growing
+        // between attempts.
+        let connect_retry = self
+            .tunnel_options
+            .connect_retry
+            .unwrap_or(DEFAULT_CONNECT_RETRY)
+            .to_string();
+        args.push(OsString::from("--connect-retry"));
+        args.push(OsString::from(&connect_retry));
+        args.push(OsString::from(&connect_retry));
+        args.push(OsString::from("--connect-retry-max"));
+        args.push(OsString::from(
+            self.tunnel_options
+                .connect_retry_max
+                .unwrap_or(DEFAULT_CONNECT_RETRY_MAX)
+                .to_string(),
+        ));
+
         if !self.enable_ipv6 {
             args.push(OsString::from("--pull-filter"));
             args.push(OsString::from("ignore"));
@@ -252,6 +354,27 @@ impl OpenVpnCommand {
         args
     }
 
+    /// Returns all arguments that the subprocess would be spawned with, with the paths to any
+    /// credential files replaced by a placeholder. Safe to log.
+    pub fn redacted_arguments(&self) -> Vec<String> {
+        let secret_paths: Vec<OsString> = self
+            .user_pass_path
+            .iter()
+            .chain(self.proxy_auth_path.iter())
+            .map(OsString::from)
+            .collect();
+        self.get_arguments()
+            .into_iter()
+            .map(|arg| {
+                if secret_paths.contains(&arg) {
+                    REDACTED.to_owned()
+                } else {
+                    arg.to_string_lossy().into_owned()
+                }
+            })
+            .collect()
+    }
+
     fn base_arguments() -> Vec<&'static str> {
         let mut args = vec![];
         for arglist in BASE_ARGUMENTS.iter() {
@@ -273,7 +396,15 @@ impl OpenVpnCommand {
 
     fn remote_arguments(&self) -> Vec<String> {
         let mut args: Vec<String> = vec![];
-        if let Some(ref endpoint) = self.remote {
+        // OpenVPN connects to the relay through the local obfuscation transport, as if it were the
+        // relay.
+        let remote = match (&self.proxy_settings, self.proxy_port) {
+            (Some(net::openvpn::ProxySettings::Obfuscation(_)), Some(proxy_port)) => Some(
+                net::Endpoint::new(Ipv4Addr::LOCALHOST, proxy_port, net::TransportProtocol::Udp),
+            ),
+            _ => self.remote,
+        };
+        if let Some(ref endpoint) = remote {
             args.push("--proto".to_owned());
             args.push(match endpoint.protocol {
                 net::TransportProtocol::Udp => "udp".to_owned(),
@@ -325,6 +456,27 @@ impl OpenVpnCommand {
                 args.push("255.255.255.255".to_owned());
                 args.push("net_gateway".to_owned());
             }
+            Some(net::openvpn::ProxySettings::Http(ref http_proxy)) => {
+                args.push("--http-proxy".to_owned());
+                args.push(http_proxy.address.ip().to_string());
+                args.push(http_proxy.address.port().to_string());
+
+                // The credentials file is passed inline since `--http-proxy-user-pass` on its own
+                // leaves the authentication method set to "none".
+                if let Some(ref _auth) = http_proxy.auth {
+                    if let Some(ref auth_file) = self.proxy_auth_path {
+                        args.push(auth_file.to_string_lossy().to_string());
+                        args.push("basic".to_owned());
+                    } else {
+                        log::error!("Proxy credentials present but credentials file missing");
+                    }
+                }
+
+                args.push("--route".to_owned());
+                args.push(http_proxy.address.ip().to_string());
+                args.push("255.255.255.255".to_owned());
+                args.push("net_gateway".to_owned());
+            }
             Some(net::openvpn::ProxySettings::Shadowsocks(ref ss)) => {
                 args.push("--socks-proxy".to_owned());
                 args.push("127.0.0.1".to_owned());
@@ -340,6 +492,16 @@ impl OpenVpnCommand {
                 args.push("255.255.255.255".to_owned());
                 args.push("net_gateway".to_owned());
             }
+            Some(net::openvpn::ProxySettings::Obfuscation(ref obfuscation)) => {
+                if self.proxy_port.is_none() {
+                    panic!("Dynamic proxy port was not registered with OpenVpnCommand");
+                }
+
+                args.push("--route".to_owned());
+                args.push(obfuscation.peer.ip().to_string());
+                args.push("255.255.255.255".to_owned());
+                args.push("net_gateway".to_owned());
+            }
             None => {}
         };
         args
@@ -348,12 +510,13 @@ impl OpenVpnCommand {
 
 impl fmt::Display for OpenVpnCommand {
     /// Format the program and arguments of an `OpenVpnCommand` for display. Any non-utf8 data
-    /// is lossily converted using the utf8 replacement character.
+    /// is lossily converted using the utf8 replacement character. Credential file paths are
+    /// redacted.
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.write_str(&shell_escape::escape(self.openvpn_bin.to_string_lossy()))?;
-        for arg in &self.get_arguments() {
+        for arg in self.redacted_arguments() {
             fmt.write_str(" ")?;
-            fmt.write_str(&shell_escape::escape(arg.to_string_lossy()))?;
+            fmt.write_str(&shell_escape::escape(arg.into()))?;
         }
         Ok(())
     }
@@ -406,6 +569,20 @@ impl StoppableProcess for OpenVpnProcHandle {
         Ok(())
     }
 
+    #[cfg(unix)]
+    fn terminate(&self) -> io::Result<()> {
+        use nix::{
+            sys::signal::{kill, Signal},
+            unistd::Pid,
+        };
+
+        let pid = self.inner.pids().first().cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "The OpenVPN process has no PID")
+        })?;
+        kill(Pid::from_raw(pid as i32), Signal::SIGTERM)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+    }
+
     fn has_stopped(&self) -> io::Result<bool> {
         match self.inner.try_wait() {
             Ok(None) => Ok(false),
@@ -418,9 +595,16 @@ impl StoppableProcess for OpenVpnProcHandle {
 
 #[cfg(test)]
 mod tests {
-    use super::OpenVpnCommand;
+    use super::{OpenVpnCommand, REDACTED};
     use std::{ffi::OsString, net::Ipv4Addr};
-    use talpid_types::net::{Endpoint, TransportProtocol};
+    use talpid_types::net::{openvpn, Endpoint, TransportProtocol};
+
+    fn http_proxy(auth: Option<openvpn::ProxyAuth>) -> openvpn::ProxySettings {
+        openvpn::ProxySettings::Http(openvpn::HttpProxySettings {
+            address: "10.0.0.1:8080".parse().unwrap(),
+            auth,
+        })
+    }
 
     #[test]
     fn passes_one_remote() {
@@ -447,4 +631,273 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_tls_crypt_v2_key() {
+        let testee_args = OpenVpnCommand::new("")
+            .tls_crypt_v2("./a/key")
+            .get_arguments();
+        let expected: Vec<OsString> = ["--tls-crypt-v2", "./a/key"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert!(testee_args.windows(2).any(|window| window == &expected[..]));
+
+        let testee_args = OpenVpnCommand::new("").get_arguments();
+        assert!(!testee_args.contains(&OsString::from("--tls-crypt-v2")));
+    }
+
+    #[test]
+    fn passes_server_verification() {
+        let testee_args = OpenVpnCommand::new("")
+            .verify_x509_name("se-got-001")
+            .get_arguments();
+        for arg in &[
+            &["--remote-cert-tls", "server"][..],
+            &["--verify-x509-name", "se-got-001", "name"][..],
+        ] {
+            let arg: Vec<OsString> = arg.iter().map(OsString::from).collect();
+            assert!(testee_args
+                .windows(arg.len())
+                .any(|window| window == &arg[..]));
+        }
+
+        let testee_args = OpenVpnCommand::new("").get_arguments();
+        assert!(!testee_args.contains(&OsString::from("--verify-x509-name")));
+        let expected: Vec<OsString> = ["--remote-cert-tls", "server"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert!(testee_args.windows(2).any(|window| window == &expected[..]));
+    }
+
+    #[test]
+    fn passes_log_append() {
+        let testee_args = OpenVpnCommand::new("").log("./a/log").get_arguments();
+        assert!(testee_args.contains(&OsString::from("--log")));
+        assert!(!testee_args.contains(&OsString::from("--log-append")));
+
+        let testee_args = OpenVpnCommand::new("")
+            .log("./a/log")
+            .log_append(true)
+            .get_arguments();
+        let expected: Vec<OsString> = ["--log-append", "./a/log"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert!(testee_args.windows(2).any(|window| window == &expected[..]));
+        assert!(!testee_args.contains(&OsString::from("--log")));
+    }
+
+    #[test]
+    fn passes_mtu_options() {
+        let options = openvpn::TunnelOptions {
+            mssfix: Some(1400),
+            fragment: Some(1300),
+            tun_mtu: Some(1500),
+            ..openvpn::TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&options)
+            .get_arguments();
+        for arg in &[
+            ["--mssfix", "1400"],
+            ["--fragment", "1300"],
+            ["--tun-mtu", "1500"],
+        ] {
+            let arg: Vec<OsString> = arg.iter().map(OsString::from).collect();
+            assert!(testee_args.windows(2).any(|window| window == &arg[..]));
+        }
+    }
+
+    #[test]
+    fn omits_unset_mtu_options() {
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&openvpn::TunnelOptions::default())
+            .get_arguments();
+        for arg in &["--mssfix", "--fragment", "--tun-mtu"] {
+            assert!(!testee_args.contains(&OsString::from(arg)));
+        }
+    }
+
+    #[test]
+    fn passes_verbosity() {
+        let options = openvpn::TunnelOptions {
+            verbosity: Some(6),
+            ..openvpn::TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&options)
+            .get_arguments();
+        let expected: Vec<OsString> = ["--verb", "6"].iter().map(OsString::from).collect();
+        assert!(testee_args.windows(2).any(|window| window == &expected[..]));
+        let verb_count = |args: &[OsString]| args.iter().filter(|arg| *arg == "--verb").count();
+        assert_eq!(verb_count(&testee_args), 1);
+
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&openvpn::TunnelOptions::default())
+            .get_arguments();
+        let expected: Vec<OsString> = ["--verb", "3"].iter().map(OsString::from).collect();
+        assert!(testee_args.windows(2).any(|window| window == &expected[..]));
+        assert_eq!(verb_count(&testee_args), 1);
+    }
+
+    #[test]
+    fn passes_ping_options() {
+        let options = openvpn::TunnelOptions {
+            ping: Some(10),
+            ping_restart: Some(60),
+            ..openvpn::TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&options)
+            .get_arguments();
+        for arg in &[["--ping", "10"], ["--ping-restart", "60"]] {
+            let arg: Vec<OsString> = arg.iter().map(OsString::from).collect();
+            assert!(testee_args.windows(2).any(|window| window == &arg[..]));
+        }
+        assert!(!testee_args.contains(&OsString::from("--ping-exit")));
+
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&openvpn::TunnelOptions::default())
+            .get_arguments();
+        for arg in &[["--ping", "4"], ["--ping-exit", "25"]] {
+            let arg: Vec<OsString> = arg.iter().map(OsString::from).collect();
+            assert!(testee_args.windows(2).any(|window| window == &arg[..]));
+        }
+        assert!(!testee_args.contains(&OsString::from("--ping-restart")));
+    }
+
+    #[test]
+    fn passes_connect_retry_options() {
+        let options = openvpn::TunnelOptions {
+            connect_retry: Some(5),
+            connect_retry_max: Some(3),
+            ..openvpn::TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&options)
+            .get_arguments();
+        let expected: Vec<OsString> = ["--connect-retry", "5", "5", "--connect-retry-max", "3"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert!(testee_args.windows(5).any(|window| window == &expected[..]));
+
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&openvpn::TunnelOptions::default())
+            .get_arguments();
+        let expected: Vec<OsString> = ["--connect-retry", "0", "0", "--connect-retry-max", "1"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert!(testee_args.windows(5).any(|window| window == &expected[..]));
+        assert_eq!(
+            testee_args
+                .iter()
+                .filter(|arg| *arg == "--connect-retry-max")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn passes_http_proxy_without_auth() {
+        let testee_args = OpenVpnCommand::new("")
+            .proxy_settings(http_proxy(None))
+            .proxy_auth("./auth")
+            .get_arguments();
+        let expected: Vec<OsString> = [
+            "--http-proxy",
+            "10.0.0.1",
+            "8080",
+            "--route",
+            "10.0.0.1",
+            "255.255.255.255",
+            "net_gateway",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        assert!(testee_args.ends_with(&expected));
+    }
+
+    #[test]
+    fn passes_http_proxy_with_auth() {
+        let auth = openvpn::ProxyAuth {
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .proxy_settings(http_proxy(Some(auth)))
+            .proxy_auth("./auth")
+            .get_arguments();
+        let expected: Vec<OsString> = [
+            "--http-proxy",
+            "10.0.0.1",
+            "8080",
+            "./auth",
+            "basic",
+            "--route",
+            "10.0.0.1",
+            "255.255.255.255",
+            "net_gateway",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        assert!(testee_args.ends_with(&expected));
+    }
+
+    #[test]
+    fn passes_obfuscation_port_as_remote() {
+        let relay = Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 1194, TransportProtocol::Udp);
+        let obfuscation = openvpn::ProxySettings::Obfuscation(openvpn::ObfuscationProxySettings {
+            peer: "192.0.2.1:443".parse().unwrap(),
+            key: "mullvad".to_owned(),
+        });
+        let testee_args = OpenVpnCommand::new("")
+            .remote(relay)
+            .proxy_settings(obfuscation)
+            .proxy_port(5000)
+            .get_arguments();
+
+        let remote: Vec<OsString> = ["--proto", "udp", "--remote", "127.0.0.1", "5000"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert!(testee_args.windows(5).any(|window| window == &remote[..]));
+        let route: Vec<OsString> = ["--route", "192.0.2.1", "255.255.255.255", "net_gateway"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert!(testee_args.ends_with(&route));
+        assert!(!testee_args.contains(&OsString::from("1194")));
+        assert!(!testee_args.contains(&OsString::from("--socks-proxy")));
+    }
+
+    #[test]
+    fn redacts_credential_paths() {
+        let auth = openvpn::ProxyAuth {
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+        };
+        let mut cmd = OpenVpnCommand::new("openvpn");
+        cmd.user_pass("./user-pass")
+            .proxy_settings(http_proxy(Some(auth)))
+            .proxy_auth("./proxy-auth");
+
+        let args = cmd.redacted_arguments();
+        let user_pass_index = args
+            .iter()
+            .position(|arg| arg == "--auth-user-pass")
+            .unwrap();
+        assert_eq!(args[user_pass_index + 1], REDACTED);
+        assert!(!args.iter().any(|arg| arg.contains("./user-pass")));
+        assert!(!args.iter().any(|arg| arg.contains("./proxy-auth")));
+
+        let displayed = cmd.to_string();
+        assert!(!displayed.contains("./user-pass"));
+        assert!(!displayed.contains("./proxy-auth"));
+    }
 }
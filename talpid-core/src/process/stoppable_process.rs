@@ -5,6 +5,45 @@ use std::{
 
 static POLL_INTERVAL_MS: Duration = Duration::from_millis(50);
 
+/// How long to wait for a process at each step before escalating to the next one when stopping
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KillSchedule {
+    /// How long to wait for the process to exit after asking it to stop gracefully.
+    pub stop_timeout: Duration,
+    /// How long to wait for the process to exit after sending it SIGTERM, before killing it. If
+    /// `None`, the process is killed as soon as `stop_timeout` has passed.
+    pub terminate_timeout: Option<Duration>,
+}
+
+impl KillSchedule {
+    /// Returns a schedule that kills the process if it hasn't stopped gracefully within
+    /// `stop_timeout`.
+    pub fn new(stop_timeout: Duration) -> Self {
+        KillSchedule {
+            stop_timeout,
+            terminate_timeout: None,
+        }
+    }
+
+    /// Sends SIGTERM to the process before killing it, and waits `timeout` for it to exit.
+    pub fn terminate_timeout(mut self, timeout: Duration) -> Self {
+        self.terminate_timeout = Some(timeout);
+        self
+    }
+}
+
+/// The step that ultimately stopped a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOutcome {
+    /// The process stopped gracefully.
+    Stopped,
+    /// The process exited after receiving SIGTERM.
+    Terminated,
+    /// The process had to be killed.
+    Killed,
+}
+
 /// A best effort attempt at stopping a subprocess whilst also ensuring that the subprocess is
 /// killed eventually.
 pub trait StoppableProcess
@@ -17,23 +56,56 @@ where
     /// Kills a process unconditionally. Implementations should strive to never fail.
     fn kill(&self) -> io::Result<()>;
 
+    /// Asks a process to terminate by sending it SIGTERM. Fails on platforms without signals.
+    fn terminate(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Terminating processes is not supported",
+        ))
+    }
+
     /// Check if process is stopped. This method must not block.
     fn has_stopped(&self) -> io::Result<bool>;
 
     /// Attempts to stop a process gracefully in the given time period, otherwise kills the
     /// process.
     fn nice_kill(&self, timeout: Duration) -> io::Result<()> {
+        self.escalating_kill(&KillSchedule::new(timeout))
+            .map(|_| ())
+    }
+
+    /// Attempts to stop a process gracefully, then to terminate it, and finally kills it, waiting
+    /// for as long as the schedule says between each step. Returns the step that stopped the
+    /// process.
+    fn escalating_kill(&self, schedule: &KillSchedule) -> io::Result<StopOutcome> {
         log::debug!("Trying to stop child process gracefully");
         self.stop();
-        if wait_timeout(self, timeout)? {
+        if wait_timeout(self, schedule.stop_timeout)? {
             log::debug!("Child process terminated gracefully");
+            return Ok(StopOutcome::Stopped);
+        }
+
+        if let Some(terminate_timeout) = schedule.terminate_timeout {
+            log::warn!(
+                "Child process did not terminate gracefully within timeout, sending SIGTERM"
+            );
+            match self.terminate() {
+                Ok(()) => {
+                    if wait_timeout(self, terminate_timeout)? {
+                        log::debug!("Child process terminated after SIGTERM");
+                        return Ok(StopOutcome::Terminated);
+                    }
+                    log::warn!("Child process ignored SIGTERM, forcing termination");
+                }
+                Err(error) => log::error!("Failed to send SIGTERM to child process: {}", error),
+            }
         } else {
             log::warn!(
                 "Child process did not terminate gracefully within timeout, forcing termination"
             );
-            self.kill()?;
         }
-        Ok(())
+        self.kill()?;
+        Ok(StopOutcome::Killed)
     }
 }
 /// Wait for a process to die for a maximum of `timeout`. Returns true if the process died within
@@ -51,3 +123,95 @@ where
     }
     Ok(false)
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Step {
+        Stop,
+        Terminate,
+        Kill,
+    }
+
+    /// A process that records the steps taken to stop it, and exits once it reaches `exits_on`.
+    struct MockProcess {
+        exits_on: Step,
+        steps: Mutex<Vec<Step>>,
+    }
+
+    impl MockProcess {
+        fn new(exits_on: Step) -> Self {
+            MockProcess {
+                exits_on,
+                steps: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn steps(&self) -> Vec<Step> {
+            self.steps.lock().clone()
+        }
+    }
+
+    impl StoppableProcess for MockProcess {
+        fn stop(&self) {
+            self.steps.lock().push(Step::Stop);
+        }
+
+        fn kill(&self) -> io::Result<()> {
+            self.steps.lock().push(Step::Kill);
+            Ok(())
+        }
+
+        fn terminate(&self) -> io::Result<()> {
+            self.steps.lock().push(Step::Terminate);
+            Ok(())
+        }
+
+        fn has_stopped(&self) -> io::Result<bool> {
+            Ok(self.steps.lock().contains(&self.exits_on))
+        }
+    }
+
+    #[test]
+    fn test_escalation_order() {
+        let schedule = KillSchedule::new(TIMEOUT).terminate_timeout(TIMEOUT);
+
+        let process = MockProcess::new(Step::Kill);
+        assert_eq!(
+            process.escalating_kill(&schedule).unwrap(),
+            StopOutcome::Killed
+        );
+        assert_eq!(process.steps(), [Step::Stop, Step::Terminate, Step::Kill]);
+
+        let process = MockProcess::new(Step::Terminate);
+        assert_eq!(
+            process.escalating_kill(&schedule).unwrap(),
+            StopOutcome::Terminated
+        );
+        assert_eq!(process.steps(), [Step::Stop, Step::Terminate]);
+
+        let process = MockProcess::new(Step::Stop);
+        assert_eq!(
+            process.escalating_kill(&schedule).unwrap(),
+            StopOutcome::Stopped
+        );
+        assert_eq!(process.steps(), [Step::Stop]);
+    }
+
+    #[test]
+    fn test_default_schedule_skips_sigterm() {
+        let process = MockProcess::new(Step::Kill);
+        assert_eq!(
+            process
+                .escalating_kill(&KillSchedule::new(TIMEOUT))
+                .unwrap(),
+            StopOutcome::Killed
+        );
+        assert_eq!(process.steps(), [Step::Stop, Step::Kill]);
+    }
+}
@@ -1,13 +1,67 @@
+mod obfuscation;
 mod shadowsocks;
 
 pub use std::io::Result;
 
-use self::shadowsocks::ShadowsocksProxyMonitor;
-use std::{fmt, path::PathBuf, sync::mpsc};
-use talpid_types::net::openvpn;
+use self::{obfuscation::ObfuscationProxyMonitor, shadowsocks::ShadowsocksProxyMonitor};
+use crate::mktemp;
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    net::{Ipv4Addr, TcpListener, UdpSocket},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::net::openvpn::{self, ProxyPortPreference};
 
+/// The kind of proxy that a `ProxyMonitor` is monitoring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// A generic proxy running on localhost, not managed by us.
+    Local,
+    /// A generic proxy running on a remote host, not managed by us.
+    Remote,
+    /// The bundled Shadowsocks proxy client.
+    Shadowsocks,
+    /// A generic HTTP proxy, not managed by us.
+    Http,
+    /// The bundled obfuscation transport.
+    Obfuscation,
+}
+
+impl fmt::Display for ProxyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyKind::Local => f.write_str("local"),
+            ProxyKind::Remote => f.write_str("remote"),
+            ProxyKind::Shadowsocks => f.write_str("Shadowsocks"),
+            ProxyKind::Http => f.write_str("HTTP"),
+            ProxyKind::Obfuscation => f.write_str("obfuscation"),
+        }
+    }
+}
+
+/// Traffic that a proxy has carried, as counted on its connection to the peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProxyStats {
+    /// Bytes received from the peer.
+    pub bytes_in: u64,
+    /// Bytes sent to the peer.
+    pub bytes_out: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WaitResult {
-    UnexpectedExit(String),
+    /// The proxy exited without being asked to.
+    UnexpectedExit {
+        kind: ProxyKind,
+        /// Exit code of the proxy process, if it could be determined.
+        exit_code: Option<i32>,
+        /// The last lines the proxy process wrote to its output, if available.
+        stderr_tail: Option<String>,
+    },
     ProperShutdown,
 }
 
@@ -20,11 +74,53 @@ pub trait ProxyMonitor: Send {
 
     /// The port bound to.
     fn port(&self) -> u16;
+
+    /// The kind of proxy being monitored.
+    fn kind(&self) -> ProxyKind;
+
+    /// Whether the proxy accepts connections on its port. Proxies that are not managed by us are
+    /// assumed to be ready.
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Traffic carried by the proxy so far, if the proxy reports it.
+    fn proxy_stats(&self) -> Option<ProxyStats> {
+        None
+    }
+
+    /// Blocks until the proxy accepts connections on its port. Fails with
+    /// `io::ErrorKind::TimedOut` if it isn't ready within `timeout`. By default, `is_ready` is
+    /// polled until it returns `true`.
+    fn wait_ready(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.is_ready() {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The proxy did not accept connections in time",
+                ));
+            }
+            thread::sleep(std::cmp::min(READY_POLL_INTERVAL, deadline - now));
+        }
+    }
 }
 
+/// Time between checks of whether a proxy is ready.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 impl fmt::Debug for dyn ProxyMonitor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ProxyMonitor {{ port: {} }}", self.port())
+        write!(
+            f,
+            "ProxyMonitor {{ kind: {:?}, port: {} }}",
+            self.kind(),
+            self.port()
+        )
     }
 }
 
@@ -36,12 +132,13 @@ struct NoopProxyMonitor {
     tx: mpsc::Sender<()>,
     rx: mpsc::Receiver<()>,
     port: u16,
+    kind: ProxyKind,
 }
 
 impl NoopProxyMonitor {
-    fn start(port: u16) -> Result<Self> {
+    fn start(port: u16, kind: ProxyKind) -> Result<Self> {
         let (tx, rx) = mpsc::channel();
-        Ok(NoopProxyMonitor { tx, rx, port })
+        Ok(NoopProxyMonitor { tx, rx, port, kind })
     }
 }
 
@@ -60,6 +157,10 @@ impl ProxyMonitor for NoopProxyMonitor {
     fn port(&self) -> u16 {
         self.port
     }
+
+    fn kind(&self) -> ProxyKind {
+        self.kind
+    }
 }
 
 struct NoopProxyMonitorCloseHandle {
@@ -81,23 +182,278 @@ pub struct ProxyResourceData {
     pub log_dir: Option<PathBuf>,
 }
 
+/// Returns the loopback port that a proxy should bind to, given the configured preference.
+/// Zero is returned when the proxy should bind to an ephemeral port.
+pub fn select_local_port(
+    preference: Option<ProxyPortPreference>,
+    mut is_available: impl FnMut(u16) -> bool,
+) -> Result<u16> {
+    match preference {
+        None => Ok(0),
+        Some(ProxyPortPreference::Preferred(port)) => {
+            if port != 0 && is_available(port) {
+                Ok(port)
+            } else {
+                log::warn!(
+                    "Preferred proxy port {} is unavailable. Using an ephemeral port",
+                    port
+                );
+                Ok(0)
+            }
+        }
+        Some(ProxyPortPreference::Range { start, end }) => {
+            if start == 0 || start > end {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid proxy port range {}-{}", start, end),
+                ));
+            }
+            (start..=end)
+                .find(|port| is_available(*port))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("Every proxy port in the range {}-{} is in use", start, end),
+                    )
+                })
+        }
+    }
+}
+
+/// Checks whether a TCP port is free on the loopback interface.
+fn is_loopback_port_available(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+/// Checks whether a UDP port is free on the loopback interface.
+fn is_loopback_udp_port_available(port: u16) -> bool {
+    UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+/// Returns a UDP port on the loopback interface that is currently free.
+fn ephemeral_udp_port() -> Result<u16> {
+    Ok(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port())
+}
+
+/// Writes `contents` to a new temporary file that only the owner can read. Used to keep secrets
+/// off the command line of proxy processes. The file is removed when dropped.
+fn create_secret_file(contents: &[u8]) -> Result<mktemp::TempFile> {
+    let temp_file = mktemp::TempFile::new();
+    let mut file = fs::File::create(&temp_file)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(PermissionsExt::from_mode(0o400))?;
+    }
+    file.write_all(contents)?;
+    Ok(temp_file)
+}
+
 pub fn start_proxy(
     settings: &openvpn::ProxySettings,
+    port_preference: Option<ProxyPortPreference>,
     resource_data: &ProxyResourceData,
 ) -> Result<Box<dyn ProxyMonitor>> {
     match settings {
         openvpn::ProxySettings::Local(local_settings) => {
             // These are generic proxy settings with the proxy client not managed by us.
-            Ok(Box::new(NoopProxyMonitor::start(local_settings.port)?))
+            Ok(Box::new(NoopProxyMonitor::start(
+                local_settings.port,
+                ProxyKind::Local,
+            )?))
         }
         openvpn::ProxySettings::Remote(remote_settings) => {
             // These are generic proxy settings with the proxy client not managed by us.
             Ok(Box::new(NoopProxyMonitor::start(
                 remote_settings.address.port(),
+                ProxyKind::Remote,
             )?))
         }
-        openvpn::ProxySettings::Shadowsocks(ss_settings) => Ok(Box::new(
-            ShadowsocksProxyMonitor::start(ss_settings, resource_data)?,
-        )),
+        openvpn::ProxySettings::Http(http_settings) => {
+            // These are generic proxy settings with the proxy client not managed by us.
+            Ok(Box::new(NoopProxyMonitor::start(
+                http_settings.address.port(),
+                ProxyKind::Http,
+            )?))
+        }
+        openvpn::ProxySettings::Shadowsocks(ss_settings) => {
+            Ok(Box::new(ShadowsocksProxyMonitor::start(
+                ss_settings,
+                select_local_port(port_preference, is_loopback_port_available)?,
+                resource_data,
+            )?))
+        }
+        openvpn::ProxySettings::Obfuscation(obfuscation_settings) => {
+            Ok(Box::new(ObfuscationProxyMonitor::start(
+                obfuscation_settings,
+                select_local_port(port_preference, is_loopback_udp_port_available)?,
+                resource_data,
+            )?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Close handle of a fake proxy monitor, which has nothing to close.
+    struct FakeCloseHandle;
+
+    impl ProxyMonitorCloseHandle for FakeCloseHandle {
+        fn close(self: Box<Self>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Monitor that binds to the port chosen by `select_local_port`, treating `occupied` as taken.
+    struct FakeProxyMonitor {
+        port: u16,
+    }
+
+    impl FakeProxyMonitor {
+        fn start(preference: Option<ProxyPortPreference>, occupied: &[u16]) -> Result<Self> {
+            let port = select_local_port(preference, |port| !occupied.contains(&port))?;
+            Ok(FakeProxyMonitor { port })
+        }
+    }
+
+    impl ProxyMonitor for FakeProxyMonitor {
+        fn close_handle(&mut self) -> Box<dyn ProxyMonitorCloseHandle> {
+            Box::new(FakeCloseHandle)
+        }
+
+        fn wait(self: Box<Self>) -> Result<WaitResult> {
+            Ok(WaitResult::ProperShutdown)
+        }
+
+        fn port(&self) -> u16 {
+            self.port
+        }
+
+        fn kind(&self) -> ProxyKind {
+            ProxyKind::Shadowsocks
+        }
+    }
+
+    /// Monitor of a proxy that starts accepting connections at `ready_at`.
+    struct DelayedProxyMonitor {
+        ready_at: Instant,
+    }
+
+    impl ProxyMonitor for DelayedProxyMonitor {
+        fn close_handle(&mut self) -> Box<dyn ProxyMonitorCloseHandle> {
+            Box::new(FakeCloseHandle)
+        }
+
+        fn wait(self: Box<Self>) -> Result<WaitResult> {
+            Ok(WaitResult::ProperShutdown)
+        }
+
+        fn port(&self) -> u16 {
+            0
+        }
+
+        fn kind(&self) -> ProxyKind {
+            ProxyKind::Shadowsocks
+        }
+
+        fn is_ready(&self) -> bool {
+            Instant::now() >= self.ready_at
+        }
+    }
+
+    #[test]
+    fn test_secret_file() {
+        let file = create_secret_file(b"secret").unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"secret");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o400);
+        }
+        let path = file.to_path_buf();
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_wait_ready() {
+        let started = Instant::now();
+        let monitor = DelayedProxyMonitor {
+            ready_at: started + Duration::from_millis(200),
+        };
+        assert!(monitor.wait_ready(Duration::from_secs(5)).is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_wait_ready_timeout() {
+        let monitor = DelayedProxyMonitor {
+            ready_at: Instant::now() + Duration::from_secs(60),
+        };
+        let started = Instant::now();
+        assert_eq!(
+            monitor
+                .wait_ready(Duration::from_millis(200))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_no_preference_uses_ephemeral_port() {
+        let monitor = FakeProxyMonitor::start(None, &[]).unwrap();
+        assert_eq!(monitor.port(), 0);
+    }
+
+    #[test]
+    fn test_port_range_honored() {
+        let range = ProxyPortPreference::Range {
+            start: 41000,
+            end: 41003,
+        };
+        let monitor = FakeProxyMonitor::start(Some(range), &[41000, 41001]).unwrap();
+        assert_eq!(monitor.port(), 41002);
+    }
+
+    #[test]
+    fn test_port_range_occupied() {
+        let range = ProxyPortPreference::Range {
+            start: 41000,
+            end: 41001,
+        };
+        let error = FakeProxyMonitor::start(Some(range), &[41000, 41001])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+    }
+
+    #[test]
+    fn test_invalid_port_range() {
+        let range = ProxyPortPreference::Range {
+            start: 41001,
+            end: 41000,
+        };
+        let error = FakeProxyMonitor::start(Some(range), &[]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_preferred_port_falls_back_to_ephemeral() {
+        let preferred = Some(ProxyPortPreference::Preferred(41000));
+        assert_eq!(
+            FakeProxyMonitor::start(preferred, &[]).unwrap().port(),
+            41000
+        );
+        assert_eq!(
+            FakeProxyMonitor::start(preferred, &[41000]).unwrap().port(),
+            0
+        );
     }
 }
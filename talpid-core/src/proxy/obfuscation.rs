@@ -0,0 +1,202 @@
+pub use std::io::Result;
+
+use crate::{logging, mktemp};
+
+use std::{
+    env,
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{ProxyKind, ProxyMonitor, ProxyMonitorCloseHandle, ProxyResourceData, WaitResult};
+use talpid_types::net::openvpn::ObfuscationProxySettings;
+
+const OBFUSCATION_LOG_FILENAME: &str = "obfuscation.log";
+/// Number of log lines to include when reporting that the transport exited unexpectedly.
+const OBFUSCATION_LOG_TAIL_LINES: usize = 10;
+#[cfg(unix)]
+const OBFUSCATION_BIN_FILENAME: &str = "udp2raw";
+#[cfg(windows)]
+const OBFUSCATION_BIN_FILENAME: &str = "udp2raw.exe";
+
+/// Returns the arguments that make the obfuscation client accept OpenVPN traffic on `local` and
+/// forward it, disguised as TCP, to the peer. The key is read from `conf_file`, so that it doesn't
+/// show up on the command line.
+fn get_arguments(
+    settings: &ObfuscationProxySettings,
+    local: SocketAddr,
+    conf_file: &Path,
+) -> Vec<String> {
+    vec![
+        "-c".to_owned(),
+        "-l".to_owned(),
+        local.to_string(),
+        "-r".to_owned(),
+        settings.peer.to_string(),
+        "--raw-mode".to_owned(),
+        "faketcp".to_owned(),
+        "--conf-file".to_owned(),
+        conf_file.display().to_string(),
+    ]
+}
+
+/// Returns the contents of the configuration file that holds the key.
+fn get_conf_file_contents(settings: &ObfuscationProxySettings) -> String {
+    format!("-k {}\n", settings.key)
+}
+
+/// Monitors a bundled obfuscation transport. Unlike the other proxies, it isn't a SOCKS proxy:
+/// OpenVPN connects to its UDP port as if it were the relay.
+pub struct ObfuscationProxyMonitor {
+    subproc: Arc<duct::Handle>,
+    closed: Arc<AtomicBool>,
+    port: u16,
+    logfile: PathBuf,
+    _conf_file: mktemp::TempFile,
+}
+
+impl ObfuscationProxyMonitor {
+    /// Starts the transport on `local_port`, or on an ephemeral port if it is zero.
+    pub fn start(
+        settings: &ObfuscationProxySettings,
+        local_port: u16,
+        resource_data: &ProxyResourceData,
+    ) -> Result<Self> {
+        let binary = resource_data.resource_dir.join(OBFUSCATION_BIN_FILENAME);
+        let port = if local_port == 0 {
+            super::ephemeral_udp_port()?
+        } else {
+            local_port
+        };
+
+        let log_dir: PathBuf = if let Some(ref log_dir) = resource_data.log_dir {
+            log_dir.clone()
+        } else {
+            env::temp_dir()
+        };
+        let logfile = log_dir.join(OBFUSCATION_LOG_FILENAME);
+        logging::rotate_log(&logfile)
+            .map_err(|_| Error::new(ErrorKind::Other, "Failed to rotate log file"))?;
+
+        let conf_file = super::create_secret_file(get_conf_file_contents(settings).as_bytes())?;
+        let args = get_arguments(
+            settings,
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            conf_file.as_ref(),
+        );
+        log::debug!("Starting obfuscation transport: {:?} {:?}", binary, args);
+        let subproc = duct::cmd(binary, args)
+            .unchecked()
+            .stdin_null()
+            .stderr_to_stdout()
+            .stdout_path(&logfile)
+            .start()?;
+
+        Ok(Self {
+            subproc: Arc::new(subproc),
+            closed: Arc::new(AtomicBool::new(false)),
+            port,
+            logfile,
+            _conf_file: conf_file,
+        })
+    }
+
+    /// Returns the last lines written by the transport, if any.
+    fn read_log_tail(logfile: &Path) -> Option<String> {
+        let lines = logging::tail(logfile, OBFUSCATION_LOG_TAIL_LINES).ok()?;
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+}
+
+impl ProxyMonitor for ObfuscationProxyMonitor {
+    fn close_handle(&mut self) -> Box<dyn ProxyMonitorCloseHandle> {
+        Box::new(ObfuscationProxyMonitorCloseHandle {
+            subproc: self.subproc.clone(),
+            closed: self.closed.clone(),
+        })
+    }
+
+    fn wait(self: Box<Self>) -> Result<WaitResult> {
+        let output = self.subproc.wait()?;
+        if self.closed.load(Ordering::SeqCst) {
+            Ok(WaitResult::ProperShutdown)
+        } else {
+            Ok(WaitResult::UnexpectedExit {
+                kind: ProxyKind::Obfuscation,
+                exit_code: output.status.code(),
+                stderr_tail: Self::read_log_tail(&self.logfile),
+            })
+        }
+    }
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn kind(&self) -> ProxyKind {
+        ProxyKind::Obfuscation
+    }
+
+    /// There's no way to probe a UDP port, so the transport is ready for as long as it runs.
+    fn is_ready(&self) -> bool {
+        matches!(self.subproc.try_wait(), Ok(None))
+    }
+}
+
+pub struct ObfuscationProxyMonitorCloseHandle {
+    subproc: Arc<duct::Handle>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ProxyMonitorCloseHandle for ObfuscationProxyMonitorCloseHandle {
+    fn close(self: Box<Self>) -> Result<()> {
+        if !self.closed.swap(true, Ordering::SeqCst) {
+            self.subproc.kill()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arguments() {
+        let settings = ObfuscationProxySettings {
+            peer: "192.0.2.1:443".parse().unwrap(),
+            key: "mullvad".to_owned(),
+        };
+        let args = get_arguments(
+            &settings,
+            "127.0.0.1:1194".parse().unwrap(),
+            Path::new("udp2raw.conf"),
+        );
+        assert_eq!(
+            args,
+            [
+                "-c",
+                "-l",
+                "127.0.0.1:1194",
+                "-r",
+                "192.0.2.1:443",
+                "--raw-mode",
+                "faketcp",
+                "--conf-file",
+                "udp2raw.conf"
+            ]
+        );
+        assert!(!args.contains(&settings.key));
+        assert_eq!(get_conf_file_contents(&settings), "-k mullvad\n");
+    }
+}
@@ -1,6 +1,6 @@
 pub use std::io::Result;
 
-use crate::logging;
+use crate::{logging, mktemp};
 use regex::Regex;
 
 use std::{
@@ -10,8 +10,8 @@ use std::{
     fmt,
     fs::File,
     io::{BufRead, Error, ErrorKind},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -20,16 +20,14 @@ use std::{
     time::Duration,
 };
 
-use super::{ProxyMonitor, ProxyMonitorCloseHandle, ProxyResourceData, WaitResult};
+use super::{ProxyKind, ProxyMonitor, ProxyMonitorCloseHandle, ProxyResourceData, WaitResult};
 use talpid_types::net::openvpn::ShadowsocksProxySettings;
 
 struct ShadowsocksCommand {
     shadowsocks_bin: OsString,
     local: Option<SocketAddr>,
-    peer: Option<SocketAddr>,
-    peer_password: Option<String>,
-    // This should map to the shadowsocks-rust `CipherType` type.
-    cipher: Option<String>,
+    /// Configuration file that holds the peer, including its password.
+    config: Option<PathBuf>,
 }
 
 impl ShadowsocksCommand {
@@ -37,9 +35,7 @@ impl ShadowsocksCommand {
         ShadowsocksCommand {
             shadowsocks_bin,
             local: None,
-            peer: None,
-            peer_password: None,
-            cipher: None,
+            config: None,
         }
     }
 
@@ -48,18 +44,8 @@ impl ShadowsocksCommand {
         self
     }
 
-    pub fn peer(&mut self, peer: SocketAddr) -> &mut Self {
-        self.peer = Some(peer);
-        self
-    }
-
-    pub fn peer_password(&mut self, password: String) -> &mut Self {
-        self.peer_password = Some(password);
-        self
-    }
-
-    pub fn cipher(&mut self, cipher: String) -> &mut Self {
-        self.cipher = Some(cipher);
+    pub fn config(&mut self, config: impl AsRef<Path>) -> &mut Self {
+        self.config = Some(config.as_ref().to_path_buf());
         self
     }
 
@@ -79,25 +65,28 @@ impl ShadowsocksCommand {
             args.push(format!("{}:{}", local.ip(), local.port()));
         }
 
-        if let Some(ref peer) = self.peer {
-            args.push("--server-addr".to_owned());
-            args.push(format!("{}:{}", peer.ip(), peer.port()));
-        }
-
-        if let Some(ref peer_password) = self.peer_password {
-            args.push("--password".to_owned());
-            args.push(peer_password.to_owned());
-        }
-
-        if let Some(ref cipher) = self.cipher {
-            args.push("--encrypt-method".to_owned());
-            args.push(cipher.to_string());
+        if let Some(ref config) = self.config {
+            args.push("--config".to_owned());
+            args.push(config.display().to_string());
         }
 
         args
     }
 }
 
+/// Returns the contents of the sslocal configuration file for `settings`. The password is passed
+/// this way so that it doesn't show up on the command line.
+fn get_config_contents(settings: &ShadowsocksProxySettings) -> String {
+    serde_json::json!({
+        "server": settings.peer.ip().to_string(),
+        "server_port": settings.peer.port(),
+        "password": settings.password,
+        // This should map to the shadowsocks-rust `CipherType` type.
+        "method": settings.cipher,
+    })
+    .to_string()
+}
+
 impl fmt::Display for ShadowsocksCommand {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.write_str(&shell_escape::escape(
@@ -115,9 +104,15 @@ pub struct ShadowsocksProxyMonitor {
     subproc: Arc<duct::Handle>,
     closed: Arc<AtomicBool>,
     port: u16,
+    logfile: PathBuf,
+    _config_file: mktemp::TempFile,
 }
 
 const SHADOWSOCKS_LOG_FILENAME: &str = "shadowsocks.log";
+/// Number of log lines to include when reporting that the proxy exited unexpectedly.
+const SHADOWSOCKS_LOG_TAIL_LINES: usize = 10;
+/// How long to wait for the proxy to accept a connection when checking whether it is ready.
+const READY_CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
 #[cfg(unix)]
 const SHADOWSOCKS_BIN_FILENAME: &str = "sslocal";
 #[cfg(windows)]
@@ -126,6 +121,7 @@ const SHADOWSOCKS_BIN_FILENAME: &str = "sslocal.exe";
 impl ShadowsocksProxyMonitor {
     pub fn start(
         settings: &ShadowsocksProxySettings,
+        local_port: u16,
         resource_data: &ProxyResourceData,
     ) -> Result<Self> {
         let binary = resource_data
@@ -133,11 +129,13 @@ impl ShadowsocksProxyMonitor {
             .join(SHADOWSOCKS_BIN_FILENAME)
             .into_os_string();
 
+        let config_file = super::create_secret_file(get_config_contents(settings).as_bytes())?;
         let mut cmd = ShadowsocksCommand::new(binary)
-            .local(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0))
-            .peer(settings.peer)
-            .peer_password(settings.password.clone())
-            .cipher(settings.cipher.clone())
+            .local(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                local_port,
+            ))
+            .config(&config_file)
             .build();
 
         let log_dir: PathBuf = if let Some(ref log_dir) = resource_data.log_dir {
@@ -160,6 +158,8 @@ impl ShadowsocksProxyMonitor {
                 subproc: Arc::new(subproc),
                 closed: Arc::new(AtomicBool::new(false)),
                 port,
+                logfile,
+                _config_file: config_file,
             }),
             Err(err) => {
                 let _ = subproc.kill();
@@ -214,6 +214,16 @@ impl ShadowsocksProxyMonitor {
 
         Err(Error::new(ErrorKind::Other, "No port number present"))
     }
+
+    /// Returns the last lines written by the proxy, if any. Stderr is redirected to the log file.
+    fn read_log_tail(logfile: &Path) -> Option<String> {
+        let lines = logging::tail(logfile, SHADOWSOCKS_LOG_TAIL_LINES).ok()?;
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
 }
 
 impl ProxyMonitor for ShadowsocksProxyMonitor {
@@ -229,13 +239,11 @@ impl ProxyMonitor for ShadowsocksProxyMonitor {
             if self.closed.load(Ordering::SeqCst) {
                 Ok(WaitResult::ProperShutdown)
             } else {
-                Ok(WaitResult::UnexpectedExit(
-                    if let Some(exit_code) = output.status.code() {
-                        format!("Exit code: {}", exit_code)
-                    } else {
-                        "Exit code is indeterminable".to_string()
-                    },
-                ))
+                Ok(WaitResult::UnexpectedExit {
+                    kind: ProxyKind::Shadowsocks,
+                    exit_code: output.status.code(),
+                    stderr_tail: Self::read_log_tail(&self.logfile),
+                })
             }
         })?
     }
@@ -243,6 +251,15 @@ impl ProxyMonitor for ShadowsocksProxyMonitor {
     fn port(&self) -> u16 {
         self.port
     }
+
+    fn kind(&self) -> ProxyKind {
+        ProxyKind::Shadowsocks
+    }
+
+    fn is_ready(&self) -> bool {
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, self.port));
+        TcpStream::connect_timeout(&address, READY_CONNECT_TIMEOUT).is_ok()
+    }
 }
 
 pub struct ShadowsocksProxyMonitorCloseHandle {
@@ -259,3 +276,43 @@ impl ProxyMonitorCloseHandle for ShadowsocksProxyMonitorCloseHandle {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_not_in_arguments() {
+        let settings = ShadowsocksProxySettings {
+            peer: "192.0.2.1:443".parse().unwrap(),
+            password: "mullvad".to_owned(),
+            cipher: "aes-256-gcm".to_owned(),
+        };
+        let mut cmd = ShadowsocksCommand::new(OsString::from("sslocal"));
+        cmd.local("127.0.0.1:1080".parse().unwrap())
+            .config("sslocal.json");
+        assert_eq!(
+            cmd.get_arguments(),
+            [
+                "--no-delay",
+                "--local-addr",
+                "127.0.0.1:1080",
+                "--config",
+                "sslocal.json"
+            ]
+        );
+        assert!(!cmd.to_string().contains(&settings.password));
+
+        let config: serde_json::Value =
+            serde_json::from_str(&get_config_contents(&settings)).unwrap();
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "server": "192.0.2.1",
+                "server_port": 443,
+                "password": "mullvad",
+                "method": "aes-256-gcm",
+            })
+        );
+    }
+}
@@ -1,5 +1,8 @@
 use crate::{
-    routing::{imp::RouteManagerCommand, NetNode, Node, RequiredRoute, Route},
+    routing::{
+        imp::{DefaultRouteListeners, DesiredRoutes, RouteFilter, RouteManagerCommand},
+        NetNode, Node, RequiredRoute, Route,
+    },
     split_tunnel,
 };
 
@@ -108,8 +111,12 @@ pub struct RouteManagerImpl {
     default_routes: HashSet<Route>,
     best_default_node_v4: Option<Node>,
     best_default_node_v6: Option<Node>,
+    default_route_listeners: DefaultRouteListeners,
 
     split_table_id: i32,
+
+    // routes to reapply when resuming from being suspended
+    desired_routes: DesiredRoutes,
 }
 
 impl RouteManagerImpl {
@@ -140,8 +147,11 @@ impl RouteManagerImpl {
             default_routes: HashSet::new(),
             best_default_node_v4: None,
             best_default_node_v6: None,
+            default_route_listeners: DefaultRouteListeners::default(),
 
             split_table_id,
+
+            desired_routes: DesiredRoutes::default(),
         };
 
         monitor.default_routes = monitor.get_default_routes().await?;
@@ -325,6 +335,10 @@ impl RouteManagerImpl {
     }
 
     async fn add_required_routes(&mut self, required_routes: HashSet<RequiredRoute>) -> Result<()> {
+        if !self.desired_routes.insert(&required_routes) {
+            return Ok(());
+        }
+
         let mut required_normal_routes = HashSet::new();
         let mut required_default_routes = HashSet::new();
 
@@ -436,66 +450,56 @@ impl RouteManagerImpl {
     }
 
     async fn update_default_routes(&mut self) -> Result<()> {
-        let new_best_v4 = Self::pick_best_default_node(&self.default_routes, IpVersion::V4);
-        if self.best_default_node_v4 != new_best_v4 && new_best_v4.is_some() {
-            let new_node = new_best_v4.unwrap();
-            let old_node = self.best_default_node_v4.take();
-            let v4_routes: Vec<_> = self
-                .required_default_routes
-                .iter()
-                .filter(|ip| ip.destination.is_ipv4())
-                .cloned()
-                .collect();
-            for route in v4_routes {
-                let new_route =
-                    Route::new(new_node.clone(), route.destination).table(route.table_id);
-
-                if let Some(old_node) = &old_node {
-                    let old_route =
-                        Route::new(old_node.clone(), route.destination).table(route.table_id);
+        let mut changed = false;
 
-                    if let Err(e) = self.delete_route(&old_route).await {
-                        log::error!("Failed to remove old route {} - {}", &old_route, e);
-                    }
-                }
-                if let Err(e) = self.add_route(new_route).await {
-                    log::error!("Failed to add new route {} - {}", &new_node, e);
-                }
+        let new_best_v4 = Self::pick_best_default_node(&self.default_routes, IpVersion::V4);
+        if let Some(new_node) = new_best_v4 {
+            if self.best_default_node_v4.as_ref() != Some(&new_node) {
+                let old_node = self.best_default_node_v4.take();
+                self.reroute_default_routes(IpVersion::V4, old_node.as_ref(), &new_node)
+                    .await;
+                self.best_default_node_v4 = Some(new_node);
+                changed = true;
             }
-            self.best_default_node_v4 = Some(new_node);
         }
 
+        // The IPv6 default route can change independently of the IPv4 one on dual-stack systems.
         let new_best_v6 = Self::pick_best_default_node(&self.default_routes, IpVersion::V6);
-        if self.best_default_node_v6 != new_best_v6 && new_best_v6.is_some() {
-            let new_node = new_best_v6.unwrap();
-            let old_node = self.best_default_node_v6.take();
-            let v6_routes: Vec<_> = self
-                .required_default_routes
-                .iter()
-                .filter(|ip| !ip.destination.is_ipv4())
-                .cloned()
-                .collect();
-
-            for route in v6_routes {
-                let new_route =
-                    Route::new(new_node.clone(), route.destination).table(route.table_id);
+        if let Some(new_node) = new_best_v6 {
+            if self.best_default_node_v6.as_ref() != Some(&new_node) {
+                let old_node = self.best_default_node_v6.take();
+                self.reroute_default_routes(IpVersion::V6, old_node.as_ref(), &new_node)
+                    .await;
+                self.best_default_node_v6 = Some(new_node);
+                changed = true;
+            }
+        }
 
-                if let Some(old_node) = &old_node {
-                    let old_route =
-                        Route::new(old_node.clone(), route.destination).table(route.table_id);
+        if changed {
+            self.default_route_listeners.notify();
+        }
+        Ok(())
+    }
 
-                    if let Err(e) = self.delete_route(&old_route).await {
-                        log::error!("Failed to remove old route {} - {}", &old_route, e);
-                    }
-                }
-                if let Err(e) = self.add_route(new_route).await {
-                    log::error!("Failed to add new route {} - {}", &new_node, e);
+    /// Moves the required default routes of the given IP version from `old_node` to `new_node`.
+    async fn reroute_default_routes(
+        &mut self,
+        version: IpVersion,
+        old_node: Option<&Node>,
+        new_node: &Node,
+    ) {
+        let routes =
+            rerouted_default_routes(&self.required_default_routes, version, old_node, new_node);
+        for (old_route, new_route) in routes {
+            if let Some(old_route) = old_route {
+                if let Err(e) = self.delete_route(&old_route).await {
+                    log::error!("Failed to remove old route {} - {}", &old_route, e);
                 }
             }
-            self.best_default_node_v6 = Some(new_node);
+            if let Err(e) = self.add_route(new_route).await {
+                log::error!("Failed to add new route {} - {}", new_node, e);
+            }
         }
-
-        Ok(())
     }
 
     fn pick_best_default_node(routes: &HashSet<Route>, version: IpVersion) -> Option<Node> {
@@ -535,32 +539,59 @@ impl RouteManagerImpl {
             let route =
                 Route::new(best_node, required_route.destination).table(required_route.table_id);
             if let Err(e) = self.delete_route(&route).await {
-                if let Error::NetlinkError(err) = &e {
-                    if let rtnetlink::ErrorKind::NetlinkError(msg) = err.get_ref().kind() {
-                        // -3 means that the route doesn't exist anymore anyway
-                        if msg.code == -3 {
-                            continue;
-                        }
-                    }
+                if !is_missing_route_error(&e) {
+                    log::error!("Failed to remove route - {} - {}", route, e);
                 }
-                log::error!("Failed to remove route - {} - {}", route, e);
             }
         }
         self.required_default_routes.clear();
 
         for route in self.added_routes.drain().collect::<Vec<_>>().iter() {
             if let Err(e) = self.delete_route(&route).await {
-                if let Error::NetlinkError(err) = &e {
-                    if let rtnetlink::ErrorKind::NetlinkError(msg) = err.get_ref().kind() {
-                        // -3 means that the route doesn't exist anymore anyway
-                        if msg.code == -3 {
-                            continue;
-                        }
-                    }
+                if !is_missing_route_error(&e) {
+                    log::error!("Failed to remove route - {} - {}", route, e);
+                }
+            }
+        }
+    }
+
+    /// Removes the added routes selected by `filter`, and forgets the required routes they were
+    /// added for. Returns the first error, after having tried to remove every selected route.
+    async fn clear_matching(&mut self, filter: &RouteFilter) -> Result<()> {
+        let mut result = Ok(());
+        for route in &self.forget_matching(filter) {
+            if let Err(e) = self.delete_route(route).await {
+                if is_missing_route_error(&e) {
+                    continue;
                 }
                 log::error!("Failed to remove route - {} - {}", route, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
             }
         }
+        result
+    }
+
+    /// Forgets the added routes selected by `filter` and the required routes they were added for,
+    /// without removing them from the routing table. Returns the forgotten routes.
+    fn forget_matching(&mut self, filter: &RouteFilter) -> Vec<Route> {
+        let routes: Vec<Route> = self
+            .added_routes
+            .iter()
+            .filter(|route| filter.matches(route))
+            .cloned()
+            .collect();
+
+        for route in &routes {
+            self.added_routes.remove(route);
+            self.required_default_routes.retain(|required| {
+                required.table_id != route.table_id || required.destination != route.prefix
+            });
+            self.desired_routes
+                .retain(|required| !route.applies(required));
+        }
+        routes
     }
 
 
@@ -593,6 +624,9 @@ impl RouteManagerImpl {
                 log::debug!("Adding routes: {:?}", routes);
                 let _ = result_rx.send(self.add_required_routes(routes.clone()).await);
             }
+            RouteManagerCommand::DefaultRouteChanges(listener) => {
+                self.default_route_listeners.add(listener);
+            }
             RouteManagerCommand::EnableExclusionsRoutes(result_rx) => {
                 let _ = result_rx.send(self.enable_exclusions_routes().await);
             }
@@ -606,6 +640,31 @@ impl RouteManagerImpl {
             RouteManagerCommand::ClearRoutes => {
                 log::debug!("Clearing routes");
                 self.cleanup_routes().await;
+                self.desired_routes.clear();
+            }
+            RouteManagerCommand::ClearMatching(filter, result_tx) => {
+                log::debug!("Clearing matching routes");
+                let _ = result_tx.send(self.clear_matching(&filter).await);
+            }
+            RouteManagerCommand::GetRoutes(result_tx) => {
+                let _ = result_tx.send(self.added_routes.iter().cloned().collect());
+            }
+            RouteManagerCommand::Suspend(wait_tx) => {
+                if self.desired_routes.suspend() {
+                    log::debug!("Suspending routes");
+                    self.cleanup_routes().await;
+                }
+                let _ = wait_tx.send(());
+            }
+            RouteManagerCommand::Resume(result_tx) => {
+                let result = match self.desired_routes.resume() {
+                    Some(routes) => {
+                        log::debug!("Resuming routes: {:?}", routes);
+                        self.add_required_routes(routes).await
+                    }
+                    None => Ok(()),
+                };
+                let _ = result_tx.send(result);
             }
         }
         Ok(())
@@ -873,6 +932,39 @@ impl Drop for RouteManagerImpl {
     }
 }
 
+/// Returns the routes that replace each other when the best default node for `version` changes
+/// from `old_node` to `new_node`, as pairs of the route to remove, if any, and the route to add.
+/// Required default routes of the other IP version are unaffected.
+fn rerouted_default_routes(
+    required_default_routes: &HashSet<RequiredDefaultRoute>,
+    version: IpVersion,
+    old_node: Option<&Node>,
+    new_node: &Node,
+) -> Vec<(Option<Route>, Route)> {
+    let ipv4 = version == IpVersion::V4;
+    required_default_routes
+        .iter()
+        .filter(|route| route.destination.is_ipv4() == ipv4)
+        .map(|route| {
+            let old_route = old_node.map(|old_node| {
+                Route::new(old_node.clone(), route.destination).table(route.table_id)
+            });
+            let new_route = Route::new(new_node.clone(), route.destination).table(route.table_id);
+            (old_route, new_route)
+        })
+        .collect()
+}
+
+/// Returns whether `error` means that the route to delete didn't exist anymore anyway.
+fn is_missing_route_error(error: &Error) -> bool {
+    if let Error::NetlinkError(err) = error {
+        if let rtnetlink::ErrorKind::NetlinkError(msg) = err.get_ref().kind() {
+            return msg.code == -3;
+        }
+    }
+    false
+}
+
 fn ip_to_bytes(addr: IpAddr) -> Vec<u8> {
     match addr {
         IpAddr::V4(addr) => addr.octets().to_vec(),
@@ -900,6 +992,120 @@ mod test {
     use std::collections::HashSet;
 
 
+    #[test]
+    fn test_ipv6_default_route_change() {
+        let required_default_routes: HashSet<RequiredDefaultRoute> = vec![
+            RequiredDefaultRoute {
+                table_id: RT_TABLE_MAIN,
+                destination: "193.138.218.0/24".parse().unwrap(),
+            },
+            RequiredDefaultRoute {
+                table_id: RT_TABLE_MAIN,
+                destination: "2a03:1b20::/32".parse().unwrap(),
+            },
+            RequiredDefaultRoute {
+                table_id: 100,
+                destination: "::/0".parse().unwrap(),
+            },
+        ]
+        .into_iter()
+        .collect();
+        let old_node = Node::new("fe80::1".parse().unwrap(), "eth0".to_string());
+        let new_node = Node::new("fe80::2".parse().unwrap(), "wlan0".to_string());
+
+        let mut routes = rerouted_default_routes(
+            &required_default_routes,
+            IpVersion::V6,
+            Some(&old_node),
+            &new_node,
+        );
+        routes.sort_by_key(|(_, new_route)| new_route.table_id);
+
+        assert_eq!(
+            routes,
+            vec![
+                (
+                    Some(Route::new(
+                        old_node.clone(),
+                        "2a03:1b20::/32".parse().unwrap()
+                    )),
+                    Route::new(new_node.clone(), "2a03:1b20::/32".parse().unwrap()),
+                ),
+                (
+                    Some(Route::new(old_node, "::/0".parse().unwrap()).table(100)),
+                    Route::new(new_node.clone(), "::/0".parse().unwrap()).table(100),
+                ),
+            ]
+        );
+
+        // The IPv4 routes are rerouted only when the IPv4 default route changes.
+        let v4_node = Node::address("192.168.1.1".parse().unwrap());
+        assert_eq!(
+            rerouted_default_routes(&required_default_routes, IpVersion::V4, None, &v4_node),
+            vec![(
+                None,
+                Route::new(v4_node.clone(), "193.138.218.0/24".parse().unwrap())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_forget_matching_keeps_other_routes() {
+        let mut runtime = tokio02::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let mut manager = runtime.block_on(async {
+            RouteManagerImpl::new(HashSet::new())
+                .await
+                .expect("Failed to initialize route manager")
+        });
+
+        let tunnel_node = Node::device("tun0".to_string());
+        let cleared = Route::new(tunnel_node.clone(), "10.64.0.1/32".parse().unwrap());
+        let kept = Route::new(tunnel_node.clone(), "0.0.0.0/1".parse().unwrap());
+        let kept_default = RequiredDefaultRoute {
+            table_id: RT_TABLE_MAIN,
+            destination: "193.138.218.0/24".parse().unwrap(),
+        };
+        manager.added_routes = vec![cleared.clone(), kept.clone()].into_iter().collect();
+        manager.required_default_routes = vec![
+            kept_default,
+            RequiredDefaultRoute {
+                table_id: RT_TABLE_MAIN,
+                destination: cleared.prefix,
+            },
+        ]
+        .into_iter()
+        .collect();
+        manager.desired_routes.insert(
+            &vec![
+                RequiredRoute::new(cleared.prefix, tunnel_node.clone()),
+                RequiredRoute::new(kept.prefix, tunnel_node.clone()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let filter = RouteFilter(Box::new(|route: &Route| route.prefix.prefix() == 32));
+        assert_eq!(manager.forget_matching(&filter), vec![cleared]);
+
+        assert_eq!(
+            manager.added_routes,
+            vec![kept.clone()].into_iter().collect()
+        );
+        assert_eq!(
+            manager.required_default_routes,
+            vec![kept_default].into_iter().collect()
+        );
+        manager.desired_routes.suspend();
+        assert_eq!(
+            manager.desired_routes.resume(),
+            Some(
+                vec![RequiredRoute::new(kept.prefix, tunnel_node)]
+                    .into_iter()
+                    .collect()
+            )
+        );
+    }
+
     /// Tests if dropping inside a tokio runtime panics
     #[test]
     fn test_drop_in_executor() {
@@ -1,4 +1,7 @@
-use crate::routing::{imp::RouteManagerCommand, NetNode, Node, RequiredRoute, Route};
+use crate::routing::{
+    imp::{DefaultRouteListeners, DesiredRoutes, RouteFilter, RouteManagerCommand},
+    NetNode, Node, RequiredRoute, Route,
+};
 
 use futures::{
     channel::mpsc,
@@ -40,6 +43,10 @@ pub enum Error {
     /// Unexpected output from netstat
     #[error(display = "Unexpected output from netstat")]
     BadOutputFromNetstat,
+
+    /// Packets cannot be routed based on the process that sent them on macOS.
+    #[error(display = "Routing excluded processes outside the tunnel is not supported on macOS")]
+    ExclusionsRoutesUnsupported,
 }
 
 /// Route manager can be in 1 of 4 states -
@@ -56,8 +63,13 @@ pub enum Error {
 pub struct RouteManagerImpl {
     default_destinations: HashSet<IpNetwork>,
     applied_routes: HashSet<Route>,
+    /// Routes that send DNS requests from excluded processes through the tunnel.
+    exclusions_dns_routes: HashSet<Route>,
     v4_gateway: Option<Node>,
     v6_gateway: Option<Node>,
+    /// Routes to reapply when resuming from being suspended.
+    desired_routes: DesiredRoutes,
+    default_route_listeners: DefaultRouteListeners,
     connectivity_change:
         Option<Box<dyn FusedStream<Item = std::io::Result<()>> + Unpin + Send + Sync>>,
 }
@@ -73,9 +85,12 @@ impl RouteManagerImpl {
         let mut manager = Self {
             default_destinations: HashSet::new(),
             applied_routes: HashSet::new(),
+            exclusions_dns_routes: HashSet::new(),
             connectivity_change: Some(Box::new(monitor.fuse())),
             v4_gateway,
             v6_gateway,
+            desired_routes: DesiredRoutes::default(),
+            default_route_listeners: DefaultRouteListeners::default(),
         };
 
         manager.add_required_routes(required_routes).await?;
@@ -103,6 +118,43 @@ impl RouteManagerImpl {
                         },
                         Some(RouteManagerCommand::ClearRoutes) => {
                             self.cleanup_routes().await;
+                            self.default_destinations.clear();
+                            self.desired_routes.clear();
+                        },
+                        Some(RouteManagerCommand::ClearMatching(filter, result_tx)) => {
+                            let _ = result_tx.send(self.clear_matching(&filter).await);
+                        },
+                        Some(RouteManagerCommand::GetRoutes(result_tx)) => {
+                            let _ = result_tx.send(self.current_routes());
+                        },
+                        Some(RouteManagerCommand::Suspend(tx)) => {
+                            self.suspend_routes().await;
+                            let _ = tx.send(());
+                        },
+                        Some(RouteManagerCommand::Resume(result_tx)) => {
+                            let result = match self.desired_routes.resume() {
+                                Some(routes) => self.add_required_routes(routes).await,
+                                None => Ok(()),
+                            };
+                            let _ = result_tx.send(result);
+                        },
+                        Some(RouteManagerCommand::DefaultRouteChanges(listener)) => {
+                            self.default_route_listeners.add(listener);
+                        },
+                        Some(RouteManagerCommand::EnableExclusionsRoutes(result_tx)) => {
+                            let _ = result_tx.send(self.enable_exclusions_routes().await);
+                        },
+                        Some(RouteManagerCommand::DisableExclusionsRoutes) => {
+                            self.disable_exclusions_routes().await;
+                        },
+                        Some(RouteManagerCommand::RouteExclusionsDns(
+                            tunnel_alias,
+                            dns_servers,
+                            result_tx,
+                        )) => {
+                            let result =
+                                self.route_exclusions_dns(&tunnel_alias, &dns_servers).await;
+                            let _ = result_tx.send(result);
                         },
                         None => {
                             break;
@@ -114,6 +166,8 @@ impl RouteManagerImpl {
                     let v4_gateway = Self::get_default_node_cmd("-inet").await.unwrap_or(None);
                     let v6_gateway = Self::get_default_node_cmd("-inet6").await.unwrap_or(None);
 
+                    let changed = v4_gateway != self.v4_gateway || v6_gateway != self.v6_gateway;
+
                     if v4_gateway != self.v4_gateway {
                         self.v4_gateway = v4_gateway;
                         self.apply_new_default_route(&self.v4_gateway, true).await;
@@ -123,6 +177,10 @@ impl RouteManagerImpl {
                         self.v6_gateway = v6_gateway;
                         self.apply_new_default_route(&self.v6_gateway, false).await;
                     }
+
+                    if changed {
+                        self.default_route_listeners.notify();
+                    }
                 },
                 complete => {
                     break;
@@ -133,6 +191,10 @@ impl RouteManagerImpl {
     }
 
     async fn add_required_routes(&mut self, required_routes: HashSet<RequiredRoute>) -> Result<()> {
+        if !self.desired_routes.insert(&required_routes) {
+            return Ok(());
+        }
+
         let mut routes_to_apply = vec![];
         let mut default_destinations = HashSet::new();
 
@@ -162,8 +224,113 @@ impl RouteManagerImpl {
             };
         }
 
-        self.default_destinations = default_destinations;
+        // Destinations added earlier keep being routed through the default node as well.
+        self.default_destinations.extend(default_destinations);
+
+        Ok(())
+    }
+
+    /// Removes the applied routes, keeping them in `desired_routes` so that they can be reapplied
+    /// once resumed. DNS routes for excluded processes are not affected.
+    async fn suspend_routes(&mut self) {
+        if !self.desired_routes.suspend() {
+            return;
+        }
+        let destinations_to_remove = self
+            .applied_routes
+            .drain()
+            .map(|route| route.prefix)
+            .chain(self.default_destinations.drain())
+            .collect::<HashSet<_>>();
+
+        for destination in destinations_to_remove {
+            match Self::delete_route(destination).await {
+                Ok(status) if !status.success() => {
+                    log::debug!("Failed to remove route to {}", destination)
+                }
+                Ok(_) => (),
+                Err(e) => log::error!("Failed to remove route to {} - {}", destination, e),
+            }
+        }
+    }
+
+    /// Removes the current routes selected by `filter`, and forgets the required routes they were
+    /// applied for. Returns the first error, after having tried to remove every selected route.
+    async fn clear_matching(&mut self, filter: &RouteFilter) -> Result<()> {
+        let mut result = Ok(());
+        for route in &self.forget_matching(filter) {
+            match Self::delete_route(route.prefix).await {
+                Ok(status) if !status.success() => {
+                    log::debug!("Failed to remove route to {}", route.prefix)
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    log::error!("Failed to remove route to {} - {}", route.prefix, e);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Forgets the current routes selected by `filter` and the required routes they were applied
+    /// for, without removing them from the routing table. Returns the forgotten routes.
+    fn forget_matching(&mut self, filter: &RouteFilter) -> Vec<Route> {
+        let routes: Vec<Route> = self
+            .current_routes()
+            .into_iter()
+            .filter(|route| filter.matches(route))
+            .collect();
+
+        for route in &routes {
+            self.applied_routes
+                .retain(|applied| applied.prefix != route.prefix);
+            self.exclusions_dns_routes.remove(route);
+            self.default_destinations.remove(&route.prefix);
+            self.desired_routes
+                .retain(|required| !route.applies(required));
+        }
+        routes
+    }
+
+    /// Unlike Linux, macOS has no way to mark the packets of a process and route them using a
+    /// separate routing table, so excluded processes can't be routed outside the tunnel.
+    async fn enable_exclusions_routes(&mut self) -> Result<()> {
+        Err(Error::ExclusionsRoutesUnsupported)
+    }
 
+    /// Removes the DNS routes added by `route_exclusions_dns`.
+    async fn disable_exclusions_routes(&mut self) {
+        for route in self.exclusions_dns_routes.drain() {
+            match Self::delete_route(route.prefix).await {
+                Ok(status) if !status.success() => {
+                    log::debug!("Failed to remove DNS route {}", route)
+                }
+                Ok(_) => (),
+                Err(e) => log::error!("Failed to remove DNS route {} - {}", route, e),
+            }
+        }
+    }
+
+    /// Routes DNS requests through the tunnel interface. Since there's no separate routing table
+    /// for excluded processes on macOS, the host routes apply to all traffic. Any DNS routes added
+    /// by a previous call are replaced.
+    async fn route_exclusions_dns(
+        &mut self,
+        tunnel_alias: &str,
+        dns_servers: &[IpAddr],
+    ) -> Result<()> {
+        self.disable_exclusions_routes().await;
+
+        for route in exclusions_dns_routes(tunnel_alias, dns_servers) {
+            let status = Self::add_route(&route).await?;
+            if !status.success() {
+                log::error!("Failed to add DNS route {}", route);
+            }
+            self.exclusions_dns_routes.insert(route);
+        }
         Ok(())
     }
 
@@ -251,6 +418,7 @@ impl RouteManagerImpl {
         let destinations_to_remove = self
             .applied_routes
             .iter()
+            .chain(self.exclusions_dns_routes.iter())
             .map(|route| &route.prefix)
             .chain(self.default_destinations.iter());
 
@@ -266,21 +434,45 @@ impl RouteManagerImpl {
         }
     }
 
+    /// Returns the routes that are currently applied. Destinations that are routed through the
+    /// default node are reported via the current default node, since `applied_routes` is not
+    /// updated when the default route changes.
+    fn current_routes(&self) -> Vec<Route> {
+        let default_routes = self.default_destinations.iter().filter_map(|destination| {
+            let gateway = if destination.is_ipv4() {
+                &self.v4_gateway
+            } else {
+                &self.v6_gateway
+            };
+            gateway
+                .as_ref()
+                .map(|node| Route::new(node.clone(), *destination))
+        });
+
+        self.applied_routes
+            .iter()
+            .filter(|route| !self.default_destinations.contains(&route.prefix))
+            .chain(self.exclusions_dns_routes.iter())
+            .cloned()
+            .chain(default_routes)
+            .collect()
+    }
+
     async fn apply_new_default_route(&self, new_node: &Option<Node>, v4: bool) {
-        for destination in self.default_destinations.iter() {
-            if destination.is_ipv4() == v4 {
-                let _ = Self::delete_route(*destination).await;
-
-                if let Some(node) = new_node {
-                    log::error!("Resetting default route for {}", destination);
-                    match Self::add_route(&Route::new(node.clone(), *destination)).await {
-                        Ok(status) => {
-                            if !status.success() {
-                                log::error!("Failed to reapply route");
-                            }
+        for (destination, route) in
+            rerouted_default_routes(&self.default_destinations, new_node, v4)
+        {
+            let _ = Self::delete_route(destination).await;
+
+            if let Some(route) = route {
+                log::error!("Resetting default route for {}", destination);
+                match Self::add_route(&route).await {
+                    Ok(status) => {
+                        if !status.success() {
+                            log::error!("Failed to reapply route");
                         }
-                        Err(e) => log::error!("Failed to reset route: {}", e),
                     }
+                    Err(e) => log::error!("Failed to reset route: {}", e),
                 }
             }
         }
@@ -288,6 +480,39 @@ impl RouteManagerImpl {
 }
 
 
+/// Returns the destinations that have to be rerouted when the default node of the given IP version
+/// changes to `new_node`, along with their new route. The route is `None` if there no longer is a
+/// default node. Destinations of the other IP version are unaffected.
+fn rerouted_default_routes(
+    default_destinations: &HashSet<IpNetwork>,
+    new_node: &Option<Node>,
+    v4: bool,
+) -> Vec<(IpNetwork, Option<Route>)> {
+    default_destinations
+        .iter()
+        .filter(|destination| destination.is_ipv4() == v4)
+        .map(|destination| {
+            let route = new_node
+                .as_ref()
+                .map(|node| Route::new(node.clone(), *destination));
+            (*destination, route)
+        })
+        .collect()
+}
+
+/// Returns host routes that send requests to `dns_servers` through the tunnel interface.
+fn exclusions_dns_routes(tunnel_alias: &str, dns_servers: &[IpAddr]) -> HashSet<Route> {
+    dns_servers
+        .iter()
+        .map(|server| {
+            Route::new(
+                Node::device(tunnel_alias.to_string()),
+                IpNetwork::from(*server),
+            )
+        })
+        .collect()
+}
+
 fn ip_vers(prefix: IpNetwork) -> &'static str {
     if prefix.is_ipv4() {
         "-inet"
@@ -352,3 +577,142 @@ async fn listen_for_default_route_changes() -> Result<impl Stream<Item = std::io
 
     Ok(monitor)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::oneshot;
+
+    fn new_manager() -> RouteManagerImpl {
+        RouteManagerImpl {
+            default_destinations: HashSet::new(),
+            applied_routes: HashSet::new(),
+            exclusions_dns_routes: HashSet::new(),
+            v4_gateway: None,
+            v6_gateway: None,
+            desired_routes: DesiredRoutes::default(),
+            default_route_listeners: DefaultRouteListeners::default(),
+            connectivity_change: Some(Box::new(
+                futures::stream::pending::<io::Result<()>>().fuse(),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_exclusions_dns_routes() {
+        let servers: Vec<IpAddr> = vec![
+            "10.64.0.1".parse().unwrap(),
+            "fc00:bbbb::1".parse().unwrap(),
+        ];
+        let routes = exclusions_dns_routes("utun3", &servers);
+
+        let expected: HashSet<Route> = vec![
+            Route::new(
+                Node::device("utun3".to_string()),
+                "10.64.0.1/32".parse().unwrap(),
+            ),
+            Route::new(
+                Node::device("utun3".to_string()),
+                "fc00:bbbb::1/128".parse().unwrap(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(routes, expected);
+        assert!(exclusions_dns_routes("utun3", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_ipv6_default_route_change() {
+        let v4_destination: IpNetwork = "193.138.218.0/24".parse().unwrap();
+        let v6_destination: IpNetwork = "2a03:1b20::/32".parse().unwrap();
+        let default_destinations: HashSet<IpNetwork> =
+            vec![v4_destination, v6_destination].into_iter().collect();
+        let new_node = Some(Node::new("fe80::1".parse().unwrap(), "en0".to_string()));
+
+        assert_eq!(
+            rerouted_default_routes(&default_destinations, &new_node, false),
+            vec![(
+                v6_destination,
+                Some(Route::new(new_node.clone().unwrap(), v6_destination))
+            )]
+        );
+        assert_eq!(
+            rerouted_default_routes(&default_destinations, &None, true),
+            vec![(v4_destination, None)]
+        );
+    }
+
+    #[test]
+    fn test_forget_matching_keeps_other_routes() {
+        let mut manager = new_manager();
+        let gateway = Node::address("192.168.1.1".parse().unwrap());
+        manager.v4_gateway = Some(gateway.clone());
+
+        let tunnel_node = Node::device("utun3".to_string());
+        let cleared = Route::new(tunnel_node.clone(), "10.64.0.1/32".parse().unwrap());
+        let kept = Route::new(tunnel_node.clone(), "0.0.0.0/1".parse().unwrap());
+        let kept_default: IpNetwork = "193.138.218.0/24".parse().unwrap();
+        manager.applied_routes = vec![
+            cleared.clone(),
+            kept.clone(),
+            Route::new(gateway.clone(), kept_default),
+        ]
+        .into_iter()
+        .collect();
+        manager.default_destinations = vec![kept_default].into_iter().collect();
+        manager.desired_routes.insert(
+            &vec![
+                RequiredRoute::new(cleared.prefix, tunnel_node.clone()),
+                RequiredRoute::new(kept.prefix, tunnel_node.clone()),
+                RequiredRoute::new(kept_default, NetNode::DefaultNode),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let filter = RouteFilter(Box::new(|route: &Route| route.prefix.prefix() == 32));
+        assert_eq!(manager.forget_matching(&filter), vec![cleared]);
+
+        let remaining: HashSet<Route> = manager.current_routes().into_iter().collect();
+        assert_eq!(
+            remaining,
+            vec![kept.clone(), Route::new(gateway, kept_default)]
+                .into_iter()
+                .collect()
+        );
+        manager.desired_routes.suspend();
+        assert_eq!(
+            manager.desired_routes.resume(),
+            Some(
+                vec![
+                    RequiredRoute::new(kept.prefix, tunnel_node),
+                    RequiredRoute::new(kept_default, NetNode::DefaultNode),
+                ]
+                .into_iter()
+                .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn test_enable_exclusions_routes_unsupported() {
+        let (manage_tx, manage_rx) = mpsc::unbounded();
+        let (result_tx, result_rx) = oneshot::channel();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+        let mut runtime = tokio02::runtime::Runtime::new().unwrap();
+        runtime.spawn(new_manager().run(manage_rx));
+
+        manage_tx
+            .unbounded_send(RouteManagerCommand::EnableExclusionsRoutes(result_tx))
+            .unwrap();
+        let result = runtime.block_on(result_rx).unwrap();
+        assert!(matches!(result, Err(Error::ExclusionsRoutesUnsupported)));
+
+        manage_tx
+            .unbounded_send(RouteManagerCommand::Shutdown(shutdown_tx))
+            .unwrap();
+        runtime.block_on(shutdown_rx).unwrap();
+    }
+}
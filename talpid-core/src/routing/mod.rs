@@ -43,6 +43,38 @@ impl Route {
         self.table_id = new_id;
         self
     }
+
+    /// Retrieve the destination of the route
+    pub fn prefix(&self) -> IpNetwork {
+        self.prefix
+    }
+
+    /// Retrieve the network node that the route goes through
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// Retrieve the metric of the route, if it has one
+    pub fn metric(&self) -> Option<u32> {
+        self.metric
+    }
+
+    /// Returns whether this route could have been applied for `required`. Routes through the
+    /// default node match whatever node they currently go through.
+    #[cfg(not(target_os = "windows"))]
+    fn applies(&self, required: &RequiredRoute) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            if self.table_id != required.table_id {
+                return false;
+            }
+        }
+        self.prefix == required.prefix
+            && match &required.node {
+                NetNode::RealNode(node) => *node == self.node,
+                NetNode::DefaultNode => true,
+            }
+    }
 }
 
 impl fmt::Display for Route {
@@ -1,16 +1,21 @@
 #![cfg_attr(target_os = "android", allow(dead_code))]
 #![cfg_attr(target_os = "windows", allow(dead_code))]
 // TODO: remove the allow(dead_code) for android once it's up to scratch.
-use super::RequiredRoute;
+use super::{RequiredRoute, Route};
 
-use futures::channel::{
-    mpsc::{self, UnboundedSender},
-    oneshot,
+use futures::{
+    channel::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    future::Future,
+    FutureExt,
 };
-use std::collections::HashSet;
+use parking_lot::Mutex;
+use std::{any::Any, collections::HashSet, fmt, io, panic::AssertUnwindSafe, sync::Arc};
 use talpid_types::ErrorExt;
 
-#[cfg(target_os = "linux")]
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 use std::net::IpAddr;
 
 #[cfg(target_os = "macos")]
@@ -30,6 +35,9 @@ pub use imp::Error as PlatformError;
 /// Errors that can be encountered whilst initializing RouteManager
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
+    /// Failed to create the runtime that the route manager runs on
+    #[error(display = "Failed to initialize route manager runtime")]
+    RuntimeInit(#[error(source)] io::Error),
     /// Routing manager thread panicked before starting routing manager
     #[error(display = "Routing manager thread panicked before starting routing manager")]
     RoutingManagerThreadPanic,
@@ -42,6 +50,14 @@ pub enum Error {
     /// Attempt to use route manager that has been dropped
     #[error(display = "Cannot send message to route manager since it is down")]
     RouteManagerDown,
+    /// No DNS servers were given to route through the tunnel
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "No DNS servers to route through the tunnel")]
+    NoExclusionsDnsServers,
+    /// The tunnel interface to route DNS requests through does not exist
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Cannot route DNS requests through unknown interface {}", _0)]
+    UnknownInterface(String, #[error(source)] nix::Error),
 }
 
 #[derive(Debug)]
@@ -51,12 +67,18 @@ pub enum RouteManagerCommand {
         oneshot::Sender<Result<(), PlatformError>>,
     ),
     ClearRoutes,
+    ClearMatching(RouteFilter, oneshot::Sender<Result<(), PlatformError>>),
+    GetRoutes(oneshot::Sender<Vec<Route>>),
+    Suspend(oneshot::Sender<()>),
+    Resume(oneshot::Sender<Result<(), PlatformError>>),
     Shutdown(oneshot::Sender<()>),
-    #[cfg(target_os = "linux")]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    DefaultRouteChanges(UnboundedSender<()>),
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
     EnableExclusionsRoutes(oneshot::Sender<Result<(), PlatformError>>),
-    #[cfg(target_os = "linux")]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
     DisableExclusionsRoutes,
-    #[cfg(target_os = "linux")]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
     RouteExclusionsDns(
         String,
         Vec<IpAddr>,
@@ -64,12 +86,30 @@ pub enum RouteManagerCommand {
     ),
 }
 
+/// Selects some of the applied routes, such as the ones to remove in
+/// [`RouteManager::clear_matching`].
+pub struct RouteFilter(Box<dyn Fn(&Route) -> bool + Send>);
+
+impl RouteFilter {
+    /// Returns whether `route` is selected.
+    pub fn matches(&self, route: &Route) -> bool {
+        (self.0)(route)
+    }
+}
+
+impl fmt::Debug for RouteFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RouteFilter")
+    }
+}
+
 /// RouteManager applies a set of routes to the route table.
 /// If a destination has to be routed through the default node,
 /// the route will be adjusted dynamically when the default route changes.
 pub struct RouteManager {
     manage_tx: Option<UnboundedSender<RouteManagerCommand>>,
     runtime: tokio02::runtime::Runtime,
+    last_error: Arc<Mutex<Option<String>>>,
 }
 
 impl RouteManager {
@@ -78,16 +118,24 @@ impl RouteManager {
     /// routes.
     pub fn new(required_routes: HashSet<RequiredRoute>) -> Result<Self, Error> {
         let (manage_tx, manage_rx) = mpsc::unbounded();
-        let mut runtime = tokio02::runtime::Runtime::new().expect("Failed to spawn runtime");
+        let mut runtime = tokio02::runtime::Runtime::new().map_err(Error::RuntimeInit)?;
         let manager = runtime.block_on(imp::RouteManagerImpl::new(required_routes))?;
-        runtime.handle().spawn(manager.run(manage_rx));
+        let last_error = Arc::new(Mutex::new(None));
+        spawn_monitored(runtime.handle(), manager.run(manage_rx), last_error.clone());
 
         Ok(Self {
             runtime,
             manage_tx: Some(manage_tx),
+            last_error,
         })
     }
 
+    /// Returns a description of why the route manager stopped, if it crashed. Once this returns
+    /// an error, every other method fails with [`Error::RouteManagerDown`].
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().clone()
+    }
+
     /// Stops RouteManager and removes all of the applied routes.
     pub fn stop(&mut self) {
         if let Some(tx) = self.manage_tx.take() {
@@ -109,50 +157,98 @@ impl RouteManager {
 
     /// Applies the given routes until [`RouteManager::stop`] is called.
     pub fn add_routes(&mut self, routes: HashSet<RequiredRoute>) -> Result<(), Error> {
+        let result = self.add_routes_async(routes);
+        self.runtime.block_on(result)
+    }
+
+    /// Like [`RouteManager::add_routes`], but returns a future that resolves once the routes have
+    /// been applied instead of blocking. The routes are handed to the route manager right away,
+    /// whether or not the future is polled.
+    pub fn add_routes_async(
+        &self,
+        routes: HashSet<RequiredRoute>,
+    ) -> impl Future<Output = Result<(), Error>> {
+        let result_rx =
+            self.send_command(|result_tx| RouteManagerCommand::AddRoutes(routes, result_tx));
+        async move { receive_result(result_rx?).await }
+    }
+
+    /// Returns a stream that produces an item whenever the default node of either IP version
+    /// changes. The stream ends when the route manager is stopped.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn default_route_changes(&mut self) -> Result<UnboundedReceiver<()>, Error> {
+        let tx = self.manage_tx.as_ref().ok_or(Error::RouteManagerDown)?;
+        let (changes_tx, changes_rx) = mpsc::unbounded();
+        tx.unbounded_send(RouteManagerCommand::DefaultRouteChanges(changes_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        Ok(changes_rx)
+    }
+
+    /// Removes all routes previously applied in [`RouteManager::new`] or
+    /// [`RouteManager::add_routes`].
+    pub fn clear_routes(&mut self) -> Result<(), Error> {
         if let Some(tx) = &self.manage_tx {
-            let (result_tx, result_rx) = oneshot::channel();
-            if tx
-                .unbounded_send(RouteManagerCommand::AddRoutes(routes, result_tx))
-                .is_err()
-            {
+            if tx.unbounded_send(RouteManagerCommand::ClearRoutes).is_err() {
                 return Err(Error::RouteManagerDown);
             }
-
-            match self.runtime.block_on(result_rx) {
-                Ok(result) => result.map_err(Error::PlatformError),
-                Err(error) => {
-                    log::trace!(
-                        "{}",
-                        error.display_chain_with_msg("oneshot channel is closed")
-                    );
-                    Ok(())
-                }
-            }
+            Ok(())
         } else {
             Err(Error::RouteManagerDown)
         }
     }
 
-    /// Removes all routes previously applied in [`RouteManager::new`] or
-    /// [`RouteManager::add_routes`].
-    pub fn clear_routes(&mut self) -> Result<(), Error> {
+    /// Removes the applied routes for which `predicate` returns `true`, and forgets the required
+    /// routes they were applied for so that they aren't reapplied when resuming. Other routes are
+    /// left in place.
+    pub fn clear_matching(
+        &mut self,
+        predicate: impl Fn(&Route) -> bool + Send + 'static,
+    ) -> Result<(), Error> {
+        let result_rx = self.send_command(|result_tx| {
+            RouteManagerCommand::ClearMatching(RouteFilter(Box::new(predicate)), result_tx)
+        })?;
+        self.runtime.block_on(receive_result(result_rx))
+    }
+
+    /// Returns the routes that are currently applied.
+    pub fn get_routes(&mut self) -> Result<Vec<Route>, Error> {
+        let result_rx = self.send_command(RouteManagerCommand::GetRoutes)?;
+        match self.runtime.block_on(result_rx) {
+            Ok(routes) => Ok(routes),
+            Err(error) => {
+                log::trace!("{}", error.display_chain_with_msg("channel is closed"));
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Removes all applied routes, but keeps track of them so that they can be reapplied by
+    /// [`RouteManager::resume_routes`]. Routes added while suspended are not applied until then.
+    pub fn suspend_routes(&mut self) -> Result<(), Error> {
         if let Some(tx) = &self.manage_tx {
-            if tx.unbounded_send(RouteManagerCommand::ClearRoutes).is_err() {
+            let (wait_tx, wait_rx) = oneshot::channel();
+            if tx
+                .unbounded_send(RouteManagerCommand::Suspend(wait_tx))
+                .is_err()
+            {
                 return Err(Error::RouteManagerDown);
             }
+
+            if let Err(error) = self.runtime.block_on(wait_rx) {
+                log::trace!("{}", error.display_chain_with_msg("channel is closed"));
+            }
             Ok(())
         } else {
             Err(Error::RouteManagerDown)
         }
     }
 
-    /// Route PID-associated packets through the physical interface.
-    #[cfg(target_os = "linux")]
-    pub fn enable_exclusions_routes(&mut self) -> Result<(), Error> {
+    /// Reapplies the routes removed by [`RouteManager::suspend_routes`].
+    pub fn resume_routes(&mut self) -> Result<(), Error> {
         if let Some(tx) = &self.manage_tx {
             let (result_tx, result_rx) = oneshot::channel();
             if tx
-                .unbounded_send(RouteManagerCommand::EnableExclusionsRoutes(result_tx))
+                .unbounded_send(RouteManagerCommand::Resume(result_tx))
                 .is_err()
             {
                 return Err(Error::RouteManagerDown);
@@ -170,8 +266,24 @@ impl RouteManager {
         }
     }
 
+    /// Route PID-associated packets through the physical interface. Not supported on macOS, where
+    /// this always fails.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn enable_exclusions_routes(&mut self) -> Result<(), Error> {
+        let result = self.enable_exclusions_routes_async();
+        self.runtime.block_on(result)
+    }
+
+    /// Like [`RouteManager::enable_exclusions_routes`], but returns a future that resolves once
+    /// the routes have been applied instead of blocking.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn enable_exclusions_routes_async(&self) -> impl Future<Output = Result<(), Error>> {
+        let result_rx = self.send_command(RouteManagerCommand::EnableExclusionsRoutes);
+        async move { receive_result(result_rx?).await }
+    }
+
     /// Stop routing PID-associated packets through the physical interface.
-    #[cfg(target_os = "linux")]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
     pub fn disable_exclusions_routes(&self) -> Result<(), Error> {
         if let Some(tx) = &self.manage_tx {
             if tx
@@ -186,13 +298,15 @@ impl RouteManager {
         }
     }
 
-    /// Route DNS requests through the tunnel interface.
-    #[cfg(target_os = "linux")]
+    /// Route DNS requests through the tunnel interface. Fails if `dns_servers` is empty or if
+    /// there's no interface named `tunnel_alias`.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
     pub fn route_exclusions_dns(
         &mut self,
         tunnel_alias: &str,
         dns_servers: &[IpAddr],
     ) -> Result<(), Error> {
+        validate_exclusions_dns(tunnel_alias, dns_servers)?;
         if let Some(tx) = &self.manage_tx {
             let (result_tx, result_rx) = oneshot::channel();
             if tx
@@ -217,6 +331,125 @@ impl RouteManager {
             Err(Error::RouteManagerDown)
         }
     }
+
+    /// Sends the command created by `make_command` to the route manager, and returns the
+    /// receiving end of the channel that the route manager responds on.
+    fn send_command<T>(
+        &self,
+        make_command: impl FnOnce(oneshot::Sender<T>) -> RouteManagerCommand,
+    ) -> Result<oneshot::Receiver<T>, Error> {
+        let tx = self.manage_tx.as_ref().ok_or(Error::RouteManagerDown)?;
+        let (result_tx, result_rx) = oneshot::channel();
+        tx.unbounded_send(make_command(result_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        Ok(result_rx)
+    }
+}
+
+/// Waits for the route manager to respond to a command.
+async fn receive_result(
+    result_rx: oneshot::Receiver<Result<(), PlatformError>>,
+) -> Result<(), Error> {
+    match result_rx.await {
+        Ok(result) => result.map_err(Error::PlatformError),
+        Err(error) => {
+            log::trace!("{}", error.display_chain_with_msg("channel is closed"));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn validate_exclusions_dns(tunnel_alias: &str, dns_servers: &[IpAddr]) -> Result<(), Error> {
+    if dns_servers.is_empty() {
+        return Err(Error::NoExclusionsDnsServers);
+    }
+    nix::net::if_::if_nametoindex(tunnel_alias)
+        .map_err(|error| Error::UnknownInterface(tunnel_alias.to_string(), error))?;
+    Ok(())
+}
+
+/// Keeps track of the routes a route manager should apply, so that they can be reapplied after
+/// having been suspended.
+#[derive(Debug, Default)]
+pub struct DesiredRoutes {
+    routes: HashSet<RequiredRoute>,
+    suspended: bool,
+}
+
+impl DesiredRoutes {
+    /// Remembers the given routes. Returns whether they should be applied right away, which is the
+    /// case unless the routes are suspended.
+    pub fn insert(&mut self, routes: &HashSet<RequiredRoute>) -> bool {
+        self.routes.extend(routes.iter().cloned());
+        !self.suspended
+    }
+
+    /// Marks the routes as suspended. Returns `false` if they already were.
+    pub fn suspend(&mut self) -> bool {
+        !std::mem::replace(&mut self.suspended, true)
+    }
+
+    /// Returns the routes that should be reapplied, or `None` if the routes were not suspended.
+    pub fn resume(&mut self) -> Option<HashSet<RequiredRoute>> {
+        if std::mem::replace(&mut self.suspended, false) {
+            Some(self.routes.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Forgets the routes for which `keep` returns `false`.
+    pub fn retain(&mut self, keep: impl FnMut(&RequiredRoute) -> bool) {
+        self.routes.retain(keep);
+    }
+
+    /// Forgets all routes.
+    pub fn clear(&mut self) {
+        self.routes.clear();
+        self.suspended = false;
+    }
+}
+
+/// Subscribers to changes of the default route, see [`RouteManager::default_route_changes`].
+#[derive(Debug, Default)]
+pub struct DefaultRouteListeners(Vec<UnboundedSender<()>>);
+
+impl DefaultRouteListeners {
+    pub fn add(&mut self, listener: UnboundedSender<()>) {
+        self.0.push(listener);
+    }
+
+    /// Notifies every listener of a change, and forgets the ones that have gone away.
+    pub fn notify(&mut self) {
+        self.0
+            .retain(|listener| listener.unbounded_send(()).is_ok());
+    }
+}
+
+/// Spawns the route manager future, recording the panic message in `last_error` if it panics.
+fn spawn_monitored(
+    handle: &tokio02::runtime::Handle,
+    manager: impl Future<Output = ()> + Send + 'static,
+    last_error: Arc<Mutex<Option<String>>>,
+) -> tokio02::task::JoinHandle<()> {
+    handle.spawn(async move {
+        if let Err(payload) = AssertUnwindSafe(manager).catch_unwind().await {
+            let message = panic_message(&*payload);
+            log::error!("Route manager panicked: {}", message);
+            *last_error.lock() = Some(message);
+        }
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
 }
 
 impl Drop for RouteManager {
@@ -224,3 +457,210 @@ impl Drop for RouteManager {
         self.stop();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::routing::{NetNode, Node};
+    use futures::StreamExt;
+
+    fn required_routes() -> HashSet<RequiredRoute> {
+        vec![
+            RequiredRoute::new("10.0.0.0/8".parse().unwrap(), NetNode::DefaultNode),
+            RequiredRoute::new(
+                "10.64.0.1/32".parse().unwrap(),
+                Node::device("tun0".to_string()),
+            ),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_resume_restores_suspended_routes() {
+        let mut desired_routes = DesiredRoutes::default();
+        assert!(desired_routes.insert(&required_routes()));
+
+        assert!(desired_routes.suspend());
+        assert!(!desired_routes.suspend());
+        assert_eq!(desired_routes.resume(), Some(required_routes()));
+        assert_eq!(desired_routes.resume(), None);
+    }
+
+    #[test]
+    fn test_routes_added_while_suspended_are_deferred() {
+        let mut desired_routes = DesiredRoutes::default();
+        assert!(desired_routes.suspend());
+
+        assert!(!desired_routes.insert(&required_routes()));
+        assert_eq!(desired_routes.resume(), Some(required_routes()));
+        assert!(desired_routes.insert(&required_routes()));
+    }
+
+    #[test]
+    fn test_clear_forgets_suspended_routes() {
+        let mut desired_routes = DesiredRoutes::default();
+        desired_routes.insert(&required_routes());
+        desired_routes.suspend();
+
+        desired_routes.clear();
+        assert_eq!(desired_routes.resume(), None);
+        assert!(desired_routes.suspend());
+        assert_eq!(desired_routes.resume(), Some(HashSet::new()));
+    }
+
+    #[test]
+    fn test_retain_keeps_other_routes() {
+        let mut desired_routes = DesiredRoutes::default();
+        desired_routes.insert(&required_routes());
+        let cleared = Route::new(
+            Node::device("tun0".to_string()),
+            "10.64.0.1/32".parse().unwrap(),
+        );
+
+        desired_routes.retain(|required| !cleared.applies(required));
+        desired_routes.suspend();
+        let expected: HashSet<_> = required_routes()
+            .into_iter()
+            .filter(|required| required.prefix != cleared.prefix)
+            .collect();
+        assert_eq!(expected.len(), 1);
+        assert_eq!(desired_routes.resume(), Some(expected));
+    }
+
+    #[test]
+    fn test_route_applies_to_default_node() {
+        let gateway = Node::address("192.168.1.1".parse().unwrap());
+        let route = Route::new(gateway.clone(), "10.0.0.0/8".parse().unwrap());
+        assert!(route.applies(&RequiredRoute::new(
+            "10.0.0.0/8".parse().unwrap(),
+            NetNode::DefaultNode
+        )));
+        assert!(route.applies(&RequiredRoute::new("10.0.0.0/8".parse().unwrap(), gateway)));
+        assert!(!route.applies(&RequiredRoute::new(
+            "10.0.0.0/8".parse().unwrap(),
+            Node::device("tun0".to_string())
+        )));
+        assert!(!route.applies(&RequiredRoute::new(
+            "10.0.0.0/16".parse().unwrap(),
+            NetNode::DefaultNode
+        )));
+    }
+
+    #[test]
+    fn test_last_error_after_panic() {
+        let runtime = tokio02::runtime::Runtime::new().unwrap();
+        let (manage_tx, mut manage_rx) = mpsc::unbounded();
+        let last_error = Arc::new(Mutex::new(None));
+
+        let manager = async move {
+            let _command = manage_rx.next().await;
+            panic!("failed to apply routes");
+        };
+        let join_handle = spawn_monitored(runtime.handle(), manager, last_error.clone());
+
+        let mut route_manager = RouteManager {
+            manage_tx: Some(manage_tx),
+            runtime,
+            last_error,
+        };
+        assert_eq!(route_manager.last_error(), None);
+
+        route_manager.clear_routes().unwrap();
+        route_manager.runtime.block_on(join_handle).unwrap();
+
+        assert_eq!(
+            route_manager.last_error(),
+            Some("failed to apply routes".to_string())
+        );
+        assert!(matches!(
+            route_manager.add_routes(HashSet::new()),
+            Err(Error::RouteManagerDown)
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_route_operations() {
+        let runtime = tokio02::runtime::Runtime::new().unwrap();
+        let (manage_tx, mut manage_rx) = mpsc::unbounded();
+        let last_error = Arc::new(Mutex::new(None));
+
+        // Only responds once both operations have been issued, which would never happen if
+        // issuing one of them blocked until it had completed.
+        let manager = async move {
+            let mut result_txs = vec![];
+            while result_txs.len() < 2 {
+                match manage_rx.next().await {
+                    Some(RouteManagerCommand::AddRoutes(_, result_tx)) => {
+                        result_txs.push(result_tx)
+                    }
+                    command => panic!("Unexpected command: {:?}", command),
+                }
+            }
+            for result_tx in result_txs {
+                let _ = result_tx.send(Ok(()));
+            }
+        };
+        spawn_monitored(runtime.handle(), manager, last_error.clone());
+
+        let mut route_manager = RouteManager {
+            manage_tx: Some(manage_tx),
+            runtime,
+            last_error,
+        };
+        let first = route_manager.add_routes_async(required_routes());
+        let second = route_manager.add_routes_async(HashSet::new());
+        let (first, second) = route_manager
+            .runtime
+            .block_on(futures::future::join(first, second));
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+
+        route_manager.manage_tx = None;
+        let result = route_manager.add_routes_async(HashSet::new());
+        assert!(matches!(
+            route_manager.runtime.block_on(result),
+            Err(Error::RouteManagerDown)
+        ));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_route_exclusions_dns_validation() {
+        #[cfg(target_os = "linux")]
+        const LOOPBACK: &str = "lo";
+        #[cfg(target_os = "macos")]
+        const LOOPBACK: &str = "lo0";
+
+        let mut route_manager = RouteManager {
+            manage_tx: None,
+            runtime: tokio02::runtime::Runtime::new().unwrap(),
+            last_error: Arc::new(Mutex::new(None)),
+        };
+        let dns_servers: Vec<IpAddr> = vec!["10.64.0.1".parse().unwrap()];
+
+        assert!(matches!(
+            route_manager.route_exclusions_dns(LOOPBACK, &[]),
+            Err(Error::NoExclusionsDnsServers)
+        ));
+        assert!(matches!(
+            route_manager.route_exclusions_dns("nonexistent-tun", &dns_servers),
+            Err(Error::UnknownInterface(ref name, _)) if name == "nonexistent-tun"
+        ));
+        // Valid input makes it past validation, to the stopped route manager.
+        assert!(matches!(
+            route_manager.route_exclusions_dns(LOOPBACK, &dns_servers),
+            Err(Error::RouteManagerDown)
+        ));
+    }
+
+    #[test]
+    fn test_no_last_error_after_shutdown() {
+        let mut runtime = tokio02::runtime::Runtime::new().unwrap();
+        let last_error = Arc::new(Mutex::new(None));
+        let join_handle = spawn_monitored(runtime.handle(), async {}, last_error.clone());
+
+        runtime.block_on(join_handle).unwrap();
+        assert_eq!(*last_error.lock(), None);
+    }
+}
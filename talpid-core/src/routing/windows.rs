@@ -1,5 +1,9 @@
 use super::NetNode;
-use crate::{routing::RequiredRoute, winnet};
+use crate::{
+    routing::{RequiredRoute, Route},
+    winnet,
+};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use std::collections::HashSet;
 
 /// Windows routing errors.
@@ -14,6 +18,9 @@ pub enum Error {
     /// Failure to clear routes
     #[error(display = "Failed to clear applied routes")]
     ClearRoutesFailed,
+    /// WinNet does not report the routes that it has applied
+    #[error(display = "Listing the applied routes is not supported on Windows")]
+    GetRoutesUnsupported,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -61,6 +68,14 @@ impl RouteManager {
         }
     }
 
+    /// Returns a stream that produces an item whenever the default route changes. The stream ends
+    /// when the default route callbacks are cleared.
+    pub fn default_route_changes(&mut self) -> Result<UnboundedReceiver<()>> {
+        let (changes_tx, changes_rx) = mpsc::unbounded();
+        self.add_default_route_callback(Some(notify_default_route_change), changes_tx);
+        Ok(changes_rx)
+    }
+
     /// Removes all routes previously applied in [`RouteManager::new`] or
     /// [`RouteManager::add_routes`].
     pub fn clear_default_route_callbacks(&mut self) {
@@ -100,6 +115,12 @@ impl RouteManager {
         }
     }
 
+    /// Returns the routes that are currently applied. Not supported on Windows, where this always
+    /// fails.
+    pub fn get_routes(&self) -> Result<Vec<Route>> {
+        Err(Error::GetRoutesUnsupported)
+    }
+
     /// Removes all routes previously applied in [`RouteManager::new`] or
     /// [`RouteManager::add_routes`].
     pub fn clear_routes(&self) -> Result<()> {
@@ -116,3 +137,14 @@ impl Drop for RouteManager {
         self.stop();
     }
 }
+
+/// Forwards default route changes to the `UnboundedSender<()>` passed as context.
+unsafe extern "system" fn notify_default_route_change(
+    _event_type: winnet::WinNetDefaultRouteChangeEventType,
+    _address_family: winnet::WinNetAddrFamily,
+    _interface_luid: u64,
+    ctx: *mut libc::c_void,
+) {
+    let changes_tx = &*(ctx as *const UnboundedSender<()>);
+    let _ = changes_tx.unbounded_send(());
+}
@@ -0,0 +1,129 @@
+use super::TunnelEvent;
+use parking_lot::Mutex;
+use std::{
+    sync::{mpsc, Arc},
+    thread,
+};
+
+/// Delivers `TunnelEvent`s to several independent subscribers. Each subscriber receives the
+/// events in the order they were sent, on a thread of its own, so a slow subscriber delays neither
+/// the other subscribers nor the tunnel that produces the events.
+///
+/// Since delivery is asynchronous, a listener that has to finish handling an event before the
+/// tunnel proceeds should be given to the tunnel monitor directly instead. Clones share their
+/// subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct TunnelEventSubscribers {
+    senders: Arc<Mutex<Vec<mpsc::Sender<TunnelEvent>>>>,
+}
+
+impl TunnelEventSubscribers {
+    /// Creates a fan-out without any subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a subscriber. `on_event` is called on a separate thread for every event sent
+    /// after this, until all clones of this value are dropped.
+    pub fn subscribe<F>(&mut self, mut on_event: F) -> &mut Self
+    where
+        F: FnMut(TunnelEvent) + Send + 'static,
+    {
+        let event_rx = self.events();
+        thread::spawn(move || {
+            for event in event_rx {
+                on_event(event);
+            }
+        });
+        self
+    }
+
+    /// Returns a channel that receives every event sent after this, until the receiver is
+    /// dropped.
+    pub fn events(&self) -> mpsc::Receiver<TunnelEvent> {
+        let (event_tx, event_rx) = mpsc::channel();
+        self.senders.lock().push(event_tx);
+        event_rx
+    }
+
+    /// Sends an event to every subscriber without waiting for them to handle it. Subscribers that
+    /// have gone away are removed.
+    pub fn notify(&self, event: TunnelEvent) {
+        self.senders
+            .lock()
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    /// Returns a listener that can be given to a tunnel monitor as its `on_event` callback.
+    pub fn into_listener(self) -> impl Fn(TunnelEvent) + Send + Sync + Clone + 'static {
+        move |event| self.notify(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn events() -> Vec<TunnelEvent> {
+        vec![
+            TunnelEvent::InterfaceUp("tun0".to_owned()),
+            TunnelEvent::Reconnecting {
+                attempt: 1,
+                next_delay: Duration::from_secs(5),
+            },
+            TunnelEvent::AuthFailed(None),
+            TunnelEvent::Warning("reliability layer failure".to_owned()),
+        ]
+    }
+
+    fn receive(events_rx: &mpsc::Receiver<TunnelEvent>, count: usize) -> Vec<TunnelEvent> {
+        (0..count)
+            .map(|_| events_rx.recv_timeout(RECV_TIMEOUT).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_subscribers_receive_same_sequence() {
+        let (first_tx, first_rx) = mpsc::channel();
+        let (second_tx, second_rx) = mpsc::channel();
+        let mut subscribers = TunnelEventSubscribers::new();
+        subscribers
+            .subscribe(move |event| first_tx.send(event).unwrap())
+            .subscribe(move |event| second_tx.send(event).unwrap());
+
+        let on_event = subscribers.into_listener();
+        for event in events() {
+            on_event(event);
+        }
+
+        assert_eq!(receive(&first_rx, events().len()), events());
+        assert_eq!(receive(&second_rx, events().len()), events());
+    }
+
+    #[test]
+    fn test_slow_subscriber_does_not_block_delivery() {
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (slow_tx, slow_rx) = mpsc::channel();
+        let (fast_tx, fast_rx) = mpsc::channel();
+        let mut subscribers = TunnelEventSubscribers::new();
+        subscribers
+            .subscribe(move |event| {
+                let _ = release_rx.recv();
+                slow_tx.send(event).unwrap();
+            })
+            .subscribe(move |event| fast_tx.send(event).unwrap());
+
+        let start = Instant::now();
+        for event in events() {
+            subscribers.notify(event);
+        }
+        assert!(start.elapsed() < RECV_TIMEOUT);
+        assert_eq!(receive(&fast_rx, events().len()), events());
+
+        drop(release_tx);
+        assert_eq!(receive(&slow_rx, events().len()), events());
+    }
+}
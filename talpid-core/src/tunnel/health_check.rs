@@ -0,0 +1,200 @@
+use std::{net::Ipv4Addr, sync::mpsc, thread, time::Duration};
+use talpid_types::net::openvpn::HealthCheckOptions;
+
+/// How long a single probe waits for a reply.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sends a probe to a host and reports whether the host answered.
+pub trait Prober: Send + 'static {
+    /// Returns `true` if `addr` answered the probe.
+    fn probe(&mut self, addr: Ipv4Addr) -> bool;
+}
+
+/// Probes a host by sending a single ICMP echo request through the tunnel interface, using the
+/// system `ping` command.
+pub struct PingProber {
+    interface: String,
+}
+
+impl PingProber {
+    /// Creates a prober that pings through the given interface.
+    pub fn new(interface: String) -> Self {
+        PingProber { interface }
+    }
+}
+
+impl Prober for PingProber {
+    fn probe(&mut self, addr: Ipv4Addr) -> bool {
+        match ping_once_cmd(addr, &self.interface).run() {
+            Ok(output) => output.status.success(),
+            Err(error) => {
+                log::error!("Failed to run ping command: {}", error);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn ping_once_cmd(addr: Ipv4Addr, interface: &str) -> duct::Expression {
+    let timeout_secs = PROBE_TIMEOUT.as_secs().to_string();
+    let mut args = vec!["-n", "-c", "1"];
+    if cfg!(target_os = "linux") {
+        args.extend_from_slice(&["-w", &timeout_secs, "-I", interface]);
+    } else {
+        args.extend_from_slice(&["-t", &timeout_secs, "-b", interface]);
+    }
+    let addr = addr.to_string();
+    args.push(&addr);
+
+    duct::cmd("ping", args)
+        .stdin_null()
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+}
+
+#[cfg(windows)]
+fn ping_once_cmd(addr: Ipv4Addr, _interface: &str) -> duct::Expression {
+    let timeout_millis = PROBE_TIMEOUT.as_millis().to_string();
+    let addr = addr.to_string();
+
+    duct::cmd("ping", &["-n", "1", "-w", &timeout_millis, &addr])
+        .stdin_null()
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+}
+
+/// Probes the tunnel gateway at a fixed interval on a background thread, and reports when
+/// several consecutive probes have failed. The health check stops when dropped.
+pub struct HealthCheck {
+    _stop_tx: mpsc::Sender<()>,
+}
+
+impl HealthCheck {
+    /// Starts probing `gateway`. `on_unhealthy` is called at most once, after which probing
+    /// stops.
+    pub fn start(
+        options: HealthCheckOptions,
+        gateway: Ipv4Addr,
+        prober: Box<dyn Prober>,
+        on_unhealthy: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        thread::spawn(move || {
+            if run(options, gateway, prober, stop_rx) {
+                on_unhealthy();
+            }
+        });
+        HealthCheck { _stop_tx: stop_tx }
+    }
+}
+
+/// Returns `true` if the failure threshold was reached, and `false` if the health check was
+/// stopped.
+fn run(
+    options: HealthCheckOptions,
+    gateway: Ipv4Addr,
+    mut prober: Box<dyn Prober>,
+    stop_rx: mpsc::Receiver<()>,
+) -> bool {
+    let failure_threshold = options.failure_threshold.max(1);
+    let mut failures = 0;
+    loop {
+        match stop_rx.recv_timeout(options.interval) {
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => return false,
+        }
+        if prober.probe(gateway) {
+            failures = 0;
+            continue;
+        }
+        failures += 1;
+        log::warn!(
+            "Tunnel health check probe to {} failed ({}/{})",
+            gateway,
+            failures,
+            failure_threshold
+        );
+        if failures >= failure_threshold {
+            return true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers probes according to a script, failing once the script runs out.
+    struct ScriptedProber {
+        replies: Arc<Mutex<Vec<bool>>>,
+        probes: Arc<Mutex<u32>>,
+    }
+
+    impl Prober for ScriptedProber {
+        fn probe(&mut self, _addr: Ipv4Addr) -> bool {
+            *self.probes.lock().unwrap() += 1;
+            let mut replies = self.replies.lock().unwrap();
+            if replies.is_empty() {
+                false
+            } else {
+                replies.remove(0)
+            }
+        }
+    }
+
+    fn scripted_prober(replies: Vec<bool>) -> (Box<dyn Prober>, Arc<Mutex<u32>>) {
+        let probes = Arc::new(Mutex::new(0));
+        let prober = ScriptedProber {
+            replies: Arc::new(Mutex::new(replies)),
+            probes: probes.clone(),
+        };
+        (Box::new(prober), probes)
+    }
+
+    fn options(failure_threshold: u32) -> HealthCheckOptions {
+        HealthCheckOptions {
+            interval: Duration::from_millis(1),
+            failure_threshold,
+        }
+    }
+
+    #[test]
+    fn test_unhealthy_after_consecutive_failures() {
+        let (prober, probes) = scripted_prober(vec![false, false, true, false, false, false]);
+        let (_stop_tx, stop_rx) = mpsc::channel();
+        assert!(run(options(3), Ipv4Addr::new(10, 8, 0, 1), prober, stop_rx));
+        // The successful third probe resets the failure count.
+        assert_eq!(*probes.lock().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_stopped_health_check_is_not_unhealthy() {
+        let (prober, probes) = scripted_prober(vec![]);
+        let (stop_tx, stop_rx) = mpsc::channel();
+        drop(stop_tx);
+        assert!(!run(
+            options(1),
+            Ipv4Addr::new(10, 8, 0, 1),
+            prober,
+            stop_rx
+        ));
+        assert_eq!(*probes.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_on_unhealthy_called() {
+        let (prober, _probes) = scripted_prober(vec![]);
+        let (unhealthy_tx, unhealthy_rx) = mpsc::channel();
+        let _health_check =
+            HealthCheck::start(options(2), Ipv4Addr::new(10, 8, 0, 1), prober, move || {
+                let _ = unhealthy_tx.send(());
+            });
+        unhealthy_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("health check did not report the tunnel as unhealthy");
+    }
+}
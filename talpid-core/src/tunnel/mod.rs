@@ -6,10 +6,15 @@ use std::{
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::{Path, PathBuf},
+    time::Duration,
 };
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
-use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
+use talpid_types::net::{
+    wireguard as wireguard_types, Endpoint, TransportProtocol, TunnelParameters,
+};
+#[cfg(not(target_os = "android"))]
+use talpid_types::ErrorExt;
 
 #[cfg(target_os = "android")]
 pub use self::tun_provider::TunConfig;
@@ -18,9 +23,16 @@ pub use self::tun_provider::TunConfig;
 #[cfg(not(target_os = "android"))]
 pub mod openvpn;
 
+/// Probes for detecting tunnels that are up but pass no traffic.
+#[cfg(not(target_os = "android"))]
+pub mod health_check;
+
 /// A module for all WireGuard related tunnel management.
 pub mod wireguard;
 
+/// Delivery of tunnel events to multiple subscribers.
+pub mod event_subscribers;
+
 /// A module for low level platform specific tunnel device management.
 pub(crate) mod tun_provider;
 
@@ -70,10 +82,38 @@ pub enum Error {
 pub enum TunnelEvent {
     /// Sent when the tunnel fails to connect due to an authentication error.
     AuthFailed(Option<String>),
+    /// Sent when the tunnel interface has been opened, before routes are configured. Contains
+    /// the name of the interface.
+    InterfaceUp(String),
     /// Sent when the tunnel comes up and is ready for traffic.
     Up(TunnelMetadata),
-    /// Sent when the tunnel goes down.
-    Down,
+    /// Sent when the tunnel goes down, summarizing the session.
+    Down {
+        /// Bytes received over the tunnel during the session, if known, otherwise zero.
+        rx_bytes: u64,
+        /// Bytes sent over the tunnel during the session, if known, otherwise zero.
+        tx_bytes: u64,
+        /// How long the tunnel was up.
+        duration: Duration,
+    },
+    /// Sent when the tunnel is up but repeatedly fails health check probes.
+    Unhealthy,
+    /// Sent when the tunnel goes down but the VPN client is reconnecting on its own, for example
+    /// after OpenVPN's `--ping-restart`. Sent again for every further attempt to reconnect that
+    /// the client makes before the tunnel comes back up. OpenVPN only makes further attempts if
+    /// `TunnelOptions::connect_retry_max` allows it, since it otherwise exits after the first
+    /// failed attempt, so `attempt` is never more than 1 by default.
+    Reconnecting {
+        /// How many times in a row the client has tried to reconnect, starting at 1.
+        attempt: u32,
+        /// How long the client waits before making this attempt.
+        next_delay: Duration,
+    },
+    /// Sent when the tunnel comes back up after `Reconnecting`.
+    Reconnected(TunnelMetadata),
+    /// Sent when the VPN client logs a warning, such as about a deprecated option or a cipher
+    /// that had to be negotiated down.
+    Warning(String),
 }
 
 /// Information about a VPN tunnel.
@@ -87,26 +127,87 @@ pub struct TunnelMetadata {
     pub ipv4_gateway: Ipv4Addr,
     /// The IP to the IPv6 default gateway on the tunnel interface.
     pub ipv6_gateway: Option<Ipv6Addr>,
+    /// Whether IPv6 was actually configured on the tunnel interface.
+    pub ipv6_available: bool,
+    /// DNS servers pushed by the server, in the order they were pushed. Empty if the server did
+    /// not push any.
+    pub dns_servers: Vec<IpAddr>,
+    /// The relay endpoint that the tunnel connects to, if known.
+    pub remote: Option<Endpoint>,
+    /// Short id of the tunnel session, which also prefixes the log lines of the tunnel monitor.
+    /// `None` if the tunnel monitor does not assign one.
+    pub session_id: Option<String>,
+}
+
+impl TunnelMetadata {
+    /// The transport protocol used to reach the relay, if known.
+    pub fn transport(&self) -> Option<TransportProtocol> {
+        self.remote.map(|remote| remote.protocol)
+    }
+
+    /// The port on the relay that the tunnel connects to, if known.
+    pub fn port(&self) -> Option<u16> {
+        self.remote.map(|remote| remote.address.port())
+    }
+
+    /// The DNS servers to use inside the tunnel, which are also the only ones the firewall
+    /// allows DNS traffic to. These are the pushed DNS servers if there are any, otherwise the
+    /// tunnel gateways.
+    ///
+    /// The Windows firewall can only allow DNS traffic to the gateways, so other pushed servers
+    /// are ignored there.
+    pub fn tunnel_dns_servers(&self) -> Vec<IpAddr> {
+        let mut gateways = vec![IpAddr::from(self.ipv4_gateway)];
+        gateways.extend(self.ipv6_gateway.map(IpAddr::from));
+
+        let pushed_servers: Vec<IpAddr> = self
+            .dns_servers
+            .iter()
+            .filter(|server| server.is_ipv4() || self.ipv6_available)
+            .filter(|server| {
+                let allowed = cfg!(not(windows)) || gateways.contains(server);
+                if !allowed {
+                    log::warn!(
+                        "Ignoring pushed DNS server {}, which is not a gateway",
+                        server
+                    );
+                }
+                allowed
+            })
+            .cloned()
+            .collect();
+
+        if pushed_servers.is_empty() {
+            gateways
+        } else {
+            pushed_servers
+        }
+    }
 }
 
 #[cfg(not(target_os = "android"))]
 impl TunnelEvent {
     /// Converts an `openvpn_plugin::EventType` to a `TunnelEvent`.
-    /// Returns `None` if there is no corresponding `TunnelEvent`.
+    /// Returns `None` if there is no corresponding `TunnelEvent`. `tunnel_alias` is the name that
+    /// OpenVPN was told to use for the tunnel interface, if any.
     fn from_openvpn_event(
         event: openvpn_plugin::EventType,
         env: &HashMap<String, String>,
+        tunnel_alias: Option<&str>,
     ) -> Option<TunnelEvent> {
         match event {
             openvpn_plugin::EventType::AuthFailed => {
                 let reason = env.get("auth_failed_reason").cloned();
                 Some(TunnelEvent::AuthFailed(reason))
             }
+            openvpn_plugin::EventType::Up => {
+                let interface = parse_interface_name(env, tunnel_alias)
+                    .expect("No \"dev\" in interface up event");
+                Some(TunnelEvent::InterfaceUp(interface))
+            }
             openvpn_plugin::EventType::RouteUp => {
-                let interface = env
-                    .get("dev")
-                    .expect("No \"dev\" in tunnel up event")
-                    .to_owned();
+                let interface =
+                    parse_interface_name(env, tunnel_alias).expect("No \"dev\" in tunnel up event");
                 let ips = vec![env
                     .get("ifconfig_local")
                     .expect("No \"ifconfig_local\" in tunnel up event")
@@ -122,18 +223,106 @@ impl TunnelEvent {
                         .parse()
                         .expect("V6 Tunnel gateway IP not in valid format")
                 });
+                // The server may not push IPv6 even if it was enabled for the tunnel.
+                let ipv6_available = env
+                    .get("ifconfig_ipv6_local")
+                    .map(|v6_str| v6_str.parse::<Ipv6Addr>().is_ok())
+                    .unwrap_or(false);
+                let dns_servers = parse_pushed_dns_servers(env);
                 Some(TunnelEvent::Up(TunnelMetadata {
                     interface,
                     ips,
                     ipv4_gateway,
                     ipv6_gateway,
+                    ipv6_available,
+                    dns_servers,
+                    // The plugin doesn't know which relay was used. `OpenVpnMonitor` fills it in
+                    // from the tunnel parameters, along with the session id.
+                    remote: None,
+                    session_id: None,
                 }))
             }
-            openvpn_plugin::EventType::RoutePredown => Some(TunnelEvent::Down),
+            openvpn_plugin::EventType::RoutePredown => {
+                let restarting = env
+                    .get("signal")
+                    .map(|signal| OPENVPN_RESTART_SIGNALS.contains(&signal.as_str()))
+                    .unwrap_or(false);
+                if restarting {
+                    // The plugin can't know how long OpenVPN waits before reconnecting.
+                    // `OpenVpnMonitor` fills in the delay.
+                    Some(TunnelEvent::Reconnecting {
+                        attempt: 1,
+                        next_delay: Duration::default(),
+                    })
+                } else {
+                    // The plugin can't know how long the tunnel was up. `OpenVpnMonitor` fills
+                    // in the duration.
+                    let counter = |name| env.get(name).and_then(|value| value.parse().ok());
+                    Some(TunnelEvent::Down {
+                        rx_bytes: counter("bytes_received").unwrap_or(0),
+                        tx_bytes: counter("bytes_sent").unwrap_or(0),
+                        duration: Duration::default(),
+                    })
+                }
+            }
             _ => None,
         }
     }
 }
+
+/// Values of the `signal` environment variable for which OpenVPN tears the tunnel down only to
+/// reconnect without exiting.
+#[cfg(not(target_os = "android"))]
+const OPENVPN_RESTART_SIGNALS: &[&str] = &["ping-restart", "connection-reset", "SIGUSR1", "SIGHUP"];
+
+/// Returns the name of the tunnel interface. This is `tunnel_alias` if set, as on Windows where
+/// the TAP adapter is picked by its alias, and otherwise the `dev` variable set by OpenVPN.
+#[cfg(not(target_os = "android"))]
+fn parse_interface_name(
+    env: &HashMap<String, String>,
+    tunnel_alias: Option<&str>,
+) -> Option<String> {
+    tunnel_alias
+        .or_else(|| env.get("dev").map(String::as_str))
+        .map(str::to_owned)
+}
+
+/// Extracts the DNS servers from the `dhcp-option DNS` and `dhcp-option DNS6` options that the
+/// server pushed. OpenVPN passes pushed options to plugins as `foreign_option_1`,
+/// `foreign_option_2`, etc.
+#[cfg(not(target_os = "android"))]
+fn parse_pushed_dns_servers(env: &HashMap<String, String>) -> Vec<IpAddr> {
+    let mut options: Vec<(usize, &str)> = env
+        .iter()
+        .filter_map(|(key, value)| {
+            let index = key.strip_prefix("foreign_option_")?.parse().ok()?;
+            Some((index, value.as_str()))
+        })
+        .collect();
+    options.sort_by_key(|(index, _)| *index);
+
+    options
+        .into_iter()
+        .filter_map(|(_, option)| {
+            let mut parts = option.split_whitespace();
+            if parts.next() != Some("dhcp-option") {
+                return None;
+            }
+            match parts.next() {
+                Some("DNS") | Some("DNS6") => (),
+                _ => return None,
+            }
+            let address = parts.next()?;
+            match address.parse() {
+                Ok(address) => Some(address),
+                Err(_) => {
+                    log::warn!("Ignoring invalid pushed DNS server: {}", address);
+                    None
+                }
+            }
+        })
+        .collect()
+}
 /// Abstraction for monitoring a generic VPN tunnel.
 pub struct TunnelMonitor {
     monitor: InternalTunnelMonitor,
@@ -161,7 +350,7 @@ impl TunnelMonitor {
         match tunnel_parameters {
             #[cfg(not(target_os = "android"))]
             TunnelParameters::OpenVpn(config) => {
-                Self::start_openvpn_tunnel(&config, log_file, resource_dir, on_event)
+                Self::start_openvpn_tunnel(&config, log_file, resource_dir, on_event, route_manager)
             }
             #[cfg(target_os = "android")]
             TunnelParameters::OpenVpn(_) => Err(Error::UnsupportedPlatform),
@@ -185,6 +374,7 @@ impl TunnelMonitor {
                 if let Some(proxy) = &params.proxy {
                     match proxy {
                         openvpn_types::ProxySettings::Shadowsocks(..) => "sslocal.exe",
+                        openvpn_types::ProxySettings::Obfuscation(..) => "udp2raw.exe",
                         _ => "openvpn.exe",
                     }
                 } else {
@@ -225,11 +415,23 @@ impl TunnelMonitor {
         log: Option<PathBuf>,
         resource_dir: &Path,
         on_event: L,
+        route_manager: &mut RouteManager,
     ) -> Result<Self>
     where
         L: Fn(TunnelEvent) + Send + Sync + 'static,
     {
         let monitor = openvpn::OpenVpnMonitor::start(on_event, config, log, resource_dir)?;
+        // Unlike UDP, a TCP connection often stalls after the default route has moved to another
+        // interface.
+        if config.config.endpoint.protocol == TransportProtocol::Tcp {
+            match route_manager.default_route_changes() {
+                Ok(changes) => monitor.restart_on_default_route_changes(Box::new(changes)),
+                Err(error) => log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to listen for default route changes")
+                ),
+            }
+        }
         Ok(TunnelMonitor {
             monitor: InternalTunnelMonitor::OpenVpn(monitor),
         })
@@ -325,7 +527,7 @@ impl CloseHandle {
     pub fn close(self) -> io::Result<()> {
         match self {
             #[cfg(not(target_os = "android"))]
-            CloseHandle::OpenVpn(handle) => handle.close(),
+            CloseHandle::OpenVpn(handle) => handle.close().map(|_| ()),
             CloseHandle::Wireguard(mut handle) => {
                 handle.close();
                 Ok(())
@@ -411,3 +613,197 @@ fn is_ipv6_enabled_in_os() -> Result<bool> {
         Ok(true)
     }
 }
+
+#[cfg(all(test, not(target_os = "android")))]
+mod tests {
+    use super::*;
+
+    fn route_up_env(ipv6_local: Option<&str>) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        env.insert("dev".to_owned(), "tun0".to_owned());
+        env.insert("ifconfig_local".to_owned(), "10.8.0.2".to_owned());
+        env.insert("route_vpn_gateway".to_owned(), "10.8.0.1".to_owned());
+        if let Some(ipv6_local) = ipv6_local {
+            env.insert("ifconfig_ipv6_local".to_owned(), ipv6_local.to_owned());
+            env.insert(
+                "route_ipv6_gateway_1".to_owned(),
+                "fdda:d0d0:cafe:1194::".to_owned(),
+            );
+        }
+        env
+    }
+
+    fn up_metadata(env: &HashMap<String, String>) -> TunnelMetadata {
+        match TunnelEvent::from_openvpn_event(openvpn_plugin::EventType::RouteUp, env, None) {
+            Some(TunnelEvent::Up(metadata)) => metadata,
+            event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+
+    fn dev_env(dev: &str) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        env.insert("dev".to_owned(), dev.to_owned());
+        env
+    }
+
+    #[test]
+    fn test_interface_up() {
+        assert_eq!(
+            TunnelEvent::from_openvpn_event(openvpn_plugin::EventType::Up, &dev_env("tun0"), None),
+            Some(TunnelEvent::InterfaceUp("tun0".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_interface_name_from_env() {
+        assert_eq!(
+            parse_interface_name(&dev_env("tun0"), None),
+            Some("tun0".to_owned())
+        );
+        assert_eq!(
+            parse_interface_name(&dev_env("utun3"), None),
+            Some("utun3".to_owned())
+        );
+        assert_eq!(parse_interface_name(&HashMap::new(), None), None);
+    }
+
+    #[test]
+    fn test_interface_name_from_alias() {
+        assert_eq!(
+            parse_interface_name(&dev_env("tap0"), Some("Mullvad")),
+            Some("Mullvad".to_owned())
+        );
+        assert_eq!(
+            parse_interface_name(&HashMap::new(), Some("Mullvad")),
+            Some("Mullvad".to_owned())
+        );
+        let metadata = up_metadata_with_alias(&route_up_env(None), "Mullvad");
+        assert_eq!(metadata.interface, "Mullvad");
+    }
+
+    fn up_metadata_with_alias(env: &HashMap<String, String>, alias: &str) -> TunnelMetadata {
+        match TunnelEvent::from_openvpn_event(openvpn_plugin::EventType::RouteUp, env, Some(alias))
+        {
+            Some(TunnelEvent::Up(metadata)) => metadata,
+            event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_route_up_with_ipv6() {
+        let metadata = up_metadata(&route_up_env(Some("fdda:d0d0:cafe:1194::1000")));
+        assert!(metadata.ipv6_available);
+        assert!(metadata.ipv6_gateway.is_some());
+    }
+
+    fn route_predown(signal: Option<&str>) -> Option<TunnelEvent> {
+        let mut env = HashMap::new();
+        if let Some(signal) = signal {
+            env.insert("signal".to_owned(), signal.to_owned());
+        }
+        TunnelEvent::from_openvpn_event(openvpn_plugin::EventType::RoutePredown, &env, None)
+    }
+
+    #[test]
+    fn test_route_predown_on_restart() {
+        let reconnecting = Some(TunnelEvent::Reconnecting {
+            attempt: 1,
+            next_delay: Duration::default(),
+        });
+        assert_eq!(route_predown(Some("ping-restart")), reconnecting);
+        assert_eq!(route_predown(Some("SIGUSR1")), reconnecting);
+    }
+
+    #[test]
+    fn test_route_predown_on_exit() {
+        let down = Some(TunnelEvent::Down {
+            rx_bytes: 0,
+            tx_bytes: 0,
+            duration: Duration::default(),
+        });
+        assert_eq!(route_predown(Some("SIGTERM")), down);
+        assert_eq!(route_predown(Some("ping-exit")), down);
+        assert_eq!(route_predown(None), down);
+    }
+
+    #[test]
+    fn test_route_predown_traffic_counters() {
+        let mut env = HashMap::new();
+        env.insert("bytes_received".to_owned(), "4096".to_owned());
+        env.insert("bytes_sent".to_owned(), "1024".to_owned());
+        assert_eq!(
+            TunnelEvent::from_openvpn_event(openvpn_plugin::EventType::RoutePredown, &env, None),
+            Some(TunnelEvent::Down {
+                rx_bytes: 4096,
+                tx_bytes: 1024,
+                duration: Duration::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_route_up_with_pushed_dns() {
+        let mut env = route_up_env(None);
+        env.insert(
+            "foreign_option_2".to_owned(),
+            "dhcp-option DNS6 fdda:d0d0:cafe:1194::1".to_owned(),
+        );
+        env.insert(
+            "foreign_option_10".to_owned(),
+            "dhcp-option DNS 10.8.0.2".to_owned(),
+        );
+        env.insert(
+            "foreign_option_1".to_owned(),
+            "dhcp-option DNS 10.8.0.1".to_owned(),
+        );
+        env.insert(
+            "foreign_option_3".to_owned(),
+            "dhcp-option DOMAIN example.com".to_owned(),
+        );
+        env.insert(
+            "foreign_option_4".to_owned(),
+            "dhcp-option DNS not-an-ip".to_owned(),
+        );
+
+        let metadata = up_metadata(&env);
+        let expected: Vec<IpAddr> = vec![
+            "10.8.0.1".parse().unwrap(),
+            "fdda:d0d0:cafe:1194::1".parse().unwrap(),
+            "10.8.0.2".parse().unwrap(),
+        ];
+        assert_eq!(metadata.dns_servers, expected);
+    }
+
+    #[test]
+    fn test_route_up_without_pushed_dns() {
+        let metadata = up_metadata(&route_up_env(None));
+        assert!(metadata.dns_servers.is_empty());
+    }
+
+    #[test]
+    fn test_tunnel_dns_servers() {
+        let mut metadata = up_metadata(&route_up_env(None));
+        let gateway = IpAddr::from(metadata.ipv4_gateway);
+        assert_eq!(metadata.tunnel_dns_servers(), vec![gateway]);
+
+        let pushed: IpAddr = "10.8.0.53".parse().unwrap();
+        let pushed_v6: IpAddr = "fdda:d0d0:cafe:1194::53".parse().unwrap();
+        metadata.dns_servers = vec![pushed, pushed_v6];
+        metadata.ipv6_available = false;
+        if cfg!(windows) {
+            assert_eq!(metadata.tunnel_dns_servers(), vec![gateway]);
+        } else {
+            assert_eq!(metadata.tunnel_dns_servers(), vec![pushed]);
+        }
+
+        metadata.dns_servers = vec![gateway];
+        assert_eq!(metadata.tunnel_dns_servers(), vec![gateway]);
+    }
+
+    #[test]
+    fn test_route_up_without_ipv6() {
+        let metadata = up_metadata(&route_up_env(None));
+        assert!(!metadata.ipv6_available);
+        assert_eq!(metadata.ipv6_gateway, None);
+    }
+}
@@ -1,16 +1,21 @@
-use super::TunnelEvent;
+use super::{
+    event_subscribers::TunnelEventSubscribers,
+    health_check::{HealthCheck, PingProber},
+    TunnelEvent, TunnelMetadata,
+};
 use crate::{
-    mktemp,
+    logging, mktemp,
     process::{
         openvpn::{OpenVpnCommand, OpenVpnProcHandle},
-        stoppable_process::StoppableProcess,
+        stoppable_process::{KillSchedule, StopOutcome, StoppableProcess},
     },
     proxy::{self, ProxyMonitor, ProxyResourceData},
 };
 use std::{
     collections::HashMap,
-    fs,
-    io::{self, Write},
+    fmt, fs,
+    io::{self, BufRead, Seek, SeekFrom, Write},
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
     process::ExitStatus,
     sync::{
@@ -18,13 +23,15 @@ use std::{
         mpsc, Arc,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use talpid_types::net::openvpn;
+use talpid_types::net::{openvpn, Endpoint};
 use tokio02::task;
 #[cfg(target_os = "linux")]
 use which;
 
+pub use crate::proxy::{ProxyKind, ProxyStats};
+
 
 /// Results from fallible operations on the OpenVPN tunnel.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -37,6 +44,10 @@ pub enum Error {
     #[error(display = "Failed to initialize the tokio runtime")]
     RuntimeError(#[error(source)] io::Error),
 
+    /// The tokio runtime must have at least one core thread.
+    #[error(display = "Invalid number of runtime threads: {}", _0)]
+    InvalidRuntimeThreads(usize),
+
     /// Unable to start, wait for or kill the OpenVPN process.
     #[error(display = "Error in OpenVPN process management: {}", _0)]
     ChildProcessError(&'static str, #[error(source)] io::Error),
@@ -59,9 +70,15 @@ pub enum Error {
     #[error(display = "The TAP adapter appears to be disabled")]
     DisabledTapAdapter,
 
-    /// OpenVPN process died unexpectedly
+    /// OpenVPN process died unexpectedly. `code` is its exit code, which is missing if it was
+    /// killed by a signal. See `exit_code_reason`.
     #[error(display = "OpenVPN process died unexpectedly")]
-    ChildProcessDied,
+    ChildProcessDied { code: Option<i32> },
+
+    /// OpenVPN exited after failing to connect as many times as `--connect-retry-max` allows.
+    /// This is a clean exit, after which another relay can be tried.
+    #[error(display = "OpenVPN gave up after failing to connect")]
+    ConnectRetriesExhausted,
 
     /// The IP routing program was not found.
     #[cfg(target_os = "linux")]
@@ -76,24 +93,94 @@ pub enum Error {
     #[error(display = "No OpenVPN plugin found at {}", _0)]
     PluginNotFound(String),
 
+    /// The plugin filename override is not a plain filename.
+    #[error(display = "Invalid OpenVPN plugin filename: {}", _0)]
+    InvalidPluginFilename(String),
+
+    /// The CA certificate that the server is verified against was not found.
+    #[error(display = "No CA certificate found at {}", _0)]
+    CaNotFound(String),
+
+    /// The directory that OpenVPN and the proxy log to could not be created.
+    #[error(display = "Failed to create log directory {}", _0)]
+    CreateLogDirError(String, #[error(source)] io::Error),
+
+    /// A directory that OpenVPN or the proxy need to write to is not writable.
+    #[error(display = "Unable to write to directory {}", _0)]
+    DirectoryNotWritable(String, #[error(source)] io::Error),
+
+    /// OpenVPN is not logging to a file, so there is no log to move.
+    #[error(display = "OpenVPN is not logging to a file")]
+    NoLogFile,
+
+    /// The OpenVPN log from a previous session could not be cleared.
+    #[error(display = "Failed to clear the OpenVPN log")]
+    ClearLogError(#[error(source)] io::Error),
+
+    /// The OpenVPN log could not be moved to its new location.
+    #[error(display = "Failed to move the OpenVPN log")]
+    MoveLogError(#[error(source)] io::Error),
+
     /// Error while writing credentials to temporary file.
     #[error(display = "Error while writing credentials to temporary file")]
     CredentialsWriteError(#[error(source)] io::Error),
 
+    /// The tunnel options are invalid.
+    #[error(display = "Invalid tunnel options: {}", _0)]
+    InvalidTunnelOptions(String),
+
+    /// The expected name of the server certificate is invalid.
+    #[error(display = "Invalid server certificate name: {}", _0)]
+    InvalidX509Name(String),
+
+    /// The proxy settings are invalid.
+    #[error(display = "Invalid proxy settings: {}", _0)]
+    InvalidProxySettings(String),
+
+    /// A remote server is not given as an IP address. OpenVPN would resolve it outside the
+    /// tunnel, leaking the lookup.
+    #[error(display = "Remote server is not an IP address: {}", _0)]
+    UnresolvedRemote(String),
+
+    /// Unable to read the OpenVPN config file to check its remote servers.
+    #[error(display = "Failed to read the OpenVPN config file")]
+    ReadConfigError(#[error(source)] io::Error),
+
+    /// A tls-crypt-v2 key was given while the OpenVPN config file uses tls-auth. OpenVPN can
+    /// only use one of them.
+    #[error(display = "A tls-crypt-v2 key cannot be combined with tls-auth")]
+    ConflictingTlsAuth,
+
     /// Failures related to the proxy service.
     #[error(display = "Unable to start the proxy service")]
     StartProxyError(#[error(source)] io::Error),
 
+    /// The proxy did not accept connections in time.
+    #[error(display = "The proxy was not ready within {:?}", _0)]
+    ProxyNotReady(Duration),
+
+    /// No matching tunnel event arrived in time.
+    #[error(display = "No matching tunnel event within {:?}", _0)]
+    EventTimeout(Duration),
+
     /// Error while monitoring proxy service
     #[error(display = "Error while monitoring proxy service")]
     MonitorProxyError(#[error(source)] io::Error),
 
     /// The proxy exited unexpectedly
     #[error(
-        display = "The proxy exited unexpectedly providing these details: {}",
-        _0
+        display = "The {} proxy exited unexpectedly{}",
+        kind,
+        format_proxy_exit(exit_code, stderr_tail)
     )]
-    ProxyExited(String),
+    ProxyExited {
+        /// The kind of proxy that exited.
+        kind: ProxyKind,
+        /// Exit code of the proxy process, if it could be determined.
+        exit_code: Option<i32>,
+        /// The last lines the proxy process wrote to its output, if available.
+        stderr_tail: Option<String>,
+    },
 
     /// Failure in Windows syscall.
     #[cfg(windows)]
@@ -102,11 +189,51 @@ pub enum Error {
 }
 
 
+/// Default number of core threads in the runtime that runs the event server.
+pub const DEFAULT_RUNTIME_THREADS: usize = 1;
+
+/// Data ciphers that OpenVPN may negotiate without a warning, unless others are given to the
+/// monitor.
+pub const DEFAULT_ALLOWED_DATA_CIPHERS: &[&str] =
+    &["AES-256-GCM", "AES-128-GCM", "CHACHA20-POLY1305"];
+
+/// Environment variable that overrides the directory that credential files are written to.
+const TEMP_DIR_ENV_VAR: &str = "TALPID_OPENVPN_TEMP_DIR";
+/// Environment variable that overrides the path to the CA certificate, which is otherwise read
+/// from the resource directory.
+const CA_PATH_ENV_VAR: &str = "TALPID_OPENVPN_CA_PATH";
+/// Environment variable that overrides the filename of the OpenVPN plugin, which is still looked
+/// up in the resource directory.
+const PLUGIN_FILENAME_ENV_VAR: &str = "TALPID_OPENVPN_PLUGIN_FILENAME";
+/// Environment variable that provides the OpenVPN username when the tunnel parameters lack one.
+const USERNAME_ENV_VAR: &str = "TALPID_OPENVPN_USERNAME";
+/// Environment variable that provides the OpenVPN password when the tunnel parameters lack one.
+const PASSWORD_ENV_VAR: &str = "TALPID_OPENVPN_PASSWORD";
+/// Environment variable that caps the size of the OpenVPN log, in bytes. Unset by default, in
+/// which case the log is never rotated.
+const MAX_LOG_SIZE_ENV_VAR: &str = "TALPID_OPENVPN_MAX_LOG_SIZE";
+/// Minimum time between soft restarts of OpenVPN triggered by default route changes.
+const DEFAULT_ROUTE_RESTART_INTERVAL: Duration = Duration::from_secs(10);
+/// Time between checks of the OpenVPN log for new warnings and for exceeding its size cap.
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long to wait for a proxy to accept connections, unless set in the tunnel options.
+const DEFAULT_PROXY_READY_TIMEOUT: Duration = Duration::from_secs(5);
+/// When set, the OpenVPN event server listens on a socket in the abstract namespace instead of a
+/// socket file in /tmp.
+#[cfg(target_os = "linux")]
+const ABSTRACT_SOCKET_ENV_VAR: &str = "TALPID_OPENVPN_ABSTRACT_SOCKET";
+
 #[cfg(unix)]
 static OPENVPN_DIE_TIMEOUT: Duration = Duration::from_secs(4);
 #[cfg(windows)]
 static OPENVPN_DIE_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Returns the schedule used to stop OpenVPN when closing the tunnel. OpenVPN is given
+/// `OPENVPN_DIE_TIMEOUT` to exit gracefully before being killed.
+pub fn default_kill_schedule() -> KillSchedule {
+    KillSchedule::new(OPENVPN_DIE_TIMEOUT)
+}
+
 
 #[cfg(target_os = "macos")]
 const OPENVPN_PLUGIN_FILENAME: &str = "libtalpid_openvpn_plugin.dylib";
@@ -124,16 +251,34 @@ const OPENVPN_BIN_FILENAME: &str = "openvpn.exe";
 #[derive(Debug)]
 pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
     child: Arc<C::ProcessHandle>,
+    /// Short id that prefixes the log lines of this monitor and is included in its tunnel events.
+    session_id: String,
     proxy_monitor: Option<Box<dyn ProxyMonitor>>,
     log_path: Option<PathBuf>,
+    /// Keeps the OpenVPN log below its size cap, if it has one, until the monitor is dropped.
+    log_size_limiter: Option<LogSizeLimiter>,
     closed: Arc<AtomicBool>,
+    connected_endpoint: Arc<parking_lot::Mutex<Option<SocketAddr>>>,
     /// Keep the `TempFile` for the user-pass file in the struct, so it's removed on drop.
-    _user_pass_file: mktemp::TempFile,
+    _user_pass_file: Option<mktemp::TempFile>,
     /// Keep the 'TempFile' for the proxy user-pass file in the struct, so it's removed on drop.
     _proxy_auth_file: Option<mktemp::TempFile>,
+    /// Keep the 'TempFile' for the tls-crypt-v2 key in the struct, so it's removed on drop.
+    _tls_crypt_v2_file: Option<mktemp::TempFile>,
+    /// Called once the OpenVPN process has exited.
+    on_exit: Option<ExitCallback>,
+    /// Reports warnings from the OpenVPN log until the monitor is dropped.
+    _log_warning_watcher: Option<LogWarningWatcher>,
+    /// Receives every `TunnelEvent` of the tunnel.
+    event_subscribers: TunnelEventSubscribers,
+    /// Keeps count of OpenVPN's attempts to reconnect.
+    reconnect_tracker: Arc<ReconnectTracker>,
 
-    runtime: tokio02::runtime::Runtime,
+    runtime: tokio02::runtime::Handle,
+    /// The runtime created for this monitor, if it wasn't given one to share.
+    _dedicated_runtime: Option<tokio02::runtime::Runtime>,
     event_server_abort_tx: triggered::Trigger,
+    event_server_abort_rx: triggered::Listener,
     server_join_handle: Option<task::JoinHandle<std::result::Result<(), event_server::Error>>>,
 }
 
@@ -149,33 +294,200 @@ impl OpenVpnMonitor<OpenVpnCommand> {
     where
         L: Fn(TunnelEvent) + Send + Sync + 'static,
     {
-        let user_pass_file =
-            Self::create_credentials_file(&params.config.username, &params.config.password)
-                .map_err(Error::CredentialsWriteError)?;
+        Self::start_with_runtime_threads(
+            on_event,
+            params,
+            log_path,
+            resource_dir,
+            DEFAULT_RUNTIME_THREADS,
+        )
+    }
 
-        let proxy_auth_file =
-            Self::create_proxy_auth_file(&params.proxy).map_err(Error::CredentialsWriteError)?;
+    /// Like `start`, but runs the event server on a runtime with `runtime_threads` core threads.
+    pub fn start_with_runtime_threads<L>(
+        on_event: L,
+        params: &openvpn::TunnelParameters,
+        log_path: Option<PathBuf>,
+        resource_dir: &Path,
+        runtime_threads: usize,
+    ) -> Result<Self>
+    where
+        L: Fn(TunnelEvent) + Send + Sync + 'static,
+    {
+        Self::start_internal(
+            on_event,
+            params,
+            log_path,
+            resource_dir,
+            runtime_threads,
+            None,
+        )
+    }
 
-        let user_pass_file_path = user_pass_file.to_path_buf();
+    /// Like `start`, but runs the event server on an existing runtime rather than creating one.
+    /// The runtime is left running when the tunnel stops, so it can be shared between monitors.
+    pub fn start_with_runtime<L>(
+        on_event: L,
+        params: &openvpn::TunnelParameters,
+        log_path: Option<PathBuf>,
+        resource_dir: &Path,
+        runtime: tokio02::runtime::Handle,
+    ) -> Result<Self>
+    where
+        L: Fn(TunnelEvent) + Send + Sync + 'static,
+    {
+        Self::start_internal(
+            on_event,
+            params,
+            log_path,
+            resource_dir,
+            DEFAULT_RUNTIME_THREADS,
+            Some(runtime),
+        )
+    }
 
-        let proxy_auth_file_path = match proxy_auth_file {
-            Some(ref file) => Some(file.to_path_buf()),
-            _ => None,
+    fn start_internal<L>(
+        on_event: L,
+        params: &openvpn::TunnelParameters,
+        log_path: Option<PathBuf>,
+        resource_dir: &Path,
+        runtime_threads: usize,
+        runtime: Option<tokio02::runtime::Handle>,
+    ) -> Result<Self>
+    where
+        L: Fn(TunnelEvent) + Send + Sync + 'static,
+    {
+        openvpn::validate_tunnel_options(&params.options, params.config.endpoint.protocol)
+            .map_err(Error::InvalidTunnelOptions)?;
+        if let Some(ref name) = params.config.verify_x509_name {
+            openvpn::validate_x509_name(name).map_err(Error::InvalidX509Name)?;
+        }
+        let session_id = new_session_id();
+
+        let temp_dir = std::env::var_os(TEMP_DIR_ENV_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        check_writable_dir(&temp_dir)?;
+        if let Some(ref log_path) = log_path {
+            create_log_dir(log_path)?;
+        }
+        let max_log_size = max_log_size_from_env();
+        if let (Some(log_path), Some(_)) = (&log_path, max_log_size) {
+            clear_log(log_path).map_err(Error::ClearLogError)?;
+        }
+
+        let (username, password) =
+            resolve_credentials(&params.config, |name| std::env::var(name).ok());
+        let user_pass_file = Self::create_credentials_file(&username, &password, &temp_dir)
+            .map_err(Error::CredentialsWriteError)?;
+
+        let proxy_auth_file = Self::create_proxy_auth_file(&params.proxy, &temp_dir)
+            .map_err(Error::CredentialsWriteError)?;
+
+        let tls_crypt_v2_file = params
+            .config
+            .tls_crypt_v2_key
+            .as_ref()
+            .map(|key| Self::create_key_file(key, &temp_dir))
+            .transpose()
+            .map_err(Error::CredentialsWriteError)?;
+
+        // These files are only read by OpenVPN while connecting. The tls-crypt-v2 key is not among
+        // them, since OpenVPN reads it again on every restart, so it is kept until the monitor is
+        // dropped.
+        let read_once_files: Vec<PathBuf> = std::iter::once(&user_pass_file)
+            .chain(proxy_auth_file.iter())
+            .map(|file| file.to_path_buf())
+            .collect();
+
+        let event_subscribers = TunnelEventSubscribers::new();
+        let on_event = {
+            let event_subscribers = event_subscribers.clone();
+            Arc::new(move |event: TunnelEvent| {
+                event_subscribers.notify(event.clone());
+                on_event(event)
+            })
+        };
+        let health_check_options = params.options.health_check;
+        let health_check = parking_lot::Mutex::new(None);
+        let reconnect_tracker = Arc::new(ReconnectTracker::new(Duration::from_secs(
+            params
+                .options
+                .connect_retry
+                .unwrap_or(crate::process::openvpn::DEFAULT_CONNECT_RETRY)
+                .into(),
+        )));
+        let session_tracker = Arc::new(SessionTracker::default());
+        #[cfg(windows)]
+        let tunnel_alias = Some(
+            crate::winnet::get_tap_interface_alias()
+                .map_err(Error::WinnetError)?
+                .to_string_lossy()
+                .into_owned(),
+        );
+        #[cfg(not(windows))]
+        let tunnel_alias: Option<String> = None;
+
+        let on_exit = {
+            let on_event = on_event.clone();
+            let session_tracker = session_tracker.clone();
+            move || {
+                if let Some(down_event) = session_tracker.end() {
+                    on_event(down_event);
+                }
+            }
         };
 
-        let on_openvpn_event = move |event, env| {
-            if event == openvpn_plugin::EventType::RouteUp {
-                // The user-pass file has been read. Try to delete it early.
-                let _ = fs::remove_file(&user_pass_file_path);
+        let on_log_warning = {
+            let on_event = on_event.clone();
+            move |warning| on_event(TunnelEvent::Warning(warning))
+        };
 
-                // The proxy auth file has been read. Try to delete it early.
-                if let Some(ref file_path) = &proxy_auth_file_path {
-                    let _ = fs::remove_file(file_path);
+        let on_restart_pause = {
+            let on_event = on_event.clone();
+            let reconnect_tracker = reconnect_tracker.clone();
+            let session_id = session_id.clone();
+            move |delay| {
+                if let Some(event) = reconnect_tracker.restart_paused(delay) {
+                    on_event(with_session_id(event, &session_id));
                 }
             }
-            match TunnelEvent::from_openvpn_event(event, &env) {
-                Some(tunnel_event) => on_event(tunnel_event),
-                None => log::debug!("Ignoring OpenVpnEvent {:?}", event),
+        };
+
+        let remote = params.config.endpoint;
+        let event_session_id = session_id.clone();
+        let event_reconnect_tracker = reconnect_tracker.clone();
+        let on_openvpn_event = move |event, env| {
+            remove_read_once_files(event, &read_once_files);
+            match TunnelEvent::from_openvpn_event(event, &env, tunnel_alias.as_deref()) {
+                Some(tunnel_event) => {
+                    let tunnel_event = with_session_id(
+                        with_remote(
+                            session_tracker.track(event_reconnect_tracker.track(tunnel_event)),
+                            remote,
+                        ),
+                        &event_session_id,
+                    );
+                    match &tunnel_event {
+                        TunnelEvent::Up(metadata) | TunnelEvent::Reconnected(metadata) => {
+                            if let Some(options) = health_check_options {
+                                let on_event = on_event.clone();
+                                *health_check.lock() = Some(HealthCheck::start(
+                                    options,
+                                    metadata.ipv4_gateway,
+                                    Box::new(PingProber::new(metadata.interface.clone())),
+                                    move || on_event(TunnelEvent::Unhealthy),
+                                ));
+                            }
+                        }
+                        TunnelEvent::Down { .. } | TunnelEvent::Reconnecting { .. } => {
+                            *health_check.lock() = None
+                        }
+                        _ => (),
+                    }
+                    on_event(tunnel_event)
+                }
+                None => log::debug!("[{}] Ignoring OpenVpnEvent {:?}", event_session_id, event),
             }
         };
 
@@ -190,72 +502,326 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             log_dir,
         };
 
-        let proxy_monitor = Self::start_proxy(&params.proxy, &proxy_resources)?;
+        let proxy_monitor = Self::start_proxy(
+            &session_id,
+            &params.proxy,
+            params.options.proxy_port,
+            params
+                .options
+                .proxy_ready_timeout
+                .unwrap_or(DEFAULT_PROXY_READY_TIMEOUT),
+            &proxy_resources,
+        )?;
 
-        let cmd = Self::create_openvpn_cmd(
+        let mut cmd = Self::create_openvpn_cmd(
             params,
             user_pass_file.as_ref(),
             match proxy_auth_file {
                 Some(ref file) => Some(file.as_ref()),
                 _ => None,
             },
+            tls_crypt_v2_file.as_ref().map(|file| file.as_ref()),
             resource_dir,
             &proxy_monitor,
         )?;
 
-        let plugin_path = Self::get_plugin_path(resource_dir)?;
+        let plugin_path = Self::get_plugin_path(
+            resource_dir,
+            std::env::var_os(PLUGIN_FILENAME_ENV_VAR).map(PathBuf::from),
+        )?;
 
-        Self::new_internal(
-            cmd,
-            on_openvpn_event,
-            &plugin_path,
-            log_path,
-            user_pass_file,
-            proxy_auth_file,
-            proxy_monitor,
-        )
+        // OpenVPN must append to a capped log, or it would keep writing past the end of the file
+        // after it has been truncated.
+        cmd.log_append(max_log_size.is_some());
+
+        let builder = OpenVpnMonitorBuilder::new(cmd, plugin_path)
+            .session_id(session_id)
+            .log_path(log_path)
+            .max_log_size(max_log_size)
+            .user_pass_file(user_pass_file)
+            .proxy_auth_file(proxy_auth_file)
+            .tls_crypt_v2_file(tls_crypt_v2_file)
+            .proxy_monitor(proxy_monitor)
+            .runtime_threads(runtime_threads)
+            .runtime(runtime)
+            .on_exit(on_exit)
+            .on_log_warning(on_log_warning)
+            .on_restart_pause(on_restart_pause)
+            .event_subscribers(event_subscribers)
+            .reconnect_tracker(reconnect_tracker);
+        #[cfg(target_os = "linux")]
+        let builder = builder.abstract_socket(std::env::var_os(ABSTRACT_SOCKET_ENV_VAR).is_some());
+
+        builder.build(on_openvpn_event)
     }
 }
 
-impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
-    fn new_internal<L>(
-        mut cmd: C,
-        on_event: L,
-        plugin_path: impl AsRef<Path>,
-        log_path: Option<PathBuf>,
-        user_pass_file: mktemp::TempFile,
-        proxy_auth_file: Option<mktemp::TempFile>,
-        proxy_monitor: Option<Box<dyn ProxyMonitor>>,
-    ) -> Result<OpenVpnMonitor<C>>
+/// Builder for an `OpenVpnMonitor` around any `OpenVpnBuilder`. Only the OpenVPN process builder
+/// and the plugin path are required, everything else has a default.
+pub(crate) struct OpenVpnMonitorBuilder<C: OpenVpnBuilder> {
+    cmd: C,
+    plugin_path: PathBuf,
+    session_id: String,
+    log_path: Option<PathBuf>,
+    max_log_size: Option<u64>,
+    user_pass_file: Option<mktemp::TempFile>,
+    proxy_auth_file: Option<mktemp::TempFile>,
+    tls_crypt_v2_file: Option<mktemp::TempFile>,
+    proxy_monitor: Option<Box<dyn ProxyMonitor>>,
+    runtime_threads: usize,
+    runtime: Option<tokio02::runtime::Handle>,
+    event_server_options: event_server::ServerOptions,
+    #[cfg(target_os = "linux")]
+    abstract_socket: bool,
+    on_exit: Option<Box<dyn FnOnce() + Send>>,
+    on_log_warning: Option<Box<dyn Fn(String) + Send>>,
+    on_restart_pause: Option<Box<dyn Fn(Duration) + Send>>,
+    allowed_data_ciphers: Vec<String>,
+    event_subscribers: TunnelEventSubscribers,
+    reconnect_tracker: Arc<ReconnectTracker>,
+}
+
+/// Stream that produces an item whenever the default route changes.
+pub type DefaultRouteChanges = Box<dyn futures::Stream<Item = ()> + Send + Unpin>;
+
+impl<C: OpenVpnBuilder + 'static> OpenVpnMonitorBuilder<C> {
+    pub fn new(cmd: C, plugin_path: impl AsRef<Path>) -> Self {
+        OpenVpnMonitorBuilder {
+            cmd,
+            plugin_path: plugin_path.as_ref().to_path_buf(),
+            session_id: new_session_id(),
+            log_path: None,
+            max_log_size: None,
+            user_pass_file: None,
+            proxy_auth_file: None,
+            tls_crypt_v2_file: None,
+            proxy_monitor: None,
+            runtime_threads: DEFAULT_RUNTIME_THREADS,
+            runtime: None,
+            event_server_options: event_server::ServerOptions::default(),
+            #[cfg(target_os = "linux")]
+            abstract_socket: false,
+            on_exit: None,
+            on_log_warning: None,
+            on_restart_pause: None,
+            allowed_data_ciphers: DEFAULT_ALLOWED_DATA_CIPHERS
+                .iter()
+                .map(|cipher| cipher.to_string())
+                .collect(),
+            event_subscribers: TunnelEventSubscribers::new(),
+            reconnect_tracker: Arc::new(ReconnectTracker::default()),
+        }
+    }
+
+    /// Sets the id that identifies the session in logs and tunnel events. A new one is generated
+    /// by default.
+    pub fn session_id(mut self, session_id: String) -> Self {
+        self.session_id = session_id;
+        self
+    }
+
+    /// Sets the file that OpenVPN should log to.
+    pub fn log_path(mut self, log_path: Option<PathBuf>) -> Self {
+        self.log_path = log_path;
+        self
+    }
+
+    /// Caps the size of the OpenVPN log. Once the log grows past `max_log_size` bytes, it is moved
+    /// to a `.old` file next to it, replacing any earlier one, and the log starts over. Disabled
+    /// by default. OpenVPN must be appending to its log for this to work.
+    pub fn max_log_size(mut self, max_log_size: Option<u64>) -> Self {
+        self.max_log_size = max_log_size;
+        self
+    }
+
+    /// Sets the user-pass file, which is removed when the monitor is dropped.
+    pub fn user_pass_file(mut self, user_pass_file: mktemp::TempFile) -> Self {
+        self.user_pass_file = Some(user_pass_file);
+        self
+    }
+
+    /// Sets the proxy user-pass file, which is removed when the monitor is dropped.
+    pub fn proxy_auth_file(mut self, proxy_auth_file: Option<mktemp::TempFile>) -> Self {
+        self.proxy_auth_file = proxy_auth_file;
+        self
+    }
+
+    /// Sets the tls-crypt-v2 key file, which is removed when the monitor is dropped.
+    pub fn tls_crypt_v2_file(mut self, tls_crypt_v2_file: Option<mktemp::TempFile>) -> Self {
+        self.tls_crypt_v2_file = tls_crypt_v2_file;
+        self
+    }
+
+    /// Sets the monitor of the proxy that OpenVPN connects through.
+    pub fn proxy_monitor(mut self, proxy_monitor: Option<Box<dyn ProxyMonitor>>) -> Self {
+        self.proxy_monitor = proxy_monitor;
+        self
+    }
+
+    /// Sets the number of core threads of the runtime that the event server runs on.
+    pub fn runtime_threads(mut self, runtime_threads: usize) -> Self {
+        self.runtime_threads = runtime_threads;
+        self
+    }
+
+    /// Sets an existing runtime to run the event server on. If not set, a runtime with
+    /// `runtime_threads` core threads is created for the monitor.
+    pub fn runtime(mut self, runtime: Option<tokio02::runtime::Handle>) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Sets the concurrency limit and request timeout of the event server. Neither is limited by
+    /// default.
+    pub fn event_server_options(
+        mut self,
+        event_server_options: event_server::ServerOptions,
+    ) -> Self {
+        self.event_server_options = event_server_options;
+        self
+    }
+
+    /// Sets whether the event server should listen on a socket in the abstract namespace, which
+    /// leaves no file behind, rather than on a socket file.
+    #[cfg(target_os = "linux")]
+    pub fn abstract_socket(mut self, abstract_socket: bool) -> Self {
+        self.abstract_socket = abstract_socket;
+        self
+    }
+
+    /// Sets a function to call once the OpenVPN process has exited.
+    pub fn on_exit(mut self, on_exit: impl FnOnce() + Send + 'static) -> Self {
+        self.on_exit = Some(Box::new(on_exit));
+        self
+    }
+
+    /// Sets a function to call with each warning that OpenVPN writes to its log. Has no effect
+    /// unless OpenVPN logs to a file.
+    pub fn on_log_warning(mut self, on_log_warning: impl Fn(String) + Send + 'static) -> Self {
+        self.on_log_warning = Some(Box::new(on_log_warning));
+        self
+    }
+
+    /// Sets a function to call with the wait whenever OpenVPN logs that it pauses before
+    /// restarting. Has no effect unless OpenVPN logs to a file.
+    pub fn on_restart_pause(
+        mut self,
+        on_restart_pause: impl Fn(Duration) + Send + 'static,
+    ) -> Self {
+        self.on_restart_pause = Some(Box::new(on_restart_pause));
+        self
+    }
+
+    /// Sets the data ciphers that OpenVPN may negotiate. Any other cipher is reported as a warning
+    /// through `on_log_warning`. Defaults to `DEFAULT_ALLOWED_DATA_CIPHERS`.
+    pub fn allowed_data_ciphers(mut self, allowed_data_ciphers: Vec<String>) -> Self {
+        self.allowed_data_ciphers = allowed_data_ciphers;
+        self
+    }
+
+    /// Sets the subscribers that the tunnel events of the monitor are delivered to. They are
+    /// available through `OpenVpnMonitor::event_subscribers`.
+    pub fn event_subscribers(mut self, event_subscribers: TunnelEventSubscribers) -> Self {
+        self.event_subscribers = event_subscribers;
+        self
+    }
+
+    /// Sets the tracker that counts OpenVPN's attempts to reconnect. It is read by
+    /// `OpenVpnMonitor::reconnect_attempts` and `OpenVpnMonitor::next_reconnect_delay`.
+    pub(crate) fn reconnect_tracker(mut self, reconnect_tracker: Arc<ReconnectTracker>) -> Self {
+        self.reconnect_tracker = reconnect_tracker;
+        self
+    }
+
+    /// Starts the event server and the OpenVPN process. OpenVPN plugin events are passed to
+    /// `on_event`.
+    pub fn build<L>(self, on_event: L) -> Result<OpenVpnMonitor<C>>
     where
         L: Fn(openvpn_plugin::EventType, HashMap<String, String>) + Send + Sync + 'static,
     {
+        let OpenVpnMonitorBuilder {
+            mut cmd,
+            plugin_path,
+            session_id,
+            log_path,
+            max_log_size,
+            user_pass_file,
+            proxy_auth_file,
+            tls_crypt_v2_file,
+            proxy_monitor,
+            runtime_threads,
+            runtime,
+            event_server_options,
+            #[cfg(target_os = "linux")]
+            abstract_socket,
+            on_exit,
+            on_log_warning,
+            on_restart_pause,
+            allowed_data_ciphers,
+            event_subscribers,
+            reconnect_tracker,
+        } = self;
+
+        let (runtime, dedicated_runtime) = match runtime {
+            Some(runtime) => (runtime, None),
+            None => {
+                if runtime_threads < 1 {
+                    return Err(Error::InvalidRuntimeThreads(runtime_threads));
+                }
+                let dedicated_runtime = tokio02::runtime::Builder::new()
+                    .threaded_scheduler()
+                    .core_threads(runtime_threads)
+                    .enable_all()
+                    .build()
+                    .map_err(Error::RuntimeError)?;
+                (dedicated_runtime.handle().clone(), Some(dedicated_runtime))
+            }
+        };
+
         let uuid = uuid::Uuid::new_v4().to_string();
+        #[cfg(not(target_os = "linux"))]
+        let abstract_socket = false;
         let ipc_path = if cfg!(windows) {
             format!("//./pipe/talpid-openvpn-{}", uuid)
+        } else if abstract_socket {
+            format!(
+                "{}talpid-openvpn-{}",
+                event_server::ABSTRACT_SOCKET_PREFIX,
+                uuid
+            )
         } else {
             format!("/tmp/talpid-openvpn-{}", uuid)
         };
 
         let (event_server_abort_tx, event_server_abort_rx) = triggered::trigger();
 
-        let mut runtime = tokio02::runtime::Builder::new()
-            .threaded_scheduler()
-            .core_threads(1)
-            .enable_all()
-            .build()
-            .map_err(Error::RuntimeError)?;
+        let connected_endpoint = Arc::new(parking_lot::Mutex::new(None));
+        let on_event = {
+            let connected_endpoint = connected_endpoint.clone();
+            let session_id = session_id.clone();
+            move |event, env: HashMap<String, String>| {
+                log::info!("[{}] OpenVPN event {:?} {:?}", session_id, event, env);
+                if event == openvpn_plugin::EventType::RouteUp {
+                    *connected_endpoint.lock() = parse_connected_endpoint(&env);
+                }
+                on_event(event, env)
+            }
+        };
 
+        // Every event is handled by `on_event`, which must be fully set up before the server binds,
+        // since the plugin may connect as soon as the socket exists.
         let (start_tx, start_rx) = mpsc::channel();
-        let server_join_handle = runtime.spawn(event_server::start(
+        let peer_verifier = event_server::ChildPeerVerifier::new();
+        let server_join_handle = runtime.spawn(event_server::start_with_options(
             ipc_path.clone(),
             start_tx,
             on_event,
-            event_server_abort_rx,
+            peer_verifier.clone(),
+            event_server_abort_rx.clone(),
+            event_server_options,
         ));
         if let Err(_) = start_rx.recv() {
-            return Err(runtime
-                .block_on(server_join_handle)
+            return Err(futures::executor::block_on(server_join_handle)
                 .expect("Failed to resolve quit handle future")
                 .map_err(Error::EventDispatcherError)
                 .unwrap_err());
@@ -266,110 +832,585 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
             .log(log_path.as_ref().map(|p| p.as_path()))
             .start()
             .map_err(|e| Error::ChildProcessError("Failed to start", e))?;
-
+        peer_verifier.set_child_pid(child.pid());
+        let log_warning_watcher = match (&log_path, on_log_warning, on_restart_pause) {
+            (Some(_), None, None) | (None, ..) => None,
+            (Some(log_path), on_log_warning, on_restart_pause) => Some(LogWarningWatcher::start(
+                log_path.clone(),
+                session_id.clone(),
+                on_log_warning.unwrap_or_else(|| Box::new(|_| ())),
+                on_restart_pause.unwrap_or_else(|| Box::new(|_| ())),
+                allowed_data_ciphers,
+            )),
+        };
+        let log_size_limiter = match (&log_path, max_log_size) {
+            (Some(log_path), Some(max_log_size)) => Some(LogSizeLimiter::start(
+                log_path.clone(),
+                session_id.clone(),
+                max_log_size,
+            )),
+            _ => None,
+        };
+        log::debug!("[{}] Started OpenVPN", session_id);
         Ok(OpenVpnMonitor {
             child: Arc::new(child),
+            session_id,
             proxy_monitor,
             log_path,
+            log_size_limiter,
             closed: Arc::new(AtomicBool::new(false)),
+            connected_endpoint,
             _user_pass_file: user_pass_file,
             _proxy_auth_file: proxy_auth_file,
+            _tls_crypt_v2_file: tls_crypt_v2_file,
+            on_exit: on_exit.map(ExitCallback),
+            _log_warning_watcher: log_warning_watcher,
+            event_subscribers,
+            reconnect_tracker,
 
             runtime,
+            _dedicated_runtime: dedicated_runtime,
             event_server_abort_tx,
+            event_server_abort_rx,
             server_join_handle: Some(server_join_handle),
         })
     }
+}
 
-    /// Creates a handle to this monitor, allowing the tunnel to be closed while some other
-    /// thread is blocked in `wait`.
-    pub fn close_handle(&self) -> OpenVpnCloseHandle<C::ProcessHandle> {
-        OpenVpnCloseHandle {
-            child: self.child.clone(),
-            closed: self.closed.clone(),
-        }
+/// Function called once the OpenVPN process has exited.
+struct ExitCallback(Box<dyn FnOnce() + Send>);
+
+impl fmt::Debug for ExitCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExitCallback")
     }
+}
 
-    /// Consumes the monitor and waits for both proxy and tunnel, as applicable.
-    pub fn wait(mut self) -> Result<()> {
-        if let Some(mut proxy_monitor) = self.proxy_monitor.take() {
-            let (tx_tunnel, rx) = mpsc::channel();
-            let tx_proxy = tx_tunnel.clone();
-            let tunnel_close_handle = self.close_handle();
-            let proxy_close_handle = proxy_monitor.close_handle();
+/// Reports `WARNING:` lines, unexpected data ciphers and restart pauses as OpenVPN appends them to
+/// its log, until dropped.
+#[derive(Debug)]
+struct LogWarningWatcher {
+    stop: Arc<AtomicBool>,
+}
 
-            enum Stopped {
-                Tunnel(Result<()>),
-                Proxy(proxy::Result<proxy::WaitResult>),
+impl LogWarningWatcher {
+    fn start(
+        log_path: PathBuf,
+        session_id: String,
+        on_warning: Box<dyn Fn(String) + Send>,
+        on_restart_pause: Box<dyn Fn(Duration) + Send>,
+        allowed_data_ciphers: Vec<String>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            if let Err(error) = watch_log_warnings(
+                &log_path,
+                &thread_stop,
+                &*on_warning,
+                &*on_restart_pause,
+                &allowed_data_ciphers,
+            ) {
+                log::debug!(
+                    "[{}] Stopped watching the OpenVPN log for warnings: {}",
+                    session_id,
+                    error
+                );
             }
+        });
+        LogWarningWatcher { stop }
+    }
+}
 
-            thread::spawn(move || {
-                tx_tunnel.send(Stopped::Tunnel(self.wait_tunnel())).unwrap();
-                let _ = proxy_close_handle.close();
-            });
-
-            thread::spawn(move || {
-                tx_proxy.send(Stopped::Proxy(proxy_monitor.wait())).unwrap();
-                let _ = tunnel_close_handle.close();
-            });
+impl Drop for LogWarningWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
 
-            let result = rx.recv().expect("wait got no result");
-            let _ = rx.recv();
+/// Follows the log at `log_path` and calls `on_warning` for each complete warning line and each
+/// data cipher that isn't in `allowed_data_ciphers`, and `on_restart_pause` for each restart
+/// pause, until `stop` is set. The file is followed by handle, so the log can be rotated while
+/// being watched.
+fn watch_log_warnings(
+    log_path: &Path,
+    stop: &AtomicBool,
+    on_warning: &dyn Fn(String),
+    on_restart_pause: &dyn Fn(Duration),
+    allowed_data_ciphers: &[String],
+) -> io::Result<()> {
+    // OpenVPN may not have created the log yet.
+    let mut reader = loop {
+        if stop.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        match fs::File::open(log_path) {
+            Ok(file) => break io::BufReader::new(file),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                thread::sleep(LOG_POLL_INTERVAL)
+            }
+            Err(error) => return Err(error),
+        }
+    };
 
-            match result {
-                Stopped::Tunnel(tunnel_result) => tunnel_result,
-                Stopped::Proxy(proxy_result) => {
-                    // The proxy should never exit before openvpn.
-                    match proxy_result {
-                        Ok(proxy::WaitResult::ProperShutdown) => {
-                            Err(Error::ProxyExited("No details".to_owned()))
-                        }
-                        Ok(proxy::WaitResult::UnexpectedExit(details)) => {
-                            Err(Error::ProxyExited(details))
-                        }
-                        Err(err) => Err(err).map_err(Error::MonitorProxyError),
-                    }
-                }
+    let mut position = 0;
+    let mut line = Vec::new();
+    while !stop.load(Ordering::SeqCst) {
+        if reader.get_ref().metadata()?.len() < position {
+            // OpenVPN truncated the log when it started.
+            reader.seek(SeekFrom::Start(0))?;
+            position = 0;
+            line.clear();
+        }
+        let read = reader.read_until(b'\n', &mut line)?;
+        position += read as u64;
+        if line.ends_with(b"\n") {
+            let text = String::from_utf8_lossy(&line);
+            if let Some(warning) = parse_warning(&text) {
+                on_warning(warning);
+            } else if let Some(warning) = check_data_cipher(&text, allowed_data_ciphers) {
+                on_warning(warning);
+            } else if let Some(delay) = parse_restart_pause(&text) {
+                on_restart_pause(delay);
             }
-        } else {
-            // No proxy active, wait only for the tunnel.
-            self.wait_tunnel()
+            line.clear();
+        } else if read == 0 {
+            thread::sleep(LOG_POLL_INTERVAL);
         }
     }
+    Ok(())
+}
 
-    /// Supplement `inner_wait_tunnel()` with logging and error handling.
-    fn wait_tunnel(&mut self) -> Result<()> {
-        let result = self.inner_wait_tunnel();
-        match result {
-            WaitResult::Child(Ok(exit_status), closed) => {
-                if exit_status.success() || closed {
+/// Rotates the OpenVPN log whenever it grows past its size cap, until dropped.
+#[derive(Debug)]
+struct LogSizeLimiter {
+    log_path: Arc<parking_lot::Mutex<PathBuf>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl LogSizeLimiter {
+    fn start(log_path: PathBuf, session_id: String, max_size: u64) -> Self {
+        let log_path = Arc::new(parking_lot::Mutex::new(log_path));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_log_path = log_path.clone();
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            while !thread_stop.load(Ordering::SeqCst) {
+                let log_path = thread_log_path.lock().clone();
+                if let Err(error) = limit_log_size(&log_path, max_size) {
                     log::debug!(
-                        "OpenVPN exited, as expected, with exit status: {}",
-                        exit_status
+                        "[{}] Failed to rotate the OpenVPN log: {}",
+                        session_id,
+                        error
                     );
-                    Ok(())
-                } else {
-                    log::error!("OpenVPN died unexpectedly with status: {}", exit_status);
-                    Err(self.postmortem())
                 }
+                thread::sleep(LOG_POLL_INTERVAL);
             }
-            WaitResult::Child(Err(e), _) => {
-                log::error!("OpenVPN process wait error: {}", e);
-                Err(Error::ChildProcessError("Error when waiting", e))
-            }
-            WaitResult::EventDispatcher => {
-                log::error!("OpenVPN Event server exited unexpectedly");
-                Err(Error::EventDispatcherExited)
-            }
-        }
+        });
+        LogSizeLimiter { log_path, stop }
     }
 
-    /// Waits for both the child process and the event dispatcher in parallel. After both have
-    /// returned this returns the earliest result.
-    fn inner_wait_tunnel(&mut self) -> WaitResult {
-        let child_wait_handle = self.child.clone();
-        let closed_handle = self.closed.clone();
-        let child_close_handle = self.close_handle();
+    /// Makes the limiter follow a log that has been moved to `log_path`.
+    fn set_log_path(&self, log_path: PathBuf) {
+        *self.log_path.lock() = log_path;
+    }
+}
+
+impl Drop for LogSizeLimiter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Returns the path that the log at `log_path` is rotated to.
+fn old_log_path(log_path: &Path) -> PathBuf {
+    let mut old_log_path = log_path.as_os_str().to_owned();
+    old_log_path.push(".old");
+    PathBuf::from(old_log_path)
+}
+
+/// Copies the log at `log_path` to its `.old` file and truncates it, if it is larger than
+/// `max_size` bytes. The log is copied rather than moved, since OpenVPN keeps writing to the file
+/// it has open. Returns whether the log was rotated.
+///
+/// Anything that OpenVPN writes between the copy and the truncation is lost.
+fn limit_log_size(log_path: &Path, max_size: u64) -> io::Result<bool> {
+    let size = match fs::metadata(log_path) {
+        Ok(metadata) => metadata.len(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    if size <= max_size {
+        return Ok(false);
+    }
+    fs::copy(log_path, old_log_path(log_path))?;
+    fs::OpenOptions::new()
+        .write(true)
+        .open(log_path)?
+        .set_len(0)?;
+    log::debug!(
+        "Rotated OpenVPN log at {} after it reached {} bytes",
+        log_path.display(),
+        size
+    );
+    Ok(true)
+}
+
+/// Empties the log at `log_path` and removes its `.old` file, so that a capped log, which
+/// OpenVPN appends to, only holds the current session.
+fn clear_log(log_path: &Path) -> io::Result<()> {
+    fs::File::create(log_path)?;
+    match fs::remove_file(old_log_path(log_path)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the last `lines` lines of a log that may have been rotated by `limit_log_size`,
+/// continuing into the `.old` file if the current log is shorter than that.
+fn tail_rotated_log(log_path: &Path, lines: usize) -> io::Result<Vec<String>> {
+    let current = logging::tail(log_path, lines)?;
+    if current.len() >= lines {
+        return Ok(current);
+    }
+    let mut tail = logging::tail(&old_log_path(log_path), lines - current.len())?;
+    tail.extend(current);
+    Ok(tail)
+}
+
+/// Reads the size cap of the OpenVPN log from the environment, if there is one.
+fn max_log_size_from_env() -> Option<u64> {
+    let value = std::env::var(MAX_LOG_SIZE_ENV_VAR).ok()?;
+    match value.parse() {
+        Ok(max_log_size) => Some(max_log_size),
+        Err(_) => {
+            log::warn!("Ignoring invalid {} value: {}", MAX_LOG_SIZE_ENV_VAR, value);
+            None
+        }
+    }
+}
+
+impl<C: OpenVpnBuilder> Drop for OpenVpnMonitor<C> {
+    fn drop(&mut self) {
+        // Tasks on a shared runtime are not stopped by dropping the monitor.
+        self.event_server_abort_tx.trigger();
+    }
+}
+
+/// Calls `restart` for every item produced by `changes`, except for those that arrive less than
+/// `min_interval` after the previous restart.
+async fn restart_on_route_changes(
+    mut changes: impl futures::Stream<Item = ()> + Unpin,
+    min_interval: Duration,
+    session_id: String,
+    mut restart: impl FnMut(),
+) {
+    use futures::StreamExt;
+
+    let mut last_restart: Option<Instant> = None;
+    while let Some(()) = changes.next().await {
+        if let Some(last_restart) = last_restart {
+            if last_restart.elapsed() < min_interval {
+                log::debug!(
+                    "[{}] Ignoring default route change, OpenVPN was restarted recently",
+                    session_id
+                );
+                continue;
+            }
+        }
+        last_restart = Some(Instant::now());
+        restart();
+    }
+}
+
+impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
+    /// Soft restarts OpenVPN whenever `default_route_changes` produces an item, at most once per
+    /// `DEFAULT_ROUTE_RESTART_INTERVAL`. A TCP tunnel may otherwise stall after the default route
+    /// has moved to another interface.
+    pub fn restart_on_default_route_changes(&self, default_route_changes: DefaultRouteChanges) {
+        let child = self.child.clone();
+        let closed = self.closed.clone();
+        let session_id = self.session_id.clone();
+        let restarts = restart_on_route_changes(
+            default_route_changes,
+            DEFAULT_ROUTE_RESTART_INTERVAL,
+            session_id.clone(),
+            move || {
+                if closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                log::info!("[{}] Default route changed, restarting OpenVPN", session_id);
+                if let Err(error) = child.soft_restart() {
+                    log::error!("[{}] Failed to restart OpenVPN: {}", session_id, error);
+                }
+            },
+        );
+        // Stop with the event server, since the runtime may outlive the monitor.
+        let stopped = self.event_server_abort_rx.clone();
+        self.runtime
+            .spawn(futures::future::select(Box::pin(restarts), stopped));
+    }
+
+    /// Returns the short id that identifies this session in logs and in the metadata of tunnel
+    /// events.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Returns the PID of the OpenVPN process, or `None` if it has already exited.
+    pub fn openvpn_pid(&self) -> Option<u32> {
+        self.child.pid()
+    }
+
+    /// Creates a handle to this monitor, allowing the tunnel to be closed while some other
+    /// thread is blocked in `wait`.
+    pub fn close_handle(&self) -> OpenVpnCloseHandle<C::ProcessHandle> {
+        OpenVpnCloseHandle {
+            child: self.child.clone(),
+            closed: self.closed.clone(),
+        }
+    }
+
+    /// Returns the subscribers that receive the tunnel events of this monitor, so that more can be
+    /// added.
+    pub fn event_subscribers(&self) -> &TunnelEventSubscribers {
+        &self.event_subscribers
+    }
+
+    /// Blocks until a tunnel event for which `matcher` returns `true` arrives, and returns it.
+    /// Only events that arrive after this is called are considered. Fails if no such event
+    /// arrives within `timeout`.
+    pub fn wait_for_event(
+        &self,
+        matcher: impl Fn(&TunnelEvent) -> bool,
+        timeout: Duration,
+    ) -> Result<TunnelEvent> {
+        let events = self.event_subscribers.events();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match events.recv_timeout(remaining) {
+                Ok(event) if matcher(&event) => return Ok(event),
+                Ok(_) => (),
+                Err(_) => return Err(Error::EventTimeout(timeout)),
+            }
+        }
+    }
+
+    /// Returns how many times in a row OpenVPN has tried to reconnect without the tunnel coming
+    /// back up, or zero if it isn't reconnecting. This never goes past 1 unless
+    /// `TunnelOptions::connect_retry_max` is set, as OpenVPN otherwise exits instead of retrying.
+    pub fn reconnect_attempts(&self) -> u32 {
+        self.reconnect_tracker.attempts()
+    }
+
+    /// Returns how long OpenVPN waits before its latest attempt to reconnect, if it is
+    /// reconnecting.
+    pub fn next_reconnect_delay(&self) -> Option<Duration> {
+        self.reconnect_tracker.next_delay()
+    }
+
+    /// Returns the server endpoint that OpenVPN connected to, once the tunnel has come up.
+    pub fn connected_endpoint(&self) -> Option<SocketAddr> {
+        *self.connected_endpoint.lock()
+    }
+
+    /// Returns the traffic carried by the proxy, if the tunnel goes through a proxy that reports
+    /// it. Unlike the OpenVPN byte counters, this is the traffic that is actually sent over the
+    /// network.
+    pub fn proxy_stats(&self) -> Option<ProxyStats> {
+        self.proxy_monitor.as_ref()?.proxy_stats()
+    }
+
+    /// Returns the path to the OpenVPN log file, if OpenVPN is logging to a file.
+    pub fn log_path(&self) -> Option<&Path> {
+        self.log_path.as_ref().map(|path| path.as_path())
+    }
+
+    /// Returns the last `lines` lines of the OpenVPN log. Returns no lines if OpenVPN is not
+    /// logging to a file or has not created the log file yet. If the log has a size cap, lines
+    /// that have been rotated out of it are included.
+    pub fn tail_log(&self, lines: usize) -> io::Result<Vec<String>> {
+        match (self.log_path(), &self.log_size_limiter) {
+            (Some(log_path), Some(_)) => tail_rotated_log(log_path, lines),
+            (Some(log_path), None) => logging::tail(log_path, lines),
+            (None, _) => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the ciphers and TLS version negotiated for the current connection, as reported in
+    /// the OpenVPN log. Returns `None` until negotiation has completed, or if OpenVPN is not
+    /// logging to a file.
+    pub fn negotiated_crypto(&self) -> Option<CryptoInfo> {
+        match self.tail_log(usize::MAX) {
+            Ok(lines) => parse_negotiated_crypto(lines.iter().map(String::as_str)),
+            Err(error) => {
+                log::debug!(
+                    "[{}] Failed to read OpenVPN log: {}",
+                    self.session_id,
+                    error
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns the warnings that OpenVPN has written to its log during this session, oldest
+    /// first. Empty if OpenVPN is not logging to a file.
+    pub fn warnings(&self) -> Vec<String> {
+        match self.tail_log(usize::MAX) {
+            Ok(lines) => parse_warnings(lines.iter().map(String::as_str)),
+            Err(error) => {
+                log::debug!(
+                    "[{}] Failed to read OpenVPN log: {}",
+                    self.session_id,
+                    error
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Moves the OpenVPN log to `new_path` while OpenVPN is running.
+    ///
+    /// OpenVPN cannot be told to reopen its log, but it keeps writing to the file it already has
+    /// open, so it continues logging at `new_path`. `log_path`, `tail_log` and the postmortem
+    /// analysis follow the log to its new location. Where the running OpenVPN holds the log in a
+    /// way that prevents it from being moved, such as on Windows, an error is returned and the
+    /// log stays where it was.
+    pub fn move_log(&mut self, new_path: PathBuf) -> Result<()> {
+        let log_path = self.log_path.as_ref().ok_or(Error::NoLogFile)?;
+        fs::rename(log_path, &new_path).map_err(Error::MoveLogError)?;
+        log::debug!(
+            "[{}] Moved OpenVPN log from {} to {}",
+            self.session_id,
+            log_path.display(),
+            new_path.display()
+        );
+        if let Some(log_size_limiter) = &self.log_size_limiter {
+            log_size_limiter.set_log_path(new_path.clone());
+        }
+        self.log_path = Some(new_path);
+        Ok(())
+    }
+
+    /// Consumes the monitor and waits for both proxy and tunnel, as applicable.
+    pub fn wait(mut self) -> Result<()> {
+        if let Some(mut proxy_monitor) = self.proxy_monitor.take() {
+            let (tx_tunnel, rx) = mpsc::channel();
+            let tx_proxy = tx_tunnel.clone();
+            let tunnel_close_handle = self.close_handle();
+            let proxy_close_handle = proxy_monitor.close_handle();
+            let proxy_kind = proxy_monitor.kind();
+
+            enum Stopped {
+                Tunnel(Result<()>),
+                Proxy(proxy::Result<proxy::WaitResult>),
+            }
+
+            thread::spawn(move || {
+                tx_tunnel.send(Stopped::Tunnel(self.wait_tunnel())).unwrap();
+                let _ = proxy_close_handle.close();
+            });
+
+            thread::spawn(move || {
+                tx_proxy.send(Stopped::Proxy(proxy_monitor.wait())).unwrap();
+                let _ = tunnel_close_handle.close();
+            });
+
+            let result = rx.recv().expect("wait got no result");
+            let _ = rx.recv();
+
+            match result {
+                Stopped::Tunnel(tunnel_result) => tunnel_result,
+                // The proxy should never exit before openvpn.
+                Stopped::Proxy(proxy_result) => Err(proxy_exit_error(proxy_kind, proxy_result)),
+            }
+        } else {
+            // No proxy active, wait only for the tunnel.
+            self.wait_tunnel()
+        }
+    }
+
+    /// Supplement `inner_wait_tunnel()` with logging and error handling.
+    fn wait_tunnel(&mut self) -> Result<()> {
+        let result = self.inner_wait_tunnel();
+        if let Some(ExitCallback(on_exit)) = self.on_exit.take() {
+            on_exit();
+        }
+        self.handle_wait_result(result)
+    }
+
+    /// Only the closed flag captured in `result` tells whether the exit was requested. The live
+    /// flag is also set when the event dispatcher kills OpenVPN after it has exited.
+    fn handle_wait_result(&mut self, result: WaitResult) -> Result<()> {
+        match result {
+            WaitResult::Child(_, true) | WaitResult::EventDispatcher(true) => {
+                // The tunnel was closed on request, possibly before it ever came up. Killing
+                // OpenVPN can make it or the event server exit in any number of ways, none of
+                // which are errors.
+                log::debug!(
+                    "[{}] OpenVPN was closed, wait result: {:?}",
+                    self.session_id,
+                    result
+                );
+                Ok(())
+            }
+            WaitResult::EventDispatcher(false) => {
+                log::error!(
+                    "[{}] OpenVPN Event server exited unexpectedly",
+                    self.session_id
+                );
+                Err(Error::EventDispatcherExited)
+            }
+            WaitResult::Child(Ok(exit_status), false) => {
+                if exit_status.success() {
+                    log::debug!(
+                        "[{}] OpenVPN exited, as expected, with exit status: {}",
+                        self.session_id,
+                        exit_status
+                    );
+                    Ok(())
+                } else {
+                    let code = exit_status.code();
+                    let error = self.postmortem(code);
+                    if let Error::ConnectRetriesExhausted = error {
+                        log::info!(
+                            "[{}] OpenVPN gave up connecting, exit status: {}",
+                            self.session_id,
+                            exit_status
+                        );
+                        return Err(error);
+                    }
+                    match code.and_then(exit_code_reason) {
+                        Some(reason) => log::error!(
+                            "[{}] OpenVPN died unexpectedly with status: {} ({})",
+                            self.session_id,
+                            exit_status,
+                            reason
+                        ),
+                        None => log::error!(
+                            "[{}] OpenVPN died unexpectedly with status: {}",
+                            self.session_id,
+                            exit_status
+                        ),
+                    }
+                    Err(error)
+                }
+            }
+            WaitResult::Child(Err(e), false) => {
+                log::error!("[{}] OpenVPN process wait error: {}", self.session_id, e);
+                Err(Error::ChildProcessError("Error when waiting", e))
+            }
+        }
+    }
+
+    /// Waits for both the child process and the event dispatcher in parallel. After both have
+    /// returned this returns the earliest result.
+    fn inner_wait_tunnel(&mut self) -> WaitResult {
+        let child_wait_handle = self.child.clone();
+        let closed_handle = self.closed.clone();
+        let child_close_handle = self.close_handle();
 
         let (child_tx, rx) = mpsc::channel();
         let dispatcher_tx = child_tx.clone();
@@ -383,13 +1424,17 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
             event_server_abort_tx.trigger();
         });
 
+        let dispatcher_closed_handle = self.closed.clone();
         let server_join_handle = self
             .server_join_handle
             .take()
             .expect("No event server quit handle");
         self.runtime.spawn(async move {
             let _ = server_join_handle.await;
-            dispatcher_tx.send(WaitResult::EventDispatcher).unwrap();
+            let closed = dispatcher_closed_handle.load(Ordering::SeqCst);
+            dispatcher_tx
+                .send(WaitResult::EventDispatcher(closed))
+                .unwrap();
             let _ = child_close_handle.close();
         });
 
@@ -399,53 +1444,95 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
     }
 
     /// Performs a postmortem analysis to attempt to provide a more detailed error result.
-    fn postmortem(&mut self) -> Error {
-        #[cfg(windows)]
-        {
-            if let Some(log_path) = self.log_path.take() {
-                if let Ok(log) = fs::read_to_string(log_path) {
-                    if log.contains("There are no TAP-Windows adapters on this system") {
-                        return Error::MissingTapAdapter;
-                    }
-                    if log.contains("CreateFile failed on TAP device") {
-                        return Error::DisabledTapAdapter;
-                    }
+    fn postmortem(&mut self, code: Option<i32>) -> Error {
+        if let Ok(lines) = self.tail_log(usize::MAX) {
+            let log = lines.join("\n");
+            if log.contains("times unsuccessful, exiting") {
+                return Error::ConnectRetriesExhausted;
+            }
+            #[cfg(windows)]
+            {
+                if log.contains("There are no TAP-Windows adapters on this system") {
+                    return Error::MissingTapAdapter;
+                }
+                if log.contains("CreateFile failed on TAP device") {
+                    return Error::DisabledTapAdapter;
                 }
             }
         }
 
-        Error::ChildProcessDied
+        Error::ChildProcessDied { code }
     }
 
+    /// Writes the proxy credentials to a file that OpenVPN can read, if the proxy is
+    /// authenticated through a file. See `ProxyAuthMode`.
     fn create_proxy_auth_file(
         proxy_settings: &Option<openvpn::ProxySettings>,
+        temp_dir: &Path,
     ) -> std::result::Result<Option<mktemp::TempFile>, io::Error> {
-        if let Some(openvpn::ProxySettings::Remote(ref remote_proxy)) = proxy_settings {
-            if let Some(ref proxy_auth) = remote_proxy.auth {
-                return Ok(Some(Self::create_credentials_file(
-                    &proxy_auth.username,
-                    &proxy_auth.password,
-                )?));
-            }
+        let proxy_auth = match proxy_settings.as_ref().and_then(proxy_auth_mode) {
+            Some(ProxyAuthMode::File(proxy_auth)) => proxy_auth,
+            Some(ProxyAuthMode::ProxyConfig) | None => return Ok(None),
+        };
+        if let Some(proxy_auth) = proxy_auth {
+            return Ok(Some(Self::create_credentials_file(
+                &proxy_auth.username,
+                &proxy_auth.password,
+                temp_dir,
+            )?));
         }
         Ok(None)
     }
 
     /// Starts a proxy service, as applicable.
     fn start_proxy(
+        session_id: &str,
         proxy_settings: &Option<openvpn::ProxySettings>,
+        port_preference: Option<openvpn::ProxyPortPreference>,
+        ready_timeout: Duration,
         proxy_resources: &ProxyResourceData,
     ) -> Result<Option<Box<dyn ProxyMonitor>>> {
         if let Some(ref settings) = proxy_settings {
-            let proxy_monitor =
-                proxy::start_proxy(settings, proxy_resources).map_err(Error::StartProxyError)?;
-            return Ok(Some(proxy_monitor));
+            openvpn::validate_proxy_settings(settings).map_err(Error::InvalidProxySettings)?;
+            let proxy_monitor = proxy::start_proxy(settings, port_preference, proxy_resources)
+                .map_err(Error::StartProxyError)?;
+            return Self::wait_for_proxy(session_id, proxy_monitor, ready_timeout).map(Some);
         }
         Ok(None)
     }
 
-    fn create_credentials_file(username: &str, password: &str) -> io::Result<mktemp::TempFile> {
-        let temp_file = mktemp::TempFile::new();
+    /// Blocks until the proxy accepts connections, so that OpenVPN isn't launched before it can
+    /// connect through the proxy. The proxy is stopped if it isn't ready within `ready_timeout`.
+    fn wait_for_proxy(
+        session_id: &str,
+        mut proxy_monitor: Box<dyn ProxyMonitor>,
+        ready_timeout: Duration,
+    ) -> Result<Box<dyn ProxyMonitor>> {
+        if let Err(error) = proxy_monitor.wait_ready(ready_timeout) {
+            log::error!(
+                "[{}] {} proxy is not ready: {}",
+                session_id,
+                proxy_monitor.kind(),
+                error
+            );
+            if let Err(error) = proxy_monitor.close_handle().close() {
+                log::error!(
+                    "[{}] Failed to stop proxy that was not ready: {}",
+                    session_id,
+                    error
+                );
+            }
+            return Err(Error::ProxyNotReady(ready_timeout));
+        }
+        Ok(proxy_monitor)
+    }
+
+    fn create_credentials_file(
+        username: &str,
+        password: &str,
+        temp_dir: &Path,
+    ) -> io::Result<mktemp::TempFile> {
+        let temp_file = mktemp::TempFile::new_in(temp_dir);
         log::debug!("Writing credentials to {}", temp_file.as_ref().display());
         let mut file = fs::File::create(&temp_file)?;
         Self::set_user_pass_file_permissions(&file)?;
@@ -453,6 +1540,17 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         Ok(temp_file)
     }
 
+    fn create_key_file(key: &[u8], temp_dir: &Path) -> io::Result<mktemp::TempFile> {
+        let temp_file = mktemp::TempFile::new_in(temp_dir);
+        log::debug!(
+            "Writing tls-crypt-v2 key to {}",
+            temp_file.as_ref().display()
+        );
+        let mut file = fs::File::create(&temp_file)?;
+        Self::set_user_pass_file_permissions(&file)?;
+        file.write_all(key)?;
+        Ok(temp_file)
+    }
 
     #[cfg(unix)]
     fn set_user_pass_file_permissions(file: &fs::File) -> io::Result<()> {
@@ -466,8 +1564,14 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         Ok(())
     }
 
-    fn get_plugin_path(resource_dir: &Path) -> Result<PathBuf> {
-        let path = resource_dir.join(OPENVPN_PLUGIN_FILENAME);
+    /// Returns the plugin named `filename_override`, or `OPENVPN_PLUGIN_FILENAME` if not given, in
+    /// `resource_dir`, as long as the file exists.
+    fn get_plugin_path(resource_dir: &Path, filename_override: Option<PathBuf>) -> Result<PathBuf> {
+        let filename = filename_override.unwrap_or_else(|| PathBuf::from(OPENVPN_PLUGIN_FILENAME));
+        if filename.file_name() != Some(filename.as_os_str()) {
+            return Err(Error::InvalidPluginFilename(filename.display().to_string()));
+        }
+        let path = resource_dir.join(filename);
         if path.exists() {
             log::trace!("Using OpenVPN plugin at {}", path.display());
             Ok(path)
@@ -480,11 +1584,18 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         params: &openvpn::TunnelParameters,
         user_pass_file: &Path,
         proxy_auth_file: Option<&Path>,
+        tls_crypt_v2_file: Option<&Path>,
         resource_dir: &Path,
         proxy_monitor: &Option<Box<dyn ProxyMonitor>>,
     ) -> Result<OpenVpnCommand> {
         let mut cmd = OpenVpnCommand::new(Self::get_openvpn_bin(resource_dir)?);
+        check_remote_endpoint(params.config.endpoint.address)?;
         if let Some(config) = Self::get_config_path(resource_dir) {
+            let config_contents = fs::read_to_string(&config).map_err(Error::ReadConfigError)?;
+            check_config_remotes(&config_contents)?;
+            if tls_crypt_v2_file.is_some() {
+                check_config_without_tls_auth(&config_contents)?;
+            }
             cmd.config(config);
         }
         #[cfg(target_os = "linux")]
@@ -493,7 +1604,10 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
             .user_pass(user_pass_file)
             .tunnel_options(&params.options)
             .enable_ipv6(params.generic_options.enable_ipv6)
-            .ca(resource_dir.join("ca.crt"));
+            .ca(Self::get_ca_path(
+                resource_dir,
+                std::env::var_os(CA_PATH_ENV_VAR).map(PathBuf::from),
+            )?);
         #[cfg(windows)]
         cmd.tunnel_alias(Some(
             crate::winnet::get_tap_interface_alias().map_err(Error::WinnetError)?,
@@ -504,6 +1618,12 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         if let Some(proxy_auth_file) = proxy_auth_file {
             cmd.proxy_auth(proxy_auth_file);
         }
+        if let Some(tls_crypt_v2_file) = tls_crypt_v2_file {
+            cmd.tls_crypt_v2(tls_crypt_v2_file);
+        }
+        if let Some(ref name) = params.config.verify_x509_name {
+            cmd.verify_x509_name(name.as_str());
+        }
         if let Some(proxy) = proxy_monitor {
             cmd.proxy_port(proxy.port());
         }
@@ -521,6 +1641,18 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
         }
     }
 
+    /// Returns `ca_override`, or `ca.crt` in `resource_dir` if not given, as long as the file
+    /// exists.
+    fn get_ca_path(resource_dir: &Path, ca_override: Option<PathBuf>) -> Result<PathBuf> {
+        let path = ca_override.unwrap_or_else(|| resource_dir.join("ca.crt"));
+        if path.exists() {
+            log::trace!("Using CA certificate at {}", path.display());
+            Ok(path)
+        } else {
+            Err(Error::CaNotFound(path.display().to_string()))
+        }
+    }
+
     fn get_config_path(resource_dir: &Path) -> Option<PathBuf> {
         let path = resource_dir.join("openvpn.conf");
         if path.exists() {
@@ -531,102 +1663,643 @@ impl<C: OpenVpnBuilder + 'static> OpenVpnMonitor<C> {
     }
 }
 
-/// A handle to an `OpenVpnMonitor` for closing it.
-#[derive(Debug, Clone)]
-pub struct OpenVpnCloseHandle<H: ProcessHandle = OpenVpnProcHandle> {
-    child: Arc<H>,
-    closed: Arc<AtomicBool>,
+/// Turns the `Up` event that follows a `Reconnecting` event into `Reconnected`, since the plugin
+/// reports both the first connection and a reconnection as `RouteUp`. Also counts the attempts
+/// to reconnect, since the plugin only reports the first one.
+#[derive(Debug, Default)]
+pub(crate) struct ReconnectTracker {
+    /// How long OpenVPN waits before its first attempt to reconnect.
+    first_delay: Duration,
+    state: parking_lot::Mutex<ReconnectState>,
 }
 
-impl<H: ProcessHandle> OpenVpnCloseHandle<H> {
-    /// Kills the underlying OpenVPN process, making the `OpenVpnMonitor::wait` method return.
-    pub fn close(self) -> io::Result<()> {
-        if !self.closed.swap(true, Ordering::SeqCst) {
-            self.child.kill()
-        } else {
-            Ok(())
-        }
-    }
+#[derive(Debug, Default)]
+struct ReconnectState {
+    /// Attempts to reconnect in a row. Zero if not reconnecting.
+    attempts: u32,
+    next_delay: Duration,
+    /// Whether the restart pause before the first attempt has yet to be logged.
+    first_pause_pending: bool,
 }
 
-/// Internal enum to differentiate between if the child process or the event dispatcher died first.
-#[derive(Debug)]
-enum WaitResult {
-    Child(io::Result<ExitStatus>, bool),
-    EventDispatcher,
-}
+impl ReconnectTracker {
+    fn new(first_delay: Duration) -> Self {
+        ReconnectTracker {
+            first_delay,
+            state: parking_lot::Mutex::new(ReconnectState::default()),
+        }
+    }
 
-/// Trait for types acting as OpenVPN process starters for `OpenVpnMonitor`.
-pub trait OpenVpnBuilder {
-    /// The type of handles to subprocesses this builder produces.
-    type ProcessHandle: ProcessHandle;
+    fn track(&self, event: TunnelEvent) -> TunnelEvent {
+        let mut state = self.state.lock();
+        match event {
+            TunnelEvent::Up(metadata) if state.attempts > 0 => {
+                *state = ReconnectState::default();
+                TunnelEvent::Reconnected(metadata)
+            }
+            TunnelEvent::Reconnecting { .. } => {
+                *state = ReconnectState {
+                    attempts: 1,
+                    next_delay: self.first_delay,
+                    first_pause_pending: true,
+                };
+                TunnelEvent::Reconnecting {
+                    attempt: state.attempts,
+                    next_delay: state.next_delay,
+                }
+            }
+            event => event,
+        }
+    }
 
-    /// Set the OpenVPN plugin to the given values.
-    fn plugin(&mut self, path: impl AsRef<Path>, args: Vec<String>) -> &mut Self;
+    /// Registers that OpenVPN pauses for `delay` before restarting. Returns the event to send if
+    /// this starts a new attempt to reconnect. The first pause belongs to the attempt that was
+    /// reported when the tunnel went down.
+    fn restart_paused(&self, delay: Duration) -> Option<TunnelEvent> {
+        let mut state = self.state.lock();
+        if state.attempts == 0 {
+            return None;
+        }
+        state.next_delay = delay;
+        if state.first_pause_pending {
+            state.first_pause_pending = false;
+            return None;
+        }
+        state.attempts += 1;
+        Some(TunnelEvent::Reconnecting {
+            attempt: state.attempts,
+            next_delay: delay,
+        })
+    }
 
-    /// Set the OpenVPN log file path to use.
-    fn log(&mut self, log_path: Option<impl AsRef<Path>>) -> &mut Self;
+    fn attempts(&self) -> u32 {
+        self.state.lock().attempts
+    }
 
-    /// Spawn the subprocess and return a handle.
-    fn start(&self) -> io::Result<Self::ProcessHandle>;
+    fn next_delay(&self) -> Option<Duration> {
+        let state = self.state.lock();
+        Some(state.next_delay).filter(|_| state.attempts > 0)
+    }
 }
 
-/// Trait for types acting as handles to subprocesses for `OpenVpnMonitor`
-pub trait ProcessHandle: Send + Sync + 'static {
-    /// Block until the subprocess exits or there is an error in the wait syscall.
-    fn wait(&self) -> io::Result<ExitStatus>;
-
-    /// Kill the subprocess.
-    fn kill(&self) -> io::Result<()>;
+/// Checks that the server OpenVPN connects to is a usable IP address, so OpenVPN never has to
+/// resolve it.
+fn check_remote_endpoint(address: SocketAddr) -> Result<()> {
+    if address.ip().is_unspecified() {
+        return Err(Error::UnresolvedRemote(address.to_string()));
+    }
+    Ok(())
 }
 
-impl OpenVpnBuilder for OpenVpnCommand {
-    type ProcessHandle = OpenVpnProcHandle;
+/// Checks that every `remote` directive in an OpenVPN config file uses an IP address rather than
+/// a host name. OpenVPN adds these to the servers given on the command line.
+fn check_config_remotes(config: &str) -> Result<()> {
+    for line in config.lines() {
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some("remote") {
+            continue;
+        }
+        match parts.next() {
+            Some(host) if host.parse::<IpAddr>().is_ok() => (),
+            host => return Err(Error::UnresolvedRemote(host.unwrap_or_default().to_owned())),
+        }
+    }
+    Ok(())
+}
 
-    fn plugin(&mut self, path: impl AsRef<Path>, args: Vec<String>) -> &mut Self {
-        self.plugin(path, args)
+/// Checks that an OpenVPN config file does not set up tls-auth, which cannot be used together
+/// with tls-crypt-v2.
+fn check_config_without_tls_auth(config: &str) -> Result<()> {
+    let uses_tls_auth = config.lines().any(|line| {
+        let directive = line.split_whitespace().next();
+        directive == Some("tls-auth") || directive == Some("<tls-auth>")
+    });
+    if uses_tls_auth {
+        return Err(Error::ConflictingTlsAuth);
     }
+    Ok(())
+}
 
-    fn log(&mut self, log_path: Option<impl AsRef<Path>>) -> &mut Self {
-        if let Some(log_path) = log_path {
-            self.log(log_path)
-        } else {
-            self
+/// How the credentials of a proxy are supplied.
+#[derive(Debug, PartialEq)]
+enum ProxyAuthMode<'a> {
+    /// OpenVPN authenticates with the proxy, using credentials read from a file. This is the only
+    /// option for the proxies that OpenVPN connects to natively, if they require authentication.
+    File(Option<&'a openvpn::ProxyAuth>),
+    /// The bundled proxy authenticates with its peer itself. Its monitor passes the secret in a
+    /// configuration file that only the proxy reads, so it never shows up on a command line or in
+    /// the OpenVPN arguments.
+    ProxyConfig,
+}
+
+/// Returns how the credentials of the given proxy are supplied, or `None` if the proxy is not
+/// authenticated at all.
+fn proxy_auth_mode(proxy_settings: &openvpn::ProxySettings) -> Option<ProxyAuthMode<'_>> {
+    match proxy_settings {
+        openvpn::ProxySettings::Local(_) => None,
+        openvpn::ProxySettings::Remote(remote_proxy) => {
+            Some(ProxyAuthMode::File(remote_proxy.auth.as_ref()))
+        }
+        openvpn::ProxySettings::Http(http_proxy) => {
+            Some(ProxyAuthMode::File(http_proxy.auth.as_ref()))
+        }
+        openvpn::ProxySettings::Shadowsocks(_) | openvpn::ProxySettings::Obfuscation(_) => {
+            Some(ProxyAuthMode::ProxyConfig)
         }
     }
+}
 
-    fn start(&self) -> io::Result<OpenVpnProcHandle> {
-        OpenVpnProcHandle::new(self.build())
+/// Adds `session_id` to the metadata of `event`, if it has any.
+fn with_session_id(event: TunnelEvent, session_id: &str) -> TunnelEvent {
+    match event {
+        TunnelEvent::Up(metadata) => TunnelEvent::Up(TunnelMetadata {
+            session_id: Some(session_id.to_owned()),
+            ..metadata
+        }),
+        TunnelEvent::Reconnected(metadata) => TunnelEvent::Reconnected(TunnelMetadata {
+            session_id: Some(session_id.to_owned()),
+            ..metadata
+        }),
+        event => event,
     }
 }
 
-impl ProcessHandle for OpenVpnProcHandle {
-    fn wait(&self) -> io::Result<ExitStatus> {
-        self.inner.wait().map(|output| output.status)
+/// Adds the relay endpoint, which is known from the tunnel parameters, to the metadata of `Up` and
+/// `Reconnected` events.
+fn with_remote(event: TunnelEvent, remote: Endpoint) -> TunnelEvent {
+    match event {
+        TunnelEvent::Up(metadata) => TunnelEvent::Up(TunnelMetadata {
+            remote: Some(remote),
+            ..metadata
+        }),
+        TunnelEvent::Reconnected(metadata) => TunnelEvent::Reconnected(TunnelMetadata {
+            remote: Some(remote),
+            ..metadata
+        }),
+        event => event,
     }
+}
 
-    fn kill(&self) -> io::Result<()> {
-        self.nice_kill(OPENVPN_DIE_TIMEOUT)
+/// Removes the files that OpenVPN has finished reading once the routes are up. They are also
+/// removed when the monitor is dropped, this just shortens the time they exist on disk.
+fn remove_read_once_files(event: openvpn_plugin::EventType, paths: &[PathBuf]) {
+    if event == openvpn_plugin::EventType::RouteUp {
+        for path in paths {
+            let _ = fs::remove_file(path);
+        }
     }
 }
 
+/// Completes the `Down` event that ends a session with how long the tunnel was up. Reconnections
+/// are part of the same session.
+#[derive(Debug, Default)]
+struct SessionTracker {
+    up_since: parking_lot::Mutex<Option<Instant>>,
+}
 
-mod event_server {
-    use futures::stream::TryStreamExt;
-    use parity_tokio_ipc::{Endpoint as IpcEndpoint, SecurityAttributes};
-    use std::{
-        collections::HashMap,
-        pin::Pin,
+impl SessionTracker {
+    fn track(&self, event: TunnelEvent) -> TunnelEvent {
+        match event {
+            TunnelEvent::Up(metadata) => {
+                self.up_since.lock().get_or_insert_with(Instant::now);
+                TunnelEvent::Up(metadata)
+            }
+            TunnelEvent::Down {
+                rx_bytes, tx_bytes, ..
+            } => TunnelEvent::Down {
+                rx_bytes,
+                tx_bytes,
+                duration: self
+                    .up_since
+                    .lock()
+                    .take()
+                    .map(|up_since| up_since.elapsed())
+                    .unwrap_or_default(),
+            },
+            event => event,
+        }
+    }
+
+    /// Returns the `Down` event for a session that OpenVPN ended without taking the tunnel down,
+    /// such as when it's killed. OpenVPN reports no traffic counters then, so they are zero.
+    fn end(&self) -> Option<TunnelEvent> {
+        let up_since = self.up_since.lock().take()?;
+        Some(TunnelEvent::Down {
+            rx_bytes: 0,
+            tx_bytes: 0,
+            duration: up_since.elapsed(),
+        })
+    }
+}
+
+/// Checks that files can be created in `dir`, so that an unusable directory is reported up front
+/// instead of as an obscure failure once OpenVPN or the proxy is running.
+fn check_writable_dir(dir: &Path) -> Result<()> {
+    let probe = mktemp::TempFile::new_in(dir);
+    fs::File::create(&probe)
+        .map(|_| ())
+        .map_err(|error| Error::DirectoryNotWritable(dir.display().to_string(), error))
+}
+
+/// Returns a new id for an OpenVPN session. It is short enough to prefix log lines with, while
+/// still telling apart the sessions of a single run of the daemon.
+fn new_session_id() -> String {
+    let mut session_id = uuid::Uuid::new_v4().to_simple().to_string();
+    session_id.truncate(8);
+    session_id
+}
+
+/// Creates the directory of `log_path`, along with any missing parents, and checks that it is
+/// writable. The proxy logs to the same directory, and OpenVPN fails confusingly if it can't open
+/// its log.
+fn create_log_dir(log_path: &Path) -> Result<()> {
+    match log_path.parent() {
+        Some(log_dir) if !log_dir.as_os_str().is_empty() => {
+            fs::create_dir_all(log_dir)
+                .map_err(|error| Error::CreateLogDirError(log_dir.display().to_string(), error))?;
+            check_writable_dir(log_dir)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the username and password to authenticate with. Fields that are empty in `config` are
+/// read from the environment through `env_var`, so that credentials can be injected without being
+/// stored in the settings.
+fn resolve_credentials(
+    config: &openvpn::ConnectionConfig,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> (String, String) {
+    let resolve = |value: &String, name| {
+        if value.is_empty() {
+            env_var(name).unwrap_or_default()
+        } else {
+            value.clone()
+        }
+    };
+    (
+        resolve(&config.username, USERNAME_ENV_VAR),
+        resolve(&config.password, PASSWORD_ENV_VAR),
+    )
+}
+
+/// A TAP adapter that OpenVPN could use, as reported by `available_tap_adapters`.
+#[cfg(windows)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapAdapterInfo {
+    /// Alias of the adapter, e.g. `Mullvad`.
+    pub alias: String,
+    /// Name of the adapter, e.g. `Mullvad VPN TAP Adapter #2`.
+    pub name: String,
+    /// Whether the adapter is enabled. OpenVPN fails to open disabled adapters.
+    pub enabled: bool,
+    /// Whether this is the adapter that OpenVPN would be told to use.
+    pub in_use: bool,
+}
+
+/// Lists the TAP adapters on the system, so that a missing or disabled adapter can be reported
+/// before connecting rather than only after OpenVPN has failed.
+#[cfg(windows)]
+pub fn available_tap_adapters() -> Result<Vec<TapAdapterInfo>> {
+    let adapters = crate::winnet::get_tap_adapters().map_err(Error::WinnetError)?;
+    let tap_alias = crate::winnet::get_tap_interface_alias()
+        .ok()
+        .map(|alias| alias.to_string_lossy().into_owned());
+    Ok(tap_adapter_info(adapters, tap_alias.as_deref()))
+}
+
+/// Describes `adapters`, given the alias of the adapter that OpenVPN would use, if any.
+#[cfg(windows)]
+fn tap_adapter_info(
+    adapters: Vec<crate::winnet::TapAdapter>,
+    tap_alias: Option<&str>,
+) -> Vec<TapAdapterInfo> {
+    adapters
+        .into_iter()
+        .map(|adapter| TapAdapterInfo {
+            enabled: adapter.oper_status != crate::winnet::IF_OPER_STATUS_NOT_PRESENT,
+            // Aliases are case insensitive on Windows.
+            in_use: tap_alias
+                .map(|tap_alias| tap_alias.eq_ignore_ascii_case(&adapter.alias))
+                .unwrap_or(false),
+            alias: adapter.alias,
+            name: adapter.name,
+        })
+        .collect()
+}
+
+/// Cryptographic parameters negotiated by OpenVPN for a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptoInfo {
+    /// Cipher used to encrypt tunnel traffic, e.g. `AES-256-GCM`.
+    pub data_cipher: String,
+    /// TLS version of the control channel, e.g. `TLSv1.3`.
+    pub tls_version: String,
+    /// Cipher suite of the control channel, e.g. `TLS_AES_256_GCM_SHA384`.
+    pub tls_cipher: String,
+}
+
+/// Finds the most recently negotiated crypto parameters in the OpenVPN log lines, which look like:
+/// ```text
+/// Control Channel: TLSv1.3, cipher TLSv1.3 TLS_AES_256_GCM_SHA384, 4096 bit RSA
+/// Outgoing Data Channel: Cipher 'AES-256-GCM' initialized with 256 bit key
+/// ```
+fn parse_negotiated_crypto<'a>(
+    lines: impl DoubleEndedIterator<Item = &'a str>,
+) -> Option<CryptoInfo> {
+    let mut data_cipher = None;
+    let mut control_channel = None;
+    for line in lines.rev() {
+        if data_cipher.is_none() {
+            data_cipher = parse_data_cipher(line);
+        }
+        if control_channel.is_none() {
+            control_channel = parse_control_channel(line);
+        }
+        if data_cipher.is_some() && control_channel.is_some() {
+            break;
+        }
+    }
+    let (tls_version, tls_cipher) = control_channel?;
+    Some(CryptoInfo {
+        data_cipher: data_cipher?,
+        tls_version,
+        tls_cipher,
+    })
+}
+
+fn parse_warnings<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<String> {
+    lines.filter_map(parse_warning).collect()
+}
+
+/// Returns the message of an OpenVPN log line such as
+/// `2020-06-01 12:00:00 WARNING: file 'ca.crt' is group or others accessible`.
+fn parse_warning(line: &str) -> Option<String> {
+    const PREFIX: &str = "WARNING:";
+    let start = line.find(PREFIX)? + PREFIX.len();
+    let warning = line[start..].trim();
+    if warning.is_empty() {
+        None
+    } else {
+        Some(warning.to_owned())
+    }
+}
+
+/// Returns the wait from an OpenVPN log line such as
+/// `2020-06-01 12:00:00 Restart pause, 10 second(s)`.
+fn parse_restart_pause(line: &str) -> Option<Duration> {
+    const PREFIX: &str = "Restart pause, ";
+    let start = line.find(PREFIX)? + PREFIX.len();
+    let seconds = line[start..].split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Returns a warning if an `Outgoing Data Channel` log line reports a cipher that isn't one of
+/// `allowed_ciphers`, such as after OpenVPN fell back to a weaker cipher. The incoming line is
+/// ignored, since it reports the same cipher.
+fn check_data_cipher(line: &str, allowed_ciphers: &[String]) -> Option<String> {
+    if !line.contains("Outgoing Data Channel:") {
+        return None;
+    }
+    let cipher = parse_data_cipher(line)?;
+    if allowed_ciphers
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(&cipher))
+    {
+        None
+    } else {
+        Some(format!(
+            "Negotiated the data cipher {}, which is not one of the expected ciphers",
+            cipher
+        ))
+    }
+}
+
+fn parse_data_cipher(line: &str) -> Option<String> {
+    const PREFIX: &str = "Data Channel: Cipher '";
+    let start = line.find(PREFIX)? + PREFIX.len();
+    let cipher = &line[start..];
+    Some(cipher[..cipher.find('\'')?].to_owned())
+}
+
+/// Returns the TLS version and cipher suite from a control channel log line.
+fn parse_control_channel(line: &str) -> Option<(String, String)> {
+    const PREFIX: &str = "Control Channel: ";
+    let start = line.find(PREFIX)? + PREFIX.len();
+    let mut fields = line[start..].split(", ");
+    let tls_version = fields.next()?;
+    let cipher = fields.next()?.strip_prefix("cipher ")?;
+    // The cipher suite is preceded by the protocol it was defined for.
+    let tls_cipher = cipher.split_whitespace().last()?;
+    Some((tls_version.to_owned(), tls_cipher.to_owned()))
+}
+
+/// Returns a short description of what an OpenVPN exit code means, if it is known.
+pub fn exit_code_reason(code: i32) -> Option<&'static str> {
+    match code {
+        0 => Some("success"),
+        // OpenVPN uses the same code for every fatal error, including invalid arguments.
+        1 => Some("fatal error, see the OpenVPN log"),
+        _ => None,
+    }
+}
+
+/// Parses the address of the server that OpenVPN connected to from the plugin environment.
+fn parse_connected_endpoint(env: &HashMap<String, String>) -> Option<SocketAddr> {
+    let ip: IpAddr = env
+        .get("trusted_ip")
+        .or_else(|| env.get("trusted_ip6"))?
+        .parse()
+        .ok()?;
+    let port = env.get("trusted_port")?.parse().ok()?;
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Describes how a proxy exited, as a suffix for the message of `Error::ProxyExited`.
+fn format_proxy_exit(exit_code: &Option<i32>, stderr_tail: &Option<String>) -> String {
+    let mut details = String::new();
+    if let Some(exit_code) = exit_code {
+        details.push_str(&format!(" with exit code {}", exit_code));
+    }
+    let stderr_tail = stderr_tail.as_deref().map(str::trim).unwrap_or("");
+    if !stderr_tail.is_empty() {
+        let lines: Vec<_> = stderr_tail.lines().map(str::trim).collect();
+        details.push_str(&format!(": {}", lines.join("; ")));
+    }
+    details
+}
+
+/// Maps the result of waiting for a proxy that exited before OpenVPN to an error.
+fn proxy_exit_error(kind: ProxyKind, result: proxy::Result<proxy::WaitResult>) -> Error {
+    match result {
+        Ok(proxy::WaitResult::ProperShutdown) => Error::ProxyExited {
+            kind,
+            exit_code: None,
+            stderr_tail: None,
+        },
+        Ok(proxy::WaitResult::UnexpectedExit {
+            kind,
+            exit_code,
+            stderr_tail,
+        }) => Error::ProxyExited {
+            kind,
+            exit_code,
+            stderr_tail,
+        },
+        Err(err) => Error::MonitorProxyError(err),
+    }
+}
+
+/// A handle to an `OpenVpnMonitor` for closing it.
+#[derive(Debug, Clone)]
+pub struct OpenVpnCloseHandle<H: ProcessHandle = OpenVpnProcHandle> {
+    child: Arc<H>,
+    closed: Arc<AtomicBool>,
+}
+
+impl<H: ProcessHandle> OpenVpnCloseHandle<H> {
+    /// Kills the underlying OpenVPN process, making the `OpenVpnMonitor::wait` method return.
+    /// Returns how the process was stopped, or `None` if it had already been closed.
+    pub fn close(self) -> io::Result<Option<StopOutcome>> {
+        self.close_with_schedule(&default_kill_schedule())
+    }
+
+    /// Like `close`, but escalates from stopping OpenVPN gracefully to killing it according to
+    /// the given schedule.
+    pub fn close_with_schedule(self, schedule: &KillSchedule) -> io::Result<Option<StopOutcome>> {
+        if !self.closed.swap(true, Ordering::SeqCst) {
+            self.child.kill(schedule).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Internal enum to differentiate between if the child process or the event dispatcher died first.
+/// Both variants carry whether the tunnel had been closed on request when they were produced.
+#[derive(Debug)]
+enum WaitResult {
+    Child(io::Result<ExitStatus>, bool),
+    EventDispatcher(bool),
+}
+
+/// Trait for types acting as OpenVPN process starters for `OpenVpnMonitor`.
+pub trait OpenVpnBuilder {
+    /// The type of handles to subprocesses this builder produces.
+    type ProcessHandle: ProcessHandle;
+
+    /// Set the OpenVPN plugin to the given values.
+    fn plugin(&mut self, path: impl AsRef<Path>, args: Vec<String>) -> &mut Self;
+
+    /// Set the OpenVPN log file path to use.
+    fn log(&mut self, log_path: Option<impl AsRef<Path>>) -> &mut Self;
+
+    /// Spawn the subprocess and return a handle.
+    fn start(&self) -> io::Result<Self::ProcessHandle>;
+}
+
+/// Trait for types acting as handles to subprocesses for `OpenVpnMonitor`
+pub trait ProcessHandle: Send + Sync + 'static {
+    /// Block until the subprocess exits or there is an error in the wait syscall.
+    fn wait(&self) -> io::Result<ExitStatus>;
+
+    /// Kill the subprocess, escalating according to `schedule`. Returns how it was stopped.
+    fn kill(&self, schedule: &KillSchedule) -> io::Result<StopOutcome>;
+
+    /// Returns the process ID of the subprocess, or `None` if it is unknown or the subprocess
+    /// has exited.
+    fn pid(&self) -> Option<u32>;
+
+    /// Makes the subprocess reconnect without exiting.
+    fn soft_restart(&self) -> io::Result<()>;
+}
+
+impl OpenVpnBuilder for OpenVpnCommand {
+    type ProcessHandle = OpenVpnProcHandle;
+
+    fn plugin(&mut self, path: impl AsRef<Path>, args: Vec<String>) -> &mut Self {
+        self.plugin(path, args)
+    }
+
+    fn log(&mut self, log_path: Option<impl AsRef<Path>>) -> &mut Self {
+        if let Some(log_path) = log_path {
+            self.log(log_path)
+        } else {
+            self
+        }
+    }
+
+    fn start(&self) -> io::Result<OpenVpnProcHandle> {
+        log::trace!("OpenVPN arguments: {:?}", self.redacted_arguments());
+        OpenVpnProcHandle::new(self.build())
+    }
+}
+
+impl ProcessHandle for OpenVpnProcHandle {
+    fn wait(&self) -> io::Result<ExitStatus> {
+        self.inner.wait().map(|output| output.status)
+    }
+
+    fn kill(&self, schedule: &KillSchedule) -> io::Result<StopOutcome> {
+        self.escalating_kill(schedule)
+    }
+
+    fn pid(&self) -> Option<u32> {
+        // The PID may be reused by another process once OpenVPN has been reaped.
+        match self.has_stopped() {
+            Ok(false) => self.inner.pids().first().cloned(),
+            Ok(true) | Err(_) => None,
+        }
+    }
+
+    /// OpenVPN does a soft restart when it receives SIGUSR1.
+    #[cfg(unix)]
+    fn soft_restart(&self) -> io::Result<()> {
+        use nix::{
+            sys::signal::{kill, Signal},
+            unistd::Pid,
+        };
+
+        let pid = self.pid().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "The OpenVPN process has no PID")
+        })?;
+        kill(Pid::from_raw(pid as i32), Signal::SIGUSR1)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+    }
+
+    /// Without the management interface, there's no way to signal OpenVPN on Windows.
+    #[cfg(windows)]
+    fn soft_restart(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Soft restarting OpenVPN is not supported on Windows",
+        ))
+    }
+}
+
+
+mod event_server {
+    use futures::stream::TryStreamExt;
+    #[cfg(windows)]
+    use parity_tokio_ipc::{Endpoint as IpcEndpoint, SecurityAttributes};
+    use std::{
+        collections::HashMap,
+        pin::Pin,
+        sync::Arc,
         task::{Context, Poll},
+        time::Duration,
+    };
+    use tokio02::{
+        io::{AsyncRead, AsyncWrite},
+        sync::watch,
     };
-    use tokio02::io::{AsyncRead, AsyncWrite};
     use tonic::{
         self,
         transport::{server::Connected, Server},
         Request, Response,
     };
 
-    mod proto {
+    pub mod proto {
         tonic::include_proto!("talpid_openvpn_plugin");
     }
     use proto::{
@@ -634,17 +2307,133 @@ mod event_server {
         EventType,
     };
 
+    /// Permissions of the Unix socket. Only the owner, the daemon user that also runs the
+    /// OpenVPN plugin, may connect.
+    #[cfg(unix)]
+    pub const SOCKET_MODE: u32 = 0o600;
+
+    /// IPC paths starting with this name a socket in the abstract namespace on Linux. The plugin
+    /// argument cannot contain the leading NUL byte of an abstract socket address.
+    pub const ABSTRACT_SOCKET_PREFIX: &str = "@";
+
     #[derive(err_derive::Error, Debug)]
+    #[error(no_from)]
     pub enum Error {
         /// Failure to set up the IPC server.
         #[error(display = "Failed to create pipe or Unix socket")]
         StartServer(#[error(source)] std::io::Error),
 
+        /// Failure to restrict access to the IPC endpoint.
+        #[cfg(unix)]
+        #[error(display = "Failed to set permissions on the Unix socket")]
+        SetPermissions(#[error(source)] std::io::Error),
+
         /// An error occurred while the server was running.
         #[error(display = "Tonic error")]
         TonicError(#[error(source)] tonic::transport::Error),
     }
 
+    /// How long to wait for the OpenVPN process ID when a peer connects before it is known.
+    const CHILD_PID_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Identity of a process connecting to the event server, as far as it can be determined.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PeerIdentity {
+        pub pid: Option<u32>,
+        pub uid: Option<u32>,
+    }
+
+    /// Decides whether a connecting peer may deliver OpenVPN events. Peers are not verified on
+    /// Windows, where the identity of a pipe client cannot be determined.
+    #[tonic::async_trait]
+    pub trait PeerVerifier: Send + Sync + 'static {
+        /// Returns whether `peer` is allowed to connect.
+        async fn verify(&self, peer: &PeerIdentity) -> bool;
+    }
+
+    /// Returns a `permission_denied` status if `peer` is rejected by `verifier`.
+    #[cfg_attr(windows, allow(dead_code))]
+    pub async fn check_peer(
+        verifier: &dyn PeerVerifier,
+        peer: &PeerIdentity,
+    ) -> std::result::Result<(), tonic::Status> {
+        if verifier.verify(peer).await {
+            Ok(())
+        } else {
+            Err(tonic::Status::permission_denied(format!(
+                "Peer {:?} is not the OpenVPN process",
+                peer
+            )))
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum ChildState {
+        Starting,
+        Started(Option<u32>),
+    }
+
+    /// Only accepts the OpenVPN process spawned by the monitor, running as the same user as the
+    /// daemon. Peers whose process ID or user cannot be determined are only checked on the
+    /// properties that are known.
+    #[derive(Debug, Clone)]
+    pub struct ChildPeerVerifier {
+        child_tx: Arc<watch::Sender<ChildState>>,
+        child_rx: watch::Receiver<ChildState>,
+    }
+
+    impl ChildPeerVerifier {
+        pub fn new() -> Self {
+            let (child_tx, child_rx) = watch::channel(ChildState::Starting);
+            ChildPeerVerifier {
+                child_tx: Arc::new(child_tx),
+                child_rx,
+            }
+        }
+
+        /// Registers the process ID of the spawned OpenVPN process.
+        pub fn set_child_pid(&self, pid: Option<u32>) {
+            let _ = self.child_tx.broadcast(ChildState::Started(pid));
+        }
+
+        /// Waits for the OpenVPN process to be registered, since the plugin may connect before
+        /// the monitor has had a chance to do so.
+        async fn child_pid(&self) -> Option<Option<u32>> {
+            let mut child_rx = self.child_rx.clone();
+            let started = async move {
+                while let Some(state) = child_rx.recv().await {
+                    if let ChildState::Started(pid) = state {
+                        return Some(pid);
+                    }
+                }
+                None
+            };
+            tokio02::time::timeout(CHILD_PID_TIMEOUT, started)
+                .await
+                .ok()
+                .flatten()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl PeerVerifier for ChildPeerVerifier {
+        async fn verify(&self, peer: &PeerIdentity) -> bool {
+            #[cfg(unix)]
+            {
+                if let Some(uid) = peer.uid {
+                    if uid != unsafe { libc::getuid() } {
+                        return false;
+                    }
+                }
+            }
+            match (peer.pid, self.child_pid().await) {
+                (Some(peer_pid), Some(Some(child_pid))) => peer_pid == child_pid,
+                (Some(_), Some(None)) | (None, Some(_)) => true,
+                (_, None) => false,
+            }
+        }
+    }
+
     /// Implements a gRPC service used to process events sent to by OpenVPN.
     #[derive(Debug)]
     pub struct OpenvpnEventProxyImpl<L> {
@@ -660,8 +2449,6 @@ mod event_server {
             &self,
             request: Request<EventType>,
         ) -> std::result::Result<Response<()>, tonic::Status> {
-            log::info!("OpenVPN event {:?}", request);
-
             let request = request.into_inner();
 
             let event_type = openvpn_plugin::EventType::try_from(request.event)
@@ -673,36 +2460,245 @@ mod event_server {
         }
     }
 
+    /// Settings for the gRPC server that receives events from the plugin.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct ServerOptions {
+        /// Maximum number of requests handled concurrently on a connection. Unlimited if `None`.
+        pub concurrency_limit_per_connection: Option<usize>,
+        /// Time after which a request is abandoned. Requests never time out if `None`.
+        pub timeout: Option<Duration>,
+    }
+
+    impl ServerOptions {
+        fn server_builder(&self) -> Server {
+            let mut builder = Server::builder();
+            if let Some(limit) = self.concurrency_limit_per_connection {
+                builder = builder.concurrency_limit_per_connection(limit);
+            }
+            if let Some(timeout) = self.timeout {
+                builder = builder.timeout(timeout);
+            }
+            builder
+        }
+    }
+
+    /// Starts the event server with the default `ServerOptions`.
     pub async fn start<L>(
         ipc_path: String,
         server_start_tx: std::sync::mpsc::Sender<()>,
         on_event: L,
+        peer_verifier: impl PeerVerifier,
+        abort_rx: triggered::Listener,
+    ) -> std::result::Result<(), Error>
+    where
+        L: Fn(openvpn_plugin::EventType, HashMap<String, String>) + Send + Sync + 'static,
+    {
+        start_with_options(
+            ipc_path,
+            server_start_tx,
+            on_event,
+            peer_verifier,
+            abort_rx,
+            ServerOptions::default(),
+        )
+        .await
+    }
+
+    #[cfg(unix)]
+    pub async fn start_with_options<L>(
+        ipc_path: String,
+        server_start_tx: std::sync::mpsc::Sender<()>,
+        on_event: L,
+        peer_verifier: impl PeerVerifier,
+        abort_rx: triggered::Listener,
+        options: ServerOptions,
+    ) -> std::result::Result<(), Error>
+    where
+        L: Fn(openvpn_plugin::EventType, HashMap<String, String>) + Send + Sync + 'static,
+    {
+        // Abstract sockets have no file to remove and no permissions. Connections are still
+        // verified below.
+        let (listener, _remove_socket) = match abstract_socket_address(&ipc_path) {
+            Some(address) => (
+                tokio02::net::UnixListener::bind(&address).map_err(Error::StartServer)?,
+                None,
+            ),
+            None => (
+                bind_private_socket(&ipc_path)?,
+                Some(RemoveOnDrop(ipc_path.clone())),
+            ),
+        };
+        // From here on, connections are queued by the listener until the server accepts them, and
+        // `on_event` is already in place to handle their events. So the plugin may deliver events
+        // as soon as it learns about the socket, even before OpenVPN is known to have started.
+        let _ = server_start_tx.send(());
+
+        // Connections are verified when they are accepted, since the gRPC handlers cannot tell
+        // which connection a request arrived on.
+        let peer_verifier = Arc::new(peer_verifier);
+        let incoming = listener.try_filter(move |stream| {
+            let peer = unix_peer_identity(stream);
+            let peer_verifier = peer_verifier.clone();
+            async move {
+                match peer {
+                    Ok(peer) => match check_peer(&*peer_verifier, &peer).await {
+                        Ok(()) => true,
+                        Err(status) => {
+                            log::error!("Rejecting OpenVPN event connection: {}", status.message());
+                            false
+                        }
+                    },
+                    Err(error) => {
+                        log::error!("Failed to identify OpenVPN event peer: {}", error);
+                        false
+                    }
+                }
+            }
+        });
+
+        let server = OpenvpnEventProxyImpl { on_event };
+
+        options
+            .server_builder()
+            .add_service(OpenvpnEventProxyServer::new(server))
+            .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
+            .await
+            .map_err(Error::TonicError)
+    }
+
+    /// On Windows, the identity of a pipe client cannot be obtained through `parity_tokio_ipc`.
+    /// Access is instead restricted by the security descriptor of the pipe.
+    #[cfg(windows)]
+    pub async fn start_with_options<L>(
+        ipc_path: String,
+        server_start_tx: std::sync::mpsc::Sender<()>,
+        on_event: L,
+        _peer_verifier: impl PeerVerifier,
         abort_rx: triggered::Listener,
+        options: ServerOptions,
     ) -> std::result::Result<(), Error>
     where
         L: Fn(openvpn_plugin::EventType, HashMap<String, String>) + Send + Sync + 'static,
     {
         let mut endpoint = IpcEndpoint::new(ipc_path.clone());
-        endpoint.set_security_attributes(SecurityAttributes::allow_everyone_create().unwrap());
+        // Empty security attributes leave the pipe with the default security descriptor. It only
+        // grants write access to LocalSystem, administrators and the owner of the daemon process.
+        // Everyone else may only open the pipe for reading, which isn't enough to send events.
+        // `parity_tokio_ipc` offers no way of applying a stricter descriptor.
+        endpoint.set_security_attributes(SecurityAttributes::empty());
         let incoming = endpoint.incoming().map_err(Error::StartServer)?;
         let _ = server_start_tx.send(());
 
         let server = OpenvpnEventProxyImpl { on_event };
 
-        Server::builder()
+        options
+            .server_builder()
             .add_service(OpenvpnEventProxyServer::new(server))
             .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
             .await
             .map_err(Error::TonicError)
     }
 
-    #[derive(Debug)]
-    pub struct StreamBox<T: AsyncRead + AsyncWrite>(pub T);
-    impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {}
-    impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
-        fn poll_read(
-            mut self: Pin<&mut Self>,
-            cx: &mut Context<'_>,
+    #[cfg(target_os = "linux")]
+    fn unix_peer_identity(stream: &tokio02::net::UnixStream) -> std::io::Result<PeerIdentity> {
+        use std::{mem, os::unix::io::AsRawFd};
+
+        let mut cred: libc::ucred = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(PeerIdentity {
+            pid: Some(cred.pid as u32),
+            uid: Some(cred.uid),
+        })
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn unix_peer_identity(stream: &tokio02::net::UnixStream) -> std::io::Result<PeerIdentity> {
+        let cred = stream.peer_cred()?;
+        Ok(PeerIdentity {
+            pid: None,
+            uid: Some(cred.uid),
+        })
+    }
+
+    /// Binds a Unix socket at `ipc_path` that only its owner may connect to. The socket is bound
+    /// in a directory that only the owner can access, and is moved to `ipc_path` once its
+    /// permissions have been restricted, so that it is never reachable with the default ones.
+    #[cfg(unix)]
+    fn bind_private_socket(
+        ipc_path: &str,
+    ) -> std::result::Result<tokio02::net::UnixListener, Error> {
+        use std::{
+            fs,
+            os::unix::fs::{DirBuilderExt, PermissionsExt},
+            path::Path,
+        };
+
+        let private_dir = format!("{}.bind", ipc_path);
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&private_dir)
+            .map_err(Error::StartServer)?;
+        let private_path = Path::new(&private_dir).join("socket");
+
+        let result = tokio02::net::UnixListener::bind(&private_path)
+            .map_err(Error::StartServer)
+            .and_then(|listener| {
+                fs::set_permissions(&private_path, fs::Permissions::from_mode(SOCKET_MODE))
+                    .map_err(Error::SetPermissions)?;
+                fs::rename(&private_path, ipc_path).map_err(Error::StartServer)?;
+                Ok(listener)
+            });
+
+        let _ = fs::remove_file(&private_path);
+        let _ = fs::remove_dir(&private_dir);
+        result
+    }
+
+    /// Returns the address of the abstract socket named by `ipc_path`, if it names one.
+    #[cfg(target_os = "linux")]
+    pub fn abstract_socket_address(ipc_path: &str) -> Option<String> {
+        if ipc_path.starts_with(ABSTRACT_SOCKET_PREFIX) {
+            Some(format!("\0{}", &ipc_path[ABSTRACT_SOCKET_PREFIX.len()..]))
+        } else {
+            None
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    pub fn abstract_socket_address(_ipc_path: &str) -> Option<String> {
+        None
+    }
+
+    /// Removes the Unix socket when the server stops.
+    #[cfg(unix)]
+    struct RemoveOnDrop(String);
+
+    #[cfg(unix)]
+    impl Drop for RemoveOnDrop {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct StreamBox<T: AsyncRead + AsyncWrite>(pub T);
+    impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {}
+    impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
             buf: &mut [u8],
         ) -> Poll<std::io::Result<usize>> {
             Pin::new(&mut self.0).poll_read(cx, buf)
@@ -738,7 +2734,7 @@ mod tests {
     use parking_lot::Mutex;
     use std::{
         path::{Path, PathBuf},
-        sync::Arc,
+        sync::{atomic::AtomicUsize, Arc},
     };
 
     #[derive(Debug, Default, Clone)]
@@ -763,43 +2759,142 @@ mod tests {
 
         fn start(&self) -> io::Result<Self::ProcessHandle> {
             self.process_handle
+                .clone()
                 .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "failed to start"))
         }
     }
 
-    #[derive(Debug, Copy, Clone)]
-    struct TestProcessHandle(i32);
+    /// A process that exits with the given status. A negative status makes waiting fail.
+    #[derive(Debug, Clone)]
+    struct TestProcessHandle {
+        status: i32,
+        /// Number of soft restarts requested from this process, shared between clones.
+        soft_restarts: Arc<AtomicUsize>,
+    }
+
+    impl TestProcessHandle {
+        fn new(status: i32) -> Self {
+            Self {
+                status,
+                soft_restarts: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+    }
 
     impl ProcessHandle for TestProcessHandle {
         #[cfg(unix)]
         fn wait(&self) -> io::Result<ExitStatus> {
             use std::os::unix::process::ExitStatusExt;
-            Ok(ExitStatus::from_raw(self.0))
+            if self.status < 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "wait failed"));
+            }
+            Ok(ExitStatus::from_raw(self.status))
         }
 
         #[cfg(windows)]
         fn wait(&self) -> io::Result<ExitStatus> {
             use std::os::windows::process::ExitStatusExt;
-            Ok(ExitStatus::from_raw(self.0 as u32))
+            if self.status < 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "wait failed"));
+            }
+            Ok(ExitStatus::from_raw(self.status as u32))
         }
 
-        fn kill(&self) -> io::Result<()> {
+        fn kill(&self, _schedule: &KillSchedule) -> io::Result<StopOutcome> {
+            Ok(StopOutcome::Stopped)
+        }
+
+        fn pid(&self) -> Option<u32> {
+            Some(TEST_PID)
+        }
+
+        fn soft_restart(&self) -> io::Result<()> {
+            self.soft_restarts.fetch_add(1, Ordering::SeqCst);
             Ok(())
         }
     }
 
+    /// PID reported by any `TestProcessHandle`.
+    const TEST_PID: u32 = 1234;
+
+    #[test]
+    fn exposes_openvpn_pid() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        assert_eq!(testee.openvpn_pid(), Some(TEST_PID));
+    }
+
+    #[test]
+    fn builds_with_event_server_options() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .event_server_options(event_server::ServerOptions {
+                concurrency_limit_per_connection: Some(4),
+                timeout: Some(Duration::from_secs(1)),
+            })
+            .build(|_, _| {});
+        assert!(testee.is_ok());
+    }
+
+    #[test]
+    fn default_route_change_requests_restart() {
+        let process_handle = TestProcessHandle::new(0);
+        let soft_restarts = process_handle.soft_restarts.clone();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(process_handle);
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+
+        let (changes_tx, changes_rx) = futures::channel::mpsc::unbounded();
+        testee.restart_on_default_route_changes(Box::new(changes_rx));
+        changes_tx.unbounded_send(()).unwrap();
+
+        let start = Instant::now();
+        while soft_restarts.load(Ordering::SeqCst) == 0 {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "No restart was requested"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn count_route_change_restarts(changes: usize, min_interval: Duration) -> usize {
+        let mut restarts = 0;
+        let mut runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap();
+        runtime.block_on(restart_on_route_changes(
+            futures::stream::iter(vec![(); changes]),
+            min_interval,
+            String::new(),
+            || restarts += 1,
+        ));
+        restarts
+    }
+
+    #[test]
+    fn restart_on_route_change() {
+        assert_eq!(count_route_change_restarts(1, Duration::from_secs(60)), 1);
+        assert_eq!(count_route_change_restarts(0, Duration::from_secs(60)), 0);
+    }
+
+    #[test]
+    fn route_change_restarts_rate_limited() {
+        assert_eq!(count_route_change_restarts(3, Duration::from_secs(60)), 1);
+        assert_eq!(count_route_change_restarts(3, Duration::from_secs(0)), 3);
+    }
+
     #[test]
     fn sets_plugin() {
         let builder = TestOpenVpnBuilder::default();
-        let _ = OpenVpnMonitor::new_internal(
-            builder.clone(),
-            |_, _| {},
-            "./my_test_plugin",
-            None,
-            TempFile::new(),
-            None,
-            None,
-        );
+        let _ = OpenVpnMonitorBuilder::new(builder.clone(), "./my_test_plugin").build(|_, _| {});
         assert_eq!(
             Some(PathBuf::from("./my_test_plugin")),
             *builder.plugin.lock()
@@ -809,15 +2904,9 @@ mod tests {
     #[test]
     fn sets_log() {
         let builder = TestOpenVpnBuilder::default();
-        let _ = OpenVpnMonitor::new_internal(
-            builder.clone(),
-            |_, _| {},
-            "",
-            Some(PathBuf::from("./my_test_log_file")),
-            TempFile::new(),
-            None,
-            None,
-        );
+        let _ = OpenVpnMonitorBuilder::new(builder.clone(), "")
+            .log_path(Some(PathBuf::from("./my_test_log_file")))
+            .build(|_, _| {});
         assert_eq!(
             Some(PathBuf::from("./my_test_log_file")),
             *builder.log.lock()
@@ -827,40 +2916,1610 @@ mod tests {
     #[test]
     fn exit_successfully() {
         let mut builder = TestOpenVpnBuilder::default();
-        builder.process_handle = Some(TestProcessHandle(0));
-        let testee =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
         assert!(testee.wait().is_ok());
     }
 
     #[test]
     fn exit_error() {
         let mut builder = TestOpenVpnBuilder::default();
-        builder.process_handle = Some(TestProcessHandle(1));
-        let testee =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap();
+        builder.process_handle = Some(TestProcessHandle::new(1));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
         assert!(testee.wait().is_err());
     }
 
+    #[test]
+    fn exit_error_after_dispatcher_close() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(1));
+        let mut testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+
+        // OpenVPN died on its own, after which the dispatcher closed the tunnel, setting the flag.
+        let result = WaitResult::Child(testee.child.wait(), false);
+        let _ = testee.close_handle().close();
+        assert!(testee.handle_wait_result(result).is_err());
+    }
+
+    #[test]
+    fn exit_code_propagated() {
+        // `TestProcessHandle` takes a raw wait status, which holds the exit code in its second
+        // byte on Unix.
+        #[cfg(unix)]
+        const EXIT_CODE_ONE: i32 = 1 << 8;
+        #[cfg(windows)]
+        const EXIT_CODE_ONE: i32 = 1;
+
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(EXIT_CODE_ONE));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        match testee.wait() {
+            Err(Error::ChildProcessDied { code }) => {
+                assert_eq!(code, Some(1));
+                assert!(exit_code_reason(1).is_some());
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn connect_retries_exhausted() {
+        #[cfg(unix)]
+        const EXIT_CODE_ONE: i32 = 1 << 8;
+        #[cfg(windows)]
+        const EXIT_CODE_ONE: i32 = 1;
+
+        let log_file = TempFile::new();
+        fs::write(
+            &log_file,
+            "TCP: connect to [AF_INET]192.0.2.1:443 failed: Connection refused\n\
+             All connections have been connect-retry-max (1) times unsuccessful, exiting\n",
+        )
+        .unwrap();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(EXIT_CODE_ONE));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .build(|_, _| {})
+            .unwrap();
+        match testee.wait() {
+            Err(Error::ConnectRetriesExhausted) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
     #[test]
     fn wait_closed() {
         let mut builder = TestOpenVpnBuilder::default();
-        builder.process_handle = Some(TestProcessHandle(1));
-        let testee =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap();
+        builder.process_handle = Some(TestProcessHandle::new(1));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        testee.close_handle().close().unwrap();
+        assert!(testee.wait().is_ok());
+    }
+
+    #[test]
+    fn close_reports_outcome_once() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(1));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        let close_handle = testee.close_handle();
+        assert_eq!(
+            close_handle.clone().close().unwrap(),
+            Some(StopOutcome::Stopped)
+        );
+        assert_eq!(close_handle.close().unwrap(), None);
+    }
+
+    #[test]
+    fn dispatcher_exit_after_close() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(1));
+        let mut testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
         testee.close_handle().close().unwrap();
+        assert!(testee
+            .handle_wait_result(WaitResult::EventDispatcher(true))
+            .is_ok());
+        testee.event_server_abort_tx.trigger();
         assert!(testee.wait().is_ok());
     }
 
+    #[test]
+    fn dispatcher_exit_without_close() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let mut testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        // The dispatcher kills OpenVPN when it exits, which sets the closed flag.
+        testee.close_handle().close().unwrap();
+        assert!(matches!(
+            testee.handle_wait_result(WaitResult::EventDispatcher(false)),
+            Err(Error::EventDispatcherExited)
+        ));
+    }
+
+    #[test]
+    fn close_before_up() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(-1));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        testee.close_handle().close().unwrap();
+        assert!(testee.wait().is_ok());
+    }
+
+    #[test]
+    fn wait_error_without_close() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(-1));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        assert!(testee.wait().is_err());
+    }
+
+    fn http_proxy(auth: Option<openvpn::ProxyAuth>) -> Option<openvpn::ProxySettings> {
+        Some(openvpn::ProxySettings::Http(openvpn::HttpProxySettings {
+            address: "10.0.0.1:8080".parse().unwrap(),
+            auth,
+        }))
+    }
+
+    #[test]
+    fn http_proxy_auth_file() {
+        let auth = openvpn::ProxyAuth {
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+        };
+        let auth_file = OpenVpnMonitor::<TestOpenVpnBuilder>::create_proxy_auth_file(
+            &http_proxy(Some(auth)),
+            &std::env::temp_dir(),
+        )
+        .unwrap()
+        .expect("no auth file created");
+        assert_eq!(fs::read_to_string(&auth_file).unwrap(), "user\npass\n");
+    }
+
+    #[test]
+    fn http_proxy_without_auth_file() {
+        let auth_file = OpenVpnMonitor::<TestOpenVpnBuilder>::create_proxy_auth_file(
+            &http_proxy(None),
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        assert!(auth_file.is_none());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn tap_adapter_info_from_listing() {
+        let listing = "Mullvad\tMullvad VPN TAP Adapter\t2\n\
+                       Mullvad-1\tMullvad VPN TAP Adapter #2\t6\n";
+        let adapters = crate::winnet::parse_tap_adapters(listing).unwrap();
+        assert_eq!(
+            tap_adapter_info(adapters, Some("mullvad")),
+            vec![
+                TapAdapterInfo {
+                    alias: "Mullvad".to_owned(),
+                    name: "Mullvad VPN TAP Adapter".to_owned(),
+                    enabled: true,
+                    in_use: true,
+                },
+                TapAdapterInfo {
+                    alias: "Mullvad-1".to_owned(),
+                    name: "Mullvad VPN TAP Adapter #2".to_owned(),
+                    enabled: false,
+                    in_use: false,
+                },
+            ]
+        );
+
+        assert_eq!(crate::winnet::parse_tap_adapters(""), Some(vec![]));
+        assert_eq!(
+            crate::winnet::parse_tap_adapters("Mullvad\tMullvad VPN TAP Adapter\tup\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn bundled_proxy_auth_in_proxy_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = openvpn::ProxySettings::Shadowsocks(openvpn::ShadowsocksProxySettings {
+            peer: "192.0.2.1:443".parse().unwrap(),
+            password: "secret".to_owned(),
+            cipher: "aes-256-gcm".to_owned(),
+        });
+        assert_eq!(proxy_auth_mode(&settings), Some(ProxyAuthMode::ProxyConfig));
+
+        let auth_file = OpenVpnMonitor::<TestOpenVpnBuilder>::create_proxy_auth_file(
+            &Some(settings),
+            temp_dir.path(),
+        )
+        .unwrap();
+        assert!(auth_file.is_none());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn proxy_proper_shutdown_error() {
+        let error = proxy_exit_error(ProxyKind::Local, Ok(proxy::WaitResult::ProperShutdown));
+        match error {
+            Error::ProxyExited {
+                kind: ProxyKind::Local,
+                exit_code: None,
+                stderr_tail: None,
+            } => (),
+            _ => panic!("Wrong error"),
+        }
+    }
+
+    #[test]
+    fn proxy_unexpected_exit_error() {
+        let result = Ok(proxy::WaitResult::UnexpectedExit {
+            kind: ProxyKind::Shadowsocks,
+            exit_code: Some(1),
+            stderr_tail: Some("Address already in use".to_owned()),
+        });
+        match proxy_exit_error(ProxyKind::Shadowsocks, result) {
+            Error::ProxyExited {
+                kind: ProxyKind::Shadowsocks,
+                exit_code: Some(1),
+                stderr_tail: Some(tail),
+            } => assert_eq!(tail, "Address already in use"),
+            _ => panic!("Wrong error"),
+        }
+    }
+
+    #[test]
+    fn proxy_exit_message() {
+        let error = Error::ProxyExited {
+            kind: ProxyKind::Shadowsocks,
+            exit_code: Some(1),
+            stderr_tail: Some("bind failed\nAddress already in use\n".to_owned()),
+        };
+        assert_eq!(
+            error.to_string(),
+            "The Shadowsocks proxy exited unexpectedly with exit code 1: bind failed; Address \
+             already in use"
+        );
+
+        let error = Error::ProxyExited {
+            kind: ProxyKind::Local,
+            exit_code: None,
+            stderr_tail: None,
+        };
+        assert_eq!(error.to_string(), "The local proxy exited unexpectedly");
+    }
+
+    #[test]
+    fn proxy_wait_error() {
+        let result = Err(io::Error::new(io::ErrorKind::Other, "wait failed"));
+        match proxy_exit_error(ProxyKind::Shadowsocks, result) {
+            Error::MonitorProxyError(_) => (),
+            _ => panic!("Wrong error"),
+        }
+    }
+
+    /// Monitor of a proxy that runs until it is closed.
+    struct FakeProxyMonitor {
+        port: u16,
+        kind: ProxyKind,
+        closed: Arc<AtomicBool>,
+        tx: mpsc::Sender<()>,
+        rx: mpsc::Receiver<()>,
+        ready_rx: Option<mpsc::Receiver<()>>,
+        stats: Option<ProxyStats>,
+    }
+
+    impl FakeProxyMonitor {
+        fn new(port: u16, kind: ProxyKind) -> Self {
+            let (tx, rx) = mpsc::channel();
+            FakeProxyMonitor {
+                port,
+                kind,
+                closed: Arc::new(AtomicBool::new(false)),
+                tx,
+                rx,
+                ready_rx: None,
+                stats: None,
+            }
+        }
+
+        /// Makes the proxy signal that it is ready once `delay` has passed.
+        fn ready_after(mut self, delay: Duration) -> Self {
+            let (ready_tx, ready_rx) = mpsc::channel();
+            thread::spawn(move || {
+                thread::sleep(delay);
+                let _ = ready_tx.send(());
+            });
+            self.ready_rx = Some(ready_rx);
+            self
+        }
+    }
+
+    impl ProxyMonitor for FakeProxyMonitor {
+        fn close_handle(&mut self) -> Box<dyn proxy::ProxyMonitorCloseHandle> {
+            Box::new(FakeProxyCloseHandle {
+                closed: self.closed.clone(),
+                tx: self.tx.clone(),
+            })
+        }
+
+        fn wait(self: Box<Self>) -> proxy::Result<proxy::WaitResult> {
+            let _ = self.rx.recv();
+            Ok(proxy::WaitResult::ProperShutdown)
+        }
+
+        fn port(&self) -> u16 {
+            self.port
+        }
+
+        fn kind(&self) -> ProxyKind {
+            self.kind
+        }
+
+        fn proxy_stats(&self) -> Option<ProxyStats> {
+            self.stats
+        }
+
+        fn wait_ready(&self, timeout: Duration) -> proxy::Result<()> {
+            match &self.ready_rx {
+                Some(ready_rx) => ready_rx
+                    .recv_timeout(timeout)
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "not ready")),
+                None => Ok(()),
+            }
+        }
+    }
+
+    struct FakeProxyCloseHandle {
+        closed: Arc<AtomicBool>,
+        tx: mpsc::Sender<()>,
+    }
+
+    impl proxy::ProxyMonitorCloseHandle for FakeProxyCloseHandle {
+        fn close(self: Box<Self>) -> proxy::Result<()> {
+            self.closed.store(true, Ordering::SeqCst);
+            let _ = self.tx.send(());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn waits_for_proxy_ready() {
+        let proxy_monitor = FakeProxyMonitor::new(5000, ProxyKind::Shadowsocks)
+            .ready_after(Duration::from_millis(200));
+        let proxy_closed = proxy_monitor.closed.clone();
+        let started = Instant::now();
+        let result = OpenVpnMonitor::<TestOpenVpnBuilder>::wait_for_proxy(
+            "",
+            Box::new(proxy_monitor),
+            Duration::from_secs(5),
+        );
+        assert!(result.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(!proxy_closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn proxy_stopped_when_not_ready() {
+        let proxy_monitor = FakeProxyMonitor::new(5000, ProxyKind::Shadowsocks)
+            .ready_after(Duration::from_secs(60));
+        let proxy_closed = proxy_monitor.closed.clone();
+        let timeout = Duration::from_millis(200);
+        match OpenVpnMonitor::<TestOpenVpnBuilder>::wait_for_proxy(
+            "",
+            Box::new(proxy_monitor),
+            timeout,
+        ) {
+            Err(Error::ProxyNotReady(reported)) => assert_eq!(reported, timeout),
+            result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+        }
+        assert!(proxy_closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn obfuscation_proxy_stopped_with_tunnel() {
+        let proxy_monitor = FakeProxyMonitor::new(5000, ProxyKind::Obfuscation);
+        let proxy_closed = proxy_monitor.closed.clone();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .proxy_monitor(Some(Box::new(proxy_monitor)))
+            .build(|_, _| {})
+            .unwrap();
+        assert!(testee.wait().is_ok());
+        assert!(proxy_closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn reports_proxy_stats() {
+        let stats = ProxyStats {
+            bytes_in: 4096,
+            bytes_out: 1024,
+        };
+        let mut proxy_monitor = FakeProxyMonitor::new(5000, ProxyKind::Shadowsocks);
+        proxy_monitor.stats = Some(stats);
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .proxy_monitor(Some(Box::new(proxy_monitor)))
+            .build(|_, _| {})
+            .unwrap();
+        assert_eq!(testee.proxy_stats(), Some(stats));
+
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        assert_eq!(testee.proxy_stats(), None);
+    }
+
+    #[test]
+    fn tail_log() {
+        let log_file = TempFile::new();
+        fs::write(&log_file, "first\nsecond\nthird\n").unwrap();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .build(|_, _| {})
+            .unwrap();
+        assert_eq!(testee.log_path(), Some(log_file.as_ref()));
+        assert_eq!(testee.tail_log(2).unwrap(), vec!["second", "third"]);
+        assert_eq!(testee.tail_log(10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn negotiated_crypto_from_log() {
+        let log = "\
+2020-06-01 12:00:00 TCP/UDP: Preserving recently used remote address: [AF_INET]185.65.135.117:1194
+2020-06-01 12:00:00 UDP link remote: [AF_INET]185.65.135.117:1194
+2020-06-01 12:00:01 VERIFY OK: depth=0, C=SE, ST=Gotaland, O=Mullvad, CN=se5-openvpn
+2020-06-01 12:00:01 Control Channel: TLSv1.2, cipher TLSv1/SSLv3 ECDHE-RSA-AES256-GCM-SHA384, 4096 bit RSA
+2020-06-01 12:00:01 [se5] Peer Connection Initiated with [AF_INET]185.65.135.117:1194
+2020-06-01 12:00:02 Outgoing Data Channel: Cipher 'AES-256-GCM' initialized with 256 bit key
+2020-06-01 12:00:02 Incoming Data Channel: Cipher 'AES-256-GCM' initialized with 256 bit key
+2020-06-01 12:00:03 Initialization Sequence Completed
+";
+        let log_file = TempFile::new();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .build(|_, _| {})
+            .unwrap();
+        assert_eq!(testee.negotiated_crypto(), None);
+
+        let partial_log: String = log
+            .lines()
+            .take(4)
+            .map(|line| format!("{}\n", line))
+            .collect();
+        fs::write(&log_file, partial_log).unwrap();
+        assert_eq!(testee.negotiated_crypto(), None);
+
+        fs::write(&log_file, log).unwrap();
+        assert_eq!(
+            testee.negotiated_crypto(),
+            Some(CryptoInfo {
+                data_cipher: "AES-256-GCM".to_owned(),
+                tls_version: "TLSv1.2".to_owned(),
+                tls_cipher: "ECDHE-RSA-AES256-GCM-SHA384".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn warnings_from_log() {
+        let log = "\
+2020-06-01 12:00:00 WARNING: file 'ca.crt' is group or others accessible
+2020-06-01 12:00:00 OpenVPN 2.4.9 x86_64-pc-linux-gnu [SSL (OpenSSL)] [LZO] [LZ4] [EPOLL]
+2020-06-01 12:00:01 VERIFY OK: depth=0, C=SE, ST=Gotaland, O=Mullvad, CN=se5-openvpn
+2020-06-01 12:00:02 WARNING: 'link-mtu' is used inconsistently, local='link-mtu 1557', remote='link-mtu 1558'
+2020-06-01 12:00:03 Initialization Sequence Completed
+";
+        let log_file = TempFile::new();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .build(|_, _| {})
+            .unwrap();
+        assert!(testee.warnings().is_empty());
+
+        fs::write(&log_file, log).unwrap();
+        assert_eq!(
+            testee.warnings(),
+            vec![
+                "file 'ca.crt' is group or others accessible",
+                "'link-mtu' is used inconsistently, local='link-mtu 1557', remote='link-mtu 1558'",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_warnings_in_log() {
+        let log = [
+            "2020-06-01 12:00:01 VERIFY OK: depth=0, C=SE, ST=Gotaland, O=Mullvad, CN=se5-openvpn",
+            "2020-06-01 12:00:03 Initialization Sequence Completed",
+            "2020-06-01 12:00:04 WARNING:",
+        ];
+        assert!(parse_warnings(log.iter().copied()).is_empty());
+    }
+
+    #[test]
+    fn warnings_reported_as_logged() {
+        let log_file = TempFile::new();
+        let (tx, rx) = mpsc::channel();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .on_log_warning(move |warning| tx.send(warning).unwrap())
+            .build(|_, _| {})
+            .unwrap();
+
+        let mut file = fs::File::create(&log_file).unwrap();
+        writeln!(
+            file,
+            "2020-06-01 12:00:00 OpenVPN 2.4.9 x86_64-pc-linux-gnu"
+        )
+        .unwrap();
+        write!(file, "2020-06-01 12:00:01 WARNING: cipher fall").unwrap();
+        file.flush().unwrap();
+        assert!(rx.recv_timeout(3 * LOG_POLL_INTERVAL).is_err());
+
+        writeln!(file, "back to BF-CBC").unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "cipher fallback to BF-CBC"
+        );
+
+        drop(testee);
+        writeln!(file, "2020-06-01 12:00:02 WARNING: after close").unwrap();
+        assert!(rx.recv_timeout(3 * LOG_POLL_INTERVAL).is_err());
+    }
+
+    #[test]
+    fn weak_data_cipher_reported() {
+        let log_file = TempFile::new();
+        let (tx, rx) = mpsc::channel();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let _testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .on_log_warning(move |warning| tx.send(warning).unwrap())
+            .build(|_, _| {})
+            .unwrap();
+
+        let mut file = fs::File::create(&log_file).unwrap();
+        writeln!(
+            file,
+            "2020-06-01 12:00:02 Outgoing Data Channel: Cipher 'AES-256-GCM' initialized with 256 \
+             bit key\n\
+             2020-06-01 12:00:02 Incoming Data Channel: Cipher 'AES-256-GCM' initialized with 256 \
+             bit key"
+        )
+        .unwrap();
+        assert!(rx.recv_timeout(3 * LOG_POLL_INTERVAL).is_err());
+
+        writeln!(
+            file,
+            "2020-06-01 13:00:02 Outgoing Data Channel: Cipher 'BF-CBC' initialized with 128 bit \
+             key\n\
+             2020-06-01 13:00:02 Incoming Data Channel: Cipher 'BF-CBC' initialized with 128 bit \
+             key"
+        )
+        .unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "Negotiated the data cipher BF-CBC, which is not one of the expected ciphers"
+        );
+        assert!(rx.recv_timeout(3 * LOG_POLL_INTERVAL).is_err());
+
+        let defaults: Vec<String> = DEFAULT_ALLOWED_DATA_CIPHERS
+            .iter()
+            .map(|cipher| cipher.to_string())
+            .collect();
+        assert_eq!(
+            check_data_cipher(
+                "Outgoing Data Channel: Cipher 'AES-256-CBC' initialized with 256 bit key",
+                &defaults
+            ),
+            Some(
+                "Negotiated the data cipher AES-256-CBC, which is not one of the expected ciphers"
+                    .to_owned()
+            )
+        );
+
+        let allowed = vec!["BF-CBC".to_owned()];
+        assert_eq!(
+            check_data_cipher(
+                "Outgoing Data Channel: Cipher 'BF-CBC' initialized with 128 bit key",
+                &allowed
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn negotiated_crypto_after_renegotiation() {
+        let log = [
+            "Control Channel: TLSv1.2, cipher TLSv1/SSLv3 ECDHE-RSA-AES256-GCM-SHA384, 4096 bit RSA",
+            "Outgoing Data Channel: Cipher 'AES-256-CBC' initialized with 256 bit key",
+            "Control Channel: TLSv1.3, cipher TLSv1.3 TLS_AES_256_GCM_SHA384, 4096 bit RSA",
+            "Outgoing Data Channel: Cipher 'AES-256-GCM' initialized with 256 bit key",
+        ];
+        assert_eq!(
+            parse_negotiated_crypto(log.iter().copied()),
+            Some(CryptoInfo {
+                data_cipher: "AES-256-GCM".to_owned(),
+                tls_version: "TLSv1.3".to_owned(),
+                tls_cipher: "TLS_AES_256_GCM_SHA384".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn move_log() {
+        let log_file = TempFile::new();
+        let moved_log_file = TempFile::new();
+        fs::write(&log_file, "first\nsecond\n").unwrap();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let mut testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .build(|_, _| {})
+            .unwrap();
+
+        testee.move_log(moved_log_file.to_path_buf()).unwrap();
+        assert_eq!(testee.log_path(), Some(moved_log_file.as_ref()));
+        assert!(!log_file.as_ref().exists());
+        assert_eq!(testee.tail_log(1).unwrap(), vec!["second"]);
+    }
+
+    #[test]
+    fn move_log_failure_keeps_log_path() {
+        let log_file = TempFile::new();
+        fs::write(&log_file, "first\n").unwrap();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let mut testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .build(|_, _| {})
+            .unwrap();
+
+        let missing_dir = TempFile::new();
+        let result = testee.move_log(missing_dir.as_ref().join("openvpn.log"));
+        assert!(matches!(result, Err(Error::MoveLogError(_))));
+        assert_eq!(testee.log_path(), Some(log_file.as_ref()));
+    }
+
+    #[test]
+    fn move_without_log() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let mut testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        assert!(matches!(
+            testee.move_log(PathBuf::from("openvpn.log")),
+            Err(Error::NoLogFile)
+        ));
+    }
+
+    #[test]
+    fn tail_missing_log() {
+        let log_file = TempFile::new();
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .log_path(Some(log_file.to_path_buf()))
+            .build(|_, _| {})
+            .unwrap();
+        assert!(testee.tail_log(5).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn event_server_socket_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ipc_path = TempFile::new();
+        let ipc_path_string = ipc_path.as_ref().to_string_lossy().to_string();
+        let mut runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (start_tx, start_rx) = mpsc::channel();
+        let (abort_tx, abort_rx) = triggered::trigger();
+        let server = runtime.spawn(event_server::start(
+            ipc_path_string,
+            start_tx,
+            |_, _| {},
+            event_server::ChildPeerVerifier::new(),
+            abort_rx,
+        ));
+        let server = std::thread::spawn(move || runtime.block_on(server));
+        start_rx.recv().expect("event server failed to start");
+
+        let mode = fs::metadata(&ipc_path).unwrap().permissions().mode();
+        let private_dir = format!("{}.bind", ipc_path.as_ref().display());
+        abort_tx.trigger();
+        let _ = server.join();
+        assert_eq!(mode & 0o777, event_server::SOCKET_MODE);
+        assert!(
+            !Path::new(&private_dir).exists(),
+            "The directory the socket was bound in was not removed"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn event_server_abstract_socket() {
+        let name = format!("talpid-openvpn-test-{}", uuid::Uuid::new_v4());
+        let ipc_path = format!("{}{}", event_server::ABSTRACT_SOCKET_PREFIX, name);
+        let address = event_server::abstract_socket_address(&ipc_path).unwrap();
+        assert_eq!(address, format!("\0{}", name));
+
+        let mut runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (start_tx, start_rx) = mpsc::channel();
+        let (abort_tx, abort_rx) = triggered::trigger();
+        let server = runtime.spawn(event_server::start(
+            ipc_path.clone(),
+            start_tx,
+            |_, _| {},
+            TestPeerVerifier(std::process::id()),
+            abort_rx,
+        ));
+        let server = std::thread::spawn(move || runtime.block_on(server));
+        start_rx.recv().expect("event server failed to start");
+
+        let mut client_runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let connection = client_runtime.block_on(tokio02::net::UnixStream::connect(&address));
+
+        abort_tx.trigger();
+        let _ = server.join();
+        assert!(connection.is_ok());
+        assert!(!Path::new(&ipc_path).exists());
+        assert!(!std::env::temp_dir().join(&name).exists());
+        assert!(!Path::new("/tmp").join(&name).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn event_server_with_options() {
+        let ipc_path = format!("/tmp/talpid-openvpn-test-{}", uuid::Uuid::new_v4());
+        let options = event_server::ServerOptions {
+            concurrency_limit_per_connection: Some(4),
+            timeout: Some(Duration::from_secs(1)),
+        };
+
+        let mut runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (start_tx, start_rx) = mpsc::channel();
+        let (abort_tx, abort_rx) = triggered::trigger();
+        let server = runtime.spawn(event_server::start_with_options(
+            ipc_path.clone(),
+            start_tx,
+            |_, _| {},
+            TestPeerVerifier(std::process::id()),
+            abort_rx,
+            options,
+        ));
+        let server = std::thread::spawn(move || runtime.block_on(server));
+        start_rx.recv().expect("event server failed to start");
+
+        let mut client_runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let connection = client_runtime.block_on(tokio02::net::UnixStream::connect(&ipc_path));
+
+        abort_tx.trigger();
+        let result = server.join().unwrap().unwrap();
+        assert!(connection.is_ok());
+        assert!(result.is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn event_server_handles_event_right_after_bind() {
+        use event_server::proto::{openvpn_event_proxy_client::OpenvpnEventProxyClient, EventType};
+        use tonic::transport::{Endpoint, Uri};
+
+        let ipc_path = format!("/tmp/talpid-openvpn-test-{}", uuid::Uuid::new_v4());
+        let mut runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (start_tx, start_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let event_tx = Mutex::new(event_tx);
+        let (abort_tx, abort_rx) = triggered::trigger();
+        let server = runtime.spawn(event_server::start(
+            ipc_path.clone(),
+            start_tx,
+            move |event, env| {
+                let _ = event_tx.lock().send((event, env));
+            },
+            TestPeerVerifier(std::process::id()),
+            abort_rx,
+        ));
+        let server = std::thread::spawn(move || runtime.block_on(server));
+        start_rx.recv().expect("event server failed to start");
+
+        // Deliver the event without giving the server any time to start accepting connections.
+        let mut client_runtime = tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let response = client_runtime.block_on(async move {
+            let channel = Endpoint::from_static("lttp://[::]:50051")
+                .connect_with_connector(tower::service_fn(move |_: Uri| {
+                    tokio02::net::UnixStream::connect(ipc_path.clone())
+                }))
+                .await
+                .expect("Failed to connect to the event server");
+            let mut env = HashMap::new();
+            env.insert("dev".to_owned(), "tun0".to_owned());
+            OpenvpnEventProxyClient::new(channel)
+                .event(EventType {
+                    event: openvpn_plugin::EventType::Up as i16 as i32,
+                    env,
+                })
+                .await
+        });
+        let received = event_rx.recv_timeout(Duration::from_secs(5));
+
+        abort_tx.trigger();
+        let _ = server.join();
+        assert!(response.is_ok());
+        let (event, env) = received.expect("Event was not processed");
+        assert_eq!(event, openvpn_plugin::EventType::Up);
+        assert_eq!(env.get("dev").map(String::as_str), Some("tun0"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn filesystem_socket_by_default() {
+        assert_eq!(
+            event_server::abstract_socket_address("/tmp/talpid-openvpn-1234"),
+            None
+        );
+    }
+
+    struct TestPeerVerifier(u32);
+
+    #[tonic::async_trait]
+    impl event_server::PeerVerifier for TestPeerVerifier {
+        async fn verify(&self, peer: &event_server::PeerIdentity) -> bool {
+            peer.pid == Some(self.0)
+        }
+    }
+
+    /// Runs `future` on a runtime with a timer, which the peer verifiers need.
+    fn block_on_timer<F: std::future::Future>(future: F) -> F::Output {
+        tokio02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn event_peer_accepted() {
+        let peer = event_server::PeerIdentity {
+            pid: Some(1234),
+            uid: None,
+        };
+        assert!(block_on_timer(event_server::check_peer(&TestPeerVerifier(1234), &peer)).is_ok());
+    }
+
+    #[test]
+    fn event_peer_rejected() {
+        let peer = event_server::PeerIdentity {
+            pid: Some(4321),
+            uid: None,
+        };
+        let status =
+            block_on_timer(event_server::check_peer(&TestPeerVerifier(1234), &peer)).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn child_peer_verifier() {
+        use event_server::{PeerIdentity, PeerVerifier};
+
+        let verifier = event_server::ChildPeerVerifier::new();
+        verifier.set_child_pid(Some(1234));
+        let child = PeerIdentity {
+            pid: Some(1234),
+            uid: None,
+        };
+        let other = PeerIdentity {
+            pid: Some(4321),
+            uid: None,
+        };
+        assert!(block_on_timer(verifier.verify(&child)));
+        assert!(!block_on_timer(verifier.verify(&other)));
+    }
+
+    #[test]
+    fn child_peer_verifier_waits_for_child() {
+        use event_server::{PeerIdentity, PeerVerifier};
+
+        let verifier = event_server::ChildPeerVerifier::new();
+        let peer = PeerIdentity {
+            pid: Some(1234),
+            uid: None,
+        };
+        let accepted = block_on_timer(async {
+            let register = async {
+                tokio02::time::delay_for(Duration::from_millis(100)).await;
+                verifier.set_child_pid(Some(1234));
+            };
+            futures::future::join(verifier.verify(&peer), register)
+                .await
+                .0
+        });
+        assert!(accepted);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn child_peer_verifier_rejects_other_user() {
+        use event_server::{PeerIdentity, PeerVerifier};
+
+        let verifier = event_server::ChildPeerVerifier::new();
+        verifier.set_child_pid(Some(1234));
+        let peer = PeerIdentity {
+            pid: Some(1234),
+            uid: Some(unsafe { libc::getuid() }.wrapping_add(1)),
+        };
+        assert!(!block_on_timer(verifier.verify(&peer)));
+    }
+
+    #[test]
+    fn custom_runtime_threads() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .runtime_threads(4)
+            .build(|_, _| {})
+            .unwrap();
+        assert!(testee.wait().is_ok());
+    }
+
+    #[test]
+    fn shared_runtime() {
+        let runtime = tokio02::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        for _ in 0..2 {
+            let mut builder = TestOpenVpnBuilder::default();
+            builder.process_handle = Some(TestProcessHandle::new(0));
+            let testee = OpenVpnMonitorBuilder::new(builder, "")
+                .runtime(Some(runtime.handle().clone()))
+                .build(|_, _| {})
+                .unwrap();
+            assert!(testee.wait().is_ok());
+        }
+        let task = runtime.handle().spawn(async { 42 });
+        assert_eq!(futures::executor::block_on(task).unwrap(), 42);
+    }
+
+    #[test]
+    fn zero_runtime_threads() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let error = OpenVpnMonitorBuilder::new(builder, "")
+            .runtime_threads(0)
+            .build(|_, _| {})
+            .unwrap_err();
+        match error {
+            Error::InvalidRuntimeThreads(0) => (),
+            _ => panic!("Wrong error"),
+        }
+    }
+
+    #[test]
+    fn reconnect_sequence() {
+        let metadata = crate::tunnel::TunnelMetadata {
+            interface: "tun0".to_owned(),
+            ips: vec!["10.8.0.2".parse().unwrap()],
+            ipv4_gateway: "10.8.0.1".parse().unwrap(),
+            ipv6_gateway: None,
+            ipv6_available: false,
+            dns_servers: vec![],
+            remote: None,
+            session_id: None,
+        };
+        let down = TunnelEvent::Down {
+            rx_bytes: 4096,
+            tx_bytes: 1024,
+            duration: Duration::default(),
+        };
+        let tracker = ReconnectTracker::default();
+        let sequence = [
+            (
+                TunnelEvent::Up(metadata.clone()),
+                TunnelEvent::Up(metadata.clone()),
+            ),
+            (reconnecting(1, 0), reconnecting(1, 0)),
+            (
+                TunnelEvent::Up(metadata.clone()),
+                TunnelEvent::Reconnected(metadata.clone()),
+            ),
+            (reconnecting(1, 0), reconnecting(1, 0)),
+            (
+                TunnelEvent::Up(metadata.clone()),
+                TunnelEvent::Reconnected(metadata.clone()),
+            ),
+            (down.clone(), down.clone()),
+        ];
+        for (event, expected) in sequence.iter().cloned() {
+            assert_eq!(tracker.track(event), expected);
+        }
+    }
+
+    fn reconnecting(attempt: u32, next_delay_secs: u64) -> TunnelEvent {
+        TunnelEvent::Reconnecting {
+            attempt,
+            next_delay: Duration::from_secs(next_delay_secs),
+        }
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        let tracker = ReconnectTracker::new(Duration::from_secs(5));
+        // Pauses are only attempts to reconnect once the tunnel has gone down.
+        assert_eq!(tracker.restart_paused(Duration::from_secs(5)), None);
+        assert_eq!(tracker.attempts(), 0);
+        assert_eq!(tracker.next_delay(), None);
+
+        assert_eq!(tracker.track(reconnecting(1, 0)), reconnecting(1, 5));
+        // The first pause belongs to the attempt reported when the tunnel went down.
+        assert_eq!(tracker.restart_paused(Duration::from_secs(5)), None);
+        assert_eq!(tracker.attempts(), 1);
+
+        // OpenVPN doubles its wait after too many failed attempts.
+        for (attempt, delay) in [(2, 10), (3, 20), (4, 40)].iter().cloned() {
+            assert_eq!(
+                tracker.restart_paused(Duration::from_secs(delay)),
+                Some(reconnecting(attempt, delay))
+            );
+            assert_eq!(tracker.attempts(), attempt);
+            assert_eq!(tracker.next_delay(), Some(Duration::from_secs(delay)));
+        }
+
+        assert_eq!(
+            tracker.track(TunnelEvent::Up(test_metadata())),
+            TunnelEvent::Reconnected(test_metadata())
+        );
+        assert_eq!(tracker.attempts(), 0);
+        assert_eq!(tracker.next_delay(), None);
+
+        assert_eq!(
+            parse_restart_pause("2020-06-01 12:00:00 Restart pause, 10 second(s)"),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            parse_restart_pause("2020-06-01 12:00:00 Initialization Sequence Completed"),
+            None
+        );
+    }
+
+    #[test]
+    fn session_summary() {
+        let tracker = SessionTracker::default();
+        assert_eq!(tracker.end(), None);
+
+        tracker.track(TunnelEvent::Up(test_metadata()));
+        thread::sleep(Duration::from_millis(10));
+        tracker.track(reconnecting(1, 0));
+        tracker.track(TunnelEvent::Reconnected(test_metadata()));
+        let down = tracker.track(TunnelEvent::Down {
+            rx_bytes: 4096,
+            tx_bytes: 1024,
+            duration: Duration::default(),
+        });
+        match down {
+            TunnelEvent::Down {
+                rx_bytes,
+                tx_bytes,
+                duration,
+            } => {
+                assert_eq!((rx_bytes, tx_bytes), (4096, 1024));
+                assert!(duration >= Duration::from_millis(10));
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        assert_eq!(tracker.end(), None);
+    }
+
+    #[test]
+    fn session_summary_without_down() {
+        let tracker = SessionTracker::default();
+        tracker.track(TunnelEvent::Up(test_metadata()));
+        assert!(matches!(
+            tracker.end(),
+            Some(TunnelEvent::Down {
+                rx_bytes: 0,
+                tx_bytes: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn on_exit_called_after_exit() {
+        let exited = Arc::new(AtomicBool::new(false));
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(1));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .on_exit({
+                let exited = exited.clone();
+                move || exited.store(true, Ordering::SeqCst)
+            })
+            .build(|_, _| {})
+            .unwrap();
+        assert!(!exited.load(Ordering::SeqCst));
+        let _ = testee.wait();
+        assert!(exited.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn wait_for_up_event() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+
+        // Keep injecting events, since only those sent after `wait_for_event` subscribes count.
+        let event_subscribers = testee.event_subscribers().clone();
+        let done = Arc::new(AtomicBool::new(false));
+        let injector = {
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    event_subscribers.notify(TunnelEvent::InterfaceUp("tun0".to_owned()));
+                    event_subscribers.notify(TunnelEvent::Up(test_metadata()));
+                    thread::sleep(Duration::from_millis(10));
+                }
+            })
+        };
+
+        let event = testee.wait_for_event(
+            |event| matches!(event, TunnelEvent::Up(_)),
+            Duration::from_secs(5),
+        );
+        done.store(true, Ordering::SeqCst);
+        injector.join().unwrap();
+        assert_eq!(event.unwrap(), TunnelEvent::Up(test_metadata()));
+    }
+
+    #[test]
+    fn wait_for_event_timeout() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        testee
+            .event_subscribers()
+            .notify(TunnelEvent::Up(test_metadata()));
+
+        let timeout = Duration::from_millis(50);
+        match testee.wait_for_event(|event| matches!(event, TunnelEvent::Up(_)), timeout) {
+            Err(Error::EventTimeout(elapsed)) => assert_eq!(elapsed, timeout),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn up_metadata_has_transport() {
+        for protocol in &[
+            talpid_types::net::TransportProtocol::Tcp,
+            talpid_types::net::TransportProtocol::Udp,
+        ] {
+            let mut config = connection_config("user", "pass");
+            config.endpoint.protocol = *protocol;
+            config.endpoint.address.set_port(443);
+
+            for event in vec![
+                TunnelEvent::Up(test_metadata()),
+                TunnelEvent::Reconnected(test_metadata()),
+            ] {
+                match with_remote(event, config.endpoint) {
+                    TunnelEvent::Up(metadata) | TunnelEvent::Reconnected(metadata) => {
+                        assert_eq!(metadata.transport(), Some(*protocol));
+                        assert_eq!(metadata.port(), Some(443));
+                    }
+                    event => panic!("Unexpected event: {:?}", event),
+                }
+            }
+        }
+        assert_eq!(test_metadata().transport(), None);
+        assert_eq!(
+            with_remote(reconnecting(1, 0), connection_config("", "").endpoint),
+            reconnecting(1, 0)
+        );
+    }
+
+    fn test_metadata() -> crate::tunnel::TunnelMetadata {
+        crate::tunnel::TunnelMetadata {
+            interface: "tun0".to_owned(),
+            ips: vec!["10.8.0.2".parse().unwrap()],
+            ipv4_gateway: "10.8.0.1".parse().unwrap(),
+            ipv6_gateway: None,
+            ipv6_available: false,
+            dns_servers: vec![],
+            remote: None,
+            session_id: None,
+        }
+    }
+
+    #[test]
+    fn distinct_session_ids() {
+        let start = || {
+            let mut builder = TestOpenVpnBuilder::default();
+            builder.process_handle = Some(TestProcessHandle::new(0));
+            OpenVpnMonitorBuilder::new(builder, "")
+                .build(|_, _| {})
+                .unwrap()
+        };
+        let first = start();
+        let second = start();
+        assert_eq!(first.session_id().len(), 8);
+        assert_ne!(first.session_id(), second.session_id());
+
+        match with_session_id(TunnelEvent::Up(test_metadata()), first.session_id()) {
+            TunnelEvent::Up(metadata) => {
+                assert_eq!(metadata.session_id.as_deref(), Some(first.session_id()))
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        assert_eq!(
+            with_session_id(reconnecting(1, 0), first.session_id()),
+            reconnecting(1, 0)
+        );
+    }
+
+    fn connection_config(username: &str, password: &str) -> openvpn::ConnectionConfig {
+        openvpn::ConnectionConfig::new(
+            talpid_types::net::Endpoint::new(
+                std::net::Ipv4Addr::new(185, 65, 135, 117),
+                1194,
+                talpid_types::net::TransportProtocol::Udp,
+            ),
+            username.to_owned(),
+            password.to_owned(),
+        )
+    }
+
+    fn fake_env(name: &str) -> Option<String> {
+        match name {
+            USERNAME_ENV_VAR => Some("env-user".to_owned()),
+            PASSWORD_ENV_VAR => Some("env-pass".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn credentials_from_env() {
+        let (username, password) = resolve_credentials(&connection_config("", ""), fake_env);
+        let file = OpenVpnMonitor::<TestOpenVpnBuilder>::create_credentials_file(
+            &username,
+            &password,
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "env-user\nenv-pass\n");
+    }
+
+    #[test]
+    fn credentials_in_custom_temp_dir() {
+        let dir = TempFile::new();
+        fs::create_dir(&dir).unwrap();
+        let file = OpenVpnMonitor::<TestOpenVpnBuilder>::create_credentials_file(
+            "user",
+            "pass",
+            dir.as_ref(),
+        )
+        .unwrap();
+        assert_eq!(file.as_ref().parent(), Some(dir.as_ref()));
+        drop(file);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn tls_crypt_v2_key_file() {
+        let file = OpenVpnMonitor::<TestOpenVpnBuilder>::create_key_file(
+            b"-----BEGIN OpenVPN tls-crypt-v2 client key-----\n",
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read(&file).unwrap(),
+            b"-----BEGIN OpenVPN tls-crypt-v2 client key-----\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o400);
+        }
+    }
+
+    #[test]
+    fn read_once_files_removed_on_route_up() {
+        let files: Vec<TempFile> = (0..2).map(|_| TempFile::new()).collect();
+        for file in &files {
+            fs::write(file, b"secret").unwrap();
+        }
+        let paths: Vec<PathBuf> = files.iter().map(|file| file.to_path_buf()).collect();
+
+        remove_read_once_files(openvpn_plugin::EventType::Up, &paths);
+        assert!(paths.iter().all(|path| path.exists()));
+
+        remove_read_once_files(openvpn_plugin::EventType::RouteUp, &paths);
+        assert!(paths.iter().all(|path| !path.exists()));
+    }
+
+    #[test]
+    fn tls_crypt_v2_conflicts_with_tls_auth() {
+        assert!(check_config_without_tls_auth("client\ntls-cipher TLS-DHE\n").is_ok());
+        assert!(matches!(
+            check_config_without_tls_auth("client\ntls-auth ta.key 1\n"),
+            Err(Error::ConflictingTlsAuth)
+        ));
+        assert!(matches!(
+            check_config_without_tls_auth("client\n<tls-auth>\nkey\n</tls-auth>\n"),
+            Err(Error::ConflictingTlsAuth)
+        ));
+    }
+
+    #[test]
+    fn missing_ca() {
+        let resource_dir = TempFile::new();
+        let expected_path = resource_dir.as_ref().join("ca.crt");
+        match OpenVpnMonitor::<TestOpenVpnBuilder>::get_ca_path(resource_dir.as_ref(), None) {
+            Err(Error::CaNotFound(path)) => assert_eq!(path, expected_path.display().to_string()),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let ca_override = TempFile::new();
+        match OpenVpnMonitor::<TestOpenVpnBuilder>::get_ca_path(
+            resource_dir.as_ref(),
+            Some(ca_override.to_path_buf()),
+        ) {
+            Err(Error::CaNotFound(path)) => {
+                assert_eq!(path, ca_override.as_ref().display().to_string())
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        fs::write(&ca_override, b"").unwrap();
+        assert_eq!(
+            OpenVpnMonitor::<TestOpenVpnBuilder>::get_ca_path(
+                resource_dir.as_ref(),
+                Some(ca_override.to_path_buf()),
+            )
+            .unwrap(),
+            ca_override.to_path_buf()
+        );
+    }
+
+    #[test]
+    fn plugin_filename_override() {
+        let resource_dir = tempfile::tempdir().unwrap();
+        let get_plugin_path = |filename_override: Option<&str>| {
+            OpenVpnMonitor::<TestOpenVpnBuilder>::get_plugin_path(
+                resource_dir.path(),
+                filename_override.map(PathBuf::from),
+            )
+        };
+
+        let default_path = resource_dir.path().join(OPENVPN_PLUGIN_FILENAME);
+        let override_path = resource_dir.path().join("openvpn-plugin-x86.so");
+        fs::write(&default_path, b"").unwrap();
+        assert_eq!(get_plugin_path(None).unwrap(), default_path);
+
+        match get_plugin_path(Some("openvpn-plugin-x86.so")) {
+            Err(Error::PluginNotFound(path)) => {
+                assert_eq!(path, override_path.display().to_string())
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        fs::write(&override_path, b"").unwrap();
+        assert_eq!(
+            get_plugin_path(Some("openvpn-plugin-x86.so")).unwrap(),
+            override_path
+        );
+
+        assert!(matches!(
+            get_plugin_path(Some("../openvpn-plugin-x86.so")),
+            Err(Error::InvalidPluginFilename(_))
+        ));
+    }
+
+    #[test]
+    fn unwritable_temp_dir() {
+        // A regular file can never be used as a directory, regardless of privileges.
+        let not_a_dir = TempFile::new();
+        fs::write(&not_a_dir, b"").unwrap();
+        let dir = not_a_dir.as_ref().join("credentials");
+
+        match check_writable_dir(&dir) {
+            Err(Error::DirectoryNotWritable(path, _)) => {
+                assert_eq!(path, dir.display().to_string())
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(check_writable_dir(&std::env::temp_dir()).is_ok());
+    }
+
+    #[test]
+    fn creates_nested_log_dir() {
+        let root = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let log_dir = root.join("logs").join("openvpn");
+        create_log_dir(&log_dir.join("openvpn.log")).unwrap();
+        let created = log_dir.is_dir();
+        fs::remove_dir_all(&root).unwrap();
+        assert!(created);
+
+        // A regular file can never be used as a directory, regardless of privileges.
+        let not_a_dir = TempFile::new();
+        fs::write(&not_a_dir, b"").unwrap();
+        let log_dir = not_a_dir.as_ref().join("logs");
+        match create_log_dir(&log_dir.join("openvpn.log")) {
+            Err(Error::CreateLogDirError(path, _)) => {
+                assert_eq!(path, log_dir.display().to_string())
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn log_rotated_past_max_size() {
+        let log_file = TempFile::new();
+        let old_log_file = old_log_path(log_file.as_ref());
+        fs::write(&log_file, "first\nsecond\n").unwrap();
+        assert!(!limit_log_size(log_file.as_ref(), 100).unwrap());
+
+        fs::write(&log_file, "first\nsecond\nthird\n").unwrap();
+        let rotated = limit_log_size(log_file.as_ref(), 10);
+        let old_log = fs::read_to_string(&old_log_file);
+        let _ = fs::remove_file(&old_log_file);
+        assert!(rotated.unwrap());
+        assert_eq!(old_log.unwrap(), "first\nsecond\nthird\n");
+        assert_eq!(fs::metadata(&log_file).unwrap().len(), 0);
+
+        fs::write(&old_log_file, "first\nsecond\nthird\n").unwrap();
+        fs::write(&log_file, "fourth\n").unwrap();
+        let tail = tail_rotated_log(log_file.as_ref(), 3);
+        fs::remove_file(&old_log_file).unwrap();
+        assert_eq!(tail.unwrap(), vec!["second", "third", "fourth"]);
+    }
+
+    #[test]
+    fn credentials_from_config_take_precedence() {
+        assert_eq!(
+            resolve_credentials(&connection_config("user", "pass"), fake_env),
+            ("user".to_owned(), "pass".to_owned())
+        );
+        assert_eq!(
+            resolve_credentials(&connection_config("user", ""), fake_env),
+            ("user".to_owned(), "env-pass".to_owned())
+        );
+        assert_eq!(
+            resolve_credentials(&connection_config("", ""), |_| None),
+            (String::new(), String::new())
+        );
+    }
+
+    #[test]
+    fn remote_endpoint_must_be_ip() {
+        assert!(check_remote_endpoint("185.65.135.117:1194".parse().unwrap()).is_ok());
+        assert!(check_remote_endpoint("[2a03:1b20:1::1]:1194".parse().unwrap()).is_ok());
+        assert!(matches!(
+            check_remote_endpoint("0.0.0.0:1194".parse().unwrap()),
+            Err(Error::UnresolvedRemote(_))
+        ));
+    }
+
+    #[test]
+    fn config_remotes_must_be_ips() {
+        let config = "client\nremote 185.65.135.117 1194\nremote 2a03:1b20:1::1 1194\n";
+        assert!(check_config_remotes(config).is_ok());
+        assert!(check_config_remotes("client\nremote-cert-tls server\n").is_ok());
+
+        let config = "client\nremote se-got.mullvad.net 1194\n";
+        match check_config_remotes(config) {
+            Err(Error::UnresolvedRemote(host)) => assert_eq!(host, "se-got.mullvad.net"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(matches!(
+            check_config_remotes("remote\n"),
+            Err(Error::UnresolvedRemote(_))
+        ));
+    }
+
+    #[test]
+    fn connected_endpoint_from_env() {
+        let mut env = HashMap::new();
+        env.insert("trusted_ip".to_owned(), "185.65.135.117".to_owned());
+        env.insert("trusted_port".to_owned(), "1194".to_owned());
+        assert_eq!(
+            parse_connected_endpoint(&env),
+            Some("185.65.135.117:1194".parse().unwrap())
+        );
+
+        env.remove("trusted_ip");
+        env.insert("trusted_ip6".to_owned(), "2a03:1b20:1::1".to_owned());
+        assert_eq!(
+            parse_connected_endpoint(&env),
+            Some("[2a03:1b20:1::1]:1194".parse().unwrap())
+        );
+
+        env.remove("trusted_port");
+        assert_eq!(parse_connected_endpoint(&env), None);
+    }
+
+    #[test]
+    fn no_connected_endpoint_before_up() {
+        let mut builder = TestOpenVpnBuilder::default();
+        builder.process_handle = Some(TestProcessHandle::new(0));
+        let testee = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap();
+        assert_eq!(testee.connected_endpoint(), None);
+    }
+
     #[test]
     fn failed_process_start() {
         let builder = TestOpenVpnBuilder::default();
-        let error =
-            OpenVpnMonitor::new_internal(builder, |_, _| {}, "", None, TempFile::new(), None, None)
-                .unwrap_err();
+        let error = OpenVpnMonitorBuilder::new(builder, "")
+            .build(|_, _| {})
+            .unwrap_err();
         match error {
             Error::ChildProcessError(..) => (),
             _ => panic!("Wrong error"),
@@ -7,8 +7,12 @@ use std::{
     collections::HashSet,
     path::Path,
     sync::{mpsc, Arc, Mutex},
+    time::Instant,
+};
+use talpid_types::{
+    net::{Endpoint, TransportProtocol},
+    ErrorExt,
 };
-use talpid_types::ErrorExt;
 
 /// WireGuard config data-types
 pub mod config;
@@ -51,6 +55,8 @@ pub struct WireguardMonitor {
     close_msg_sender: mpsc::Sender<CloseMsg>,
     close_msg_receiver: mpsc::Receiver<CloseMsg>,
     pinger_stop_sender: mpsc::Sender<()>,
+    /// When the tunnel came up, if it has.
+    up_since: Arc<Mutex<Option<Instant>>>,
 }
 
 impl WireguardMonitor {
@@ -86,11 +92,13 @@ impl WireguardMonitor {
             close_msg_sender,
             close_msg_receiver,
             pinger_stop_sender: pinger_tx,
+            up_since: Arc::new(Mutex::new(None)),
         };
 
         let metadata = Self::tunnel_metadata(&iface_name, &config);
         let gateway = config.ipv4_gateway;
         let close_sender = monitor.close_msg_sender.clone();
+        let up_since = monitor.up_since.clone();
         let mut connectivity_monitor = connectivity_check::ConnectivityMonitor::new(
             gateway,
             iface_name,
@@ -101,6 +109,8 @@ impl WireguardMonitor {
         std::thread::spawn(move || {
             match connectivity_monitor.establish_connectivity() {
                 Ok(true) => {
+                    *up_since.lock().expect("Tunnel start time lock poisoned") =
+                        Some(Instant::now());
                     (on_event)(TunnelEvent::Up(metadata));
 
                     if let Err(error) = connectivity_monitor.run() {
@@ -142,9 +152,26 @@ impl WireguardMonitor {
 
         let _ = self.pinger_stop_sender.send(());
 
+        // The counters are lost once the tunnel is stopped.
+        let stats = self
+            .tunnel
+            .lock()
+            .expect("Tunnel lock poisoned")
+            .as_ref()
+            .and_then(|tunnel| tunnel.get_tunnel_stats().ok());
         self.stop_tunnel();
 
-        (self.event_callback)(TunnelEvent::Down);
+        let duration = self
+            .up_since
+            .lock()
+            .expect("Tunnel start time lock poisoned")
+            .map(|up_since| up_since.elapsed())
+            .unwrap_or_default();
+        (self.event_callback)(TunnelEvent::Down {
+            rx_bytes: stats.as_ref().map(|stats| stats.rx_bytes).unwrap_or(0),
+            tx_bytes: stats.as_ref().map(|stats| stats.tx_bytes).unwrap_or(0),
+            duration,
+        });
         wait_result
     }
 
@@ -203,6 +230,16 @@ impl WireguardMonitor {
             ips: config.tunnel.addresses.clone(),
             ipv4_gateway: config.ipv4_gateway,
             ipv6_gateway: config.ipv6_gateway,
+            ipv6_available: config.tunnel.addresses.iter().any(|ip| ip.is_ipv6()),
+            dns_servers: vec![],
+            remote: config.peers.first().map(|peer| {
+                Endpoint::new(
+                    peer.endpoint.ip(),
+                    peer.endpoint.port(),
+                    TransportProtocol::Udp,
+                )
+            }),
+            session_id: None,
         }
     }
 }
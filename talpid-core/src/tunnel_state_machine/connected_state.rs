@@ -1,6 +1,7 @@
 use super::{
     AfterDisconnect, ConnectingState, DisconnectingState, ErrorState, EventConsequence,
-    SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    ReconnectingBootstrap, SharedTunnelStateValues, TunnelCommand, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
     firewall::FirewallPolicy,
@@ -12,7 +13,7 @@ use futures01::{
 };
 use talpid_types::{
     net::{Endpoint, TunnelParameters},
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ErrorStateCause, FirewallPolicyError, ReconnectAttempt},
     BoxedError, ErrorExt,
 };
 
@@ -48,6 +49,19 @@ impl ConnectedState {
         }
     }
 
+    fn into_reconnecting_bootstrap(
+        self,
+        reconnect_attempt: ReconnectAttempt,
+    ) -> ReconnectingBootstrap {
+        ReconnectingBootstrap {
+            tunnel_events: self.tunnel_events,
+            tunnel_parameters: self.tunnel_parameters,
+            tunnel_close_event: self.tunnel_close_event,
+            close_handle: self.close_handle,
+            reconnect_attempt,
+        }
+    }
+
     fn set_firewall_policy(
         &self,
         shared_values: &mut SharedTunnelStateValues,
@@ -96,10 +110,7 @@ impl ConnectedState {
     }
 
     fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<(), BoxedError> {
-        let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
-        if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-            dns_ips.push(ipv6_gateway.into());
-        };
+        let dns_ips = self.metadata.tunnel_dns_servers();
 
         shared_values
             .dns_monitor
@@ -188,6 +199,14 @@ impl ConnectedState {
             Ok(TunnelCommand::Block(reason)) => {
                 self.disconnect(shared_values, AfterDisconnect::Block(reason))
             }
+            Ok(TunnelCommand::GetRoutes(tx)) => {
+                shared_values.send_routes(tx);
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsServers(tx)) => {
+                let _ = tx.send(self.metadata.tunnel_dns_servers());
+                SameState(self)
+            }
         }
     }
 
@@ -198,9 +217,71 @@ impl ConnectedState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, self.tunnel_events.poll()) {
-            Ok(TunnelEvent::Down) | Err(_) => {
+            Ok(TunnelEvent::Down {
+                rx_bytes,
+                tx_bytes,
+                duration,
+            }) => {
+                log::info!(
+                    "Tunnel went down after {}s, {} bytes received, {} bytes sent",
+                    duration.as_secs(),
+                    rx_bytes,
+                    tx_bytes
+                );
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
+            Err(_) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+            Ok(TunnelEvent::Unhealthy) => {
+                log::warn!("Tunnel failed its health check. Reconnecting.");
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Ok(TunnelEvent::Reconnecting {
+                attempt,
+                next_delay,
+            }) => {
+                log::info!(
+                    "Tunnel went down. Waiting for the VPN client to reconnect, attempt {} in {:?}.",
+                    attempt,
+                    next_delay
+                );
+                // The tunnel keeps running, so only the DNS servers inside it are given up until
+                // it comes back up.
+                Self::reset_dns(shared_values);
+                NewState(ConnectingState::enter_reconnecting(
+                    shared_values,
+                    self.into_reconnecting_bootstrap(ReconnectAttempt {
+                        attempt,
+                        next_delay,
+                    }),
+                ))
+            }
+            Ok(TunnelEvent::Warning(warning)) => {
+                log::warn!("VPN client warning: {}", warning);
+                SameState(self)
+            }
+            Ok(TunnelEvent::Reconnected(metadata)) => {
+                log::info!("Tunnel reconnected");
+                if metadata == self.metadata {
+                    return SameState(self);
+                }
+                // The new connection may have been assigned different addresses, so the policy and
+                // DNS settings based on the old ones must be replaced.
+                self.metadata = metadata;
+                if let Err(error) = self.set_firewall_policy(shared_values) {
+                    return self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    );
+                }
+                if let Err(error) = self.set_dns(shared_values) {
+                    log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                    return self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                    );
+                }
+                SameState(self)
+            }
             Ok(_) => SameState(self),
         }
     }
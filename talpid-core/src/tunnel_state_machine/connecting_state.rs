@@ -23,7 +23,7 @@ use std::{
 };
 use talpid_types::{
     net::{openvpn, TunnelParameters},
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ErrorStateCause, FirewallPolicyError, ReconnectAttempt},
     ErrorExt,
 };
 
@@ -43,6 +43,15 @@ pub struct ConnectingState {
     retry_attempt: u32,
 }
 
+/// Takes over a tunnel that went down while connected, while the VPN client brings it back up.
+pub struct ReconnectingBootstrap {
+    pub tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
+    pub tunnel_parameters: TunnelParameters,
+    pub tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
+    pub close_handle: Option<CloseHandle>,
+    pub reconnect_attempt: ReconnectAttempt,
+}
+
 impl ConnectingState {
     fn set_firewall_policy(
         shared_values: &mut SharedTunnelStateValues,
@@ -176,6 +185,54 @@ impl ConnectingState {
         }
     }
 
+    /// Enters the connecting state with a tunnel that is still running, instead of starting a new
+    /// one. Unlike `enter`, this doesn't generate new tunnel parameters.
+    pub fn enter_reconnecting(
+        shared_values: &mut SharedTunnelStateValues,
+        bootstrap: ReconnectingBootstrap,
+    ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        let connecting_state = ConnectingState {
+            tunnel_events: bootstrap.tunnel_events,
+            tunnel_parameters: bootstrap.tunnel_parameters,
+            tunnel_close_event: bootstrap.tunnel_close_event,
+            close_handle: bootstrap.close_handle,
+            retry_attempt: 0,
+        };
+
+        if let Err(error) =
+            Self::set_firewall_policy(shared_values, &connecting_state.tunnel_parameters)
+        {
+            Self::reset_routes(shared_values);
+            return DisconnectingState::enter(
+                shared_values,
+                (
+                    connecting_state.close_handle,
+                    connecting_state.tunnel_close_event,
+                    AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                ),
+            );
+        }
+
+        let endpoint = connecting_state.tunnel_parameters.get_tunnel_endpoint();
+        (
+            TunnelStateWrapper::from(connecting_state),
+            TunnelStateTransition::Reconnecting(endpoint, bootstrap.reconnect_attempt),
+        )
+    }
+
+    fn into_reconnecting_bootstrap(
+        self,
+        reconnect_attempt: ReconnectAttempt,
+    ) -> ReconnectingBootstrap {
+        ReconnectingBootstrap {
+            tunnel_events: self.tunnel_events,
+            tunnel_parameters: self.tunnel_parameters,
+            tunnel_close_event: self.tunnel_close_event,
+            close_handle: self.close_handle,
+            reconnect_attempt,
+        }
+    }
+
     fn into_connected_state_bootstrap(self, metadata: TunnelMetadata) -> ConnectedStateBootstrap {
         ConnectedStateBootstrap {
             metadata,
@@ -252,6 +309,14 @@ impl ConnectingState {
             Ok(TunnelCommand::Block(reason)) => {
                 self.disconnect(shared_values, AfterDisconnect::Block(reason))
             }
+            Ok(TunnelCommand::GetRoutes(tx)) => {
+                shared_values.send_routes(tx);
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsServers(tx)) => {
+                let _ = tx.send(Vec::new());
+                SameState(self)
+            }
         }
     }
 
@@ -267,10 +332,30 @@ impl ConnectingState {
                 shared_values,
                 AfterDisconnect::Block(ErrorStateCause::AuthFailed(reason)),
             ),
-            Ok(TunnelEvent::Up(metadata)) => NewState(ConnectedState::enter(
-                shared_values,
-                self.into_connected_state_bootstrap(metadata),
-            )),
+            Ok(TunnelEvent::Up(metadata)) | Ok(TunnelEvent::Reconnected(metadata)) => NewState(
+                ConnectedState::enter(shared_values, self.into_connected_state_bootstrap(metadata)),
+            ),
+            Ok(TunnelEvent::Reconnecting {
+                attempt,
+                next_delay,
+            }) => {
+                info!(
+                    "Tunnel is still down. Waiting for the VPN client to reconnect, attempt {} in \
+                     {:?}.",
+                    attempt, next_delay
+                );
+                NewState(Self::enter_reconnecting(
+                    shared_values,
+                    self.into_reconnecting_bootstrap(ReconnectAttempt {
+                        attempt,
+                        next_delay,
+                    }),
+                ))
+            }
+            Ok(TunnelEvent::Warning(warning)) => {
+                warn!("VPN client warning: {}", warning);
+                SameState(self)
+            }
             Ok(_) => SameState(self),
             Err(_) => {
                 debug!("The tunnel disconnected unexpectedly");
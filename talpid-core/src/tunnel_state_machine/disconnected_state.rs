@@ -89,6 +89,14 @@ impl TunnelState for DisconnectedState {
             }
             Ok(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Ok(TunnelCommand::Block(reason)) => NewState(ErrorState::enter(shared_values, reason)),
+            Ok(TunnelCommand::GetRoutes(tx)) => {
+                shared_values.send_routes(tx);
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsServers(tx)) => {
+                let _ = tx.send(Vec::new());
+                SameState(self)
+            }
             Ok(_) => SameState(self),
             Err(_) => Finished,
         }
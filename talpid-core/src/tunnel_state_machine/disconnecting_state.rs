@@ -45,6 +45,14 @@ impl DisconnectingState {
                 }
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
+                Ok(TunnelCommand::GetRoutes(tx)) => {
+                    shared_values.send_routes(tx);
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::GetDnsServers(tx)) => {
+                    let _ = tx.send(Vec::new());
+                    AfterDisconnect::Nothing
+                }
                 _ => AfterDisconnect::Nothing,
             },
             AfterDisconnect::Block(reason) => match event {
@@ -67,6 +75,14 @@ impl DisconnectingState {
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Ok(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
+                Ok(TunnelCommand::GetRoutes(tx)) => {
+                    shared_values.send_routes(tx);
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::GetDnsServers(tx)) => {
+                    let _ = tx.send(Vec::new());
+                    AfterDisconnect::Block(reason)
+                }
                 Err(_) => AfterDisconnect::Block(reason),
             },
             AfterDisconnect::Reconnect(retry_attempt) => match event {
@@ -89,6 +105,14 @@ impl DisconnectingState {
                 Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
                 Ok(TunnelCommand::Disconnect) | Err(_) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
+                Ok(TunnelCommand::GetRoutes(tx)) => {
+                    shared_values.send_routes(tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::GetDnsServers(tx)) => {
+                    let _ = tx.send(Vec::new());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
             },
         };
 
@@ -118,6 +118,14 @@ impl TunnelState for ErrorState {
                 NewState(DisconnectedState::enter(shared_values, ()))
             }
             Ok(TunnelCommand::Block(reason)) => NewState(ErrorState::enter(shared_values, reason)),
+            Ok(TunnelCommand::GetRoutes(tx)) => {
+                shared_values.send_routes(tx);
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsServers(tx)) => {
+                let _ = tx.send(Vec::new());
+                SameState(self)
+            }
         }
     }
 }
@@ -9,7 +9,7 @@ mod error_state;
 
 use self::{
     connected_state::{ConnectedState, ConnectedStateBootstrap},
-    connecting_state::ConnectingState,
+    connecting_state::{ConnectingState, ReconnectingBootstrap},
     disconnected_state::DisconnectedState,
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
@@ -19,7 +19,7 @@ use crate::{
     firewall::{Firewall, FirewallArguments},
     mpsc::Sender,
     offline,
-    routing::RouteManager,
+    routing::{Route, RouteManager},
     tunnel::tun_provider::TunProvider,
 };
 
@@ -30,6 +30,7 @@ use futures01::{
 use std::{
     collections::HashSet,
     io,
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::{mpsc as sync_mpsc, Arc},
     thread,
@@ -203,6 +204,12 @@ pub enum TunnelCommand {
     Disconnect,
     /// Disconnect any open tunnel and block all network access
     Block(ErrorStateCause),
+    /// Request the routes that are currently applied. `None` is sent if this isn't supported on
+    /// the platform. The sender is dropped if they can't be determined.
+    GetRoutes(oneshot::Sender<Option<Vec<Route>>>),
+    /// Request the DNS servers used inside the tunnel. An empty list is sent unless the tunnel is
+    /// connected.
+    GetDnsServers(oneshot::Sender<Vec<IpAddr>>),
 }
 
 /// Asynchronous handling of the tunnel state machine.
@@ -350,6 +357,26 @@ struct SharedTunnelStateValues {
 }
 
 impl SharedTunnelStateValues {
+    /// Replies with the routes that are currently applied, or `None` if this isn't supported on
+    /// the platform. Drops `tx` if they can't be determined.
+    pub fn send_routes(&mut self, tx: oneshot::Sender<Option<Vec<Route>>>) {
+        match self.route_manager.get_routes() {
+            Ok(routes) => {
+                let _ = tx.send(Some(routes));
+            }
+            #[cfg(target_os = "windows")]
+            Err(crate::routing::Error::GetRoutesUnsupported) => {
+                let _ = tx.send(None);
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to get the applied routes")
+                );
+            }
+        }
+    }
+
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<(), ErrorStateCause> {
         if self.allow_lan != allow_lan {
             self.allow_lan = allow_lan;
@@ -37,6 +37,10 @@ pub enum Error {
     #[error(display = "Failed to determine alias of TAP adapter")]
     GetTapAlias,
 
+    /// Failed to list the TAP adapters.
+    #[error(display = "Failed to list the TAP adapters")]
+    GetTapAdapters,
+
     /// Can't establish whether host is connected to a non-virtual network
     #[error(display = "Network connectivity undecideable")]
     ConnectivityUnkown,
@@ -111,6 +115,62 @@ pub fn get_tap_interface_alias() -> Result<OsString, Error> {
     Ok(alias.to_os_string())
 }
 
+/// A TAP adapter, as listed by `get_tap_adapters`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapAdapter {
+    pub alias: String,
+    pub name: String,
+    /// Operational status of the adapter, as an `IF_OPER_STATUS` value.
+    pub oper_status: u32,
+}
+
+/// `IF_OPER_STATUS` value of adapters that are not present, e.g. because they are disabled.
+pub const IF_OPER_STATUS_NOT_PRESENT: u32 = 6;
+
+/// Lists the TAP adapters on the system.
+pub fn get_tap_adapters() -> Result<Vec<TapAdapter>, Error> {
+    let mut listing_ptr: *mut wchar_t = ptr::null_mut();
+    let status = unsafe {
+        WinNet_GetTapInterfaces(
+            &mut listing_ptr as *mut _,
+            Some(log_sink),
+            logging_context(),
+        )
+    };
+
+    if !status {
+        return Err(Error::GetTapAdapters);
+    }
+
+    let listing = unsafe { WideCString::from_ptr_str(listing_ptr) };
+    unsafe { WinNet_ReleaseString(listing_ptr) };
+
+    parse_tap_adapters(&listing.to_string_lossy()).ok_or(Error::GetTapAdapters)
+}
+
+/// Parses the listing returned by `WinNet_GetTapInterfaces`, which has one
+/// `<alias>\t<name>\t<operational status>` line per adapter.
+pub fn parse_tap_adapters(listing: &str) -> Option<Vec<TapAdapter>> {
+    listing
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut fields = line.split('\t');
+            let alias = fields.next()?.to_owned();
+            let name = fields.next()?.to_owned();
+            let oper_status = fields.next()?.parse().ok()?;
+            if fields.next().is_some() {
+                return None;
+            }
+            Some(TapAdapter {
+                alias,
+                name,
+                oper_status,
+            })
+        })
+        .collect()
+}
+
 /// Determines the interface guid for a given adapter alias.
 pub fn interface_alias_to_guid(interface_alias: &OsStr) -> Result<String, Error> {
     let interface_alias =
@@ -422,6 +482,13 @@ mod api {
             sink_context: *const u8,
         ) -> bool;
 
+        #[link_name = "WinNet_GetTapInterfaces"]
+        pub fn WinNet_GetTapInterfaces(
+            adapters: *mut *mut wchar_t,
+            sink: Option<LogSink>,
+            sink_context: *const u8,
+        ) -> bool;
+
         #[link_name = "WinNet_InterfaceAliasToGuid"]
         pub fn WinNet_InterfaceAliasToGuid(
             interface_alias: *const wchar_t,
@@ -16,6 +16,26 @@ mod proto {
 }
 use proto::openvpn_event_proxy_client::OpenvpnEventProxyClient;
 
+/// Paths starting with this prefix name a socket in the abstract namespace, since the plugin
+/// arguments cannot contain the leading NUL byte of such an address.
+#[cfg(target_os = "linux")]
+const ABSTRACT_SOCKET_PREFIX: &str = "@";
+
+/// Returns the address to connect to for the given IPC path.
+#[cfg(target_os = "linux")]
+fn socket_address(ipc_path: String) -> String {
+    if ipc_path.starts_with(ABSTRACT_SOCKET_PREFIX) {
+        format!("\0{}", &ipc_path[ABSTRACT_SOCKET_PREFIX.len()..])
+    } else {
+        ipc_path
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_address(ipc_path: String) -> String {
+    ipc_path
+}
+
 /// Struct processing OpenVPN events and notifies listeners over IPC
 pub struct EventProcessor {
     ipc_client: OpenvpnEventProxyClient<tonic::transport::Channel>,
@@ -44,6 +64,7 @@ impl EventProcessor {
     async fn spawn_client(
         ipc_path: String,
     ) -> Result<OpenvpnEventProxyClient<tonic::transport::Channel>, tonic::transport::Error> {
+        let ipc_path = socket_address(ipc_path);
         // The URI will be ignored
         let channel = Endpoint::from_static("lttp://[::]:50051")
             .connect_with_connector(service_fn(move |_: Uri| {
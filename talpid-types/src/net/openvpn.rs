@@ -3,7 +3,7 @@ use crate::net::{
     Endpoint, GenericTunnelOptions, TransportProtocol,
 };
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 /// Information needed by `OpenVpnMonitor` to establish a tunnel connection.
 /// See [`crate::net::TunnelParameters`].
@@ -21,6 +21,14 @@ pub struct ConnectionConfig {
     pub endpoint: Endpoint,
     pub username: String,
     pub password: String,
+    /// Client key for the `tls-crypt-v2` control channel protection. Cannot be combined with a
+    /// `tls-auth` key in the OpenVPN configuration.
+    #[serde(default)]
+    pub tls_crypt_v2_key: Option<Vec<u8>>,
+    /// Common name that the server certificate must have, on top of being signed by the CA. Any
+    /// server certificate signed by the CA is accepted if `None`.
+    #[serde(default)]
+    pub verify_x509_name: Option<String>,
 }
 
 impl ConnectionConfig {
@@ -29,6 +37,8 @@ impl ConnectionConfig {
             endpoint,
             username,
             password,
+            tls_crypt_v2_key: None,
+            verify_x509_name: None,
         }
     }
 }
@@ -42,6 +52,59 @@ pub struct TunnelOptions {
     /// Optional argument for openvpn to try and limit TCP packet size,
     /// as discussed [here](https://openvpn.net/archive/openvpn-users/2003-11/msg00154.html)
     pub mssfix: Option<u16>,
+    /// Optional argument for openvpn to fragment UDP datagrams larger than this many bytes.
+    /// Only valid for UDP connections.
+    pub fragment: Option<u16>,
+    /// Optional argument for openvpn to set the MTU of the tunnel device.
+    pub tun_mtu: Option<u16>,
+    /// Loopback port, or range of ports, that a bundled proxy client should bind to.
+    pub proxy_port: Option<ProxyPortPreference>,
+    /// How long to wait for a proxy to accept connections before launching OpenVPN. Uses a
+    /// default timeout if `None`.
+    pub proxy_ready_timeout: Option<Duration>,
+    /// Periodically probe the tunnel gateway to detect a tunnel that passes no traffic.
+    /// Disabled if `None`.
+    pub health_check: Option<HealthCheckOptions>,
+    /// Optional argument for openvpn to set its log verbosity, from 0 to [`MAX_VERBOSITY`]. Uses
+    /// level 3 if `None`. Levels above 4 log packet and routing details, such as addresses of
+    /// remote hosts, which may be sensitive.
+    pub verbosity: Option<u8>,
+    /// Optional argument for openvpn to ping the server after this many seconds without sending
+    /// anything to it. Uses [`DEFAULT_PING`] if `None`.
+    pub ping: Option<u32>,
+    /// Optional argument for openvpn to restart the connection after this many seconds without
+    /// receiving anything from the server, instead of exiting after [`DEFAULT_PING_EXIT`]
+    /// seconds. Must be greater than the ping interval. Such restarts are reported as
+    /// `Reconnecting` and `Reconnected` tunnel events.
+    pub ping_restart: Option<u32>,
+    /// Optional argument for openvpn to wait this many seconds between connection attempts.
+    /// Attempts are made back to back if `None`.
+    pub connect_retry: Option<u32>,
+    /// Optional argument for openvpn to give up and exit after this many connection attempts,
+    /// where zero means never giving up. Only a single attempt is made if `None`, so that the
+    /// caller can move on to another relay.
+    pub connect_retry_max: Option<u32>,
+}
+
+/// Options for probing the gateway of an established tunnel.
+/// Stored in [`TunnelOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct HealthCheckOptions {
+    /// Time between probes.
+    pub interval: Duration,
+    /// Number of consecutive failed probes after which the tunnel is considered unhealthy.
+    pub failure_threshold: u32,
+}
+
+/// Loopback port(s) that a proxy client managed by `OpenVpnMonitor` should bind to.
+/// Stored in [`TunnelOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyPortPreference {
+    /// Bind to this port if it is free, otherwise to an ephemeral port.
+    Preferred(u16),
+    /// Bind to the first free port in this inclusive range. Fails if every port is taken.
+    Range { start: u16, end: u16 },
 }
 
 /// Proxy server options to be used by `OpenVpnMonitor` when starting a tunnel.
@@ -51,6 +114,8 @@ pub enum ProxySettings {
     Local(LocalProxySettings),
     Remote(RemoteProxySettings),
     Shadowsocks(ShadowsocksProxySettings),
+    Http(HttpProxySettings),
+    Obfuscation(ObfuscationProxySettings),
 }
 
 
@@ -69,6 +134,14 @@ impl ProxySettings {
                 endpoint: settings.get_endpoint(),
                 proxy_type: ProxyType::Shadowsocks,
             },
+            ProxySettings::Http(settings) => ProxyEndpoint {
+                endpoint: settings.get_endpoint(),
+                proxy_type: ProxyType::Custom,
+            },
+            ProxySettings::Obfuscation(settings) => ProxyEndpoint {
+                endpoint: settings.get_endpoint(),
+                proxy_type: ProxyType::Obfuscation,
+            },
         }
     }
 }
@@ -105,6 +178,23 @@ impl RemoteProxySettings {
     }
 }
 
+/// Options for a generic HTTP proxy supporting the CONNECT method.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct HttpProxySettings {
+    pub address: SocketAddr,
+    /// Credentials for basic authentication.
+    pub auth: Option<ProxyAuth>,
+}
+
+impl HttpProxySettings {
+    pub fn get_endpoint(&self) -> Endpoint {
+        Endpoint {
+            address: self.address,
+            protocol: TransportProtocol::Tcp,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct ProxyAuth {
     pub username: String,
@@ -129,6 +219,24 @@ impl ShadowsocksProxySettings {
     }
 }
 
+/// Options for a bundled obfuscation transport. OpenVPN connects to the transport on localhost,
+/// which disguises the UDP traffic as TCP on its way to the peer.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct ObfuscationProxySettings {
+    pub peer: SocketAddr,
+    /// Key shared with the peer.
+    pub key: String,
+}
+
+impl ObfuscationProxySettings {
+    pub fn get_endpoint(&self) -> Endpoint {
+        Endpoint {
+            address: self.peer,
+            protocol: TransportProtocol::Tcp,
+        }
+    }
+}
+
 /// List of ciphers usable by a Shadowsocks proxy.
 /// Cf. [`ShadowsocksProxySettings::cipher`].
 pub static SHADOWSOCKS_CIPHERS: &[&str] = &[
@@ -155,6 +263,98 @@ pub static SHADOWSOCKS_CIPHERS: &[&str] = &[
     "aes-256-pmac-siv",
 ];
 
+/// Smallest MTU-related value accepted in [`TunnelOptions`]. This is the minimum IPv4 MTU.
+pub const MIN_MTU: u16 = 576;
+/// Largest MTU-related value accepted in [`TunnelOptions`].
+pub const MAX_MTU: u16 = 9000;
+
+/// Seconds between pings to the server if [`TunnelOptions::ping`] is not set.
+pub const DEFAULT_PING: u32 = 4;
+/// Seconds without anything received from the server before OpenVPN exits, unless
+/// [`TunnelOptions::ping_restart`] is set.
+pub const DEFAULT_PING_EXIT: u32 = 25;
+
+/// Highest log verbosity accepted by OpenVPN.
+pub const MAX_VERBOSITY: u8 = 11;
+
+/// Checks whether the tunnel options are valid for a connection using `protocol`.
+pub fn validate_tunnel_options(
+    options: &TunnelOptions,
+    protocol: TransportProtocol,
+) -> Result<(), String> {
+    let values = [
+        ("mssfix", options.mssfix),
+        ("fragment", options.fragment),
+        ("tun-mtu", options.tun_mtu),
+    ];
+    for (name, value) in &values {
+        if let Some(value) = value {
+            if *value < MIN_MTU || *value > MAX_MTU {
+                return Err(format!(
+                    "{} must be between {} and {}, got {}",
+                    name, MIN_MTU, MAX_MTU, value
+                ));
+            }
+        }
+    }
+    if options.fragment.is_some() && protocol != TransportProtocol::Udp {
+        return Err(String::from("fragment can only be used with UDP"));
+    }
+    let ping = options.ping.unwrap_or(DEFAULT_PING);
+    match options.ping_restart {
+        Some(ping_restart) if ping_restart <= ping => {
+            return Err(format!(
+                "ping-restart must be greater than ping, got {} and {}",
+                ping_restart, ping
+            ));
+        }
+        None if DEFAULT_PING_EXIT <= ping => {
+            return Err(format!(
+                "ping must be less than {} unless ping-restart is set, got {}",
+                DEFAULT_PING_EXIT, ping
+            ));
+        }
+        _ => (),
+    }
+    if let Some(verbosity) = options.verbosity {
+        if verbosity > MAX_VERBOSITY {
+            return Err(format!(
+                "verb must be between 0 and {}, got {}",
+                MAX_VERBOSITY, verbosity
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Longest certificate common name accepted by [`validate_x509_name`], as limited by X.509.
+pub const MAX_X509_NAME_LEN: usize = 64;
+
+/// Checks whether `name` can be pinned as the common name of the server certificate. Only host
+/// name characters are accepted, so the name cannot be mistaken for another OpenVPN argument.
+pub fn validate_x509_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_X509_NAME_LEN {
+        return Err(format!(
+            "Certificate name must be between 1 and {} characters, got {}",
+            MAX_X509_NAME_LEN,
+            name.len()
+        ));
+    }
+    if name.starts_with('-') {
+        return Err(format!("Certificate name cannot start with '-': {}", name));
+    }
+    if let Some(invalid) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '.' || *c == '_'))
+    {
+        return Err(format!(
+            "Invalid character {:?} in certificate name: {}",
+            invalid, name
+        ));
+    }
+    Ok(())
+}
+
 /// Checks whether the proxy settings to be used by `OpenVpnMonitor` are valid.
 pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
     match proxy {
@@ -162,6 +362,9 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
             if local.port == 0 {
                 return Err(String::from("Invalid local port number"));
             }
+            if local.peer.ip().is_unspecified() {
+                return Err(String::from("Missing peer address"));
+            }
             if local.peer.ip().is_loopback() {
                 return Err(String::from(
                     "localhost is not a valid peer in this context",
@@ -172,6 +375,9 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
             }
         }
         ProxySettings::Remote(remote) => {
+            if remote.address.ip().is_unspecified() {
+                return Err(String::from("Missing remote server address"));
+            }
             if remote.address.port() == 0 {
                 return Err(String::from("Invalid port number"));
             }
@@ -179,7 +385,21 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
                 return Err(String::from("localhost is not a valid remote server"));
             }
         }
+        ProxySettings::Http(http) => {
+            if http.address.ip().is_unspecified() {
+                return Err(String::from("Missing proxy server address"));
+            }
+            if http.address.port() == 0 {
+                return Err(String::from("Invalid port number"));
+            }
+            if http.address.ip().is_loopback() {
+                return Err(String::from("localhost is not a valid proxy server"));
+            }
+        }
         ProxySettings::Shadowsocks(ss) => {
+            if ss.peer.ip().is_unspecified() {
+                return Err(String::from("Missing peer address"));
+            }
             if ss.peer.ip().is_loopback() {
                 return Err(String::from(
                     "localhost is not a valid peer in this context",
@@ -189,9 +409,215 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
                 return Err(String::from("Invalid remote port number"));
             }
             if !SHADOWSOCKS_CIPHERS.contains(&ss.cipher.as_str()) {
-                return Err(String::from("Invalid cipher"));
+                return Err(format!("Unsupported cipher \"{}\"", ss.cipher));
+            }
+        }
+        ProxySettings::Obfuscation(obfuscation) => {
+            if obfuscation.peer.ip().is_unspecified() {
+                return Err(String::from("Missing peer address"));
+            }
+            if obfuscation.peer.ip().is_loopback() {
+                return Err(String::from(
+                    "localhost is not a valid peer in this context",
+                ));
+            }
+            if obfuscation.peer.port() == 0 {
+                return Err(String::from("Invalid remote port number"));
+            }
+            if obfuscation.key.is_empty() {
+                return Err(String::from("Missing obfuscation key"));
             }
         }
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shadowsocks(peer: &str, cipher: &str) -> ProxySettings {
+        ProxySettings::Shadowsocks(ShadowsocksProxySettings {
+            peer: peer.parse().unwrap(),
+            password: "mullvad".to_owned(),
+            cipher: cipher.to_owned(),
+        })
+    }
+
+    fn obfuscation(peer: &str, key: &str) -> ProxySettings {
+        ProxySettings::Obfuscation(ObfuscationProxySettings {
+            peer: peer.parse().unwrap(),
+            key: key.to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_obfuscation_settings() {
+        assert_eq!(
+            validate_proxy_settings(&obfuscation("185.65.135.117:443", "mullvad")),
+            Ok(())
+        );
+        for (peer, key) in &[
+            ("0.0.0.0:443", "mullvad"),
+            ("127.0.0.1:443", "mullvad"),
+            ("185.65.135.117:0", "mullvad"),
+            ("185.65.135.117:443", ""),
+        ] {
+            assert!(
+                validate_proxy_settings(&obfuscation(peer, key)).is_err(),
+                "{} with key {:?} should be rejected",
+                peer,
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_valid_tunnel_options() {
+        let options = TunnelOptions {
+            mssfix: Some(1400),
+            fragment: Some(1300),
+            tun_mtu: Some(1500),
+            verbosity: Some(MAX_VERBOSITY),
+            ping: Some(10),
+            ping_restart: Some(60),
+            ..TunnelOptions::default()
+        };
+        assert_eq!(
+            validate_tunnel_options(&options, TransportProtocol::Udp),
+            Ok(())
+        );
+        assert_eq!(
+            validate_tunnel_options(&TunnelOptions::default(), TransportProtocol::Tcp),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_invalid_tunnel_options() {
+        let out_of_range = [
+            TunnelOptions {
+                mssfix: Some(MIN_MTU - 1),
+                ..TunnelOptions::default()
+            },
+            TunnelOptions {
+                fragment: Some(MAX_MTU + 1),
+                ..TunnelOptions::default()
+            },
+            TunnelOptions {
+                tun_mtu: Some(0),
+                ..TunnelOptions::default()
+            },
+            TunnelOptions {
+                verbosity: Some(MAX_VERBOSITY + 1),
+                ..TunnelOptions::default()
+            },
+        ];
+        for options in &out_of_range {
+            assert!(
+                validate_tunnel_options(options, TransportProtocol::Udp).is_err(),
+                "{:?} should be rejected",
+                options
+            );
+        }
+
+        let fragment_over_tcp = TunnelOptions {
+            fragment: Some(1300),
+            ..TunnelOptions::default()
+        };
+        assert!(validate_tunnel_options(&fragment_over_tcp, TransportProtocol::Tcp).is_err());
+    }
+
+    #[test]
+    fn test_ping_restart_not_after_ping() {
+        for ping_restart in &[5, 10] {
+            let options = TunnelOptions {
+                ping: Some(10),
+                ping_restart: Some(*ping_restart),
+                ..TunnelOptions::default()
+            };
+            assert!(validate_tunnel_options(&options, TransportProtocol::Udp).is_err());
+        }
+
+        // Without an explicit ping, ping-restart is checked against the default ping.
+        let options = TunnelOptions {
+            ping_restart: Some(DEFAULT_PING),
+            ..TunnelOptions::default()
+        };
+        assert!(validate_tunnel_options(&options, TransportProtocol::Udp).is_err());
+    }
+
+    #[test]
+    fn test_ping_not_after_ping_exit() {
+        let options = TunnelOptions {
+            ping: Some(DEFAULT_PING_EXIT),
+            ..TunnelOptions::default()
+        };
+        assert!(validate_tunnel_options(&options, TransportProtocol::Udp).is_err());
+
+        let options = TunnelOptions {
+            ping: Some(DEFAULT_PING_EXIT),
+            ping_restart: Some(DEFAULT_PING_EXIT * 2),
+            ..TunnelOptions::default()
+        };
+        assert!(validate_tunnel_options(&options, TransportProtocol::Udp).is_ok());
+    }
+
+    #[test]
+    fn test_x509_name() {
+        for name in &["se-got-001", "us1.mullvad.net", "relay_1"] {
+            assert_eq!(validate_x509_name(name), Ok(()));
+        }
+        let too_long = "a".repeat(MAX_X509_NAME_LEN + 1);
+        for name in &[
+            "",
+            "-relay",
+            "se got 001",
+            "relay\n",
+            "relay,O=Mullvad",
+            too_long.as_str(),
+        ] {
+            assert!(
+                validate_x509_name(name).is_err(),
+                "{:?} should be rejected",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_valid_shadowsocks_settings() {
+        for cipher in SHADOWSOCKS_CIPHERS {
+            assert_eq!(
+                validate_proxy_settings(&shadowsocks("185.65.135.117:443", cipher)),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            validate_proxy_settings(&shadowsocks("[2a03:1b20:1::1]:443", "aes-256-gcm")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_invalid_shadowsocks_settings() {
+        let invalid = [
+            ("185.65.135.117:443", "aes-512-gcm"),
+            ("185.65.135.117:443", ""),
+            ("185.65.135.117:443", "AES-256-GCM"),
+            ("185.65.135.117:0", "aes-256-gcm"),
+            ("0.0.0.0:443", "aes-256-gcm"),
+            ("[::]:443", "aes-256-gcm"),
+            ("127.0.0.1:443", "aes-256-gcm"),
+            ("[::1]:443", "aes-256-gcm"),
+        ];
+        for (peer, cipher) in &invalid {
+            assert!(
+                validate_proxy_settings(&shadowsocks(peer, cipher)).is_err(),
+                "{} with {} should be rejected",
+                peer,
+                cipher
+            );
+        }
+    }
+}
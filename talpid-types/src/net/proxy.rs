@@ -8,6 +8,7 @@ use std::fmt;
 pub enum ProxyType {
     Shadowsocks,
     Custom,
+    Obfuscation,
 }
 
 impl fmt::Display for ProxyType {
@@ -15,6 +16,7 @@ impl fmt::Display for ProxyType {
         let bridge = match self {
             ProxyType::Shadowsocks => "Shadowsocks",
             ProxyType::Custom => "custom bridge",
+            ProxyType::Obfuscation => "obfuscation bridge",
         };
         write!(f, "{}", bridge)
     }
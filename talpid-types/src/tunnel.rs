@@ -2,7 +2,7 @@ use crate::net::TunnelEndpoint;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, time::Duration};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -16,12 +16,24 @@ pub enum TunnelStateTransition {
     Connecting(TunnelEndpoint),
     /// Tunnel is connected.
     Connected(TunnelEndpoint),
+    /// Tunnel was connected but went down, and the VPN client is bringing it back up on its own.
+    /// Network is secured.
+    Reconnecting(TunnelEndpoint, ReconnectAttempt),
     /// Disconnecting tunnel.
     Disconnecting(ActionAfterDisconnect),
     /// Tunnel is disconnected but usually secured by blocking all connections.
     Error(ErrorState),
 }
 
+/// An attempt by the VPN client to bring a tunnel that went down back up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconnectAttempt {
+    /// How many times in a row the client has tried to reconnect, starting at 1.
+    pub attempt: u32,
+    /// How long the client waits before making this attempt.
+    pub next_delay: Duration,
+}
+
 /// Action that will be taken after disconnection is complete.
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -15,6 +15,7 @@
 #include <memory>
 #include <optional>
 #include <mutex>
+#include <sstream>
 
 using namespace winnet::routing;
 using namespace common::network;
@@ -129,6 +130,59 @@ WinNet_GetTapInterfaceAlias(
 	}
 }
 
+extern "C"
+WINNET_LINKAGE
+bool
+WINNET_API
+WinNet_GetTapInterfaces(
+	wchar_t **adapters,
+	MullvadLogSink logSink,
+	void *logSinkContext
+)
+{
+	try
+	{
+		if (nullptr == adapters)
+		{
+			THROW_ERROR("Invalid argument: adapters");
+		}
+
+		const auto tapAdapters = InterfaceUtils::GetTapAdapters(InterfaceUtils::GetAllAdapters(
+			AF_UNSPEC,
+			GAA_FLAG_SKIP_UNICAST | GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_INCLUDE_ALL_INTERFACES
+		));
+
+		//
+		// One line per adapter: "<alias>\t<name>\t<operational status>".
+		//
+
+		std::wstringstream ss;
+
+		for (const auto &adapter : tapAdapters)
+		{
+			ss << adapter.alias() << L'\t' << adapter.name() << L'\t' << adapter.raw().OperStatus << L'\n';
+		}
+
+		const auto listing = ss.str();
+
+		auto stringBuffer = new wchar_t[listing.size() + 1];
+		wcscpy(stringBuffer, listing.c_str());
+
+		*adapters = stringBuffer;
+
+		return true;
+	}
+	catch (const std::exception &err)
+	{
+		shared::logging::UnwindAndLog(logSink, logSinkContext, err);
+		return false;
+	}
+	catch (...)
+	{
+		return false;
+	}
+}
+
 extern "C"
 WINNET_LINKAGE
 bool
@@ -53,6 +53,20 @@ WinNet_GetTapInterfaceAlias(
 	void *logSinkContext
 );
 
+//
+// Lists the TAP adapters, one per line, as "<alias>\t<name>\t<operational status>".
+// The string must be released with WinNet_ReleaseString.
+//
+extern "C"
+WINNET_LINKAGE
+bool
+WINNET_API
+WinNet_GetTapInterfaces(
+	wchar_t **adapters,
+	MullvadLogSink logSink,
+	void *logSinkContext
+);
+
 extern "C"
 WINNET_LINKAGE
 bool